@@ -0,0 +1,889 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use logos::Logos;
+use miette::{miette, LabeledSpan, Severity};
+
+use crate::parser::{
+    BinaryOp, Expr, ExprKind, LogosToken, MatchArm, Param, Pattern, PostfixOp, UnaryOp,
+};
+use crate::vm::{builtin, Type};
+
+/// What a user function takes and returns.
+struct Signature {
+    required: usize,
+    // `None` for a variadic function
+    max: Option<usize>,
+    // the names and annotated types of the parameters
+    params: Vec<(String, Option<Type>)>,
+    variadic: bool,
+    returns: Option<Type>,
+}
+
+impl Signature {
+    fn new(params: &[Param], returns: &Option<String>) -> Self {
+        let variadic = params.last().is_some_and(|param| param.variadic);
+        Self {
+            required: params
+                .iter()
+                .filter(|param| param.default.is_none() && !param.variadic)
+                .count(),
+            max: (!variadic).then_some(params.len()),
+            params: params
+                .iter()
+                .map(|param| (param.name.clone(), annotated(&param.ty)))
+                .collect(),
+            variadic,
+            returns: annotated(returns),
+        }
+    }
+
+    fn accepts(&self, argc: usize) -> bool {
+        argc >= self.required && self.max.is_none_or(|max| argc <= max)
+    }
+
+    fn expected(&self) -> String {
+        match self.max {
+            None => format!("at least {}", self.required),
+            Some(max) if max == self.required => max.to_string(),
+            Some(max) => format!("{} to {max}", self.required),
+        }
+    }
+
+    /// The parameter the argument at `index` is given to.
+    fn param(&self, index: usize) -> Option<&(String, Option<Type>)> {
+        match self.variadic {
+            true => self.params.get(index.min(self.params.len() - 1)),
+            false => self.params.get(index),
+        }
+    }
+}
+
+/// The type of a `name:type` annotation, the parser only allows valid ones.
+fn annotated(ty: &Option<String>) -> Option<Type> {
+    ty.as_deref().and_then(|ty| Type::try_from(ty).ok())
+}
+
+/// The common type of two values, unknown if they differ.
+fn join(a: Option<Type>, b: Option<Type>) -> Option<Type> {
+    if a == b {
+        a
+    } else {
+        None
+    }
+}
+
+fn is_number(ty: Type) -> bool {
+    matches!(ty, Type::Integer | Type::Float)
+}
+
+/// The type of `lhs op rhs`, or `Err` if the operation can never be done on
+/// values of these types.
+fn binary_type(op: BinaryOp, lhs: Type, rhs: Type) -> Result<Option<Type>, ()> {
+    let numbers = is_number(lhs) && is_number(rhs);
+    // an int only stays an int with another int
+    let number = match (lhs, rhs) {
+        (Type::Integer, Type::Integer) => Type::Integer,
+        _ => Type::Float,
+    };
+
+    let ty = match op {
+        BinaryOp::Add | BinaryOp::AddEq => match (lhs, rhs) {
+            (Type::Array, _) => Type::Array,
+            (Type::String, _) | (_, Type::String) => Type::String,
+            // only a range is made into an array when added to
+            (Type::Iter, _) => return Ok(None),
+            _ if numbers => number,
+            _ => return Err(()),
+        },
+        BinaryOp::Sub | BinaryOp::SubEq | BinaryOp::Mod if numbers => number,
+        BinaryOp::Mul | BinaryOp::MulEq => match (lhs, rhs) {
+            (Type::String, Type::Integer) | (Type::Integer, Type::String) => Type::String,
+            _ if numbers => number,
+            _ => return Err(()),
+        },
+        BinaryOp::Div | BinaryOp::DivEq | BinaryOp::Pow if numbers => Type::Float,
+        BinaryOp::BinaryPow if (lhs, rhs) == (Type::Integer, Type::Integer) => Type::Integer,
+        BinaryOp::Less | BinaryOp::Greater | BinaryOp::LessEq | BinaryOp::GreaterEq => {
+            match (lhs, rhs) {
+                (Type::String, Type::String) | (Type::Array, Type::Array) => Type::Bool,
+                _ if numbers => Type::Bool,
+                _ => return Err(()),
+            }
+        }
+        BinaryOp::Eq | BinaryOp::NotEq | BinaryOp::And | BinaryOp::Or => Type::Bool,
+        BinaryOp::Attr => return Ok(None),
+        _ => return Err(()),
+    };
+
+    Ok(Some(ty))
+}
+
+pub(crate) fn symbol(op: BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Mul => "*",
+        BinaryOp::Div => "/",
+        BinaryOp::Add => "+",
+        BinaryOp::Sub => "-",
+        BinaryOp::Less => "<",
+        BinaryOp::Greater => ">",
+        BinaryOp::LessEq => "<=",
+        BinaryOp::GreaterEq => ">=",
+        BinaryOp::NotEq => "!=",
+        BinaryOp::Eq => "==",
+        BinaryOp::Or => "||",
+        BinaryOp::And => "&&",
+        BinaryOp::AddEq => "+=",
+        BinaryOp::SubEq => "-=",
+        BinaryOp::MulEq => "*=",
+        BinaryOp::DivEq => "/=",
+        BinaryOp::Attr => ".",
+        BinaryOp::Mod => "%",
+        BinaryOp::BinaryPow => "^",
+        BinaryOp::Pow => "**",
+    }
+}
+
+struct Variable {
+    span: Range<usize>,
+    used: bool,
+    // unknown once it has been given values of different types
+    ty: Option<Type>,
+}
+
+/// The variables of the top level or of a function body, the compiler gives
+/// every function a fresh namespace.
+#[derive(Default)]
+struct Scope {
+    variables: HashMap<String, Variable>,
+    loop_depth: usize,
+    function: Option<String>,
+    // the annotated return type of the function, the values it returns and
+    // whether it is a generator, which doesn't return them
+    returns: Option<Type>,
+    returned: Vec<(Range<usize>, Type)>,
+    yields: bool,
+}
+
+/// Finds the mistakes in a program before it is compiled: calls to unknown
+/// functions, calls with the wrong number of arguments, undefined variables,
+/// `br` and `ct` outside of loops and `&` outside of functions. Unreachable
+/// code and unused variables are only warned about.
+///
+/// The types of values are inferred where they can be, so that operations
+/// that can't work, like `"a" - 1`, and values that don't match the types
+/// functions are annotated with are found too.
+pub struct Analyzer {
+    functions: HashMap<String, Signature>,
+    scopes: Vec<Scope>,
+    errors: Vec<LabeledSpan>,
+    warnings: Vec<LabeledSpan>,
+}
+
+impl Analyzer {
+    /// `library` is code the program can call into, like the standard
+    /// library, its functions are known but it isn't checked.
+    pub fn new(library: &[Expr]) -> Self {
+        let mut analyzer = Self {
+            functions: HashMap::new(),
+            scopes: vec![Scope::default()],
+            errors: Vec::new(),
+            warnings: Vec::new(),
+        };
+        analyzer.collect_functions(library);
+        analyzer
+    }
+
+    pub fn analyze(&mut self, ast: &[Expr]) {
+        self.collect_functions(ast);
+        self.block(ast);
+        self.end_scope();
+    }
+
+    pub fn errors(&self) -> &[LabeledSpan] {
+        &self.errors
+    }
+
+    pub fn warnings(&self) -> &[LabeledSpan] {
+        &self.warnings
+    }
+
+    /// Prints the problems that were found, returns false if there are errors
+    /// the program can't run with.
+    pub fn report(&mut self, src: &str) -> bool {
+        let warnings = std::mem::take(&mut self.warnings);
+        if !warnings.is_empty() {
+            let report = miette!(
+                severity = Severity::Warning,
+                labels = warnings,
+                "{}",
+                Self::count(warnings.len(), "warning")
+            );
+            println!("{:?}", report.with_source_code(src.to_string()));
+        }
+
+        let errors = std::mem::take(&mut self.errors);
+        if errors.is_empty() {
+            return true;
+        }
+
+        let message = Self::count(errors.len(), "error");
+        let report = miette!(labels = errors, "{}", message);
+        println!("{:?}", report.with_source_code(src.to_string()));
+        false
+    }
+
+    fn count(n: usize, what: &str) -> String {
+        format!("{n} {what}{}", if n == 1 { "" } else { "s" })
+    }
+
+    fn error(&mut self, span: Range<usize>, label: String) {
+        self.errors.push(LabeledSpan::at(span, label));
+    }
+
+    fn warning(&mut self, span: Range<usize>, label: String) {
+        self.warnings.push(LabeledSpan::at(span, label));
+    }
+
+    /// Functions can be called before they are declared, and the ones
+    /// declared inside of other functions are global too.
+    fn collect_functions(&mut self, exprs: &[Expr]) {
+        for expr in exprs {
+            match &expr.inner {
+                ExprKind::InlineFunction(name, params, returns, body) => {
+                    let signature = Signature::new(params, returns);
+                    self.functions.insert(name.clone(), signature);
+                    self.collect_functions(std::slice::from_ref(body));
+                }
+                ExprKind::MultilineFunction(name, params, returns, body) => {
+                    let signature = Signature::new(params, returns);
+                    self.functions.insert(name.clone(), signature);
+                    self.collect_functions(body);
+                }
+                ExprKind::Memoized(function) => {
+                    self.collect_functions(std::slice::from_ref(function))
+                }
+                ExprKind::Ternary(_, then_block, else_block) => {
+                    self.collect_functions(then_block);
+                    self.collect_functions(else_block.as_deref().unwrap_or_default());
+                }
+                ExprKind::While(_, body) | ExprKind::Every(_, body) => self.collect_functions(body),
+                ExprKind::Try(body, _, handler) => {
+                    self.collect_functions(body);
+                    self.collect_functions(handler);
+                }
+                ExprKind::Match(_, arms) => {
+                    for arm in arms {
+                        self.collect_functions(&arm.body);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn scope(&mut self) -> &mut Scope {
+        self.scopes.last_mut().unwrap()
+    }
+
+    /// Gives `name` a value of type `ty` in the current scope, `used` leaves
+    /// it out of the unused variable warnings.
+    fn define(&mut self, name: &str, span: Range<usize>, used: bool, ty: Option<Type>) {
+        self.scope()
+            .variables
+            .entry(name.to_owned())
+            .and_modify(|variable| {
+                variable.used |= used;
+                variable.ty = join(variable.ty, ty);
+            })
+            .or_insert(Variable { span, used, ty });
+    }
+
+    /// Marks `name` as used, returns its type or `Err` if it isn't defined.
+    fn read(&mut self, name: &str) -> Result<Option<Type>, ()> {
+        match self.scope().variables.get_mut(name) {
+            Some(variable) => {
+                variable.used = true;
+                Ok(variable.ty)
+            }
+            None => Err(()),
+        }
+    }
+
+    fn end_scope(&mut self) {
+        let scope = self.scopes.pop().unwrap();
+        let mut unused = scope
+            .variables
+            .into_iter()
+            .filter(|(name, variable)| !variable.used && !name.starts_with('_'))
+            .collect::<Vec<_>>();
+        unused.sort_by_key(|(_, variable)| variable.span.start);
+
+        for (name, variable) in unused {
+            self.warning(variable.span, format!("`{name}` is never used"));
+        }
+
+        let (Some(name), Some(expected)) = (scope.function, scope.returns) else {
+            return;
+        };
+        if scope.yields {
+            return;
+        }
+
+        for (span, ty) in scope.returned {
+            if ty != expected {
+                let (expected, ty) = (expected.get_type(), ty.get_type());
+                self.error(span, format!("`{name}` returns {expected}, found {ty}"));
+            }
+        }
+    }
+
+    /// The type of the last expression of the block.
+    fn block(&mut self, exprs: &[Expr]) -> Option<Type> {
+        let mut ty = None;
+        for (i, expr) in exprs.iter().enumerate() {
+            ty = self.expr(expr);
+
+            if matches!(expr.inner, ExprKind::Return(..)) && i + 1 < exprs.len() {
+                let span = exprs[i + 1].span.start..exprs.last().unwrap().span.end;
+                self.warning(span, "unreachable code after `&`".to_string());
+            }
+        }
+
+        ty
+    }
+
+    fn function(
+        &mut self,
+        name: &str,
+        params: &[Param],
+        returns: &Option<String>,
+        body: &[Expr],
+        method: bool,
+    ) {
+        self.scopes.push(Scope {
+            function: Some(name.to_owned()),
+            returns: annotated(returns),
+            ..Scope::default()
+        });
+
+        if method {
+            self.define("self", 0..0, true, None);
+        }
+        // all of them exist before the defaults are set
+        for param in params {
+            let ty = if param.variadic {
+                Some(Type::Array)
+            } else {
+                annotated(&param.ty)
+            };
+            self.define(&param.name, 0..0, true, ty);
+        }
+        for param in params {
+            let Some(default) = &param.default else {
+                continue;
+            };
+
+            let ty = self.expr(default);
+            self.check_type(ty, annotated(&param.ty), default.span.clone(), || {
+                format!("`{}` is", param.name)
+            });
+        }
+
+        let ty = self.block(body);
+        // the value of the last expression is returned
+        if let (Some(ty), Some(last)) = (ty, body.last()) {
+            if !matches!(last.inner, ExprKind::Return(..)) {
+                self.scope().returned.push((last.span.clone(), ty));
+            }
+        }
+        self.end_scope();
+    }
+
+    /// Reports `found` if it isn't the `expected` type, `what` describes what
+    /// has that type.
+    fn check_type(
+        &mut self,
+        found: Option<Type>,
+        expected: Option<Type>,
+        span: Range<usize>,
+        what: impl FnOnce() -> String,
+    ) {
+        if let (Some(found), Some(expected)) = (found, expected) {
+            if found != expected {
+                let (found, expected) = (found.get_type(), expected.get_type());
+                self.error(span, format!("{} {expected}, found {found}", what()));
+            }
+        }
+    }
+
+    fn binary(
+        &mut self,
+        op: BinaryOp,
+        lhs: Option<Type>,
+        rhs: Option<Type>,
+        span: Range<usize>,
+    ) -> Option<Type> {
+        let (lhs, rhs) = (lhs?, rhs?);
+        match binary_type(op, lhs, rhs) {
+            Ok(ty) => ty,
+            Err(()) => {
+                let (lhs, rhs) = (lhs.get_type(), rhs.get_type());
+                let label = format!("`{}` can't be used on {lhs} and {rhs}", symbol(op));
+                self.error(span, label);
+                None
+            }
+        }
+    }
+
+    /// The names given new values anywhere in `exprs`, outside of the
+    /// functions declared there.
+    fn assigned(exprs: &[Expr], names: &mut Vec<String>) {
+        for expr in exprs {
+            match &expr.inner {
+                ExprKind::Set(name, _) | ExprKind::EqStmt(name, ..) => names.push(name.clone()),
+                ExprKind::Postfix(value, _) => {
+                    if let ExprKind::Ident(name) = &value.inner {
+                        names.push(name.clone());
+                    }
+                }
+                ExprKind::Ternary(_, then_block, else_block) => {
+                    Self::assigned(then_block, names);
+                    Self::assigned(else_block.as_deref().unwrap_or_default(), names);
+                }
+                ExprKind::While(_, body) | ExprKind::Every(_, body) => Self::assigned(body, names),
+                ExprKind::Try(body, _, handler) => {
+                    Self::assigned(body, names);
+                    Self::assigned(handler, names);
+                }
+                ExprKind::Match(_, arms) => {
+                    for arm in arms {
+                        Self::assigned(&arm.body, names);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Option<Type> {
+        let span = expr.span.clone();
+        match &expr.inner {
+            ExprKind::Int(_) => Some(Type::Integer),
+            ExprKind::Float(_) => Some(Type::Float),
+            ExprKind::Bool(_) => Some(Type::Bool),
+            ExprKind::String(_) => Some(Type::String),
+            ExprKind::Nil => Some(Type::Nil),
+            ExprKind::Error => None,
+
+            ExprKind::FString(value) => {
+                // the placeholders are only parsed when compiling, any name
+                // in them counts as used
+                for token in LogosToken::lexer(value).flatten() {
+                    if let LogosToken::Ident(name) = token {
+                        let _ = self.read(name);
+                    }
+                }
+                Some(Type::String)
+            }
+
+            ExprKind::Ident(name) => match self.read(name) {
+                Ok(ty) => ty,
+                Err(()) => {
+                    self.error(span, format!("variable `{name}` is not defined"));
+                    None
+                }
+            },
+
+            ExprKind::Set(name, value) => {
+                let ty = self.expr(value);
+                self.define(name, span, false, ty);
+                None
+            }
+
+            ExprKind::Destructure(pattern, value) => {
+                self.expr(value);
+                // the parameters unpacked at the start of a function
+                let param = matches!(&value.inner, ExprKind::Ident(name) if name.starts_with('@'));
+                self.bind(pattern, span, param);
+                None
+            }
+
+            ExprKind::EqStmt(name, op, value) => {
+                let ty = self.expr(value);
+                match self.read(name) {
+                    Ok(current) => {
+                        let ty = self.binary(*op, current, ty, span.clone());
+                        self.define(name, span, false, ty);
+                    }
+                    Err(()) => self.error(span, format!("variable `{name}` is not defined")),
+                }
+                None
+            }
+
+            ExprKind::Postfix(value, op) => {
+                let ty = self.expr(value);
+                match op {
+                    PostfixOp::Factorial => ty.filter(|ty| *ty == Type::Integer),
+                    _ => ty.filter(|ty| matches!(ty, Type::Integer | Type::Float | Type::Bool)),
+                }
+            }
+
+            ExprKind::Unary(op, value) => {
+                let ty = self.expr(value);
+                match op {
+                    UnaryOp::Not => Some(Type::Bool),
+                    UnaryOp::Neg => match ty {
+                        Some(ty) if !is_number(ty) => {
+                            let label = format!("`-` can't be used on {}", ty.get_type());
+                            self.error(span, label);
+                            None
+                        }
+                        ty => ty,
+                    },
+                    UnaryOp::Plus => ty,
+                }
+            }
+
+            ExprKind::Yield(value) => {
+                self.expr(value);
+                self.scope().yields = true;
+                None
+            }
+
+            ExprKind::Return(value) => {
+                let ty = self.expr(value);
+                if self.scope().function.is_none() {
+                    self.error(span, "`&` outside of a function".to_string());
+                } else if let Some(ty) = ty {
+                    self.scope().returned.push((value.span.clone(), ty));
+                }
+                None
+            }
+
+            ExprKind::Index(array, index) => {
+                let ty = self.expr(array);
+                self.expr(index);
+                // the characters of a string are strings too
+                ty.filter(|ty| *ty == Type::String)
+            }
+
+            ExprKind::Array(items) => {
+                for item in items {
+                    self.expr(item);
+                }
+                Some(Type::Array)
+            }
+
+            ExprKind::Binary(value, BinaryOp::Attr, attr) => {
+                self.expr(value);
+                // methods are looked up on the value when running
+                if let ExprKind::Call(_, Some(args)) = &attr.inner {
+                    for arg in args {
+                        self.expr(arg);
+                    }
+                }
+                None
+            }
+
+            ExprKind::Binary(left, op, right) => {
+                let lhs = self.expr(left);
+                let rhs = self.expr(right);
+                self.binary(*op, lhs, rhs, span)
+            }
+
+            ExprKind::Call(name, args) => {
+                let args = args.as_deref().unwrap_or_default();
+                let types = args.iter().map(|arg| self.expr(arg)).collect::<Vec<_>>();
+
+                let Some(signature) = self.functions.get(name) else {
+                    let Some(builtin) = builtin(name) else {
+                        self.error(span, format!("function `{name}` is not defined"));
+                        return None;
+                    };
+                    if !builtin.arity.contains(&args.len()) {
+                        self.error(span, builtin.arity_error(args.len()));
+                    }
+                    return builtin.returns;
+                };
+
+                let returns = signature.returns;
+                if !signature.accepts(args.len()) {
+                    let label = format!(
+                        "`{name}` takes {} argument(s), found {}",
+                        signature.expected(),
+                        args.len()
+                    );
+                    self.error(span, label);
+                    return returns;
+                }
+
+                let expected = (0..args.len())
+                    .map(|i| signature.param(i).cloned())
+                    .collect::<Vec<_>>();
+                for ((arg, ty), param) in args.iter().zip(types).zip(expected) {
+                    let Some((param, expected)) = param else {
+                        continue;
+                    };
+                    self.check_type(ty, expected, arg.span.clone(), || {
+                        format!("`{param}` of `{name}` is")
+                    });
+                }
+                returns
+            }
+
+            ExprKind::InlineFunction(name, params, returns, body) => {
+                self.function(name, params, returns, std::slice::from_ref(body), false);
+                None
+            }
+            ExprKind::MultilineFunction(name, params, returns, body) => {
+                self.function(name, params, returns, body, false);
+                None
+            }
+            ExprKind::Memoized(function) => self.expr(function),
+
+            ExprKind::Impl(_, body) => {
+                for method in body {
+                    match &method.inner {
+                        ExprKind::InlineFunction(name, params, returns, body) => {
+                            self.function(name, params, returns, std::slice::from_ref(body), true)
+                        }
+                        ExprKind::MultilineFunction(name, params, returns, body) => {
+                            self.function(name, params, returns, body, true)
+                        }
+                        _ => {}
+                    }
+                }
+                None
+            }
+
+            ExprKind::Ternary(condition, then_block, else_block) => {
+                self.expr(condition);
+                let ty = self.block(then_block);
+                match else_block {
+                    Some(else_block) => {
+                        let other = self.block(else_block);
+                        join(ty, other)
+                    }
+                    None => None,
+                }
+            }
+
+            ExprKind::While(condition, body) => {
+                self.loop_body(std::slice::from_ref(condition), body);
+                None
+            }
+
+            ExprKind::Every(list, body) => {
+                let ty = self.expr(list);
+
+                let outer_i = self.scope().variables.remove("i");
+                // the characters of a string are strings too
+                let item = ty.filter(|ty| *ty == Type::String);
+                self.define("i", span, true, item);
+                self.loop_body(&[], body);
+                // like when compiling, `i` stays defined after the loop
+                if let Some(outer_i) = outer_i {
+                    self.scope().variables.insert("i".to_string(), outer_i);
+                }
+                None
+            }
+
+            ExprKind::Break | ExprKind::Continue => {
+                if self.scope().loop_depth == 0 {
+                    let keyword = if matches!(expr.inner, ExprKind::Break) {
+                        "br"
+                    } else {
+                        "ct"
+                    };
+                    self.error(span, format!("`{keyword}` outside of a loop"));
+                }
+                None
+            }
+
+            ExprKind::Try(body, name, handler) => {
+                self.block(body);
+                if let Some(name) = name {
+                    self.define(name, span, true, Some(Type::Error));
+                }
+                self.block(handler);
+                None
+            }
+
+            ExprKind::Match(value, arms) => {
+                self.expr(value);
+
+                for MatchArm {
+                    pattern,
+                    guard,
+                    body,
+                } in arms
+                {
+                    // the arms have no spans of their own to point at, so
+                    // their names aren't warned about
+                    let mut bound = Vec::new();
+                    self.bind_arm(pattern, span.clone(), &mut bound);
+                    if let Some(guard) = guard {
+                        self.expr(guard);
+                    }
+                    self.block(body);
+
+                    for (name, outer) in bound.into_iter().rev() {
+                        match outer {
+                            Some(variable) => self.scope().variables.insert(name, variable),
+                            None => self.scope().variables.remove(&name),
+                        };
+                    }
+                }
+                None
+            }
+        }
+    }
+
+    /// Checks `condition` and `body`, which run again and again, so the
+    /// variables they change may have the type of a previous round.
+    fn loop_body(&mut self, condition: &[Expr], body: &[Expr]) {
+        let mut names = Vec::new();
+        Self::assigned(body, &mut names);
+        for name in names {
+            if let Some(variable) = self.scope().variables.get_mut(&name) {
+                variable.ty = None;
+            }
+        }
+
+        self.scope().loop_depth += 1;
+        self.block(condition);
+        self.block(body);
+        self.scope().loop_depth -= 1;
+    }
+
+    /// Binds the names of an `mc` pattern for its arm, the ones that are
+    /// already defined are compared against. `bound` gets the names along
+    /// with the variables they hid.
+    fn bind_arm(
+        &mut self,
+        pattern: &Pattern,
+        span: Range<usize>,
+        bound: &mut Vec<(String, Option<Variable>)>,
+    ) {
+        match pattern {
+            Pattern::Ident(name) | Pattern::Rest(name)
+                if bound.iter().any(|(bound, _)| bound == name) => {}
+            Pattern::Ident(name) if self.read(name).is_ok() => {}
+            Pattern::Ident(name) | Pattern::Rest(name) => {
+                let outer = self.scope().variables.remove(name);
+                self.define(name, span, true, None);
+                bound.push((name.clone(), outer));
+            }
+            Pattern::Array(patterns) | Pattern::Or(patterns) => {
+                for pattern in patterns {
+                    self.bind_arm(pattern, span.clone(), bound);
+                }
+            }
+            Pattern::HeadTail(head, tail) => {
+                self.bind_arm(head, span.clone(), bound);
+                self.bind_arm(tail, span, bound);
+            }
+            Pattern::Value(_) | Pattern::Range(..) | Pattern::Wildcard | Pattern::Type(_) => {
+                self.bind(pattern, span, true)
+            }
+        }
+    }
+
+    fn bind(&mut self, pattern: &Pattern, span: Range<usize>, used: bool) {
+        match pattern {
+            Pattern::Ident(name) | Pattern::Rest(name) => self.define(name, span, used, None),
+            Pattern::Array(patterns) | Pattern::Or(patterns) => {
+                for pattern in patterns {
+                    self.bind(pattern, span.clone(), used);
+                }
+            }
+            Pattern::HeadTail(head, tail) => {
+                self.bind(head, span.clone(), used);
+                self.bind(tail, span, used);
+            }
+            Pattern::Value(value) => {
+                self.expr(value);
+            }
+            Pattern::Range(lo, hi) => {
+                self.expr(lo);
+                self.expr(hi);
+            }
+            Pattern::Wildcard | Pattern::Type(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::PParser;
+    use crate::tokenize;
+
+    fn analyze(src: &str) -> (Vec<String>, Vec<String>) {
+        let mut analyzer = Analyzer::new(&[]);
+        analyzer.analyze(&PParser::new(src, tokenize(src)).parse());
+
+        let labels = |spans: &[LabeledSpan]| {
+            spans
+                .iter()
+                .map(|span| span.label().unwrap().to_string())
+                .collect()
+        };
+        (labels(&analyzer.errors), labels(&analyzer.warnings))
+    }
+
+    #[test]
+    fn test_analyze_errors() {
+        let (errors, warnings) = analyze(
+            "add a b=1: a + b\n$add()\n$add(1, 2, 3)\n$int(\"5\", 2, 3)\n$later(1)\n$nope(x)\nbr\n& 0\nlater ...xs: xs\nmc 1 { n: n }\n$n\n$e + pi()",
+        );
+
+        assert_eq!(
+            errors,
+            [
+                "`add` takes 1 to 2 argument(s), found 0",
+                "`add` takes 1 to 2 argument(s), found 3",
+                "`int` takes 1 argument(s), found 3",
+                "variable `x` is not defined",
+                "function `nope` is not defined",
+                "`br` outside of a loop",
+                "`&` outside of a function",
+                "variable `n` is not defined",
+                "variable `e` is not defined",
+            ]
+        );
+        assert_eq!(warnings, ["unreachable code after `&`"]);
+    }
+
+    #[test]
+    fn test_analyze_warnings() {
+        let (errors, warnings) = analyze(
+            "f x: {\n  y = x\n  _z = 1\n  & x\n  $y\n}\nev [1] { $i; ct }\nmc 1 { [a, ...b]: a }\nw, h = 1, 2\n$w",
+        );
+
+        assert!(errors.is_empty());
+        assert_eq!(
+            warnings,
+            ["unreachable code after `&`", "`h` is never used"]
+        );
+    }
+
+    #[test]
+    fn test_analyze_types() {
+        let (errors, _) = analyze(
+            "add a:int b:int -> int: a + b\nbad -> str: 1\n$add(1, \"a\")\n$\"a\" - 1\nx = 1\n$x > \"b\"\nx = [x]\n$x - 1\nn = 0\n>. n < 3 { n = n + 0.5 }\n$-len(\"a\")\n$rng(3) + [9]\n$rng(3) - 1",
+        );
+
+        assert_eq!(
+            errors,
+            [
+                "`bad` returns str, found int",
+                "`b` of `add` is int, found str",
+                "`-` can't be used on str and int",
+                "`>` can't be used on int and str",
+                "`-` can't be used on iter and int",
+            ]
+        );
+    }
+}
@@ -0,0 +1,275 @@
+//! Runs random programs, made from scrambled pieces of valid ones, through
+//! every step a program goes through: `tokenize`, `PParser`, the analyzer, the
+//! optimizer and the compiler and the VM. Whatever the input, it has to end
+//! with a diagnostic rather than a panic.
+//!
+//! `SHORTLANG_FUZZ_CASES` sets how many programs are tried and
+//! `SHORTLANG_FUZZ_SEED` the seed they are made from, `SEED` by default so
+//! that every run tries the same programs.
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::Duration;
+
+use crate::analyzer::Analyzer;
+use crate::optimizer::Optimizer;
+use crate::parser::PParser;
+use crate::tokenize;
+use crate::vm::VM;
+
+const CASES: usize = 5000;
+const SEED: u64 = 0x5407_7a46;
+// programs can loop forever, those are fine
+const STEPS: usize = 100_000;
+// far more than `STEPS` takes, or a single instruction on numbers with
+// millions of digits in a debug build, something ran away
+const TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_CALL_DEPTH: usize = 10_000;
+
+const SEEDS: &[&str] = &[
+    include_str!("../examples/factorial.sl"),
+    include_str!("../examples/fizzbuzz.sl"),
+    include_str!("../examples/functions.sl"),
+    include_str!("../examples/sqrt.sl"),
+    "a, b = [1, [2, 3]]\nh::t = \"abc\"\n$f\"{a} $h {t}\"",
+    "f x y=2 ...r: x + y + len(r)\n$f(1)\n$f(1, 2, 3, 4)",
+    "@g n: n < 2 ? n : g(n - 1) + g(n - 2)\n$g(30)",
+    "s n acc: n == 0 ? acc : s(n - 1, acc + n)\n$s(1000, 0)",
+    "p: {\n    x = 2\n    >. true { yd x; x++ }\n}\nev p() { i > 5 ? br : $i }",
+    "d v: mc v {\n    0 | 1: \"small\"\n    2..9: \"digit\"\n    [a, ...r]: r\n    n if n < 0: -n\n    str: v\n    _: nil\n}\n$d([1, 2])",
+    "tr { raise(\"no\") } ca e { $e.msg(); $e.span() }\ntr { 1 / 0 } ca { $\"zero\" }",
+    "impl int {\n    twice: self * 2\n}\n$5.twice()\n$[3, 1, 2].sort()",
+    "$rer(\"2023-10-18\", \"(\\\\d+)-(\\\\d+)\", \"$2/$1\")\n$ref(\"a1b22\", \"\\\\d+\")",
+    "$isprime(97); $factor(360); $powmod(2, 100, 7); $tobase(255, 16); $digits(1234)",
+    // few items, the permutations of longer ones take too long to print
+    "ev combs([1, 2, 3], 2) { $i }\n$perms([1, 2])\n$product([0, 1], \"ab\")",
+    "prec(100)\n$sqrt(2); $pi(80); $log(8, 2); $atan2(1, 1)",
+    "area w:int h:int -> int: w * h\n$area(3, 4)",
+    "x = [1, 2, 3]\nx[0] = 5\n$x[1:]\n$x[-1]\n$\"abc\"[1]",
+];
+
+const TOKENS: &[&str] = &[
+    "0",
+    "1",
+    "-1",
+    "2.5",
+    ".5",
+    "1e400",
+    "99999999999999999999999",
+    "\"\"",
+    "\"a\"",
+    "\"\\\\\"",
+    "\"\\x\"",
+    "f\"{a}\"",
+    "f\"$\"",
+    "f\"{\"",
+    "_a_b",
+    "a",
+    "b",
+    "f",
+    "i",
+    "x",
+    "self",
+    "nil",
+    "true",
+    "inf",
+    "+",
+    "-",
+    "*",
+    "/",
+    "%",
+    "**",
+    "^",
+    "!",
+    "++",
+    "--",
+    "==",
+    "!=",
+    "<",
+    ">",
+    "<=",
+    ">=",
+    "&&",
+    "||",
+    "&",
+    "|",
+    "=",
+    "+=",
+    "-=",
+    "*=",
+    "/=",
+    ":",
+    "::",
+    "..",
+    "...",
+    ",",
+    ".",
+    "@",
+    "$",
+    "$$",
+    "?",
+    "(",
+    ")",
+    "[",
+    "]",
+    "{",
+    "}",
+    ";",
+    "->",
+    ">.",
+    "ev",
+    "br",
+    "ct",
+    "mc",
+    "yd",
+    "tr",
+    "ca",
+    "impl",
+    "int",
+    "str",
+    "flt",
+    "len",
+    "rng",
+    "perms",
+    "re",
+    "rer",
+    "sqrt",
+    "raise",
+    "exit",
+    "prec",
+    "factor",
+    "tobase",
+    "frombase",
+    "\n",
+    " ",
+];
+
+/// The operands of the repeats of strings, the counts are the ones that can't
+/// be used as they are.
+const STRINGS: &[&str] = &["\"\"", "\"ab\"", "s", "[1]"];
+const COUNTS: &[&str] = &[
+    "0",
+    "3",
+    "-1",
+    "-99999999999999999999",
+    "99999999999999999999",
+    "4611686018427387904",
+    "2.5",
+    "nil",
+];
+
+/// Goes through every step with the program, the errors it finds are fine.
+fn run(src: &str) {
+    let std_lib = include_str!("../std/std.sl");
+    let ast_std = PParser::new(std_lib, tokenize(std_lib)).parse();
+
+    let mut parser = PParser::new(src, tokenize(src));
+    let ast = parser.parse_recovering();
+    if !parser.errors().is_empty() {
+        return;
+    }
+
+    // the programs it rejects still have to compile and run without a panic
+    Analyzer::new(&ast_std).analyze(&ast);
+    let ast = Optimizer::new(ast).optimize_all();
+
+    let mut vm = VM::new(src, ast);
+    vm.set_max_call_depth(MAX_CALL_DEPTH);
+    if vm.try_compile().is_ok() {
+        vm.optimize();
+        vm.run_steps(STEPS).ok();
+    }
+}
+
+/// Tries the program on its own thread, returning the message of the panic
+/// if it had one, or saying it hung if it is still running after `TIMEOUT`.
+fn try_run(src: &str) -> Option<String> {
+    let (sender, receiver) = mpsc::channel();
+    let src = src.to_string();
+    std::thread::spawn(move || {
+        let outcome = panic::catch_unwind(AssertUnwindSafe(|| run(&src)));
+        sender.send(outcome).ok();
+    });
+
+    match receiver.recv_timeout(TIMEOUT) {
+        Ok(Ok(())) => None,
+        Err(RecvTimeoutError::Timeout) => Some(format!("still running after {TIMEOUT:?}")),
+        Ok(Err(payload)) => Some(match payload.downcast::<String>() {
+            Ok(message) => *message,
+            Err(payload) => payload
+                .downcast::<&str>()
+                .map_or("a panic".to_string(), |message| message.to_string()),
+        }),
+        Err(RecvTimeoutError::Disconnected) => Some("the thread died".to_string()),
+    }
+}
+
+/// A string repeated by a count, written out or through `*=`.
+fn repeat(rng: &mut fastrand::Rng) -> String {
+    let s = STRINGS[rng.usize(..STRINGS.len())];
+    let n = COUNTS[rng.usize(..COUNTS.len())];
+    match rng.u8(..3) {
+        0 => format!("s = \"ab\"\n$({s} * {n})"),
+        1 => format!("s = \"ab\"\n$({n} * {s})"),
+        _ => format!("s = {s}\ns *= {n}\n$s"),
+    }
+}
+
+fn scramble(rng: &mut fastrand::Rng) -> String {
+    match rng.u8(..5) {
+        0 => return repeat(rng),
+        1 | 2 => {
+            return (0..rng.usize(1..40))
+                .map(|_| TOKENS[rng.usize(..TOKENS.len())])
+                .collect::<Vec<_>>()
+                .join(if rng.bool() { " " } else { "" })
+        }
+        _ => {}
+    }
+
+    let mut chars = SEEDS[rng.usize(..SEEDS.len())].chars().collect::<Vec<_>>();
+    for _ in 0..rng.usize(1..6) {
+        let at = rng.usize(..=chars.len());
+        let end = (at + rng.usize(..8)).min(chars.len());
+        match rng.u8(..3) {
+            0 => {
+                chars.drain(at..end);
+            }
+            1 => {
+                let token = TOKENS[rng.usize(..TOKENS.len())];
+                chars.splice(at..at, token.chars());
+            }
+            _ => {
+                let copy = chars[at..end].to_vec();
+                let to = rng.usize(..=chars.len());
+                chars.splice(to..to, copy);
+            }
+        }
+    }
+
+    chars.into_iter().collect()
+}
+
+#[test]
+fn fuzz_no_input_panics() {
+    let cases = std::env::var("SHORTLANG_FUZZ_CASES")
+        .map_or(CASES, |cases| cases.parse().expect("a number of cases"));
+    let seed = std::env::var("SHORTLANG_FUZZ_SEED")
+        .map_or(SEED, |seed| seed.parse().expect("a numeric seed"));
+
+    let mut rng = fastrand::Rng::with_seed(seed);
+    let mut failures = vec![];
+    for _ in 0..cases {
+        let src = scramble(&mut rng);
+        if let Some(error) = try_run(&src) {
+            failures.push(format!("{src:?}\n{error}"));
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{} of {cases} programs panicked or hung, with SHORTLANG_FUZZ_SEED={seed}:\n\n{}",
+        failures.len(),
+        failures.join("\n\n")
+    );
+}
@@ -0,0 +1,615 @@
+//! Answers the requests of the editor about a single document.
+
+use std::ops::Range;
+
+use crate::analyzer::Analyzer;
+use crate::parser::{Expr, ExprKind, LogosToken, PParser, Param};
+use crate::tokenize;
+use crate::vm::builtin;
+
+use super::docs::{self, Doc};
+use super::golf::golf;
+use super::json::Json;
+
+/// The kinds of semantic tokens, in the order the editor is told about them.
+pub const TOKEN_TYPES: &[&str] = &[
+    "keyword", "function", "method", "variable", "string", "number", "operator", "type",
+];
+
+const TYPES: &[&str] = &[
+    "int", "float", "str", "bool", "array", "iter", "error", "nil",
+];
+
+/// Converts between byte offsets and the line and UTF-16 column positions of
+/// the protocol.
+pub struct LineIndex<'a> {
+    text: &'a str,
+    starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    pub fn new(text: &'a str) -> Self {
+        let starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Self { text, starts }
+    }
+
+    /// The line and column of `offset`.
+    pub fn position(&self, offset: usize) -> (usize, usize) {
+        let offset = offset.min(self.text.len());
+        let line = self.starts.partition_point(|&start| start <= offset) - 1;
+        let column = self.text[self.starts[line]..offset].encode_utf16().count();
+        (line, column)
+    }
+
+    /// The byte offset of a position, clamped to the end of its line.
+    pub fn offset(&self, line: usize, column: usize) -> usize {
+        let Some(&start) = self.starts.get(line) else {
+            return self.text.len();
+        };
+        let end = self
+            .starts
+            .get(line + 1)
+            .map_or(self.text.len(), |&end| end - 1);
+
+        let mut units = 0;
+        for (i, c) in self.text[start..end].char_indices() {
+            if units >= column {
+                return start + i;
+            }
+            units += c.len_utf16();
+        }
+        end
+    }
+
+    pub fn range(&self, span: &Range<usize>) -> Json {
+        Json::object([
+            ("start", self.position_json(span.start)),
+            ("end", self.position_json(span.end)),
+        ])
+    }
+
+    fn position_json(&self, offset: usize) -> Json {
+        let (line, character) = self.position(offset);
+        Json::object([("line", line.into()), ("character", character.into())])
+    }
+
+    /// The byte offset of an LSP position object.
+    pub fn offset_of(&self, position: &Json) -> usize {
+        self.offset(
+            position.get("line").as_usize().unwrap_or(0),
+            position.get("character").as_usize().unwrap_or(0),
+        )
+    }
+}
+
+/// A function of the document, or a method when it is in an `impl` block.
+struct Definition {
+    name: String,
+    // where the name is written
+    span: Range<usize>,
+    header: String,
+    method_of: Option<String>,
+}
+
+fn parse(text: &str) -> (Vec<Expr>, PParser<'_>) {
+    let mut parser = PParser::new(text, tokenize(text));
+    let ast = parser.parse_recovering();
+    (ast, parser)
+}
+
+/// The syntax errors of the document or, once there are none, the mistakes
+/// found by the analyzer.
+pub fn diagnostics(text: &str) -> Vec<Json> {
+    let lines = LineIndex::new(text);
+    let diagnostic = |span: &Range<usize>, severity: usize, message: String| {
+        Json::object([
+            ("range", lines.range(span)),
+            ("severity", severity.into()),
+            ("source", "ShortLang".into()),
+            ("message", message.into()),
+        ])
+    };
+
+    let (ast, parser) = parse(text);
+    if !parser.errors().is_empty() {
+        return parser
+            .errors()
+            .iter()
+            .map(|error| {
+                let message = match &error.help {
+                    Some(help) => format!("{}: {}\n{help}", error.message, error.label),
+                    None => format!("{}: {}", error.message, error.label),
+                };
+                diagnostic(&error.span, 1, message)
+            })
+            .collect();
+    }
+
+    let library = include_str!("../../std/std.sl");
+    let (library, _) = parse(library);
+    let mut analyzer = Analyzer::new(&library);
+    analyzer.analyze(&ast);
+
+    let labeled = |severity: usize| {
+        move |span: &miette::LabeledSpan| {
+            let range = span.offset()..span.offset() + span.len();
+            (
+                range,
+                severity,
+                span.label().unwrap_or_default().to_string(),
+            )
+        }
+    };
+    analyzer
+        .errors()
+        .iter()
+        .map(labeled(1))
+        .chain(analyzer.warnings().iter().map(labeled(2)))
+        .map(|(range, severity, message)| diagnostic(&range, severity, message))
+        .collect()
+}
+
+/// The index of the token at `offset`, a token ending there counts when
+/// nothing starts there.
+fn token_at(tokens: &[(LogosToken, Range<usize>)], offset: usize) -> Option<usize> {
+    tokens
+        .iter()
+        .position(|(_, span)| span.contains(&offset))
+        .or_else(|| tokens.iter().position(|(_, span)| span.end == offset))
+}
+
+/// Whether the token at `index` comes right after a `.`.
+fn after_dot(tokens: &[(LogosToken, Range<usize>)], index: usize) -> bool {
+    index > 0 && tokens[index - 1].0 == LogosToken::Dot
+}
+
+fn markdown(code: &str, text: String) -> Json {
+    Json::object([(
+        "contents",
+        Json::object([
+            ("kind", "markdown".into()),
+            ("value", format!("```shortlang\n{code}\n```\n{text}").into()),
+        ]),
+    )])
+}
+
+fn builtin_markdown(doc: &Doc) -> Json {
+    let mut text = doc.description.to_string();
+    if let Some(ty) = builtin(doc.name).and_then(|builtin| builtin.returns) {
+        text.push_str(&format!("\n\nReturns `{ty}`"));
+    }
+    markdown(doc.signature, text)
+}
+
+pub fn hover(text: &str, offset: usize) -> Json {
+    let tokens = tokenize(text);
+    let Some(index) = token_at(&tokens, offset) else {
+        return Json::Null;
+    };
+
+    let (token, _) = &tokens[index];
+    let name = match token {
+        LogosToken::Ident(name) => *name,
+        LogosToken::Dollar | LogosToken::DollarDollar => {
+            return docs::function(&token.to_string()).map_or(Json::Null, builtin_markdown)
+        }
+        token => {
+            return docs::keyword(&token.to_string()).map_or(Json::Null, |doc| {
+                markdown(doc.signature, doc.description.to_string())
+            })
+        }
+    };
+
+    let (ast, _) = parse(text);
+    let method = after_dot(&tokens, index);
+    let definitions = definitions(text, &ast);
+    if let Some(definition) = definitions
+        .iter()
+        .find(|definition| definition.name == name && definition.method_of.is_some() == method)
+    {
+        let text = match &definition.method_of {
+            Some(ty) => format!("Method of `{ty}`"),
+            None => "Function".to_string(),
+        };
+        return markdown(&definition.header, text);
+    }
+
+    let doc = match method {
+        true => docs::method(name),
+        false => docs::function(name),
+    };
+    doc.map_or(Json::Null, builtin_markdown)
+}
+
+pub fn definition(text: &str, uri: &str, offset: usize) -> Json {
+    let tokens = tokenize(text);
+    let Some(index) = token_at(&tokens, offset) else {
+        return Json::Null;
+    };
+    let LogosToken::Ident(name) = tokens[index].0 else {
+        return Json::Null;
+    };
+
+    let (ast, _) = parse(text);
+    let method = after_dot(&tokens, index);
+    let lines = LineIndex::new(text);
+    let locations = definitions(text, &ast)
+        .into_iter()
+        .filter(|definition| definition.name == name && definition.method_of.is_some() == method)
+        .map(|definition| {
+            Json::object([
+                ("uri", uri.into()),
+                ("range", lines.range(&definition.span)),
+            ])
+        })
+        .collect::<Vec<_>>();
+
+    match locations.is_empty() {
+        true => Json::Null,
+        false => locations.into(),
+    }
+}
+
+pub fn completion(text: &str, offset: usize) -> Json {
+    // the start of the word being typed
+    let start = text[..offset]
+        .rfind(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .map_or(0, |i| i + 1);
+    let method = text[..start].ends_with('.');
+
+    let item = |label: &str, kind: usize, detail: String, documentation: Option<&str>| {
+        Json::object([
+            ("label", label.into()),
+            ("kind", kind.into()),
+            ("detail", detail.into()),
+            ("documentation", documentation.into()),
+        ])
+    };
+
+    let (ast, _) = parse(text);
+    let definitions = definitions(text, &ast);
+    let user = definitions
+        .iter()
+        .filter(|definition| definition.method_of.is_some() == method)
+        .map(|definition| item(&definition.name, 3, definition.header.clone(), None));
+
+    let items = match method {
+        true => user
+            .chain(
+                docs::METHODS
+                    .iter()
+                    .map(|doc| item(doc.name, 2, doc.signature.into(), Some(doc.description))),
+            )
+            .collect::<Vec<_>>(),
+        false => user
+            .chain(
+                docs::FUNCTIONS
+                    .iter()
+                    .filter(|doc| !doc.name.starts_with('$'))
+                    .map(|doc| item(doc.name, 3, doc.signature.into(), Some(doc.description))),
+            )
+            .chain(
+                docs::KEYWORDS
+                    .iter()
+                    .filter(|doc| doc.name.starts_with(char::is_alphabetic))
+                    .map(|doc| item(doc.name, 14, doc.signature.into(), Some(doc.description))),
+            )
+            .collect(),
+    };
+
+    items.into()
+}
+
+/// The semantic tokens of the document, encoded relative to each other as the
+/// protocol wants them.
+pub fn semantic_tokens(text: &str) -> Json {
+    use LogosToken::*;
+
+    let tokens = tokenize(text);
+    let lines = LineIndex::new(text);
+    let mut data = Vec::new();
+    let (mut last_line, mut last_column) = (0, 0);
+    for (i, (token, span)) in tokens.iter().enumerate() {
+        let next = tokens.get(i + 1).map(|(token, _)| token);
+        let previous = i.checked_sub(1).map(|i| &tokens[i].0);
+        let kind = match token {
+            While | Every | Break | Continue | Match | Yield | Try | Catch | Impl | True
+            | False | Nil | Inf | Return | At => "keyword",
+            Dollar | DollarDollar => "function",
+            String(_) | FString(_) => "string",
+            Int(_) | Float(_) => "number",
+            Ident(_) if previous == Some(&Dot) => "method",
+            Ident(_) if next == Some(&LParen) => "function",
+            // annotations and `impl` blocks
+            Ident(name)
+                if TYPES.contains(name) && matches!(previous, Some(Colon | Arrow | Impl)) =>
+            {
+                "type"
+            }
+            Ident(name) if name.starts_with('_') && *name != "_" => "string",
+            Ident(_) => "variable",
+            Plus | Minus | Times | Slash | BinaryPow | Pow | Eq | Bang | And | Or | Pipe
+            | Percent | Eqq | Neq | Leq | Geq | LAngle | RAngle | AddEq | SubEq | MulEq | PAdd
+            | PSub | Question | DivEq | FourDots | DotDot | ThreeDots | Arrow => "operator",
+            _ => continue,
+        };
+        let kind = TOKEN_TYPES
+            .iter()
+            .position(|&name| name == kind)
+            .unwrap_or(0);
+
+        // a token can only be on a single line
+        let mut start = span.start;
+        for part in text[span.clone()].split_inclusive('\n') {
+            let length = part.trim_end_matches('\n').encode_utf16().count();
+            let (line, column) = lines.position(start);
+            start += part.len();
+            if length == 0 {
+                continue;
+            }
+
+            let delta_column = match line == last_line {
+                true => column - last_column,
+                false => column,
+            };
+            data.extend([line - last_line, delta_column, length, kind, 0]);
+            (last_line, last_column) = (line, column);
+        }
+    }
+
+    Json::object([(
+        "data",
+        data.into_iter().map(Json::from).collect::<Vec<_>>().into(),
+    )])
+}
+
+/// Offers to golf the statements the range touches.
+pub fn code_actions(text: &str, uri: &str, range: Range<usize>) -> Json {
+    let (ast, _) = parse(text);
+    let tokens = tokenize(text);
+    let lines = LineIndex::new(text);
+
+    let mut actions = Vec::new();
+    for (i, statement) in ast.iter().enumerate() {
+        if matches!(statement.inner, ExprKind::Error) {
+            continue;
+        }
+
+        // spans of statements don't always cover their last token, so
+        // everything up to the next statement is taken
+        let end = ast.get(i + 1).map_or(text.len(), |next| next.span.start);
+        let Some(end) = tokens
+            .iter()
+            .filter(|(token, span)| {
+                span.start >= statement.span.start
+                    && span.end <= end
+                    && !matches!(token, LogosToken::Newline | LogosToken::Semi)
+            })
+            .map(|(_, span)| span.end)
+            .next_back()
+        else {
+            continue;
+        };
+
+        let span = statement.span.start..end;
+        let touched = span.start <= range.end && range.start <= span.end;
+        let Some(golfed) = golf(text, span.clone()).filter(|_| touched) else {
+            continue;
+        };
+
+        let saved = span.len() - golfed.len();
+        let edit = Json::object([("range", lines.range(&span)), ("newText", golfed.into())]);
+        actions.push(Json::object([
+            (
+                "title",
+                format!(
+                    "Golf this statement (saves {saved} byte{})",
+                    if saved == 1 { "" } else { "s" }
+                )
+                .into(),
+            ),
+            ("kind", "refactor.rewrite".into()),
+            (
+                "edit",
+                Json::object([(
+                    "changes",
+                    Json::Object(vec![(uri.to_string(), vec![edit].into())]),
+                )]),
+            ),
+        ]));
+    }
+
+    actions.into()
+}
+
+/// The functions and methods of the program, wherever they are declared.
+fn definitions(text: &str, ast: &[Expr]) -> Vec<Definition> {
+    let mut definitions = Vec::new();
+    collect_definitions(text, ast, None, &mut definitions);
+    definitions
+}
+
+fn collect_definitions(
+    text: &str,
+    exprs: &[Expr],
+    method_of: Option<&str>,
+    definitions: &mut Vec<Definition>,
+) {
+    for expr in exprs {
+        match &expr.inner {
+            ExprKind::InlineFunction(name, params, returns, body) => {
+                definitions.push(definition_of(text, expr, name, params, returns, method_of));
+                collect_definitions(text, std::slice::from_ref(body), None, definitions);
+            }
+            ExprKind::MultilineFunction(name, params, returns, body) => {
+                definitions.push(definition_of(text, expr, name, params, returns, method_of));
+                collect_definitions(text, body, None, definitions);
+            }
+            ExprKind::Memoized(function) => {
+                collect_definitions(text, std::slice::from_ref(function), method_of, definitions)
+            }
+            ExprKind::Impl(ty, body) => collect_definitions(text, body, Some(ty), definitions),
+            ExprKind::Ternary(_, then, otherwise) => {
+                collect_definitions(text, then, None, definitions);
+                if let Some(otherwise) = otherwise {
+                    collect_definitions(text, otherwise, None, definitions);
+                }
+            }
+            ExprKind::While(_, body) | ExprKind::Every(_, body) => {
+                collect_definitions(text, body, None, definitions)
+            }
+            ExprKind::Try(body, _, handler) => {
+                collect_definitions(text, body, None, definitions);
+                collect_definitions(text, handler, None, definitions);
+            }
+            ExprKind::Match(_, arms) => {
+                for arm in arms {
+                    collect_definitions(text, &arm.body, None, definitions);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn definition_of(
+    text: &str,
+    expr: &Expr,
+    name: &str,
+    params: &[Param],
+    returns: &Option<String>,
+    method_of: Option<&str>,
+) -> Definition {
+    let mut header = name.to_string();
+    for param in params {
+        header.push(' ');
+        if param.variadic {
+            header.push_str("...");
+        }
+        match param.name.starts_with('@') {
+            // a destructured parameter
+            true => header.push_str("[..]"),
+            false => header.push_str(&param.name),
+        }
+        if let Some(ty) = &param.ty {
+            header.push_str(&format!(":{ty}"));
+        }
+        if let Some(default) = &param.default {
+            header.push_str(&format!("={}", &text[default.span.clone()]));
+        }
+    }
+    if let Some(ty) = returns {
+        header.push_str(&format!(" -> {ty}"));
+    }
+
+    Definition {
+        name: name.to_string(),
+        span: expr.span.start..expr.span.start + name.len(),
+        header,
+        method_of: method_of.map(str::to_string),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(text: &str, needle: &str) -> usize {
+        text.find(needle).unwrap()
+    }
+
+    #[test]
+    fn test_line_index() {
+        let text = "a = \"é😀\"\nb = 1";
+        let lines = LineIndex::new(text);
+        assert_eq!(lines.position(at(text, "b")), (1, 0));
+        assert_eq!(lines.position(text.len()), (1, 5));
+        // é and the emoji take one and two UTF-16 units
+        assert_eq!(lines.position(at(text, "\"\n")), (0, 8));
+        assert_eq!(lines.offset(0, 8), at(text, "\"\n"));
+        assert_eq!(lines.offset(0, 100), at(text, "\n"));
+        assert_eq!(lines.offset(5, 0), text.len());
+    }
+
+    #[test]
+    fn test_analysis() {
+        let text = "area w:int h=2 -> int: w * h\nimpl int { twice: self * 2 }\n$area(3).twice()\n$sqrt(2)";
+
+        let messages = |text: &str| {
+            diagnostics(text)
+                .iter()
+                .map(|diagnostic| diagnostic.get("message").as_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert!(messages(text).is_empty());
+        assert_eq!(messages("$nope()"), ["function `nope` is not defined"]);
+        assert_eq!(
+            messages("f x x\n"),
+            ["Expected `:` after the parameters of `f` found <newline>: expected `:`\nThe body of a function comes after a colon, like `f a b: a + b`"]
+        );
+
+        let hover_text = |offset| {
+            hover(text, offset)
+                .get("contents")
+                .get("value")
+                .as_str()
+                .map(str::to_string)
+        };
+        assert_eq!(
+            hover_text(at(text, "area(")).as_deref(),
+            Some("```shortlang\narea w:int h=2 -> int\n```\nFunction")
+        );
+        assert!(hover_text(at(text, "sqrt"))
+            .unwrap()
+            .ends_with("Returns `float`"));
+        assert!(hover_text(at(text, "twice()"))
+            .unwrap()
+            .contains("Method of `int`"));
+        assert_eq!(hover_text(at(text, "3")), None);
+
+        let location = definition(text, "file:///a.sl", at(text, "twice()"));
+        assert_eq!(
+            location.as_array()[0].get("range").get("start"),
+            &Json::object([("line", 1.into()), ("character", 11.into())])
+        );
+        assert!(definition(text, "file:///a.sl", at(text, "sqrt")).is_null());
+
+        let labels = |offset| {
+            completion(text, offset)
+                .as_array()
+                .iter()
+                .map(|item| item.get("label").as_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+        let methods = labels(at(text, "twice()"));
+        assert!(methods.contains(&"twice".to_string()) && methods.contains(&"join".to_string()));
+        assert!(!methods.contains(&"area".to_string()));
+        let functions = labels(at(text, "sqrt"));
+        assert!(functions.contains(&"area".to_string()) && functions.contains(&"ev".to_string()));
+
+        // `$`, `area` and `(` of the third line
+        let data = semantic_tokens(text).get("data").as_array().to_vec();
+        let third_line = data
+            .chunks(5)
+            .scan(0, |line, token| {
+                *line += token[0].as_usize().unwrap();
+                Some((*line, token[3].as_usize().unwrap()))
+            })
+            .filter(|(line, _)| *line == 2)
+            .map(|(_, kind)| TOKEN_TYPES[kind])
+            .collect::<Vec<_>>();
+        assert_eq!(third_line, ["function", "function", "number", "method"]);
+
+        let actions = code_actions(text, "file:///a.sl", 0..0);
+        let edit = &actions.as_array()[0]
+            .get("edit")
+            .get("changes")
+            .get("file:///a.sl");
+        assert_eq!(
+            edit.as_array()[0].get("newText").as_str(),
+            Some("area w:int h=2->int:w*h")
+        );
+    }
+}
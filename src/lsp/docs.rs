@@ -0,0 +1,169 @@
+//! What the builtin functions and methods take and do, shown on hover and in
+//! completions.
+
+pub struct Doc {
+    pub name: &'static str,
+    pub signature: &'static str,
+    pub description: &'static str,
+}
+
+const fn doc(name: &'static str, signature: &'static str, description: &'static str) -> Doc {
+    Doc {
+        name,
+        signature,
+        description,
+    }
+}
+
+pub const FUNCTIONS: &[Doc] = &[
+    doc("$", "$value", "Prints the value followed by a newline."),
+    doc("$$", "$$value", "Prints the value without a newline."),
+    doc("int", "int(value)", "Converts a float, bool or string to an integer."),
+    doc("flt", "flt(value)", "Converts an integer, bool or string to a float."),
+    doc("str", "str(value)", "Converts the value to a string."),
+    doc("inp", "inp(prompt)", "Prints the prompt and reads a line from the input."),
+    doc("len", "len(value)", "The length of an array, a string or a finite sequence."),
+    doc("type", "type(value)", "The name of the type of the value, like `int` or `str`."),
+    doc("gcd", "gcd(a, b)", "The greatest common divisor of `a` and `b`."),
+    doc("lcm", "lcm(a, b)", "The least common multiple of `a` and `b`."),
+    doc("fib", "fib(n)", "The `n`th Fibonacci number."),
+    doc("abs", "abs(x)", "The absolute value of `x`."),
+    doc("floor", "floor(x)", "The largest integer not greater than `x`."),
+    doc("ceil", "ceil(x)", "The smallest integer not less than `x`."),
+    doc("exit", "exit()", "Stops the program."),
+    doc("raise", "raise(value)", "Raises an error, which a `tr` block can catch."),
+    doc("rnd", "rnd(start, end)", "A random integer from `start` (0 by default) up to `end`."),
+    doc("rng", "rng(start, end, step)", "The lazy sequence of numbers from `start` (0 by default) up to `end`, which can be `inf`. Pushing or adding to it makes the array."),
+    doc("sqrt", "sqrt(x, n)", "The `n`th root of `x`, the square root by default."),
+    doc("round", "round(x, digits)", "Rounds `x` to the given number of decimal digits."),
+    doc("isprime", "isprime(n)", "`true` if `n` is (very probably) a prime."),
+    doc("nextprime", "nextprime(n)", "The smallest prime greater than `n`."),
+    doc("factor", "factor(n)", "Array of the prime factors of `n`, e.g. `[2, 2, 3]` for 12."),
+    doc("powmod", "powmod(b, e, m)", "`b ** e % m` without the huge intermediate value."),
+    doc("invmod", "invmod(a, m)", "The inverse of `a` modulo `m`."),
+    doc("binomial", "binomial(n, k)", "`n` choose `k`."),
+    doc("isqrt", "isqrt(n)", "The integer square root of `n`."),
+    doc("digits", "digits(n, b)", "Array of the digits of `n` in base `b` (10 by default)."),
+    doc("tobase", "tobase(n, b)", "`n` as a string in base `b` (2 by default, up to 36)."),
+    doc("frombase", "frombase(s, b)", "Parses `s` as a number in base `b` (2 by default)."),
+    doc("perms", "perms(arr, k)", "Every ordering of `k` items of `arr` (all by default)."),
+    doc("combs", "combs(arr, k)", "Every choice of `k` items of `arr`, keeping their order."),
+    doc("powerset", "powerset(arr)", "Every subset of `arr`, from the smallest."),
+    doc("product", "product(a, b, ...)", "Every way of picking one item from each array."),
+    doc("sin", "sin(x, prec)", "The sine of `x`."),
+    doc("cos", "cos(x, prec)", "The cosine of `x`."),
+    doc("tan", "tan(x, prec)", "The tangent of `x`."),
+    doc("asin", "asin(x, prec)", "The arcsine of `x`."),
+    doc("acos", "acos(x, prec)", "The arccosine of `x`."),
+    doc("atan", "atan(x, prec)", "The arctangent of `x`."),
+    doc("atan2", "atan2(y, x, prec)", "The angle of the point (`x`, `y`)."),
+    doc("ln", "ln(x, prec)", "The natural logarithm of `x`."),
+    doc("log", "log(x, base, prec)", "The logarithm of `x` in the given base."),
+    doc("log2", "log2(x, prec)", "The base 2 logarithm of `x`."),
+    doc("log10", "log10(x, prec)", "The base 10 logarithm of `x`."),
+    doc("exp", "exp(x, prec)", "`e` to the power of `x`."),
+    doc("hypot", "hypot(x, y, prec)", "The length of the hypotenuse, `sqrt(x*x + y*y)`."),
+    doc("pi", "pi(prec)", "The constant π, as precise as asked for."),
+    doc("e", "e(prec)", "The constant e, as precise as asked for."),
+    doc("prec", "prec(bits)", "Sets the precision of new floats in bits, at most 65536, returning the previous one. Returns the current one without an argument. Float literals keep the precision they were parsed with."),
+    doc("re", "re(s, p)", "`true` if the pattern `p` matches anywhere in `s`."),
+    doc("ref", "ref(s, p)", "Array of every match of `p` in `s`."),
+    doc("rec", "rec(s, p)", "Array of the groups of every match, group 0 is the whole match."),
+    doc("rer", "rer(s, p, r)", "Replaces every match with `r`, `$1` refers to a group."),
+    doc("res", "res(s, p)", "Splits `s` on every match."),
+];
+
+pub const METHODS: &[Doc] = &[
+    doc(
+        "push",
+        "array.push(value)",
+        "Adds the value to the end of an array or a string.",
+    ),
+    doc(
+        "clear",
+        "array.clear()",
+        "Removes everything from an array or a string.",
+    ),
+    doc(
+        "join",
+        "array.join(separator)",
+        "The items as strings, joined with the separator.",
+    ),
+    doc(
+        "split",
+        "str.split(separator)",
+        "The parts of the string between the separators.",
+    ),
+    doc("msg", "error.msg()", "The message of a caught error."),
+    doc(
+        "span",
+        "error.span()",
+        "Where in the source a caught error happened, `[start, end]`.",
+    ),
+];
+
+pub const KEYWORDS: &[Doc] = &[
+    doc(
+        ">.",
+        ">. condition { ... }",
+        "Runs the block as long as the condition is true.",
+    ),
+    doc(
+        "ev",
+        "ev values { ... }",
+        "Runs the block for every item, the current one being `i`.",
+    ),
+    doc("br", "br", "Leaves the loop."),
+    doc("ct", "ct", "Skips to the next item of the loop."),
+    doc(
+        "mc",
+        "mc value { pattern: ... }",
+        "Evaluates the first arm whose pattern matches the value.",
+    ),
+    doc(
+        "yd",
+        "yd value",
+        "Hands the value to the loop going over the generator.",
+    ),
+    doc(
+        "tr",
+        "tr { ... } ca error { ... }",
+        "Runs the `ca` block if the `tr` block raises an error.",
+    ),
+    doc(
+        "ca",
+        "tr { ... } ca error { ... }",
+        "Handles the errors raised in the `tr` block.",
+    ),
+    doc(
+        "impl",
+        "impl type { method: ... }",
+        "Adds methods to a type, the value is `self`.",
+    ),
+];
+
+pub fn function(name: &str) -> Option<&'static Doc> {
+    FUNCTIONS.iter().find(|doc| doc.name == name)
+}
+
+pub fn method(name: &str) -> Option<&'static Doc> {
+    METHODS.iter().find(|doc| doc.name == name)
+}
+
+pub fn keyword(name: &str) -> Option<&'static Doc> {
+    KEYWORDS.iter().find(|doc| doc.name == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::BUILTINS;
+
+    #[test]
+    fn test_every_builtin_has_docs() {
+        for builtin in BUILTINS {
+            let name = builtin.name;
+            assert!(function(name).is_some(), "`{name}` has no docs");
+        }
+    }
+}
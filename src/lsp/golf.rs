@@ -0,0 +1,130 @@
+//! Rewrites code to take as few bytes as possible without changing what it
+//! does, token by token.
+
+use std::ops::Range;
+
+use crate::parser::LogosToken;
+use crate::tokenize;
+
+/// The golfed form of the code in `span`: spaces and comments are dropped
+/// unless two tokens would run together, statements are separated by `;`
+/// and strings that can be are written as `_Foo`. `None` if the code can't be
+/// made shorter.
+pub fn golf(src: &str, span: Range<usize>) -> Option<String> {
+    let code = &src[span];
+    let tokens = tokenize(code);
+    if tokens.iter().any(|(token, _)| *token == LogosToken::Error) {
+        return None;
+    }
+    // `_Foo` is a name rather than a string in a pattern
+    let has_patterns = tokens.iter().any(|(token, _)| *token == LogosToken::Match);
+
+    let mut golfed: Vec<String> = Vec::new();
+    let mut expected: Vec<LogosToken> = Vec::new();
+    for (i, (token, span)) in tokens.iter().enumerate() {
+        let last = expected.last();
+        let (text, token) = match token {
+            LogosToken::Newline | LogosToken::Semi => {
+                let next = tokens.get(i + 1).map(|(token, _)| token);
+                let redundant = matches!(last, None | Some(LogosToken::Semi | LogosToken::LBrace))
+                    || matches!(
+                        next,
+                        None | Some(LogosToken::Newline | LogosToken::Semi | LogosToken::RBrace)
+                    );
+                if redundant {
+                    continue;
+                }
+                (";".to_string(), LogosToken::Semi)
+            }
+            // a string at the start of a statement could be read as the
+            // name of a function
+            LogosToken::String(s)
+                if !has_patterns
+                    && !matches!(last, None | Some(LogosToken::Semi | LogosToken::LBrace)) =>
+            {
+                match as_name(s) {
+                    // any name, it isn't compared
+                    Some(name) => (name, LogosToken::Ident("")),
+                    None => (s.to_string(), token.clone()),
+                }
+            }
+            _ => (code[span.clone()].to_string(), token.clone()),
+        };
+
+        golfed.push(text);
+        expected.push(token);
+    }
+
+    let mut out = String::new();
+    for (i, text) in golfed.iter().enumerate() {
+        if i > 0 && runs_together(&golfed[i - 1], text) {
+            out.push(' ');
+        }
+        out.push_str(text);
+    }
+
+    // the golfed code has to lex to the same tokens
+    let relexed = tokenize(&out);
+    let same = relexed.len() == expected.len()
+        && relexed
+            .iter()
+            .zip(&expected)
+            .all(|((token, _), expected)| match expected {
+                LogosToken::Ident("") => matches!(token, LogosToken::Ident(_)),
+                expected => token == expected,
+            });
+
+    (same && out.len() < code.trim().len()).then_some(out)
+}
+
+/// `"Foo Bar"` as `_Foo_Bar`, if it is a string that can be written that way.
+fn as_name(string: &str) -> Option<String> {
+    let content = &string[1..string.len() - 1];
+    let valid = !content.is_empty()
+        && content
+            .chars()
+            .all(|c| c == ' ' || (c.is_alphanumeric() && c != '_'));
+
+    valid.then(|| format!("_{}", content.replace(' ', "_")))
+}
+
+/// Whether two tokens written without a space between them would be read as
+/// something else.
+fn runs_together(a: &str, b: &str) -> bool {
+    let joined = format!("{a}{b}");
+    let tokens = tokenize(&joined);
+    !(tokens.len() == 2 && tokens[0].1 == (0..a.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn golfed(src: &str) -> Option<String> {
+        golf(src, 0..src.len())
+    }
+
+    #[test]
+    fn test_golf() {
+        assert_eq!(
+            golfed("add a b: {\n    // the sum\n    a + b\n}\n").as_deref(),
+            Some("add a b:{a+b}")
+        );
+        assert_eq!(
+            golfed("x = 1 - -1\ny = x . len ( )").as_deref(),
+            Some("x=1- -1;y=x.len()")
+        );
+        assert_eq!(
+            golfed("$ \"Hello World\" + f\"{x}\"").as_deref(),
+            Some("$_Hello_World+f\"{x}\"")
+        );
+        // strings starting a statement or used in patterns stay strings
+        assert_eq!(golfed("\"a\" ; \"b c\"").as_deref(), Some("\"a\";\"b c\""));
+        assert_eq!(
+            golfed("mc x { \"a\" : 1 }").as_deref(),
+            Some("mc x{\"a\":1}")
+        );
+        assert_eq!(golfed("x=1"), None);
+        assert_eq!(golfed("a = 1 # 2"), None);
+    }
+}
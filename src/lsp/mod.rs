@@ -0,0 +1,256 @@
+//! A language server for ShortLang, speaking LSP over stdin and stdout.
+//!
+//! Documents are synced whole and analyzed again on every request, the
+//! programs are small enough for that to be instant.
+
+mod analysis;
+mod docs;
+mod golf;
+mod json;
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::panic::{self, AssertUnwindSafe};
+
+use analysis::LineIndex;
+pub(crate) use golf::golf;
+use json::Json;
+
+const PARSE_ERROR: usize = 32700;
+const METHOD_NOT_FOUND: usize = 32601;
+const INTERNAL_ERROR: usize = 32603;
+
+struct Server<W: Write> {
+    out: W,
+    documents: HashMap<String, String>,
+    shutdown: bool,
+}
+
+/// Runs the server until the editor asks it to exit.
+pub fn serve() {
+    let stdin = io::stdin();
+    let mut server = Server {
+        out: io::stdout(),
+        documents: HashMap::new(),
+        shutdown: false,
+    };
+
+    let mut input = stdin.lock();
+    while let Some(body) = read_message(&mut input) {
+        match Json::parse(&body) {
+            Ok(message) => {
+                if server.handle(&message) {
+                    break;
+                }
+            }
+            Err(error) => server.respond(Json::Null, Err((PARSE_ERROR, error))),
+        }
+    }
+
+    std::process::exit(if server.shutdown { 0 } else { 1 });
+}
+
+/// Reads the body of the next message, `None` once the input is closed.
+fn read_message(input: &mut impl BufRead) -> Option<String> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line).ok()? == 0 {
+            return None;
+        }
+
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse().ok();
+            }
+        }
+    }
+
+    let mut body = vec![0; length?];
+    input.read_exact(&mut body).ok()?;
+    String::from_utf8(body).ok()
+}
+
+impl<W: Write> Server<W> {
+    fn send(&mut self, message: Json) {
+        let body = message.to_string();
+        // the editor going away ends the server through the closed input
+        let _ = write!(self.out, "Content-Length: {}\r\n\r\n{body}", body.len());
+        let _ = self.out.flush();
+    }
+
+    /// Answers a request, the error codes are the negated JSON-RPC ones.
+    fn respond(&mut self, id: Json, result: Result<Json, (usize, String)>) {
+        let (key, value) = match result {
+            Ok(result) => ("result", result),
+            Err((code, message)) => (
+                "error",
+                Json::object([
+                    ("code", Json::Number(-(code as f64))),
+                    ("message", message.into()),
+                ]),
+            ),
+        };
+        self.send(Json::object([
+            ("jsonrpc", "2.0".into()),
+            ("id", id),
+            (key, value),
+        ]));
+    }
+
+    fn notify(&mut self, method: &str, params: Json) {
+        self.send(Json::object([
+            ("jsonrpc", "2.0".into()),
+            ("method", method.into()),
+            ("params", params),
+        ]));
+    }
+
+    /// Handles a request or a notification, returning whether the server
+    /// should exit.
+    fn handle(&mut self, message: &Json) -> bool {
+        let method = message.get("method").as_str().unwrap_or_default();
+        let params = message.get("params");
+        let id = message.get("id");
+
+        if id.is_null() {
+            self.notification(method, params);
+            return method == "exit";
+        }
+
+        // a bug in answering a request shouldn't take the server down
+        let result = panic::catch_unwind(AssertUnwindSafe(|| self.request(method, params)))
+            .unwrap_or_else(|_| Err((INTERNAL_ERROR, format!("Failed to answer {method}"))));
+        self.respond(id.clone(), result);
+        false
+    }
+
+    fn notification(&mut self, method: &str, params: &Json) {
+        let uri = params
+            .get("textDocument")
+            .get("uri")
+            .as_str()
+            .unwrap_or_default();
+        match method {
+            "textDocument/didOpen" => {
+                let text = params.get("textDocument").get("text").as_str();
+                self.update(uri, text.unwrap_or_default().to_string());
+            }
+            "textDocument/didChange" => {
+                // the whole text is sent on every change
+                let changes = params.get("contentChanges").as_array();
+                if let Some(text) = changes
+                    .last()
+                    .and_then(|change| change.get("text").as_str())
+                {
+                    self.update(uri, text.to_string());
+                }
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                self.publish(uri, Vec::new());
+            }
+            _ => {}
+        }
+    }
+
+    fn update(&mut self, uri: &str, text: String) {
+        let diagnostics = panic::catch_unwind(|| analysis::diagnostics(&text)).unwrap_or_default();
+        self.documents.insert(uri.to_string(), text);
+        self.publish(uri, diagnostics);
+    }
+
+    fn publish(&mut self, uri: &str, diagnostics: Vec<Json>) {
+        self.notify(
+            "textDocument/publishDiagnostics",
+            Json::object([("uri", uri.into()), ("diagnostics", diagnostics.into())]),
+        );
+    }
+
+    fn request(&mut self, method: &str, params: &Json) -> Result<Json, (usize, String)> {
+        match method {
+            "initialize" => return Ok(capabilities()),
+            "shutdown" => {
+                self.shutdown = true;
+                return Ok(Json::Null);
+            }
+            _ => {}
+        }
+
+        let uri = params
+            .get("textDocument")
+            .get("uri")
+            .as_str()
+            .unwrap_or_default();
+        let Some(text) = self.documents.get(uri) else {
+            return match method.starts_with("textDocument/") {
+                // nothing to say about a document that isn't open
+                true => Ok(Json::Null),
+                false => Err((METHOD_NOT_FOUND, format!("Unknown method {method}"))),
+            };
+        };
+
+        let lines = LineIndex::new(text);
+        let offset = lines.offset_of(params.get("position"));
+        Ok(match method {
+            "textDocument/hover" => analysis::hover(text, offset),
+            "textDocument/definition" => analysis::definition(text, uri, offset),
+            "textDocument/completion" => analysis::completion(text, offset),
+            "textDocument/semanticTokens/full" => analysis::semantic_tokens(text),
+            "textDocument/codeAction" => {
+                let range = params.get("range");
+                let range = lines.offset_of(range.get("start"))..lines.offset_of(range.get("end"));
+                analysis::code_actions(text, uri, range)
+            }
+            _ => return Err((METHOD_NOT_FOUND, format!("Unknown method {method}"))),
+        })
+    }
+}
+
+fn capabilities() -> Json {
+    let token_types = analysis::TOKEN_TYPES
+        .iter()
+        .map(|&kind| kind.into())
+        .collect::<Vec<Json>>();
+
+    Json::object([
+        (
+            "capabilities",
+            Json::object([
+                // full text on every change
+                ("textDocumentSync", 1.into()),
+                ("hoverProvider", true.into()),
+                ("definitionProvider", true.into()),
+                (
+                    "completionProvider",
+                    Json::object([("triggerCharacters", vec![".".into()].into())]),
+                ),
+                (
+                    "semanticTokensProvider",
+                    Json::object([
+                        (
+                            "legend",
+                            Json::object([
+                                ("tokenTypes", token_types.into()),
+                                ("tokenModifiers", Vec::new().into()),
+                            ]),
+                        ),
+                        ("full", true.into()),
+                    ]),
+                ),
+                ("codeActionProvider", true.into()),
+            ]),
+        ),
+        (
+            "serverInfo",
+            Json::object([
+                ("name", "ShortLang".into()),
+                ("version", env!("CARGO_PKG_VERSION").into()),
+            ]),
+        ),
+    ])
+}
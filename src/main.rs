@@ -1,15 +1,22 @@
 #![allow(non_snake_case, dead_code)]
 
-use clap::Parser;
+use analyzer::Analyzer;
+use clap::{Parser, Subcommand};
 use optimizer::Optimizer;
 use std::fs;
+use std::path::Path;
 
+mod analyzer;
+#[cfg(test)]
+mod fuzz;
+mod lsp;
 mod optimizer;
+mod pretty;
 use logos::Logos;
 use miette::{miette, Severity};
 use parser::{LogosToken, PParser};
 
-use crate::vm::VM;
+use crate::vm::{Program, VM};
 
 mod parser;
 mod vm;
@@ -18,7 +25,7 @@ mod vm;
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Args {
-    /// The input file to use
+    /// The input file to use, a program or a bytecode file written by `build`
     #[clap(name = "FILE", default_value = "main.sl")]
     file: String,
 
@@ -33,6 +40,79 @@ pub struct Args {
     /// Formats the input file to be as short as possible
     #[clap(short, long)]
     format: bool,
+
+    /// The precision of floats in bits, at most 65536
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..=vm::MAX_PRECISION as i64))]
+    precision: Option<u32>,
+
+    /// The maximum number of nested function calls (10000 by default)
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..))]
+    max_depth: Option<u64>,
+
+    /// Lists every function call that led to a runtime error
+    #[clap(long)]
+    backtrace: bool,
+
+    /// Runs the program in a debugger that pauses at the first line
+    #[clap(long)]
+    debug: bool,
+
+    /// Pauses the debugger on the line instead of the first one
+    #[clap(long = "break", value_name = "LINE", requires = "debug")]
+    breakpoints: Vec<usize>,
+
+    /// Reports how often and for how long every kind of instruction, line
+    /// and function ran
+    #[clap(long)]
+    profile: bool,
+
+    /// Writes the stacks the profiler saw to the file, in the folded format
+    /// of flamegraph tools
+    #[clap(long, value_name = "FILE", requires = "profile")]
+    folded: Option<String>,
+
+    /// Logs every instruction as it runs to stderr, with the depth of the
+    /// call stack and the size of the value stack
+    #[clap(long)]
+    trace: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Runs the language server, speaking LSP over stdin and stdout
+    Lsp,
+    /// Compiles a program to a bytecode file, which runs without being
+    /// compiled again
+    Build {
+        /// The program to compile
+        #[clap(name = "FILE")]
+        file: String,
+
+        /// The bytecode file to write, the program with the `.slc` extension
+        /// by default
+        #[clap(short, long)]
+        output: Option<String>,
+
+        /// Leaves out the spans and the source, errors can't point at the
+        /// code then
+        #[clap(long)]
+        strip: bool,
+    },
+    /// Prints a program golfed to be as short as possible, or written out to
+    /// be read with `--pretty`
+    Fmt {
+        /// The program to format
+        #[clap(name = "FILE")]
+        file: String,
+
+        /// Puts every statement on its own line with blocks indented and
+        /// spaces around operators, comments are dropped
+        #[clap(long)]
+        pretty: bool,
+    },
 }
 
 fn format_duration(duration: std::time::Duration) -> String {
@@ -72,31 +152,157 @@ fn tokenize(input: &str) -> Vec<(LogosToken, std::ops::Range<usize>)> {
         .collect::<Vec<_>>()
 }
 
-fn main() {
-    let args = Args::parse();
+/// Parses, checks and optimizes a program, ready to be compiled.
+fn parse_source(file: &str, args: &Args) -> VM {
     let std_lib = include_str!("../std/std.sl").to_owned();
-    let src = fs::read_to_string(&args.file).unwrap_or_else(|_| {
+    let src = fs::read_to_string(file).unwrap_or_else(|_| {
         println!("Error: Input file could not be read");
         std::process::exit(1);
     });
 
     let mut ast_std = PParser::new(&std_lib, tokenize(&std_lib)).parse();
     let mut ast_src = PParser::new(&src, tokenize(&src)).parse();
+
+    let mut analyzer = Analyzer::new(&ast_std);
+    analyzer.analyze(&ast_src);
+    if !analyzer.report(&src) {
+        std::process::exit(1);
+    }
     ast_std.append(&mut ast_src);
 
-    let ast = Optimizer::new(ast_std).optimize_all();
+    let mut optimizer = Optimizer::new(ast_std);
+    // the debugger steps into the functions and calls them as they are written
+    optimizer.set_keep_functions(args.debug);
+    let ast = optimizer.optimize_all();
     if args.ast {
         println!(
             "{:?}",
             miette!(severity = Severity::Advice, "AST: {:?}", ast,)
         );
+        println!(
+            "{:?}",
+            miette!(
+                severity = Severity::Advice,
+                "Inlining:\n{}",
+                optimizer.summary()
+            )
+        );
     }
 
-    let mut vm = VM::new(&src, ast);
-    if args.benchmark {
-        let start = std::time::Instant::now();
+    VM::new(&src, ast)
+}
+
+/// Loads a program written by `ShortLang build`.
+fn load_bytecode(file: &str, args: &Args) -> VM {
+    let bytes = fs::read(file).unwrap_or_else(|_| {
+        println!("Error: Input file could not be read");
+        std::process::exit(1);
+    });
+    let program = Program::decode(&bytes).unwrap_or_else(|e| exit_with(miette!("{e}")));
+
+    // the source changed since it was built, so it is run instead
+    if let Ok(src) = fs::read_to_string(&program.path) {
+        if vm::checksum(&src) != program.checksum {
+            let mut vm = parse_source(&program.path, args);
+            vm.compile();
+            vm.optimize();
+            // a file that can't be written again only runs slower next time
+            let _ = write_bytecode(&vm, &program.path, file, program.spans);
+            return vm;
+        }
+    }
+    if args.debug && !program.spans {
+        exit_with(miette!(
+            "The debugger needs the source, `{file}` was built with `--strip`"
+        ));
+    }
+    if args.precision.is_none() {
+        vm::set_float_precision(program.precision);
+    }
+
+    VM::from_program(program)
+}
+
+/// Writes the compiled program of `file` to the bytecode file `output`.
+fn write_bytecode(vm: &VM, file: &str, output: &str, spans: bool) -> Result<(), String> {
+    // absolute, the bytecode file may be run from another directory
+    let path =
+        fs::canonicalize(file).map_or_else(|_| file.to_string(), |path| path.display().to_string());
+    let bytes = vm.program(&path, spans).encode()?;
+    fs::write(output, bytes).map_err(|_| format!("{output} could not be written"))
+}
+
+fn exit_with(report: miette::Report) -> ! {
+    println!("{report:?}");
+    std::process::exit(1);
+}
+
+fn main() {
+    let args = Args::parse();
+    if let Some(Command::Lsp) = args.command {
+        lsp::serve();
+    }
+    if let Some(precision) = args.precision {
+        vm::set_float_precision(precision);
+    }
+
+    if let Some(Command::Fmt { file, pretty }) = &args.command {
+        let Ok(src) = fs::read_to_string(file) else {
+            println!("Error: {file} could not be read");
+            std::process::exit(1);
+        };
+        let formatted = match pretty {
+            true => pretty::pretty(&src, &PParser::new(&src, tokenize(&src)).parse()),
+            false => lsp::golf(&src, 0..src.len()).unwrap_or(src),
+        };
+        print!("{formatted}");
+        return;
+    }
+
+    if let Some(Command::Build {
+        file,
+        output,
+        strip,
+    }) = &args.command
+    {
+        let mut vm = parse_source(file, &args);
         vm.compile();
-        vm.run();
+        vm.optimize();
+
+        let output = output.clone().unwrap_or_else(|| {
+            let output = Path::new(file).with_extension("slc");
+            output.display().to_string()
+        });
+        if let Err(e) = write_bytecode(&vm, file, &output, !strip) {
+            exit_with(miette!("{e}"));
+        }
+        return;
+    }
+
+    let bytecode = args.file.ends_with(".slc");
+    let mut vm = match bytecode {
+        true => load_bytecode(&args.file, &args),
+        false => parse_source(&args.file, &args),
+    };
+    if let Some(depth) = args.max_depth {
+        vm.set_max_call_depth(depth as usize);
+    }
+    vm.set_backtrace(args.backtrace);
+    if args.debug {
+        vm.set_debugger(args.breakpoints);
+    }
+    if args.profile {
+        vm.set_profiler(args.folded);
+    }
+    vm.set_trace(args.trace);
+
+    let start = std::time::Instant::now();
+    if !bytecode {
+        vm.compile();
+        vm.optimize();
+    }
+    vm.run();
+    if args.benchmark {
         let run_time = format_duration(start.elapsed());
         println!(
             "\n{:?}",
@@ -106,8 +312,5 @@ fn main() {
                 run_time
             )
         );
-    } else {
-        vm.compile();
-        vm.run();
     }
 }
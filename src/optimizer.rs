@@ -1,153 +1,976 @@
-use crate::float;
-use crate::parser::{BinaryOp, Expr, ExprKind};
+use rug::ops::CompleteRound;
+use rug::{Complete, Integer};
+use std::collections::{HashMap, HashSet};
+
+use crate::parser::{
+    BinaryOp, Expr, ExprKind, MatchArm, PParser, Param, Pattern, PostfixOp, UnaryOp,
+};
+use crate::tokenize;
+use crate::vm::{self, builtin, fstring_parts, FStringPart, Value};
+
+/// The largest argument of `!`, `fib` and `binomial` that is computed ahead
+/// of time, the results get too large to be worth keeping in the AST.
+const MAX_FOLDED_ARG: u32 = 1000;
+/// The longest string that is built ahead of time.
+const MAX_FOLDED_LEN: usize = 1024;
+/// The most expressions the body of a function can have to be inlined.
+const MAX_INLINED_SIZE: usize = 16;
+/// The builtins that don't leave exactly one value, a function calling them
+/// isn't inlined.
+const IMPURE_BUILTINS: &[&str] = &["$", "$$", "exit", "raise", "prec"];
 
 pub struct Optimizer {
     ast: Vec<Expr>,
+    /// The literals of the variables of the current function, or of the top
+    /// level, that are known at this point of it.
+    constants: HashMap<String, ExprKind>,
+    /// The variables set once and never changed, the only ones that can be
+    /// replaced by their value.
+    fixed: HashSet<String>,
+    /// Whether floats can be computed ahead of time, they can't when `prec`
+    /// changes the precision of the ones computed at runtime.
+    fold_floats: bool,
+    /// The parameters and body of the functions small enough to be inlined.
+    inlinable: HashMap<String, (Vec<String>, Expr)>,
+    /// The functions being inlined, which aren't inlined again inside
+    /// themselves.
+    inlining: HashSet<String>,
+    /// Whether every function is kept as it is written, for the debugger.
+    keep_functions: bool,
+    /// The number of calls inlined, by function.
+    inlined: HashMap<String, usize>,
+    /// The functions dropped as they are never called.
+    removed: Vec<String>,
 }
 
 impl Optimizer {
     pub fn new(ast: Vec<Expr>) -> Self {
-        Self { ast }
+        Self {
+            ast,
+            constants: HashMap::new(),
+            fixed: HashSet::new(),
+            fold_floats: true,
+            inlinable: HashMap::new(),
+            inlining: HashSet::new(),
+            keep_functions: false,
+            inlined: HashMap::new(),
+            removed: Vec::new(),
+        }
     }
+
+    /// Keeps every function as it is written, neither inlined nor dropped.
+    pub fn set_keep_functions(&mut self, keep: bool) {
+        self.keep_functions = keep;
+    }
+
     pub fn optimize_all(&mut self) -> Vec<Expr> {
-        let mut exprs: Vec<Expr> = Vec::new();
-        for ast in self.ast.clone() {
-            exprs.push(self.optimize(ast));
+        let ast = std::mem::take(&mut self.ast);
+        self.scan(&ast);
+        if !self.keep_functions {
+            self.find_inlinable(&ast);
+        }
+        let ast = self.optimize_frame(ast);
+        match self.keep_functions {
+            true => ast,
+            false => self.remove_unused(ast),
         }
-        exprs
     }
-    fn constant_fold(&mut self, expr: Expr) -> Expr {
-        match expr.inner.clone() {
-            ExprKind::Binary(left, op, right) => {
-                let l = self.constant_fold(*left);
-                let r = self.constant_fold(*right);
-                let output = Expr::new(
-                    expr.span,
-                    match (l.inner, r.inner) {
-                        (ExprKind::Int(a), ExprKind::Int(b)) => match op {
-                            BinaryOp::Add => ExprKind::Int(a + b),
-                            BinaryOp::Sub => ExprKind::Int(a - b),
-                            BinaryOp::Mul => ExprKind::Int(a * b),
-                            BinaryOp::Div => ExprKind::Float(float!(a) / b),
-                            _ => expr.inner,
-                        },
-                        (ExprKind::Int(a), ExprKind::Float(b)) => match op {
-                            BinaryOp::Add => ExprKind::Float(a + b),
-                            BinaryOp::Sub => ExprKind::Float(a - b),
-                            BinaryOp::Mul => ExprKind::Float(a * b),
-                            BinaryOp::Div => ExprKind::Float(a / b),
-                            _ => expr.inner,
-                        },
-                        (ExprKind::Float(a), ExprKind::Int(b)) => match op {
-                            BinaryOp::Add => ExprKind::Float(a + b),
-                            BinaryOp::Sub => ExprKind::Float(a - b),
-                            BinaryOp::Mul => ExprKind::Float(a * b),
-                            BinaryOp::Div => ExprKind::Float(a / b),
-                            _ => expr.inner,
-                        },
-                        (ExprKind::Float(a), ExprKind::Float(b)) => match op {
-                            BinaryOp::Add => ExprKind::Float(a + b),
-                            BinaryOp::Sub => ExprKind::Float(a - b),
-                            BinaryOp::Mul => ExprKind::Float(a * b),
-                            BinaryOp::Div => ExprKind::Float(a / b),
-                            _ => expr.inner,
-                        },
-                        (ExprKind::String(lhs), ExprKind::String(rhs)) => match op {
-                            BinaryOp::Add => ExprKind::String(format!("{}{}", lhs, rhs)),
-                            _ => expr.inner,
-                        },
-                        _ => expr.inner,
-                    },
-                );
-                output
+
+    /// The functions that were inlined and the ones that were dropped, one
+    /// per line.
+    pub fn summary(&self) -> String {
+        let mut inlined = self.inlined.iter().collect::<Vec<_>>();
+        inlined.sort();
+        let mut lines = inlined
+            .into_iter()
+            .map(|(name, count)| format!("inlined `{name}` at {count} call(s)"))
+            .collect::<Vec<_>>();
+        lines.extend(
+            self.removed
+                .iter()
+                .map(|name| format!("removed `{name}`, no call to it is left")),
+        );
+        match lines.is_empty() {
+            true => "no function was inlined or removed".to_string(),
+            false => lines.join("\n"),
+        }
+    }
+
+    /// Finds the variables that never change once they are set, and whether
+    /// the precision of floats does.
+    fn scan(&mut self, ast: &[Expr]) {
+        let mut sets: HashMap<String, usize> = HashMap::new();
+        let mut changed: HashSet<String> = HashSet::new();
+        let mut fold_floats = true;
+
+        for expr in ast {
+            visit(expr, &mut |expr| match &expr.inner {
+                ExprKind::Set(name, _) => *sets.entry(name.clone()).or_default() += 1,
+                ExprKind::EqStmt(name, ..) => {
+                    changed.insert(name.clone());
+                }
+                ExprKind::Postfix(value, PostfixOp::Increase | PostfixOp::Decrease) => {
+                    changed.extend(root(value));
+                }
+                // changed in place, `.push` and `.clear` change the receiver
+                ExprKind::Binary(
+                    value,
+                    BinaryOp::AddEq
+                    | BinaryOp::SubEq
+                    | BinaryOp::MulEq
+                    | BinaryOp::DivEq
+                    | BinaryOp::Attr,
+                    _,
+                ) => changed.extend(root(value)),
+                ExprKind::InlineFunction(_, params, ..)
+                | ExprKind::MultilineFunction(_, params, ..) => {
+                    changed.extend(params.iter().map(|param| param.name.clone()));
+                }
+                ExprKind::Impl(..) => {
+                    changed.insert("self".to_string());
+                }
+                ExprKind::Every(..) => {
+                    changed.insert("i".to_string());
+                }
+                ExprKind::Try(_, Some(name), _) => {
+                    changed.insert(name.clone());
+                }
+                // the values bound by patterns and the arguments of functions
+                // point to the variables they are given, which they may change
+                ExprKind::Destructure(pattern, value) => {
+                    bound_names(pattern, &mut changed);
+                    changed.extend(ident(value));
+                }
+                ExprKind::Match(value, arms) => {
+                    for arm in arms {
+                        bound_names(&arm.pattern, &mut changed);
+                    }
+                    changed.extend(ident(value));
+                }
+                ExprKind::Call(name, _) if name == "prec" => fold_floats = false,
+                ExprKind::Call(name, Some(args)) if builtin(name).is_none() => {
+                    changed.extend(args.iter().filter_map(ident));
+                }
+                _ => {}
+            });
+        }
+
+        self.fixed = sets
+            .into_iter()
+            .filter(|(name, count)| *count == 1 && !changed.contains(name))
+            .map(|(name, _)| name)
+            .collect();
+        self.fold_floats = fold_floats;
+    }
+
+    /// Finds the functions that can take the place of their calls: the inline
+    /// ones defined once, without defaults, types or recursion, whose small
+    /// body only computes a value from their parameters.
+    fn find_inlinable(&mut self, ast: &[Expr]) {
+        let mut definitions: HashMap<String, usize> = HashMap::new();
+        let mut memoized = HashSet::new();
+        let mut candidates = vec![];
+
+        for expr in ast {
+            visit(expr, &mut |expr| match &expr.inner {
+                ExprKind::InlineFunction(name, params, returns, body) => {
+                    *definitions.entry(name.clone()).or_default() += 1;
+                    let plain = params.iter().all(|param| {
+                        param.default.is_none() && !param.variadic && param.ty.is_none()
+                    });
+                    if plain && returns.is_none() {
+                        let params: Vec<String> =
+                            params.iter().map(|param| param.name.clone()).collect();
+                        candidates.push((name.clone(), params, (**body).clone()));
+                    }
+                }
+                ExprKind::MultilineFunction(name, ..) => {
+                    *definitions.entry(name.clone()).or_default() += 1;
+                }
+                ExprKind::Memoized(function) => {
+                    if let ExprKind::InlineFunction(name, ..) = &function.inner {
+                        memoized.insert(name.clone());
+                    }
+                }
+                _ => {}
+            });
+        }
+
+        for (name, params, body) in candidates {
+            let mut size = 0;
+            visit(&body, &mut |_| size += 1);
+            if definitions[&name] == 1
+                && !memoized.contains(&name)
+                && size <= MAX_INLINED_SIZE
+                && inlinable(&body, &name, &params)
+            {
+                self.inlinable.insert(name, (params, body));
             }
-            _ => expr,
         }
     }
-    fn optimize(&mut self, expr: Expr) -> Expr {
-        match expr.inner {
-            ExprKind::Binary(_, _, _) => self.constant_fold(expr.clone()),
-            ExprKind::MultilineFunction(n, p, es) => {
-                let mut exprs: Vec<Expr> = Vec::new();
-                for e in es {
-                    exprs.push(self.optimize(e))
+
+    /// The body of `name` with its parameters replaced by the arguments of a
+    /// call to it, `None` if the call has to stay one. The arguments are
+    /// literals or variables, which give the same value wherever they are.
+    fn inline(&mut self, name: &str, args: &[Expr]) -> Option<Expr> {
+        if self.inlining.contains(name) {
+            return None;
+        }
+        let (params, body) = self.inlinable.get(name)?.clone();
+        let simple = args
+            .iter()
+            .all(|arg| literal(&arg.inner).is_some() || matches!(arg.inner, ExprKind::Ident(_)));
+        if params.len() != args.len() || !simple {
+            return None;
+        }
+
+        let args = params.into_iter().zip(args.iter().cloned()).collect();
+        let body = substitute(body, &args);
+
+        self.inlining.insert(name.to_string());
+        let body = self.optimize(body);
+        self.inlining.remove(name);
+        *self.inlined.entry(name.to_string()).or_default() += 1;
+        Some(body)
+    }
+
+    /// Drops the functions of the top level that are never called, by the
+    /// program or by the functions it calls.
+    fn remove_unused(&mut self, ast: Vec<Expr>) -> Vec<Expr> {
+        let mut called = HashSet::new();
+        let mut pending = vec![];
+        let mut calls = |expr: &Expr, pending: &mut Vec<String>| {
+            visit(expr, &mut |expr| {
+                if let ExprKind::Call(name, _) = &expr.inner {
+                    if called.insert(name.clone()) {
+                        pending.push(name.clone());
+                    }
+                }
+            })
+        };
+
+        for expr in ast.iter().filter(|expr| function_name(expr).is_none()) {
+            calls(expr, &mut pending);
+        }
+        while let Some(name) = pending.pop() {
+            for expr in &ast {
+                if function_name(expr) == Some(&name) {
+                    calls(expr, &mut pending);
                 }
-                Expr::new(expr.span, ExprKind::MultilineFunction(n, p, exprs))
             }
-            ExprKind::InlineFunction(n, p, e) => Expr::new(
-                expr.span,
-                ExprKind::InlineFunction(n, p, Box::new(self.optimize(*e))),
-            ),
-            ExprKind::While(e, es) => {
-                let mut exprs: Vec<Expr> = Vec::new();
-                for e in es {
-                    exprs.push(self.optimize(e))
-                }
-                Expr::new(
-                    expr.span,
-                    ExprKind::While(Box::new(self.optimize(*e)), exprs),
-                )
-            }
-            ExprKind::Every(e, es) => {
-                let mut exprs: Vec<Expr> = Vec::new();
-                for e in es {
-                    exprs.push(self.optimize(e))
-                }
-                Expr::new(
-                    expr.span,
-                    ExprKind::Every(Box::new(self.optimize(*e)), exprs),
-                )
-            }
-            ExprKind::Ternary(e, t, f) => {
-                let mut exprs: Vec<Expr> = Vec::new();
-                for e in t {
-                    exprs.push(self.optimize(e))
-                }
-                let mut exprs2: Vec<Expr> = Vec::new();
-                if let Some(f) = f {
-                    for e in f {
-                        exprs2.push(self.optimize(e))
+        }
+
+        ast.into_iter()
+            .filter(|expr| match function_name(expr) {
+                Some(name) if !called.contains(name) => {
+                    if !self.removed.iter().any(|removed| removed == name) {
+                        self.removed.push(name.clone());
                     }
+                    false
+                }
+                _ => true,
+            })
+            .collect()
+    }
+
+    /// Optimizes the statements of a block. The ternaries that always take
+    /// the same branch are replaced by it.
+    fn optimize_block(&mut self, exprs: Vec<Expr>) -> Vec<Expr> {
+        let mut block = Vec::with_capacity(exprs.len());
+        for expr in exprs {
+            self.optimize_statement(expr, &mut block);
+        }
+        block
+    }
+
+    /// Optimizes the body of a function, or the whole program. The variables
+    /// set in it, outside of any block, are replaced by their value in the
+    /// statements after them.
+    fn optimize_frame(&mut self, exprs: Vec<Expr>) -> Vec<Expr> {
+        let mut block = Vec::with_capacity(exprs.len());
+        for expr in exprs {
+            self.optimize_statement(expr, &mut block);
+
+            if let Some(Expr {
+                inner: ExprKind::Set(name, value),
+                ..
+            }) = block.last()
+            {
+                if self.fixed.contains(name) && literal(&value.inner).is_some() {
+                    self.constants.insert(name.clone(), value.inner.clone());
                 }
-                Expr::new(
-                    expr.span,
-                    ExprKind::Ternary(
-                        Box::new(self.optimize(*e)),
-                        exprs,
-                        if exprs2.len() > 0 { Some(exprs2) } else { None },
-                    ),
-                )
             }
-            ExprKind::Call(n, es) => {
-                let mut exprs: Vec<Expr> = Vec::new();
-                if let Some(e) = es {
-                    for e in e {
-                        exprs.push(self.optimize(e))
+        }
+        block
+    }
+
+    fn optimize_statement(&mut self, expr: Expr, block: &mut Vec<Expr>) {
+        let expr = self.optimize(expr);
+        match expr.inner {
+            // a ternary has no scope, the branch can run in the block
+            ExprKind::Ternary(condition, then_block, else_block)
+                if taken(&condition, &then_block, else_block.as_deref()) == Some(true) =>
+            {
+                block.extend(then_block)
+            }
+            inner => block.push(Expr::new(expr.span, inner)),
+        }
+    }
+
+    fn optimize_exprs(&mut self, exprs: Vec<Expr>) -> Vec<Expr> {
+        exprs.into_iter().map(|expr| self.optimize(expr)).collect()
+    }
+
+    /// Optimizes a function, which starts without any known variables as it
+    /// can't see the ones outside of it.
+    fn optimize_function<T>(
+        &mut self,
+        params: Vec<Param>,
+        body: T,
+        optimize_body: impl FnOnce(&mut Self, T) -> T,
+    ) -> (Vec<Param>, T) {
+        let outer = std::mem::take(&mut self.constants);
+        let params = params
+            .into_iter()
+            .map(|param| Param {
+                default: param.default.map(|default| self.optimize(default)),
+                ..param
+            })
+            .collect();
+        let body = optimize_body(self, body);
+        self.constants = outer;
+        (params, body)
+    }
+
+    /// The literal an operation on literals results in, computed like the VM
+    /// would. `None` when it has to be left to the runtime, like a division by
+    /// zero.
+    fn constant_fold(&self, expr: &ExprKind) -> Option<ExprKind> {
+        let value = match expr {
+            ExprKind::Binary(lhs, op, rhs) => {
+                let (lhs, rhs) = (literal(&lhs.inner)?, literal(&rhs.inner)?);
+                match op {
+                    BinaryOp::Add => lhs.binary_add(&rhs),
+                    BinaryOp::Sub => lhs.binary_sub(&rhs),
+                    BinaryOp::Mul => match (&lhs, &rhs) {
+                        // repeating a string a negative number of times fails
+                        (Value::String(s), Value::Int(n)) | (Value::Int(n), Value::String(s)) => {
+                            let len = s.len().checked_mul(n.to_usize()?)?;
+                            (len <= MAX_FOLDED_LEN).then(|| lhs.binary_mul(&rhs))?
+                        }
+                        _ => lhs.binary_mul(&rhs),
+                    },
+                    BinaryOp::Div | BinaryOp::Mod if rhs.is_zero() => None,
+                    BinaryOp::Div => lhs.binary_div(&rhs),
+                    BinaryOp::Mod => lhs.binary_mod(&rhs),
+                    BinaryOp::Pow => lhs.binary_pow(&rhs),
+                    BinaryOp::BinaryPow => lhs.binary_bitwise_xor(&rhs),
+                    BinaryOp::Less => lhs.less_than(&rhs),
+                    BinaryOp::Greater => lhs.greater_than(&rhs),
+                    BinaryOp::LessEq => lhs.less_than_or_equal(&rhs),
+                    BinaryOp::GreaterEq => lhs.greater_than_or_equal(&rhs),
+                    BinaryOp::Eq => lhs.equal_to(&rhs),
+                    BinaryOp::NotEq => lhs.not_equal_to(&rhs),
+                    BinaryOp::And => lhs.and(&rhs),
+                    BinaryOp::Or => lhs.or(&rhs),
+                    _ => None,
+                }?
+            }
+
+            ExprKind::Unary(op, value) => match (op, literal(&value.inner)?) {
+                (UnaryOp::Not, value) => Value::Bool(!value.bool_eval()),
+                (UnaryOp::Neg, Value::Int(i)) => Value::Int(-i),
+                (UnaryOp::Neg, Value::Float(f)) => {
+                    Value::Float((-&f).complete(vm::float_precision()))
+                }
+                (UnaryOp::Plus, value) => value,
+                _ => return None,
+            },
+
+            ExprKind::Postfix(value, PostfixOp::Factorial) => match &value.inner {
+                ExprKind::Int(n) => Value::Int(Integer::factorial(small(n)?).complete()),
+                _ => return None,
+            },
+
+            // the builtins that always give the same result for the same
+            // arguments, and can't fail on them
+            ExprKind::Call(name, args) => {
+                let args = args.as_deref().unwrap_or_default();
+                let args = args
+                    .iter()
+                    .map(|arg| literal(&arg.inner))
+                    .collect::<Option<Vec<_>>>()?;
+
+                match (name.as_str(), args.as_slice()) {
+                    ("fib", [Value::Int(n)]) => {
+                        Value::Int(Integer::fibonacci(small(n)?).complete())
+                    }
+                    ("abs", [Value::Int(n)]) => Value::Int(n.abs_ref().complete()),
+                    ("abs", [Value::Float(n)]) => {
+                        Value::Float(n.abs_ref().complete(vm::float_precision()))
                     }
+                    ("gcd", [Value::Int(a), Value::Int(b)]) => Value::Int(a.gcd_ref(b).complete()),
+                    ("lcm", [Value::Int(a), Value::Int(b)]) => Value::Int(a.lcm_ref(b).complete()),
+                    ("isprime", [Value::Int(n)]) => Value::Bool(vm::is_prime(n)),
+                    ("binomial", [Value::Int(n), Value::Int(k)]) => {
+                        Value::Int(n.binomial_ref(small(k)?).complete())
+                    }
+                    ("isqrt", [Value::Int(n)]) if *n >= 0 => Value::Int(n.sqrt_ref().complete()),
+                    _ => return None,
                 }
-                Expr::new(
-                    expr.span,
-                    ExprKind::Call(n, if exprs.len() > 0 { Some(exprs) } else { None }),
-                )
-            }
-            ExprKind::Match(e, es) => {
-                let mut exprs: Vec<(Expr, Vec<Expr>)> = Vec::new();
-                for e in es {
-                    let mut exprs2: Vec<Expr> = Vec::new();
-                    for e in e.1 {
-                        exprs2.push(self.optimize(e))
+            }
+
+            _ => return None,
+        };
+
+        self.to_literal(value)
+    }
+
+    /// The literal of a value computed ahead of time, `None` if it can't be
+    /// written as one.
+    fn to_literal(&self, value: Value) -> Option<ExprKind> {
+        match value {
+            Value::Int(i) => Some(ExprKind::Int(i)),
+            Value::Float(f) if self.fold_floats => Some(ExprKind::Float(f)),
+            Value::Bool(b) => Some(ExprKind::Bool(b)),
+            Value::String(s) if s.len() <= MAX_FOLDED_LEN => Some(ExprKind::String(s)),
+            Value::Nil => Some(ExprKind::Nil),
+            _ => None,
+        }
+    }
+
+    /// Builds an f-string ahead of time when every placeholder in it turns
+    /// into a literal.
+    fn fold_fstring(&mut self, value: &str) -> Option<ExprKind> {
+        let mut string = String::new();
+        for part in fstring_parts(value) {
+            match part {
+                FStringPart::Text(text) => string.push_str(&text),
+                FStringPart::Code(code) => {
+                    let placeholder = self.optimize(parse_placeholder(&code)?);
+                    string.push_str(&literal(&placeholder.inner)?.to_string());
+                }
+            }
+        }
+        self.to_literal(Value::String(string))
+    }
+
+    /// Builds an f-string ahead of time like `fold_fstring`, the calls
+    /// inlined in it only count when it is.
+    fn fold_fstring_inlined(&mut self, value: &str) -> Option<ExprKind> {
+        let inlined = self.inlined.clone();
+        let folded = self.fold_fstring(value);
+        if folded.is_none() {
+            self.inlined = inlined;
+        }
+        folded
+    }
+
+    fn optimize(&mut self, expr: Expr) -> Expr {
+        let span = expr.span;
+        let inner = match expr.inner {
+            ExprKind::Ident(name) => match self.constants.get(&name) {
+                Some(value) => value.clone(),
+                None => ExprKind::Ident(name),
+            },
+            ExprKind::FString(value) => match self.fold_fstring_inlined(&value) {
+                Some(string) => string,
+                None => ExprKind::FString(value),
+            },
+
+            ExprKind::Binary(lhs, BinaryOp::Attr, rhs) => {
+                // the call is to a method, only its arguments are optimized
+                let rhs = match rhs.inner {
+                    ExprKind::Call(name, args) => Expr::new(
+                        rhs.span,
+                        ExprKind::Call(name, args.map(|args| self.optimize_exprs(args))),
+                    ),
+                    inner => Expr::new(rhs.span, inner),
+                };
+                ExprKind::Binary(Box::new(self.optimize(*lhs)), BinaryOp::Attr, Box::new(rhs))
+            }
+            ExprKind::Binary(lhs, op, rhs) => ExprKind::Binary(
+                Box::new(self.optimize(*lhs)),
+                op,
+                Box::new(self.optimize(*rhs)),
+            ),
+            ExprKind::Unary(op, value) => ExprKind::Unary(op, Box::new(self.optimize(*value))),
+            ExprKind::Postfix(value, op) => ExprKind::Postfix(Box::new(self.optimize(*value)), op),
+            ExprKind::Index(value, index) => ExprKind::Index(
+                Box::new(self.optimize(*value)),
+                Box::new(self.optimize(*index)),
+            ),
+            ExprKind::Array(items) => ExprKind::Array(self.optimize_exprs(items)),
+            ExprKind::Call(name, args) => {
+                let args = self.optimize_exprs(args.unwrap_or_default());
+                if let Some(body) = self.inline(&name, &args) {
+                    return body;
+                }
+                ExprKind::Call(name, (!args.is_empty()).then_some(args))
+            }
+
+            ExprKind::Set(name, value) => ExprKind::Set(name, Box::new(self.optimize(*value))),
+            ExprKind::EqStmt(name, op, value) => {
+                ExprKind::EqStmt(name, op, Box::new(self.optimize(*value)))
+            }
+            ExprKind::Destructure(pattern, value) => {
+                ExprKind::Destructure(pattern, Box::new(self.optimize(*value)))
+            }
+            ExprKind::Return(value) => ExprKind::Return(Box::new(self.optimize(*value))),
+            ExprKind::Yield(value) => ExprKind::Yield(Box::new(self.optimize(*value))),
+
+            ExprKind::MultilineFunction(name, params, returns, body) => {
+                let (params, body) = self.optimize_function(params, body, Self::optimize_frame);
+                ExprKind::MultilineFunction(name, params, returns, body)
+            }
+            ExprKind::InlineFunction(name, params, returns, body) => {
+                let (params, body) =
+                    self.optimize_function(params, *body, |this, body| this.optimize(body));
+                ExprKind::InlineFunction(name, params, returns, Box::new(body))
+            }
+            ExprKind::Memoized(function) => ExprKind::Memoized(Box::new(self.optimize(*function))),
+            ExprKind::Impl(name, methods) => ExprKind::Impl(name, self.optimize_exprs(methods)),
+
+            ExprKind::Ternary(condition, then_block, else_block) => {
+                let condition = self.optimize(*condition);
+                let then_block = self.optimize_block(then_block);
+                let else_block = self.optimize_block(else_block.unwrap_or_default());
+
+                match taken(&condition, &then_block, Some(&else_block)) {
+                    Some(taken) => {
+                        let mut branch = if taken { then_block } else { else_block };
+                        if branch.len() == 1 {
+                            return branch.remove(0);
+                        }
+                        // left for the block around it to take the place of
+                        let condition = Expr::new(condition.span, ExprKind::Bool(true));
+                        ExprKind::Ternary(Box::new(condition), branch, None)
                     }
-                    exprs.push((self.optimize(e.0), exprs2))
+                    None => ExprKind::Ternary(
+                        Box::new(condition),
+                        then_block,
+                        (!else_block.is_empty()).then_some(else_block),
+                    ),
                 }
-                Expr::new(
-                    expr.span,
-                    ExprKind::Match(Box::new(self.optimize(*e)), exprs),
-                )
             }
-            ExprKind::Set(n, e) => {
-                Expr::new(expr.span, ExprKind::Set(n, Box::new(self.optimize(*e))))
+            ExprKind::While(condition, body) => ExprKind::While(
+                Box::new(self.optimize(*condition)),
+                self.optimize_block(body),
+            ),
+            ExprKind::Every(list, body) => {
+                ExprKind::Every(Box::new(self.optimize(*list)), self.optimize_block(body))
+            }
+            ExprKind::Try(body, name, handler) => ExprKind::Try(
+                self.optimize_block(body),
+                name,
+                self.optimize_block(handler),
+            ),
+            ExprKind::Match(value, arms) => {
+                let value = self.optimize(*value);
+                let arms = arms
+                    .into_iter()
+                    .map(|arm| MatchArm {
+                        pattern: arm.pattern,
+                        guard: arm.guard.map(|guard| self.optimize(guard)),
+                        body: self.optimize_block(arm.body),
+                    })
+                    .collect();
+                ExprKind::Match(Box::new(value), arms)
+            }
+
+            inner => inner,
+        };
+
+        match self.constant_fold(&inner) {
+            Some(folded) => Expr::new(span, folded),
+            None => Expr::new(span, inner),
+        }
+    }
+}
+
+/// The value of a literal, `None` for anything that is computed.
+fn literal(expr: &ExprKind) -> Option<Value> {
+    Some(match expr {
+        ExprKind::Int(i) => Value::Int(i.clone()),
+        ExprKind::Float(f) => Value::Float(f.clone()),
+        ExprKind::Bool(b) => Value::Bool(*b),
+        ExprKind::String(s) => Value::String(s.clone()),
+        ExprKind::Nil => Value::Nil,
+        _ => return None,
+    })
+}
+
+/// A number small enough to be the argument of `!`, `fib` or `binomial`
+/// ahead of time.
+fn small(n: &Integer) -> Option<u32> {
+    n.to_u32().filter(|n| *n <= MAX_FOLDED_ARG)
+}
+
+/// The branch a ternary always takes, `true` for the first one. `None` if the
+/// condition isn't a literal, or if the other branch declares something the
+/// code after it may use, as that happens even when it doesn't run.
+fn taken(condition: &Expr, then_block: &[Expr], else_block: Option<&[Expr]>) -> Option<bool> {
+    let taken = literal(&condition.inner)?.bool_eval();
+    let skipped = match taken {
+        true => else_block.unwrap_or_default(),
+        false => then_block,
+    };
+
+    let mut declares = false;
+    for expr in skipped {
+        visit(expr, &mut |expr| {
+            declares |= matches!(
+                expr.inner,
+                ExprKind::Set(..)
+                    | ExprKind::Destructure(..)
+                    | ExprKind::InlineFunction(..)
+                    | ExprKind::MultilineFunction(..)
+                    | ExprKind::Impl(..)
+                    | ExprKind::Every(..)
+                    | ExprKind::Try(..)
+                    | ExprKind::Match(..)
+            )
+        });
+    }
+    (!declares).then_some(taken)
+}
+
+/// Whether the body of the function `name` can take the place of a call to
+/// it: an expression that leaves a single value, computed from the parameters
+/// without changing them, and without calling the function again.
+fn inlinable(body: &Expr, name: &str, params: &[String]) -> bool {
+    let inlinable = |expr: &Expr| self::inlinable(expr, name, params);
+    match &body.inner {
+        ExprKind::Int(_)
+        | ExprKind::Float(_)
+        | ExprKind::Bool(_)
+        | ExprKind::String(_)
+        | ExprKind::Nil => true,
+        ExprKind::Ident(ident) => params.contains(ident),
+        ExprKind::Binary(
+            _,
+            BinaryOp::AddEq | BinaryOp::SubEq | BinaryOp::MulEq | BinaryOp::DivEq | BinaryOp::Attr,
+            _,
+        ) => false,
+        ExprKind::Binary(lhs, _, rhs) | ExprKind::Index(lhs, rhs) => {
+            inlinable(lhs) && inlinable(rhs)
+        }
+        ExprKind::Unary(_, value) | ExprKind::Postfix(value, PostfixOp::Factorial) => {
+            inlinable(value)
+        }
+        ExprKind::Array(items) => items.iter().all(inlinable),
+        // a function called with a parameter could change it
+        ExprKind::Call(callee, args) => {
+            let args = args.as_deref().unwrap_or_default();
+            let builtin = builtin(callee).is_some();
+            callee != name
+                && !IMPURE_BUILTINS.contains(&callee.as_str())
+                && args.iter().all(|arg| {
+                    inlinable(arg) && (builtin || !matches!(arg.inner, ExprKind::Ident(_)))
+                })
+        }
+        ExprKind::Ternary(condition, then_block, Some(else_block)) => {
+            inlinable(condition)
+                && [then_block, else_block]
+                    .iter()
+                    .all(|block| block.len() == 1 && inlinable(&block[0]))
+        }
+        _ => false,
+    }
+}
+
+/// Replaces the parameters in the body of an inlined function by the
+/// arguments of the call.
+fn substitute(mut body: Expr, args: &HashMap<String, Expr>) -> Expr {
+    let sub = |expr: Box<Expr>| Box::new(substitute(*expr, args));
+    let subs = |exprs: Vec<Expr>| {
+        exprs
+            .into_iter()
+            .map(|expr| substitute(expr, args))
+            .collect()
+    };
+    body.inner = match body.inner {
+        ExprKind::Ident(name) => match args.get(&name) {
+            Some(arg) => return arg.clone(),
+            None => ExprKind::Ident(name),
+        },
+        ExprKind::Binary(lhs, op, rhs) => ExprKind::Binary(sub(lhs), op, sub(rhs)),
+        ExprKind::Index(value, index) => ExprKind::Index(sub(value), sub(index)),
+        ExprKind::Unary(op, value) => ExprKind::Unary(op, sub(value)),
+        ExprKind::Postfix(value, op) => ExprKind::Postfix(sub(value), op),
+        ExprKind::Array(items) => ExprKind::Array(subs(items)),
+        ExprKind::Call(name, args) => ExprKind::Call(name, args.map(subs)),
+        ExprKind::Ternary(condition, then_block, else_block) => {
+            ExprKind::Ternary(sub(condition), subs(then_block), else_block.map(subs))
+        }
+        inner => inner,
+    };
+    body
+}
+
+/// The name of a function an expression defines.
+fn function_name(expr: &Expr) -> Option<&String> {
+    match &expr.inner {
+        ExprKind::InlineFunction(name, ..) | ExprKind::MultilineFunction(name, ..) => Some(name),
+        ExprKind::Memoized(function) => function_name(function),
+        _ => None,
+    }
+}
+
+/// The variable an expression is, `None` if it is anything else.
+fn ident(expr: &Expr) -> Option<String> {
+    match &expr.inner {
+        ExprKind::Ident(name) => Some(name.clone()),
+        _ => None,
+    }
+}
+
+/// The variable changed when an expression, like `a[0]`, is changed in place.
+fn root(expr: &Expr) -> Option<String> {
+    match &expr.inner {
+        ExprKind::Index(value, _) => root(value),
+        _ => ident(expr),
+    }
+}
+
+fn bound_names(pattern: &Pattern, names: &mut HashSet<String>) {
+    match pattern {
+        Pattern::Ident(name) | Pattern::Rest(name) => {
+            names.insert(name.clone());
+        }
+        Pattern::Array(patterns) | Pattern::Or(patterns) => {
+            for pattern in patterns {
+                bound_names(pattern, names);
+            }
+        }
+        Pattern::HeadTail(head, tail) => {
+            bound_names(head, names);
+            bound_names(tail, names);
+        }
+        _ => {}
+    }
+}
+
+/// Parses the code of a placeholder of an f-string like the VM does, `None`
+/// if it isn't a single expression.
+fn parse_placeholder(code: &str) -> Option<Expr> {
+    let mut parser = PParser::new(code, tokenize(code));
+    let mut exprs = parser.parse_recovering();
+    (parser.errors().is_empty() && exprs.len() == 1).then(|| exprs.remove(0))
+}
+
+/// Calls `f` on `expr` and on everything in it, including the code of the
+/// placeholders of f-strings.
+fn visit(expr: &Expr, f: &mut impl FnMut(&Expr)) {
+    f(expr);
+
+    let placeholders;
+    let mut children: Vec<&Expr> = vec![];
+    match &expr.inner {
+        ExprKind::Return(value)
+        | ExprKind::Yield(value)
+        | ExprKind::Memoized(value)
+        | ExprKind::EqStmt(_, _, value)
+        | ExprKind::Set(_, value)
+        | ExprKind::Destructure(_, value)
+        | ExprKind::Postfix(value, _)
+        | ExprKind::Unary(_, value) => children.push(&**value),
+        ExprKind::Binary(lhs, _, rhs) | ExprKind::Index(lhs, rhs) => {
+            children.extend([&**lhs, &**rhs])
+        }
+        ExprKind::InlineFunction(_, params, _, body) => {
+            children.extend(params.iter().filter_map(|param| param.default.as_ref()));
+            children.push(&**body);
+        }
+        ExprKind::MultilineFunction(_, params, _, body) => {
+            children.extend(params.iter().filter_map(|param| param.default.as_ref()));
+            children.extend(body);
+        }
+        ExprKind::Call(_, args) => children.extend(args.iter().flatten()),
+        ExprKind::Array(items) | ExprKind::Impl(_, items) => children.extend(items),
+        ExprKind::Ternary(condition, then_block, else_block) => {
+            children.push(&**condition);
+            children.extend(then_block);
+            children.extend(else_block.iter().flatten());
+        }
+        ExprKind::While(condition, body) | ExprKind::Every(condition, body) => {
+            children.push(&**condition);
+            children.extend(body);
+        }
+        ExprKind::Try(body, _, handler) => children.extend(body.iter().chain(handler)),
+        ExprKind::Match(value, arms) => {
+            children.push(&**value);
+            for arm in arms {
+                children.extend(&arm.guard);
+                children.extend(&arm.body);
             }
-            _ => expr,
         }
+        ExprKind::FString(value) => {
+            placeholders = fstring_parts(value)
+                .into_iter()
+                .filter_map(|part| match part {
+                    FStringPart::Code(code) => parse_placeholder(&code),
+                    FStringPart::Text(_) => None,
+                })
+                .collect::<Vec<_>>();
+            children.extend(&placeholders);
+        }
+        _ => {}
+    }
+
+    for child in children {
+        visit(child, f);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn optimize(src: &str) -> Vec<ExprKind> {
+        let ast = PParser::new(src, tokenize(src)).parse();
+        let optimized = Optimizer::new(ast).optimize_all();
+        optimized.into_iter().map(|expr| expr.inner).collect()
+    }
+
+    /// What the last statement of `src`, an assignment, sets once optimized.
+    fn folded(src: &str) -> ExprKind {
+        match optimize(src).pop() {
+            Some(ExprKind::Set(_, value)) => value.inner,
+            expr => panic!("expected an assignment, found {expr:?}"),
+        }
+    }
+
+    fn int(i: i32) -> ExprKind {
+        ExprKind::Int(Integer::from(i))
+    }
+
+    #[test]
+    fn test_constant_fold() {
+        assert_eq!(folded("x = -(2 * 3) + 10 % 4"), int(-4));
+        assert_eq!(folded("x = 2 ** 3 >= 8 && !0"), ExprKind::Bool(true));
+        assert_eq!(folded("x = fib(20) + gcd(12, 18) + (5!)"), int(6891));
+        assert_eq!(
+            folded("y = 3\nx = f\"{1 + 1} and $y \""),
+            ExprKind::String("2 and 3 ".into())
+        );
+        assert_eq!(folded("x = 7 / 2"), ExprKind::Float(crate::float!(3.5)));
+
+        // left for the runtime to fail on
+        for src in [
+            "x = 1 / 0",
+            "x = 5 % 0",
+            "x = \"a\" * -1",
+            "x = -\"a\"",
+            "x = [1] + 2",
+        ] {
+            assert!(
+                !matches!(folded(src), ExprKind::Int(_) | ExprKind::String(_)),
+                "{src}"
+            );
+        }
+
+        // the precision of the floats computed at runtime changes
+        assert!(matches!(
+            folded("prec(100)\nx = 1 / 3"),
+            ExprKind::Binary(..)
+        ));
+    }
+
+    #[test]
+    fn test_constant_propagation() {
+        let Some(ExprKind::Call(_, Some(args))) = optimize("a = 3\nb = a * 2\n$f\"{b}!\"").pop()
+        else {
+            panic!("expected a call");
+        };
+        assert_eq!(args[0].inner, ExprKind::String("6!".into()));
+
+        let changed = [
+            "a = 3\na += 1\nb = a",
+            "a = 3\na++\nb = a",
+            "a = 3\na = 4\nb = a",
+            "a = 1\nf x: x++\nf(a)\nb = a",
+        ];
+        for src in changed {
+            assert!(matches!(folded(src), ExprKind::Ident(_)), "{src}");
+        }
+
+        // not set yet
+        assert!(matches!(
+            &optimize("b = a\na = 3")[0],
+            ExprKind::Set(_, value) if value.inner == ExprKind::Ident("a".into())
+        ));
+
+        // functions can't see the variables outside of them
+        let ast = optimize("a = 1\nf: a\nf()");
+        assert!(matches!(
+            &ast[1],
+            ExprKind::InlineFunction(_, _, _, body) if body.inner == ExprKind::Ident("a".into())
+        ));
+    }
+
+    #[test]
+    fn test_dead_branches() {
+        let ast = optimize("1 ? { $1; $2 } : $3\n0 ? $4\nx = 0 ? 5 : 6");
+        assert_eq!(ast.len(), 3);
+        assert!(matches!(&ast[0], ExprKind::Call(name, _) if name == "$"));
+        assert!(matches!(&ast[1], ExprKind::Call(name, _) if name == "$"));
+        assert_eq!(folded("x = 0 ? 5 : 6"), int(6));
+
+        // `x` is declared even though the branch never runs
+        assert!(matches!(
+            optimize("0 ? { x = 1 }\n$x").as_slice(),
+            [ExprKind::Ternary(..), _]
+        ));
+    }
+
+    #[test]
+    fn test_inlining() {
+        assert!(matches!(
+            optimize("add a b: a + b\nx = add(1, 2)").as_slice(),
+            [ExprKind::Set(_, value)] if value.inner == int(3)
+        ));
+
+        // the parameters take the place of the variables given to them
+        let ast = optimize("sq x: x * x\n$sq(y)");
+        let [ExprKind::Call(_, Some(args))] = ast.as_slice() else {
+            panic!("expected only the call to `$`, found {ast:?}");
+        };
+        assert!(matches!(
+            &args[0].inner,
+            ExprKind::Binary(lhs, BinaryOp::Mul, rhs)
+                if lhs.inner == ExprKind::Ident("y".into()) && rhs.inner == lhs.inner
+        ));
+
+        // recursive, changing its parameter, printing or too many arguments
+        for src in [
+            "f n: n < 2 ? 1 : n * f(n - 1)\nx = f(3)",
+            "f n: n++\nx = f(3)",
+            "f n: $n\nx = f(3)",
+            "f n: n\nx = f(3, 4)",
+            "f n = 1: n\nx = f(3)",
+        ] {
+            assert!(matches!(folded(src), ExprKind::Call(..)), "{src}");
+        }
+    }
+
+    #[test]
+    fn test_unused_functions() {
+        let src = "f x: x + 1\ng x: {\n$x\n}\nh: g(1)\nh()";
+        let mut optimizer = Optimizer::new(PParser::new(src, tokenize(src)).parse());
+        let ast = optimizer.optimize_all();
+        assert!(matches!(
+            ast.as_slice(),
+            [function, call] if function_name(function).is_some_and(|name| name == "g")
+                && matches!(&call.inner, ExprKind::Call(name, _) if name == "g")
+        ));
+        assert_eq!(
+            optimizer.summary(),
+            "inlined `h` at 1 call(s)\nremoved `f`, no call to it is left\nremoved `h`, no call to it is left"
+        );
+
+        let mut optimizer = Optimizer::new(PParser::new(src, tokenize(src)).parse());
+        optimizer.set_keep_functions(true);
+        assert_eq!(optimizer.optimize_all().len(), 4);
     }
 }
@@ -1,12 +1,14 @@
 use miette::{miette, LabeledSpan};
-use std::hint::unreachable_unchecked;
 use std::{fmt, ops::Range};
 
 use crate::float;
+use crate::vm::float_precision;
 use logos::Logos;
 use rug::ops::CompleteRound;
 use rug::{Complete, Float, Integer};
 
+type Result<T, E = SyntaxError> = std::result::Result<T, E>;
+
 #[derive(Logos, Debug, Clone, PartialEq)]
 #[logos(skip r"[ \r\f\t]+")]
 // skip comments
@@ -46,18 +48,24 @@ pub enum LogosToken<'a> {
     Colon,
     #[token("...")]
     ThreeDots,
+    #[token("..")]
+    DotDot,
     #[token("::")]
     FourDots,
     #[token("=")]
     Eq,
     #[token("!")]
     Bang,
+    #[token("@")]
+    At,
     #[token("&")]
     Return,
     #[token("&&")]
     And,
     #[token("||")]
     Or,
+    #[token("|")]
+    Pipe,
     #[token("%")]
     Percent,
     #[token("==")]
@@ -121,6 +129,12 @@ pub enum LogosToken<'a> {
     Continue,
     #[token("mc")]
     Match,
+    #[token("yd")]
+    Yield,
+    #[token("tr")]
+    Try,
+    #[token("ca")]
+    Catch,
     #[token("impl")]
     Impl,
     #[token("\n")]
@@ -149,7 +163,10 @@ impl<'a> fmt::Display for LogosToken<'a> {
             LogosToken::Eq => write!(f, "="),
             LogosToken::Eqq => write!(f, "=="),
             LogosToken::Arrow => write!(f, "->"),
+            LogosToken::At => write!(f, "@"),
             LogosToken::ThreeDots => write!(f, "..."),
+            LogosToken::DotDot => write!(f, ".."),
+            LogosToken::Pipe => write!(f, "|"),
             LogosToken::Semi => write!(f, ";"),
             LogosToken::LAngle => write!(f, "<"),
             LogosToken::RAngle => write!(f, ">"),
@@ -185,6 +202,9 @@ impl<'a> fmt::Display for LogosToken<'a> {
             LogosToken::Continue => write!(f, "ct"),
             LogosToken::PAdd => write!(f, "++"),
             LogosToken::Match => write!(f, "mc"),
+            LogosToken::Yield => write!(f, "yd"),
+            LogosToken::Try => write!(f, "tr"),
+            LogosToken::Catch => write!(f, "ca"),
             LogosToken::Impl => write!(f, "impl"),
             LogosToken::PSub => write!(f, "--"),
         }
@@ -192,24 +212,24 @@ impl<'a> fmt::Display for LogosToken<'a> {
 }
 
 impl<'a> LogosToken<'a> {
-    pub fn to_postfix_op(&self) -> PostfixOp {
-        match self {
+    pub fn to_postfix_op(&self) -> Option<PostfixOp> {
+        Some(match self {
             Self::PAdd => PostfixOp::Increase,
             Self::PSub => PostfixOp::Decrease,
             Self::Bang => PostfixOp::Factorial,
-            _ => unsafe { unreachable_unchecked() },
-        }
+            _ => return None,
+        })
     }
-    pub fn to_unary_op(&self) -> UnaryOp {
-        match self {
+    pub fn to_unary_op(&self) -> Option<UnaryOp> {
+        Some(match self {
             Self::Minus => UnaryOp::Neg,
             Self::Plus => UnaryOp::Plus,
             Self::Bang => UnaryOp::Not,
-            _ => unsafe { unreachable_unchecked() },
-        }
+            _ => return None,
+        })
     }
-    pub fn to_binary_op(&self) -> BinaryOp {
-        match self {
+    pub fn to_binary_op(&self) -> Option<BinaryOp> {
+        Some(match self {
             Self::Times => BinaryOp::Mul,
             Self::Percent => BinaryOp::Mod,
             Self::BinaryPow => BinaryOp::BinaryPow,
@@ -231,8 +251,8 @@ impl<'a> LogosToken<'a> {
             Self::And => BinaryOp::And,
             Self::Dot => BinaryOp::Attr,
 
-            _ => unsafe { unreachable_unchecked() },
-        }
+            _ => return None,
+        })
     }
 }
 
@@ -301,14 +321,71 @@ impl BinaryOp {
     }
 }
 
+/// What the left side of an assignment or a parameter binds the value to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    Ident(String),
+    // `[a, b]`, and `a, b` on the left side of an assignment
+    Array(Vec<Pattern>),
+    // `h::t`, the first item and the rest
+    HeadTail(Box<Pattern>, Box<Pattern>),
+
+    // the rest only appear in `mc` arms
+    Wildcard,
+    // a literal, compared with ==
+    Value(Box<Expr>),
+    // `lo..hi`, both ends included
+    Range(Box<Expr>, Box<Expr>),
+    // `int`, `str`, ...
+    Type(String),
+    // `...rest` inside of an array pattern
+    Rest(String),
+    // `a | b`
+    Or(Vec<Pattern>),
+}
+
+/// A function parameter, `name`, `name=default` or `...name`, any of them
+/// can be annotated with a type like `name:int`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Param {
+    pub name: String,
+    pub default: Option<Expr>,
+    // collects the extra arguments into an array
+    pub variadic: bool,
+    // for a variadic parameter, the type of every extra argument
+    pub ty: Option<String>,
+}
+
+impl Param {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            default: None,
+            variadic: false,
+            ty: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub guard: Option<Expr>,
+    pub body: Vec<Expr>,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum ExprKind {
     Int(Integer),
     Float(Float),
     Bool(bool),
     Return(Box<Expr>),
-    InlineFunction(String, Vec<String>, Box<Expr>),
-    MultilineFunction(String, Vec<String>, Vec<Expr>),
+    Yield(Box<Expr>),
+    // the name, the parameters, the annotated return type and the body
+    InlineFunction(String, Vec<Param>, Option<String>, Box<Expr>),
+    MultilineFunction(String, Vec<Param>, Option<String>, Vec<Expr>),
+    // `@f ...: ...`, a function whose results are cached by its arguments
+    Memoized(Box<Expr>),
     EqStmt(String, BinaryOp, Box<Expr>),
     Call(String, Option<Vec<Expr>>),
     Ternary(Box<Expr>, Vec<Expr>, Option<Vec<Expr>>),
@@ -317,16 +394,16 @@ pub enum ExprKind {
     Ident(String),
     Binary(Box<Expr>, BinaryOp, Box<Expr>),
     Set(String, Box<Expr>),
+    Destructure(Pattern, Box<Expr>),
     Postfix(Box<Expr>, PostfixOp),
     Array(Vec<Expr>),
     While(Box<Expr>, Vec<Expr>),
     Every(Box<Expr>, Vec<Expr>),
+    // the body, the name the error is bound to and the handler
+    Try(Vec<Expr>, Option<String>, Vec<Expr>),
     Impl(String, Vec<Expr>),
 
-    // used inside the match statement
-    HeadTail(String, String),
-    DefaultCase,
-    Match(Box<Expr>, Vec<(Expr, Vec<Expr>)>),
+    Match(Box<Expr>, Vec<MatchArm>),
 
     Index(Box<Expr>, Box<Expr>),
     Unary(UnaryOp, Box<Expr>),
@@ -336,11 +413,22 @@ pub enum ExprKind {
     Continue,
 }
 
+/// A syntax error, the parser skips to the next statement and reports all of
+/// them once it is done.
+#[derive(Debug, Clone)]
+pub struct SyntaxError {
+    pub span: Range<usize>,
+    pub label: String,
+    pub message: String,
+    pub help: Option<String>,
+}
+
 pub struct PParser<'a> {
     source: &'a str,
     position: usize,
     current: (LogosToken<'a>, Range<usize>),
     tokens: Vec<(LogosToken<'a>, Range<usize>)>,
+    errors: Vec<SyntaxError>,
 }
 
 impl<'a> PParser<'a> {
@@ -350,33 +438,103 @@ impl<'a> PParser<'a> {
             source,
             position: 0,
             current: (LogosToken::Error, 0..0),
+            errors: Vec::new(),
         };
         x.proceed();
         x
     }
 
-    fn report_error(
-        &mut self,
+    /// A syntax error at `span`. Returning it gives up on the current
+    /// statement, `statement` records it and carries on after it.
+    fn syntax_error(
+        &self,
         span: Range<usize>,
         label: String,
         message: String,
         help: Option<String>,
-    ) {
-        let report;
-        if let Some(help) = help {
-            report = miette!(
-                labels = vec![LabeledSpan::at(span, label)],
-                help = help,
-                "{}",
-                message
-            )
-            .with_source_code(self.source.to_string());
-        } else {
-            report = miette!(labels = vec![LabeledSpan::at(span, label)], "{}", message)
-                .with_source_code(self.source.to_string());
+    ) -> SyntaxError {
+        SyntaxError {
+            span,
+            label,
+            message,
+            help,
         }
-        println!("{:?}", report);
-        std::process::exit(1);
+    }
+
+    /// Prints every syntax error, returning false if there were any.
+    fn report_errors(&self) -> bool {
+        for SyntaxError {
+            span,
+            label,
+            message,
+            help,
+        } in &self.errors
+        {
+            let labels = vec![LabeledSpan::at(span.clone(), label)];
+            let report = match help {
+                Some(help) => miette!(labels = labels, help = help, "{}", message),
+                None => miette!(labels = labels, "{}", message),
+            };
+            println!("{:?}", report.with_source_code(self.source.to_string()));
+        }
+
+        self.errors.is_empty()
+    }
+
+    /// Parses a statement, a syntax error in it is recorded and the tokens up
+    /// to the next statement are skipped, leaving an `ExprKind::Error`.
+    fn statement(&mut self) -> Expr {
+        let start = self.current.1.start;
+        let token = self.current.0.to_owned();
+        match self.declaration(token) {
+            Ok(expr) => expr,
+            Err(error) => {
+                self.errors.push(error);
+                self.synchronize();
+                Expr::new(start..self.current.1.start.max(start), ExprKind::Error)
+            }
+        }
+    }
+
+    /// Skips to the newline or semicolon ending the statement, or to the `}`
+    /// of the block it is in. Blocks opened on the way are skipped whole.
+    fn synchronize(&mut self) {
+        let mut depth = 0;
+        while !self.at_end() {
+            match self.current() {
+                LogosToken::Newline | LogosToken::Semi if depth == 0 => return,
+                LogosToken::RBrace if depth == 0 => return,
+                LogosToken::RBrace => depth -= 1,
+                LogosToken::LBrace => depth += 1,
+                _ => {}
+            }
+            self.proceed();
+        }
+    }
+
+    /// Checks that a statement is followed by a newline or a semicolon and
+    /// skips them. `closing` is the token that may end the statement instead.
+    fn end_statement(&mut self, closing: &LogosToken) {
+        let (token, span) = self.current.clone();
+        if token != LogosToken::Newline
+            && token != LogosToken::Semi
+            && token != LogosToken::Error
+            && &token != closing
+        {
+            self.errors.push(self.syntax_error(
+                span,
+                "expected newline or semicolon".to_string(),
+                format!("Expected semicolon or newline found {}", token),
+                None,
+            ));
+            self.synchronize();
+        }
+        self.skip_separator();
+    }
+
+    /// Whether all the tokens have been parsed.
+    fn at_end(&self) -> bool {
+        self.position > self.tokens.len()
     }
 
     fn back(&mut self) -> Option<(LogosToken<'a>, Range<usize>)> {
@@ -403,14 +561,271 @@ impl<'a> PParser<'a> {
         token
     }
     fn check_fun(&self) -> bool {
-        matches!(
-            self.peek(0),
-            Some(LogosToken::Ident(_)) | Some(LogosToken::Colon)
-        )
+        match self.peek(0) {
+            Some(LogosToken::Ident(_))
+            | Some(LogosToken::Colon)
+            | Some(LogosToken::ThreeDots)
+            | Some(LogosToken::Arrow) => true,
+            // `f [a, b]: ...` rather than indexing `f`
+            Some(LogosToken::LSquare) => {
+                self.pattern_followed_by(self.position, LogosToken::Colon)
+                    || self.pattern_followed_by(self.position, LogosToken::Eq)
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether the statement starting at the current token assigns to
+    /// something else than a single name.
+    fn check_destructure(&self) -> bool {
+        if self.peek(0) == Some(LogosToken::Eq)
+            || !self.pattern_followed_by(self.position - 1, LogosToken::Eq)
+        {
+            return false;
+        }
+
+        // `f a b=1: ...` is a function with a default value, the patterns of
+        // an assignment are separated by commas
+        let tokens = self.tokens[self.position - 1..]
+            .iter()
+            .map(|(token, _)| token)
+            .take_while(|token| **token != LogosToken::Eq)
+            .collect::<Vec<_>>();
+        !tokens.windows(2).any(|pair| {
+            matches!(pair[0], LogosToken::Ident(_) | LogosToken::RSquare)
+                && matches!(pair[1], LogosToken::Ident(_) | LogosToken::LSquare)
+        })
+    }
+
+    /// Whether the tokens from `position` on only make up patterns, up until
+    /// `end`.
+    fn pattern_followed_by(&self, position: usize, end: LogosToken) -> bool {
+        for (token, _) in &self.tokens[position.min(self.tokens.len())..] {
+            match token {
+                LogosToken::Ident(_)
+                | LogosToken::Comma
+                | LogosToken::FourDots
+                | LogosToken::LSquare
+                | LogosToken::RSquare => {}
+                token => return *token == end,
+            }
+        }
+
+        false
+    }
+
+    fn pattern(&mut self) -> Result<Pattern> {
+        let pattern = match self.current.0 {
+            LogosToken::LSquare => {
+                self.proceed();
+                let mut items = Vec::new();
+                while self.current() != &LogosToken::RSquare {
+                    items.push(self.pattern()?);
+                    if self.current() != &LogosToken::Comma {
+                        break;
+                    }
+                    self.proceed();
+                }
+                self.expect(LogosToken::RSquare)?;
+                self.proceed();
+                Pattern::Array(items)
+            }
+            _ => {
+                let name = self.expect_ident()?;
+                self.proceed();
+                Pattern::Ident(name)
+            }
+        };
+
+        if self.current() == &LogosToken::FourDots {
+            self.proceed();
+            let tail = self.pattern()?;
+            return Ok(Pattern::HeadTail(Box::new(pattern), Box::new(tail)));
+        }
+
+        Ok(pattern)
+    }
+
+    /// Parses `mc value { pattern: ..., pattern if guard: ... }`.
+    fn match_expr(&mut self) -> Result<Expr> {
+        let start = self.current.1.start;
+        self.proceed();
+        let value = self.expr(0)?;
+        self.expect(LogosToken::LBrace)?;
+        self.proceed();
+        let mut arms: Vec<MatchArm> = Vec::new();
+        self.skip_separator();
+        loop {
+            if self.current() == &LogosToken::RBrace {
+                break;
+            }
+
+            let pattern = self.match_pattern()?;
+            let guard = match self.current() {
+                LogosToken::Ident("if") => {
+                    self.proceed();
+                    Some(self.expr(0)?)
+                }
+                _ => None,
+            };
+            self.expect(LogosToken::Colon)?;
+            self.proceed();
+            let (body, _) = self.block()?;
+            arms.push(MatchArm {
+                pattern,
+                guard,
+                body,
+            });
+
+            let (token, span) = self.current.clone();
+            if token != LogosToken::Newline
+                && token != LogosToken::Semi
+                && token != LogosToken::RBrace
+            {
+                return Err(self.syntax_error(
+                    span.clone(),
+                    "expected newline or semicolon".to_string(),
+                    format!("Expected semicolon or newline found {}", token),
+                    None,
+                ));
+            } else {
+                self.skip_separator();
+            }
+        }
+
+        let end = self.current.1.end;
+        self.proceed();
+        Ok(Expr::new(
+            start..end,
+            ExprKind::Match(Box::new(value), arms),
+        ))
+    }
+
+    fn match_pattern(&mut self) -> Result<Pattern> {
+        let mut alternatives = vec![self.match_alternative()?];
+        while self.current() == &LogosToken::Pipe {
+            self.proceed();
+            alternatives.push(self.match_alternative()?);
+        }
+
+        Ok(match alternatives.len() {
+            1 => alternatives.pop().unwrap(),
+            _ => Pattern::Or(alternatives),
+        })
+    }
+
+    fn match_alternative(&mut self) -> Result<Pattern> {
+        let (token, span) = self.current.clone();
+        let pattern = match token {
+            LogosToken::Ident("_") => {
+                self.proceed();
+                Pattern::Wildcard
+            }
+            LogosToken::Ident(name) => {
+                self.proceed();
+                match name {
+                    "int" | "float" | "str" | "bool" | "array" | "iter" | "error" => {
+                        Pattern::Type(name.to_string())
+                    }
+                    _ => Pattern::Ident(name.to_string()),
+                }
+            }
+            LogosToken::LSquare => {
+                self.proceed();
+                let mut items = Vec::new();
+                while self.current() != &LogosToken::RSquare {
+                    if self.current() == &LogosToken::ThreeDots {
+                        self.proceed();
+                        items.push(Pattern::Rest(self.expect_ident()?));
+                        self.proceed();
+                    } else {
+                        items.push(self.match_pattern()?);
+                    }
+
+                    if self.current() != &LogosToken::Comma {
+                        break;
+                    }
+                    self.proceed();
+                }
+                self.expect(LogosToken::RSquare)?;
+                self.proceed();
+                Pattern::Array(items)
+            }
+            LogosToken::Int(_)
+            | LogosToken::Float(_)
+            | LogosToken::String(_)
+            | LogosToken::True
+            | LogosToken::False
+            | LogosToken::Nil
+            | LogosToken::Minus => {
+                let value = self.term((token, span))?;
+                if self.current() == &LogosToken::DotDot {
+                    self.proceed();
+                    let end = self.term(self.current.clone())?;
+                    Pattern::Range(Box::new(value), Box::new(end))
+                } else {
+                    Pattern::Value(Box::new(value))
+                }
+            }
+            _ => {
+                return Err(self.syntax_error(
+                    span,
+                    "this is not a pattern".to_string(),
+                    "Expected a pattern".to_string(),
+                    Some("Patterns are literals, ranges, names, types or arrays".to_string()),
+                ));
+            }
+        };
+
+        if self.current() == &LogosToken::FourDots {
+            self.proceed();
+            let tail = self.match_alternative()?;
+            return Ok(Pattern::HeadTail(Box::new(pattern), Box::new(tail)));
+        }
+
+        Ok(pattern)
+    }
+
+    /// Parses `a, [b, c], h::t = x, y, z`, the values on the right side are
+    /// put in an array when there are multiple of them.
+    fn destructure(&mut self) -> Result<Expr> {
+        let start = self.current.1.start;
+        let mut patterns = vec![self.pattern()?];
+        while self.current() == &LogosToken::Comma {
+            self.proceed();
+            patterns.push(self.pattern()?);
+        }
+
+        self.expect(LogosToken::Eq)?;
+        self.proceed();
+
+        let mut values = vec![self.expr(0)?];
+        while self.current() == &LogosToken::Comma {
+            self.proceed();
+            values.push(self.expr(0)?);
+        }
+
+        let pattern = match patterns.len() {
+            1 => patterns.pop().unwrap(),
+            _ => Pattern::Array(patterns),
+        };
+        let value = match values.len() {
+            1 => values.pop().unwrap(),
+            _ => Expr::new(
+                values[0].span.start..values.last().unwrap().span.end,
+                ExprKind::Array(values),
+            ),
+        };
+
+        Ok(Expr::new(
+            start..self.current.1.end,
+            ExprKind::Destructure(pattern, Box::new(value)),
+        ))
     }
-    pub fn block(&mut self) -> (Vec<Expr>, bool) {
+    pub fn block(&mut self) -> Result<(Vec<Expr>, bool)> {
         let mut exprs: Vec<Expr> = Vec::new();
         if self.current() == &LogosToken::LBrace {
+            let open = self.current.1.clone();
             self.proceed();
             self.skip_separator();
             loop {
@@ -418,198 +833,271 @@ impl<'a> PParser<'a> {
                     self.proceed();
                     break;
                 }
-                let current = self.current.0.to_owned();
-                let expr = self.declaration(current);
-                exprs.push(expr);
-                let (token, span) = self.current.clone();
-                if &token != &LogosToken::Newline
-                    && &token != &LogosToken::Semi
-                    && &token != &LogosToken::Error
-                    && &token != &LogosToken::RBrace
-                {
-                    self.report_error(
-                        span.clone(),
-                        "expected newline or semicolon".to_string(),
-                        format!("Expected semicolon or newline found {}", token),
-                        None,
-                    );
-                } else {
-                    self.skip_separator();
+                if self.at_end() {
+                    self.errors.push(self.syntax_error(
+                        open,
+                        "this `{` is never closed".to_string(),
+                        "Unclosed block".to_string(),
+                        Some("Add a `}` where the block ends".to_string()),
+                    ));
+                    break;
                 }
+                let expr = self.statement();
+                exprs.push(expr);
+                self.end_statement(&LogosToken::RBrace);
             }
         } else {
-            let expr = self.expr(0);
+            let expr = self.expr(0)?;
             exprs.push(expr);
         }
-        (exprs.clone(), exprs.len() == 1)
+        Ok((exprs.clone(), exprs.len() == 1))
     }
     fn skip_separator(&mut self) {
         while self.current() == &LogosToken::Newline || self.current() == &LogosToken::Semi {
             self.proceed();
         }
     }
+    /// Parses the whole source, printing every syntax error and exiting if
+    /// there were any.
     pub fn parse(&mut self) -> Vec<Expr> {
+        let exprs = self.parse_recovering();
+        if !self.report_errors() {
+            std::process::exit(1);
+        }
+        exprs
+    }
+
+    /// Parses the whole source, the statements with syntax errors are left as
+    /// `ExprKind::Error` and the errors are kept in `errors`.
+    pub fn parse_recovering(&mut self) -> Vec<Expr> {
         let mut exprs: Vec<Expr> = Vec::new();
         self.skip_separator();
-        loop {
-            if self.position >= self.tokens.len() + 1 {
-                break;
-            }
-
-            let (token, _) = self.current.clone();
-            exprs.push(self.declaration(token));
-            let (token, span) = self.current.clone();
-            if &token != &LogosToken::Newline
-                && &token != &LogosToken::Semi
-                && &token != &LogosToken::Error
-            {
-                self.report_error(
-                    span.clone(),
-                    "expected newline or semicolon".to_string(),
-                    format!("Expected semicolon or newline found {}", token),
-                    None,
-                );
-            } else {
+        while !self.at_end() {
+            // a `}` without a block to close, the statement before it is fine
+            if self.current() == &LogosToken::RBrace {
+                self.errors.push(self.syntax_error(
+                    self.current.1.clone(),
+                    "nothing to close".to_string(),
+                    "Unmatched `}`".to_string(),
+                    Some("Remove it, or add the `{` it was meant to close".to_string()),
+                ));
+                self.proceed();
                 self.skip_separator();
+                continue;
             }
+
+            exprs.push(self.statement());
+            self.end_statement(&LogosToken::RBrace);
         }
         exprs
     }
-    fn declaration(&mut self, token: LogosToken<'a>) -> Expr {
-        match token {
+
+    pub fn errors(&self) -> &[SyntaxError] {
+        &self.errors
+    }
+    fn declaration(&mut self, token: LogosToken<'a>) -> Result<Expr> {
+        Ok(match token {
             LogosToken::While => {
                 let start = self.current.1.start;
                 self.proceed();
-                let condition = self.expr(0);
-                let (block, _) = self.block();
-                return Expr::new(
+                let condition = self.expr(0)?;
+                let (block, _) = self.block()?;
+                Expr::new(
                     start..self.current.1.end,
                     ExprKind::While(Box::new(condition), block),
-                );
+                )
             }
             LogosToken::Impl => {
                 let start = self.current.1.start;
                 self.proceed();
-                let ident = self.expect_ident();
+                let ident = self.expect_ident()?;
                 self.proceed();
-                let (block, _) = self.block();
+                let (block, _) = self.block()?;
                 let end = self.current.1.end.clone();
                 Expr::new(start..end, ExprKind::Impl(ident, block))
             }
-            LogosToken::Match => {
+            LogosToken::At => {
                 let start = self.current.1.start;
                 self.proceed();
-                let condition = self.expr(0);
-                self.expect(LogosToken::LBrace);
-                self.proceed();
-                let mut exprs: Vec<(Expr, Vec<Expr>)> = Vec::new();
-                self.skip_separator();
-                loop {
-                    if self.current() == &LogosToken::RBrace {
-                        self.proceed();
-                        break;
-                    }
-                    let current = self.current.0.clone();
-                    let val = if let LogosToken::Ident(first) = current {
-                        if self.peek(0) == Some(LogosToken::FourDots) {
-                            let start = self.current.1.start.clone();
-                            self.proceed();
-                            self.proceed();
-                            let last = self.expect_ident();
-                            self.proceed();
-
-                            let end = self.current.1.end.clone();
-                            Expr::new(start..end, ExprKind::HeadTail(first.to_string(), last))
-                        } else if first == "_" {
-                            self.proceed();
-                            Expr::new(start..self.current.1.end, ExprKind::DefaultCase)
-                        } else {
-                            self.term(self.current.clone())
-                        }
-                    } else {
-                        self.term(self.current.clone())
-                    };
-                    self.expect(LogosToken::Colon);
-                    self.proceed();
-                    let (expr, _) = self.block();
-                    exprs.push((val, expr));
-
-                    let (token, span) = self.current.clone();
-                    if &token != &LogosToken::Newline
-                        && &token != &LogosToken::Semi
-                        && &token != &LogosToken::Error
-                    {
-                        self.report_error(
-                            span.clone(),
-                            "expected newline or semicolon".to_string(),
-                            format!("Expected semicolon or newline found {}", token),
-                            None,
-                        );
-                    } else {
-                        self.skip_separator();
-                    }
+                let current = self.current.0.to_owned();
+                let function = self.declaration(current)?;
+                if !matches!(
+                    function.inner,
+                    ExprKind::InlineFunction(..) | ExprKind::MultilineFunction(..)
+                ) {
+                    return Err(self.syntax_error(
+                        start..function.span.end,
+                        "not a function".to_string(),
+                        "Only functions can be memoized".to_string(),
+                        Some("Use `@name params: body`".to_string()),
+                    ));
                 }
+
                 Expr::new(
-                    start..self.current.1.end,
-                    ExprKind::Match(Box::new(condition), exprs),
+                    start..function.span.end,
+                    ExprKind::Memoized(Box::new(function)),
                 )
             }
             LogosToken::Every => {
                 let start = self.current.1.start;
                 self.proceed();
-                let for_el = self.expr(0);
-                let (block, _) = self.block();
-                return Expr::new(
+                let for_el = self.expr(0)?;
+                let (block, _) = self.block()?;
+                Expr::new(
                     start..self.current.1.end,
                     ExprKind::Every(Box::new(for_el), block),
-                );
+                )
+            }
+            LogosToken::Try => {
+                let start = self.current.1.start;
+                self.proceed();
+                let (body, _) = self.block()?;
+                self.expect(LogosToken::Catch)?;
+                self.proceed();
+                let name = match self.current() {
+                    LogosToken::Ident(_) => {
+                        let name = self.expect_ident()?;
+                        self.proceed();
+                        Some(name)
+                    }
+                    _ => None,
+                };
+                let (handler, _) = self.block()?;
+                Expr::new(
+                    start..self.current.1.end,
+                    ExprKind::Try(body, name, handler),
+                )
             }
             LogosToken::Return => {
                 let start = self.current.1.start;
                 self.proceed();
-                let expr = self.expr(0);
-                return Expr::new(start..self.current.1.end, ExprKind::Return(Box::new(expr)));
+                let expr = self.expr(0)?;
+                Expr::new(start..self.current.1.end, ExprKind::Return(Box::new(expr)))
+            }
+            LogosToken::Ident(_) | LogosToken::LSquare if self.check_destructure() => {
+                self.destructure()?
             }
             LogosToken::Ident(x) => {
                 let start = self.current.1.start;
                 if self.peek(0) == Some(LogosToken::Eq) {
                     self.proceed();
                     self.proceed();
-                    let expr = self.expr(0);
+                    let expr = self.expr(0)?;
                     Expr::new(
                         start..self.current.1.end,
                         ExprKind::Set(x.to_string(), Box::new(expr)),
                     )
                 } else if self.check_fun() {
-                    let mut params: Vec<String> = Vec::new();
+                    let mut params: Vec<Param> = Vec::new();
+                    // destructured parameters are unpacked at the start of the body
+                    let mut unpack: Vec<Expr> = Vec::new();
                     self.proceed();
-                    while self.current() != &LogosToken::Colon {
-                        params.push(self.expect_ident());
+                    while !matches!(self.current(), LogosToken::Colon | LogosToken::Arrow) {
+                        let (token, span) = self.current.clone();
+                        if !matches!(
+                            token,
+                            LogosToken::Ident(_) | LogosToken::LSquare | LogosToken::ThreeDots
+                        ) {
+                            let found = match self.at_end() {
+                                true => "the end of the program".to_string(),
+                                false => token.to_string(),
+                            };
+                            return Err(self.syntax_error(
+                                span,
+                                "expected `:`".to_string(),
+                                format!("Expected `:` after the parameters of `{x}` found {found}"),
+                                Some(format!(
+                                    "The body of a function comes after a colon, like `{x} a b: a + b`"
+                                )),
+                            ));
+                        }
+
+                        if params.last().is_some_and(|param| param.variadic) {
+                            return Err(self.syntax_error(
+                                span.clone(),
+                                "parameter after `...`".to_string(),
+                                "The variadic parameter has to be the last one".to_string(),
+                                None,
+                            ));
+                        }
+
+                        if self.current() == &LogosToken::ThreeDots {
+                            self.proceed();
+                            let mut param = Param::new(self.expect_ident()?);
+                            param.variadic = true;
+                            self.proceed();
+                            param.ty = self.annotation()?;
+                            params.push(param);
+                            continue;
+                        }
+
+                        let mut param = match self.pattern()? {
+                            Pattern::Ident(name) => Param::new(name),
+                            pattern => {
+                                // not a valid identifier, so it can't clash with one
+                                let name = format!("@{}", params.len());
+                                let value = Expr::new(span.clone(), ExprKind::Ident(name.clone()));
+                                unpack.push(Expr::new(
+                                    span.clone(),
+                                    ExprKind::Destructure(pattern, Box::new(value)),
+                                ));
+                                Param::new(name)
+                            }
+                        };
+                        param.ty = self.annotation()?;
+
+                        if self.current() == &LogosToken::Eq {
+                            self.proceed();
+                            param.default = Some(self.expr(0)?);
+                        } else if params.last().is_some_and(|param| param.default.is_some()) {
+                            return Err(self.syntax_error(
+                                span,
+                                "missing default value".to_string(),
+                                "Parameters after one with a default value need one too"
+                                    .to_string(),
+                                Some(format!("Add a default value, `{}=nil`", param.name)),
+                            ));
+                        }
+                        params.push(param);
+                    }
+                    let mut returns = None;
+                    if self.current() == &LogosToken::Arrow {
                         self.proceed();
+                        returns = Some(self.type_name()?);
+                        self.expect(LogosToken::Colon)?;
                     }
                     self.proceed();
-                    let (exprs, is_inline) = self.block();
-                    if is_inline {
+                    let (mut exprs, is_inline) = self.block()?;
+                    // an empty body ends the function at its name
+                    let end = exprs.last().map_or(start + x.len(), |expr| expr.span.end);
+                    if !unpack.is_empty() {
+                        unpack.append(&mut exprs);
+                        Expr::new(
+                            start..unpack.last().unwrap().span.end,
+                            ExprKind::MultilineFunction(x.to_string(), params, returns, unpack),
+                        )
+                    } else if is_inline {
                         Expr::new(
-                            start..exprs.last().unwrap().span.end,
+                            start..end,
                             ExprKind::InlineFunction(
                                 x.to_string(),
                                 params,
+                                returns,
                                 Box::new(exprs[0].clone()),
                             ),
                         )
                     } else {
                         Expr::new(
-                            start..exprs.last().unwrap().span.end,
-                            ExprKind::MultilineFunction(x.to_string(), params, exprs),
+                            start..end,
+                            ExprKind::MultilineFunction(x.to_string(), params, returns, exprs),
                         )
                     }
                 } else {
-                    self.expr(0)
+                    self.expr(0)?
                 }
             }
-            _ => self.expr(0),
-        }
+            _ => self.expr(0)?,
+        })
     }
 
     fn check_eof(&self, token: &LogosToken) -> bool {
@@ -649,9 +1137,9 @@ impl<'a> PParser<'a> {
     fn current(&self) -> &LogosToken {
         &self.current.0
     }
-    fn expr(&mut self, min_bp: u8) -> Expr {
+    fn expr(&mut self, min_bp: u8) -> Result<Expr> {
         let c = self.current.to_owned();
-        let mut lhs = self.term(c);
+        let mut lhs = self.term(c)?;
         let start = lhs.span.clone().start;
         loop {
             let op = self.current.0.to_owned();
@@ -664,18 +1152,19 @@ impl<'a> PParser<'a> {
                 }
                 self.proceed();
                 if op == LogosToken::LSquare {
-                    let index = self.expr(0);
+                    let index = self.expr(0)?;
                     lhs = Expr::new(
                         start..self.current.1.end,
                         ExprKind::Index(Box::new(lhs), Box::new(index)),
                     );
-                    self.expect(LogosToken::RSquare);
+                    self.expect(LogosToken::RSquare)?;
                     self.proceed();
                     continue;
                 }
+                let op = self.operator(op.to_postfix_op())?;
                 lhs = Expr::new(
                     start..self.current.1.end,
-                    ExprKind::Postfix(Box::new(lhs), op.to_postfix_op()),
+                    ExprKind::Postfix(Box::new(lhs), op),
                 );
                 continue;
             }
@@ -685,86 +1174,156 @@ impl<'a> PParser<'a> {
                 }
                 self.proceed();
                 if op == LogosToken::Question {
-                    let mhs = self.block();
+                    let mhs = self.block()?;
+                    // empty blocks end where the part before them does
+                    let end = mhs.0.last().map_or(lhs.span.end, |expr| expr.span.end);
                     if self.current() == &LogosToken::Colon {
                         self.proceed();
-                        let rhs = self.block();
+                        let rhs = self.block()?;
                         lhs = Expr::new(
-                            start..rhs.0.last().unwrap().span.end,
+                            start..rhs.0.last().map_or(end, |expr| expr.span.end),
                             ExprKind::Ternary(Box::new(lhs), mhs.0, Some(rhs.0)),
                         )
                     } else {
-                        lhs = Expr::new(
-                            start..mhs.0.last().unwrap().span.end,
-                            ExprKind::Ternary(Box::new(lhs), mhs.0, None),
-                        )
+                        lhs = Expr::new(start..end, ExprKind::Ternary(Box::new(lhs), mhs.0, None))
                     }
                     continue;
                 }
-                let rhs = self.expr(r_bp);
+                let op = self.operator(op.to_binary_op())?;
+                let rhs = self.expr(r_bp)?;
                 lhs = Expr::new(
                     start..rhs.span.end,
-                    ExprKind::Binary(Box::new(lhs), op.to_binary_op(), Box::new(rhs)),
+                    ExprKind::Binary(Box::new(lhs), op, Box::new(rhs)),
                 );
                 continue;
             }
             break;
         }
-        lhs
+        Ok(lhs)
     }
     fn peek(&self, x: usize) -> Option<LogosToken> {
         let token = self.tokens.get(self.position + x).cloned();
         token.map(|(token, _)| token)
     }
-    fn expect_ident(&mut self) -> String {
+    fn expect_ident(&mut self) -> Result<String> {
         let (token, span) = &self.current;
         if let LogosToken::Ident(ident) = self.current.0 {
-            return ident.to_string();
+            return Ok(ident.to_string());
         }
 
-        self.report_error(
+        Err(self.syntax_error(
             span.clone(),
             "expected identifier".to_string(),
             format!("Expected identifier found {}", token),
             None,
-        );
-        unreachable!()
+        ))
     }
-    fn expect(&mut self, token: LogosToken) {
+    /// The type of `name:type` in a function header, unless the colon is the
+    /// one before the body.
+    fn annotation(&mut self) -> Result<Option<String>> {
+        use LogosToken::*;
+        let annotated = self.current() == &Colon
+            && matches!(
+                self.peek(0),
+                Some(Ident("int" | "float" | "str" | "bool" | "array" | "iter" | "error") | Nil)
+            )
+            && matches!(
+                self.peek(1),
+                Some(Ident(_) | Colon | Arrow | ThreeDots | LSquare | Eq)
+            );
+        if !annotated {
+            return Ok(None);
+        }
+
+        self.proceed();
+        self.type_name().map(Some)
+    }
+
+    fn type_name(&mut self) -> Result<String> {
+        let (token, span) = self.current.clone();
+        let name = match token {
+            LogosToken::Nil => "nil",
+            LogosToken::Ident(
+                name @ ("int" | "float" | "str" | "bool" | "array" | "iter" | "error"),
+            ) => name,
+            _ => {
+                return Err(self.syntax_error(
+                    span,
+                    "unknown type".to_string(),
+                    format!("Expected a type found {token}"),
+                    Some(
+                        "The types are int, float, str, bool, array, iter, error and nil"
+                            .to_string(),
+                    ),
+                ));
+            }
+        };
+
+        self.proceed();
+        Ok(name.to_string())
+    }
+
+    fn expect(&mut self, token: LogosToken) -> Result<()> {
         let (tok, span) = &self.current;
         if tok != &token {
-            self.report_error(
+            return Err(self.syntax_error(
                 span.clone(),
                 format!("expected {}", token),
                 format!("Expected {} found {}", token, tok),
                 Some(format!("Replace it with {token}")),
-            );
+            ));
         }
+        Ok(())
     }
 
-    fn process_string(mut value: String, fstring: bool) -> String {
-        value.remove(0);
-        if fstring {
-            value.remove(0);
+    /// The operator of the current token, the binding powers only let
+    /// operators through.
+    fn operator<T>(&self, op: Option<T>) -> Result<T> {
+        if let Some(op) = op {
+            return Ok(op);
         }
-        value.remove(value.len() - 1);
+
+        let (token, span) = self.current.clone();
+        Err(self.syntax_error(
+            span,
+            "not an operator".to_string(),
+            format!("Expected an operator found {token}"),
+            None,
+        ))
+    }
+
+    fn process_string(&self, value: &str, fstring: bool) -> Result<String> {
+        // the quotes, and the `f` of an f-string
+        let value = &value[1 + fstring as usize..value.len() - 1];
 
         let mut chars = value.chars();
         let mut processed_str = String::new();
 
         while let Some(c) = chars.next() {
             if c == '\\' {
-                let next = chars.next().unwrap();
+                // the lexer doesn't end a string on an escaped quote
+                let Some(next) = chars.next() else {
+                    unreachable!("a string ending with a backslash")
+                };
                 match next {
                     'n' => processed_str.push('\n'),
                     't' => processed_str.push('\t'),
                     'r' => processed_str.push('\r'),
                     'x' => {
-                        let mut hex = String::new();
-                        hex.push(chars.next().unwrap());
-                        hex.push(chars.next().unwrap());
-                        let hex = u32::from_str_radix(&hex, 16).unwrap();
-                        processed_str.push(hex as u8 as char);
+                        let hex = chars.by_ref().take(2).collect::<String>();
+                        match u8::from_str_radix(&hex, 16) {
+                            Ok(byte) if hex.len() == 2 && !hex.starts_with('+') => {
+                                processed_str.push(byte as char)
+                            }
+                            _ => {
+                                return Err(self.syntax_error(
+                                    self.current.1.clone(),
+                                    "invalid escape".to_string(),
+                                    format!("Expected two hex digits after \\x, found '{hex}'"),
+                                    Some("Write a byte like \\x41".to_string()),
+                                ));
+                            }
+                        }
                     }
                     '$' => processed_str.push_str("\\$"),
                     '{' => processed_str.push_str("\\{"),
@@ -774,17 +1333,17 @@ impl<'a> PParser<'a> {
                 processed_str.push(c);
             }
         }
-        processed_str
+        Ok(processed_str)
     }
 
-    fn term(&mut self, token: (LogosToken, Range<usize>)) -> Expr {
+    fn term(&mut self, token: (LogosToken, Range<usize>)) -> Result<Expr> {
         let (token, span) = token;
         let kind = match token {
             LogosToken::LSquare => {
                 self.proceed();
                 let mut values = Vec::new();
                 while self.current() != &LogosToken::RSquare {
-                    let expr = self.expr(0);
+                    let expr = self.expr(0)?;
                     values.push(expr);
                     if self.current() != &LogosToken::Comma {
                         break;
@@ -794,26 +1353,33 @@ impl<'a> PParser<'a> {
                 ExprKind::Array(values)
             }
             LogosToken::Int(value) => ExprKind::Int(Integer::parse(value).unwrap().complete()),
-            LogosToken::Float(value) => ExprKind::Float(Float::parse(value).unwrap().complete(53)),
+            LogosToken::Float(value) => {
+                ExprKind::Float(Float::parse(value).unwrap().complete(float_precision()))
+            }
             LogosToken::True => ExprKind::Bool(true),
             LogosToken::False => ExprKind::Bool(false),
             LogosToken::Nil => ExprKind::Nil,
             LogosToken::Inf => ExprKind::Float(float!(rug::float::Special::Infinity)),
             LogosToken::Break => ExprKind::Break,
             LogosToken::Continue => ExprKind::Continue,
-            LogosToken::String(value) => {
-                ExprKind::String(Self::process_string(value.to_owned(), false))
-            }
-            LogosToken::FString(value) => {
-                ExprKind::FString(Self::process_string(value.to_owned(), true))
+            LogosToken::String(value) => ExprKind::String(self.process_string(value, false)?),
+            LogosToken::FString(value) => ExprKind::FString(self.process_string(value, true)?),
+            LogosToken::Yield => {
+                self.proceed();
+                let expr = self.expr(0)?;
+                return Ok(Expr::new(
+                    span.start..self.current.1.end,
+                    ExprKind::Yield(Box::new(expr)),
+                ));
             }
+            LogosToken::Match => return self.match_expr(),
             v @ LogosToken::Dollar | v @ LogosToken::DollarDollar => {
                 self.proceed();
-                let expr = self.expr(0);
-                return Expr::new(
+                let expr = self.expr(0)?;
+                return Ok(Expr::new(
                     span.start..self.current.1.end,
                     ExprKind::Call(v.to_string(), Some(vec![expr])),
-                );
+                ));
             }
             LogosToken::Ident(value) => {
                 let ident = ExprKind::Ident(value.to_string());
@@ -825,7 +1391,7 @@ impl<'a> PParser<'a> {
                         if self.current.0 == LogosToken::RParen {
                             break;
                         }
-                        let expr = self.expr(0);
+                        let expr = self.expr(0)?;
                         args.push(expr);
                         if self.current.0 != LogosToken::Comma {
                             break;
@@ -835,7 +1401,7 @@ impl<'a> PParser<'a> {
                     self.proceed();
                     let span = span.start..self.current.1.start;
                     let kind = ExprKind::Call(value.to_string(), Some(args));
-                    return Expr::new(span, kind);
+                    return Ok(Expr::new(span, kind));
                 }
                 let kind = if value.starts_with("_") {
                     ExprKind::String(value[1..].replace("_", " "))
@@ -843,39 +1409,96 @@ impl<'a> PParser<'a> {
                     ident
                 };
                 let current = &self.current;
-                return Expr::new(span.start..current.1.end, kind);
+                return Ok(Expr::new(span.start..current.1.end, kind));
             }
             LogosToken::LParen => {
                 self.proceed();
-                let expr = self.expr(5);
-                self.expect(LogosToken::RParen);
+                let expr = self.expr(5)?;
+                let (token, end) = self.current.clone();
+                if token != LogosToken::RParen {
+                    return Err(self.syntax_error(
+                        span.start..end.start,
+                        "this `(` is never closed".to_string(),
+                        format!("Expected `)` found {token}"),
+                        Some("Add a `)` after the expression".to_string()),
+                    ));
+                }
                 expr.inner
             }
             LogosToken::Minus | LogosToken::Plus => {
+                let op = self.operator(token.to_unary_op())?;
                 self.proceed();
-                let expr = self.term(self.current.clone());
+                let expr = self.term(self.current.clone())?;
                 self.back();
-                ExprKind::Unary(token.to_unary_op(), Box::new(expr))
+                ExprKind::Unary(op, Box::new(expr))
             }
             LogosToken::Bang => {
+                let op = self.operator(token.to_unary_op())?;
                 self.proceed();
-                let expr = self.expr(0);
+                let expr = self.expr(0)?;
                 self.back();
-                ExprKind::Unary(token.to_unary_op(), Box::new(expr))
+                ExprKind::Unary(op, Box::new(expr))
+            }
+            _ if self.at_end() => {
+                return Err(self.syntax_error(
+                    span,
+                    "the program ends here".to_string(),
+                    "Expected expression".to_string(),
+                    Some("Something is missing at the end of the program".to_string()),
+                ))
             }
             _ => {
-                self.report_error(
+                return Err(self.syntax_error(
                     span.clone(),
                     "this is not a value".to_string(),
                     "Expected expression".to_string(),
                     Some("Expected a value like integers, strings, etc.".to_string()),
-                );
-                unreachable!()
+                ));
             }
         };
         let current = self.current.clone();
         self.proceed();
 
-        Expr::new(span.start..current.1.end, kind)
+        Ok(Expr::new(span.start..current.1.end, kind))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenize;
+
+    #[test]
+    fn test_parse_recovers() {
+        let src = "add a b a + b\nx = (1 + 2\nf n: {\n    z = ]\n    n\n}\n$f(3)\n}\ng q: {\n  $q";
+        let mut parser = PParser::new(src, tokenize(src));
+        let ast = parser.parse_recovering();
+
+        let messages = parser
+            .errors()
+            .iter()
+            .map(|error| error.message.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            [
+                "Expected `:` after the parameters of `add` found +",
+                "Expected `)` found <newline>",
+                "Expected expression",
+                "Unmatched `}`",
+                "Unclosed block",
+            ]
+        );
+
+        // the statements around the errors are still there
+        assert!(matches!(ast[0].inner, ExprKind::Error));
+        assert!(matches!(ast[1].inner, ExprKind::Error));
+        let ExprKind::MultilineFunction(_, _, _, body) = &ast[2].inner else {
+            panic!("expected a function, found {:?}", ast[2]);
+        };
+        assert!(matches!(body[0].inner, ExprKind::Error));
+        assert!(matches!(body[1].inner, ExprKind::Ident(_)));
+        assert!(matches!(ast[3].inner, ExprKind::Call(..)));
+        assert!(matches!(ast[4].inner, ExprKind::InlineFunction(..)));
     }
 }
@@ -0,0 +1,548 @@
+//! Rewrites a program to be easy to read, the opposite of golfing it: one
+//! statement per line, blocks indented, spaces around operators and `_Foo`
+//! strings written as `"Foo"`. It prints the tree the parser builds, so the
+//! output always parses back to the same program, but comments are dropped.
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use rug::Float;
+
+use crate::analyzer::symbol;
+use crate::parser::{
+    BinaryOp, Expr, ExprKind, LogosToken, MatchArm, Param, Pattern, PostfixOp, UnaryOp,
+};
+use crate::tokenize;
+
+const INDENT: &str = "    ";
+/// The binding power of what never takes part of the code around it, like a
+/// literal, a call or anything in parentheses.
+const ATOM: u8 = u8::MAX;
+
+/// The readable form of the program `ast` was parsed from.
+pub fn pretty(src: &str, ast: &[Expr]) -> String {
+    let printer = Printer { src };
+    let mut out = String::new();
+    for expr in ast {
+        out.push_str(&printer.statement(expr, 0));
+        out.push('\n');
+    }
+    out
+}
+
+struct Printer<'a> {
+    src: &'a str,
+}
+
+/// An expression as it is printed. `lead` is the binding power of the loosest
+/// operator at its top, and `tail` the one its last part is parsed with, so an
+/// operator written after it binding at least that tightly would be taken by
+/// that part.
+struct Printed {
+    text: String,
+    lead: u8,
+    tail: u8,
+}
+
+impl Printed {
+    fn atom(text: String) -> Self {
+        Printed {
+            text,
+            lead: ATOM,
+            tail: ATOM,
+        }
+    }
+
+    /// The text, in parentheses when it can't be written as it is after
+    /// something parsed with the binding power `lead`, or before an operator
+    /// binding `next` tightly.
+    fn operand(self, lead: u8, next: Option<u8>) -> String {
+        match self.lead < lead || next.is_some_and(|next| self.tail <= next) {
+            true => format!("({})", self.text),
+            false => self.text,
+        }
+    }
+}
+
+/// The binding powers of `PParser::infix_binding_power`.
+fn binding_power(op: BinaryOp) -> (u8, u8) {
+    match op {
+        BinaryOp::Add | BinaryOp::Sub => (10, 11),
+        BinaryOp::Mul | BinaryOp::Div => (20, 21),
+        BinaryOp::Mod => (30, 31),
+        BinaryOp::BinaryPow => (40, 41),
+        BinaryOp::Pow => (50, 51),
+        BinaryOp::Attr => (60, 61),
+        BinaryOp::AddEq | BinaryOp::SubEq | BinaryOp::MulEq | BinaryOp::DivEq => (1, 2),
+        _ => (5, 6),
+    }
+}
+
+/// Whether `expr` can be written where an expression is expected, the rest
+/// are statements that need a block of their own.
+fn is_expression(expr: &Expr) -> bool {
+    !matches!(
+        expr.inner,
+        ExprKind::Set(..)
+            | ExprKind::EqStmt(..)
+            | ExprKind::Destructure(..)
+            | ExprKind::InlineFunction(..)
+            | ExprKind::MultilineFunction(..)
+            | ExprKind::Memoized(..)
+            | ExprKind::While(..)
+            | ExprKind::Every(..)
+            | ExprKind::Try(..)
+            | ExprKind::Impl(..)
+            | ExprKind::Return(..)
+            | ExprKind::Error
+    )
+}
+
+/// Whether `expr` has a ternary without an else branch, which would take the
+/// `:` after it as its own.
+fn has_open_ternary(expr: &Expr) -> bool {
+    let any = |exprs: &[Expr]| exprs.iter().any(has_open_ternary);
+    match &expr.inner {
+        ExprKind::Ternary(_, _, None) => true,
+        ExprKind::Ternary(condition, then, Some(otherwise)) => {
+            has_open_ternary(condition) || any(then) || any(otherwise)
+        }
+        ExprKind::Binary(lhs, _, rhs) | ExprKind::Index(lhs, rhs) => {
+            has_open_ternary(lhs) || has_open_ternary(rhs)
+        }
+        ExprKind::Unary(_, value) | ExprKind::Postfix(value, _) | ExprKind::Yield(value) => {
+            has_open_ternary(value)
+        }
+        ExprKind::Call(_, Some(args)) | ExprKind::Array(args) => any(args),
+        _ => false,
+    }
+}
+
+/// `s` as a string literal.
+fn quote(s: &str, fstring: bool) -> String {
+    let mut out = String::from('"');
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            // `\$` and `\{` are kept as they are written in f-strings
+            '\\' if fstring && matches!(chars.peek(), Some('$' | '{')) => out.push(c),
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            c if c.is_control() && (c as u32) < 0x100 => {
+                out.push_str(&format!("\\x{:02x}", c as u32))
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+impl Printer<'_> {
+    fn statement(&self, expr: &Expr, indent: usize) -> String {
+        match &expr.inner {
+            ExprKind::Set(name, value) => format!("{name} = {}", self.expr(value, indent).text),
+            ExprKind::EqStmt(name, op, value) => {
+                format!("{name} {} {}", symbol(*op), self.expr(value, indent).text)
+            }
+            ExprKind::Destructure(pattern, value) => self.destructure(pattern, value, indent),
+            ExprKind::InlineFunction(name, params, returns, body) => {
+                self.function(name, params, returns, std::slice::from_ref(body), indent)
+            }
+            ExprKind::MultilineFunction(name, params, returns, body) => {
+                self.function(name, params, returns, body, indent)
+            }
+            ExprKind::Memoized(function) => format!("@{}", self.statement(function, indent)),
+            ExprKind::While(condition, body) => format!(
+                ">. {} {}",
+                self.expr(condition, indent).text,
+                self.block(body, indent)
+            ),
+            ExprKind::Every(list, body) => format!(
+                "ev {} {}",
+                self.expr(list, indent).text,
+                self.block(body, indent)
+            ),
+            ExprKind::Try(body, name, handler) => format!(
+                "tr {} ca{} {}",
+                self.block(body, indent),
+                name.as_ref()
+                    .map_or(String::new(), |name| format!(" {name}")),
+                self.block(handler, indent)
+            ),
+            ExprKind::Impl(ty, methods) => format!("impl {ty} {}", self.block(methods, indent)),
+            ExprKind::Return(value) => format!("& {}", self.expr(value, indent).text),
+            _ => self.expr(expr, indent).text,
+        }
+    }
+
+    /// The statements between braces, one per line.
+    fn block(&self, exprs: &[Expr], indent: usize) -> String {
+        if exprs.is_empty() {
+            return "{}".to_string();
+        }
+
+        let mut out = String::from("{\n");
+        for expr in exprs {
+            out.push_str(&INDENT.repeat(indent + 1));
+            out.push_str(&self.statement(expr, indent + 1));
+            out.push('\n');
+        }
+        out.push_str(&INDENT.repeat(indent));
+        out.push('}');
+        out
+    }
+
+    /// A body that is a single expression on the same line, or a block.
+    fn body(&self, exprs: &[Expr], indent: usize) -> String {
+        match exprs {
+            [expr] if is_expression(expr) => self.expr(expr, indent).text,
+            _ => self.block(exprs, indent),
+        }
+    }
+
+    fn list(&self, exprs: &[Expr], indent: usize) -> String {
+        let items: Vec<_> = exprs
+            .iter()
+            .map(|expr| self.expr(expr, indent).text)
+            .collect();
+        items.join(", ")
+    }
+
+    fn function(
+        &self,
+        name: &str,
+        params: &[Param],
+        returns: &Option<String>,
+        mut body: &[Expr],
+        indent: usize,
+    ) -> String {
+        // the parameters written as patterns are named `@0`, `@1`, ... and
+        // unpacked by the first statements of the body
+        let mut patterns = HashMap::new();
+        while let [Expr {
+            inner: ExprKind::Destructure(pattern, value),
+            ..
+        }, rest @ ..] = body
+        {
+            match &value.inner {
+                ExprKind::Ident(param) if param.starts_with('@') => {
+                    patterns.insert(param.as_str(), pattern);
+                    body = rest;
+                }
+                _ => break,
+            }
+        }
+
+        let mut out = name.to_string();
+        for param in params {
+            out.push(' ');
+            if param.variadic {
+                out.push_str("...");
+            }
+            match patterns.get(param.name.as_str()) {
+                Some(pattern) => out.push_str(&self.pattern(pattern, indent)),
+                None => out.push_str(&param.name),
+            }
+            if let Some(ty) = &param.ty {
+                out.push_str(&format!(":{ty}"));
+            }
+            if let Some(default) = &param.default {
+                out.push_str(&format!("={}", self.expr(default, indent).text));
+            }
+        }
+        if let Some(returns) = returns {
+            out.push_str(&format!(" -> {returns}"));
+        }
+        format!("{out}: {}", self.body(body, indent))
+    }
+
+    fn destructure(&self, pattern: &Pattern, value: &Expr, indent: usize) -> String {
+        // `a, b = b, a` rather than `[a, b] = [b, a]`
+        let pattern = match pattern {
+            Pattern::Array(patterns) if patterns.len() > 1 => {
+                let patterns: Vec<_> = patterns.iter().map(|p| self.pattern(p, indent)).collect();
+                patterns.join(", ")
+            }
+            _ => self.pattern(pattern, indent),
+        };
+        match &value.inner {
+            ExprKind::Array(values) if values.len() > 1 => {
+                format!("{pattern} = {}", self.list(values, indent))
+            }
+            _ => format!("{pattern} = {}", self.expr(value, indent).text),
+        }
+    }
+
+    fn pattern(&self, pattern: &Pattern, indent: usize) -> String {
+        match pattern {
+            Pattern::Ident(name) | Pattern::Type(name) => name.clone(),
+            Pattern::Array(patterns) => {
+                let patterns: Vec<_> = patterns.iter().map(|p| self.pattern(p, indent)).collect();
+                format!("[{}]", patterns.join(", "))
+            }
+            Pattern::HeadTail(head, tail) => format!(
+                "{}::{}",
+                self.pattern(head, indent),
+                self.pattern(tail, indent)
+            ),
+            Pattern::Wildcard => "_".to_string(),
+            Pattern::Value(value) => self.expr(value, indent).text,
+            Pattern::Range(lo, hi) => format!(
+                "{}..{}",
+                self.expr(lo, indent).text,
+                self.expr(hi, indent).text
+            ),
+            Pattern::Rest(name) => format!("...{name}"),
+            Pattern::Or(patterns) => {
+                let patterns: Vec<_> = patterns.iter().map(|p| self.pattern(p, indent)).collect();
+                patterns.join(" | ")
+            }
+        }
+    }
+
+    fn match_arms(&self, value: &Expr, arms: &[MatchArm], indent: usize) -> String {
+        let mut out = format!("mc {} {{\n", self.expr(value, indent).text);
+        for arm in arms {
+            out.push_str(&INDENT.repeat(indent + 1));
+            out.push_str(&self.pattern(&arm.pattern, indent + 1));
+            if let Some(guard) = &arm.guard {
+                out.push_str(&format!(" if {}", self.expr(guard, indent + 1).text));
+            }
+            out.push_str(&format!(": {}\n", self.body(&arm.body, indent + 1)));
+        }
+        out.push_str(&INDENT.repeat(indent));
+        out.push('}');
+        out
+    }
+
+    /// A float as it is written in the source, its digits are rarely the ones
+    /// of the value itself.
+    fn float(&self, value: &Float, span: &Range<usize>) -> String {
+        let text = self.src.get(span.clone()).unwrap_or_default().trim();
+        match tokenize(text).as_slice() {
+            [(LogosToken::Float(_) | LogosToken::Inf, _)] => text.to_string(),
+            _ => value.to_string(),
+        }
+    }
+
+    fn expr(&self, expr: &Expr, indent: usize) -> Printed {
+        match &expr.inner {
+            ExprKind::Int(i) => Printed::atom(i.to_string()),
+            ExprKind::Float(f) => Printed::atom(self.float(f, &expr.span)),
+            ExprKind::Bool(b) => Printed::atom(b.to_string()),
+            ExprKind::Nil => Printed::atom("nil".to_string()),
+            ExprKind::Break => Printed::atom("br".to_string()),
+            ExprKind::Continue => Printed::atom("ct".to_string()),
+            ExprKind::String(s) => Printed::atom(quote(s, false)),
+            ExprKind::FString(s) => Printed::atom(format!("f{}", quote(s, true))),
+            ExprKind::Ident(name) => Printed::atom(name.clone()),
+            ExprKind::Array(items) => Printed::atom(format!("[{}]", self.list(items, indent))),
+            // `$` and `$$` print everything after them
+            ExprKind::Call(name, Some(args))
+                if (name == "$" || name == "$$") && args.len() == 1 =>
+            {
+                let value = self.expr(&args[0], indent).text;
+                let space = if value.starts_with('$') { " " } else { "" };
+                Printed {
+                    text: format!("{name}{space}{value}"),
+                    lead: ATOM,
+                    tail: 0,
+                }
+            }
+            ExprKind::Call(name, args) => Printed::atom(format!(
+                "{name}({})",
+                self.list(args.as_deref().unwrap_or_default(), indent)
+            )),
+            ExprKind::Index(value, index) => Printed {
+                text: format!(
+                    "{}[{}]",
+                    self.postfix_operand(value, 7, indent),
+                    self.expr(index, indent).text
+                ),
+                lead: 7,
+                tail: ATOM,
+            },
+            ExprKind::Postfix(value, op) => {
+                let (symbol, power) = match op {
+                    PostfixOp::Increase => ("++", 6),
+                    PostfixOp::Decrease => ("--", 6),
+                    PostfixOp::Factorial => ("!", 7),
+                };
+                Printed {
+                    text: format!("{}{symbol}", self.postfix_operand(value, power, indent)),
+                    lead: power,
+                    tail: ATOM,
+                }
+            }
+            // `!` takes everything after it, the parentheses are only there
+            // to make it clear
+            ExprKind::Unary(UnaryOp::Not, value) => {
+                let printed = self.expr(value, indent);
+                let text = match &value.inner {
+                    ExprKind::Binary(_, op, _) if *op != BinaryOp::Attr && printed.lead >= 5 => {
+                        format!("!({})", printed.text)
+                    }
+                    _ => format!("!{}", printed.text),
+                };
+                Printed {
+                    text,
+                    lead: ATOM,
+                    tail: 0,
+                }
+            }
+            // `-` and `+` only take a term, and `--` or `++` would be read as
+            // one operator
+            ExprKind::Unary(op, value) => {
+                let symbol = if *op == UnaryOp::Neg { "-" } else { "+" };
+                let printed = self.expr(value, indent);
+                let term = printed.lead == ATOM
+                    && printed.tail == ATOM
+                    && !matches!(value.inner, ExprKind::Unary(..));
+                Printed::atom(match term {
+                    true => format!("{symbol}{}", printed.text),
+                    false => format!("{symbol}({})", printed.text),
+                })
+            }
+            ExprKind::Binary(lhs, BinaryOp::Attr, rhs) => Printed {
+                text: format!(
+                    "{}.{}",
+                    self.expr(lhs, indent).operand(60, Some(60)),
+                    self.expr(rhs, indent).text
+                ),
+                lead: 60,
+                tail: 61,
+            },
+            ExprKind::Binary(lhs, op, rhs) => {
+                let (l_bp, r_bp) = binding_power(*op);
+                // a looser lhs like `x++` or `a[0]` stays as it is, the
+                // operator is still parsed after it
+                let lhs = self.expr(lhs, indent);
+                let lead = match lhs.tail <= l_bp {
+                    true => l_bp,
+                    false => l_bp.min(lhs.lead),
+                };
+                let rhs = self.expr(rhs, indent);
+                let tail = match rhs.lead < r_bp {
+                    true => r_bp,
+                    false => r_bp.min(rhs.tail),
+                };
+                Printed {
+                    text: format!(
+                        "{} {} {}",
+                        lhs.operand(0, Some(l_bp)),
+                        symbol(*op),
+                        rhs.operand(r_bp, None)
+                    ),
+                    lead,
+                    tail,
+                }
+            }
+            ExprKind::Ternary(condition, then, otherwise) => {
+                let mut text = format!("{} ? ", self.expr(condition, indent).operand(4, Some(4)));
+                match then.as_slice() {
+                    [expr] if is_expression(expr) && !has_open_ternary(expr) => {
+                        text.push_str(&self.expr(expr, indent).text)
+                    }
+                    _ => text.push_str(&self.block(then, indent)),
+                }
+                if let Some(otherwise) = otherwise {
+                    text.push_str(&format!(" : {}", self.body(otherwise, indent)));
+                }
+                Printed {
+                    text,
+                    lead: 4,
+                    tail: 0,
+                }
+            }
+            ExprKind::Match(value, arms) => Printed::atom(self.match_arms(value, arms, indent)),
+            ExprKind::Yield(value) => Printed {
+                text: format!("yd {}", self.expr(value, indent).text),
+                lead: ATOM,
+                tail: 0,
+            },
+            _ => Printed::atom(self.statement(expr, indent)),
+        }
+    }
+
+    /// The value `++`, `--`, `!` or an index binding `power` tightly applies
+    /// to, sums and the like are always put in parentheses to be clear.
+    fn postfix_operand(&self, value: &Expr, power: u8, indent: usize) -> String {
+        let printed = self.expr(value, indent);
+        match &value.inner {
+            ExprKind::Binary(_, op, _) if *op != BinaryOp::Attr => format!("({})", printed.text),
+            _ => printed.operand(power, Some(power)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::PParser;
+
+    fn parse(src: &str) -> Vec<Expr> {
+        let mut parser = PParser::new(src, tokenize(src));
+        let ast = parser.parse_recovering();
+        assert!(parser.errors().is_empty(), "{src}\n{:?}", parser.errors());
+        ast
+    }
+
+    fn pretty_src(src: &str) -> String {
+        pretty(src, &parse(src))
+    }
+
+    /// The tree of `src` without the spans, which move when it's rewritten.
+    fn shape(src: &str) -> String {
+        let debug = format!("{:?}", parse(src));
+        let mut out = String::new();
+        let mut rest = debug.as_str();
+        while let Some(start) = rest.find("span: ") {
+            out.push_str(&rest[..start]);
+            rest = rest[start..].trim_start_matches(|c: char| !matches!(c, ',' | '}'));
+        }
+        out.push_str(rest);
+        out
+    }
+
+    #[test]
+    fn test_pretty() {
+        assert_eq!(
+            pretty_src("f n:n<2?n:f(n-1)+f(n-2);i=0;>.i<20{$f(i);i++}"),
+            "f n: n < 2 ? n : f(n - 1) + f(n - 2)\ni = 0\n>. i < 20 {\n    $f(i)\n    i++\n}\n"
+        );
+        assert_eq!(
+            pretty_src("$_Hello_World+f\"{x}\\$\";a,b=b,a"),
+            "$\"Hello World\" + f\"{x}\\$\"\na, b = b, a\n"
+        );
+        assert_eq!(
+            pretty_src("g c [a,b]:{x=a+b;x*c}"),
+            "g c [a, b]: {\n    x = a + b\n    x * c\n}\n"
+        );
+        assert_eq!(pretty_src("x=(a+b)*-(2)!"), "x = ((a + b) * -2)!\n");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let programs = [
+            "f n:n<2?n:f(n-1)+f(n-2);i=0;>.i<20{$f(i);i++}",
+            "x=(a+b)*(c-d)/e%2^3**4;y=a-(b-c);z=-(-x)+(a==b)*2",
+            "$!a==b&&(c||d);$($x)+1;$$ $x;y=a&&(!b)||c",
+            "x=(a++)*2;y=(a+b)!;z=a[0][1]++;w=-a[0];v=(-a)[0];u=a.b().c(1).len()",
+            "x=a?b?1:2:3;y=a?{b?1}:2;z=a?1:b?2:3;w=c?{x=1;x}:{y=2}",
+            "@f x:int=1 ...r -> int:{&x}\ng [a,b]:{a+b}\nimpl int{d self:self*2}\nev [1,2]{$x}",
+            "tr{raise(1)}ca e{$e}\ntr{x}ca{}\nh::t=[1,2,3];[p]=[[4]]",
+            "mc x{0|1:_a\n-5..5 if x>0:{y=1;y}\n[a,...r]:a\nh::t:h\nint:_i\n_:nil}",
+            "n=1.50;m=inf;s=\"a\\\"b\\\\c\\n\\x01\";t=f\"\\{$x}\\n\";br;ct;yd x+1",
+            "x+=1;x=y*=2;f:{}",
+        ];
+        for src in programs {
+            let pretty = pretty_src(src);
+            assert_eq!(shape(&pretty), shape(src), "{src}\n{pretty}");
+            assert_eq!(pretty_src(&pretty), pretty, "{src}");
+        }
+    }
+}
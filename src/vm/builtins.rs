@@ -0,0 +1,129 @@
+use std::ops::RangeInclusive;
+
+use super::bytecode::Bytecode::{self, *};
+use super::value::Type;
+
+/// How the compiler lays out the arguments of a builtin for its instruction.
+#[derive(Debug)]
+pub(crate) enum Lowering {
+    /// Every argument, nil for the ones that aren't given.
+    Args(Bytecode),
+    /// The first argument and the second one, which defaults to the integer
+    /// or to nil.
+    Optional(Bytecode, Option<u32>),
+    /// The numbers and the precision, nil (the global one) by default.
+    Math(Bytecode),
+    /// The arguments, the instruction gets the slot of the cached pattern.
+    Regex(Bytecode),
+    Range,
+    /// Any number of pools, the instruction gets how many.
+    Product,
+}
+
+/// A function that is compiled to its own instructions instead of a call.
+#[derive(Debug)]
+pub struct Builtin {
+    pub name: &'static str,
+    pub arity: RangeInclusive<usize>,
+    /// The type of the value it returns, when it's always the same.
+    pub returns: Option<Type>,
+    pub(crate) lowering: Lowering,
+}
+
+impl Builtin {
+    const fn new(
+        name: &'static str,
+        arity: RangeInclusive<usize>,
+        returns: Option<Type>,
+        lowering: Lowering,
+    ) -> Self {
+        Builtin {
+            name,
+            arity,
+            returns,
+            lowering,
+        }
+    }
+
+    /// The message for a call with `argc` arguments it doesn't take.
+    pub fn arity_error(&self, argc: usize) -> String {
+        let (min, max) = (*self.arity.start(), *self.arity.end());
+        let expected = match max {
+            usize::MAX => format!("at least {min}"),
+            _ if min == max => min.to_string(),
+            _ => format!("{min} to {max}"),
+        };
+        format!("`{}` takes {expected} argument(s), found {argc}", self.name)
+    }
+}
+
+const INT: Option<Type> = Some(Type::Integer);
+const FLOAT: Option<Type> = Some(Type::Float);
+const STR: Option<Type> = Some(Type::String);
+const BOOL: Option<Type> = Some(Type::Bool);
+const ARRAY: Option<Type> = Some(Type::Array);
+const ITER: Option<Type> = Some(Type::Iter);
+
+#[rustfmt::skip]
+pub const BUILTINS: &[Builtin] = &[
+    Builtin::new("$", 1..=1, None, Lowering::Args(Println)),
+    Builtin::new("$$", 1..=1, None, Lowering::Args(Print)),
+    Builtin::new("int", 1..=1, INT, Lowering::Args(ToInt)),
+    Builtin::new("flt", 1..=1, FLOAT, Lowering::Args(ToFloat)),
+    Builtin::new("str", 1..=1, STR, Lowering::Args(ToString)),
+    Builtin::new("inp", 0..=1, STR, Lowering::Args(Input)),
+    Builtin::new("len", 1..=1, INT, Lowering::Args(Len)),
+    Builtin::new("type", 1..=1, STR, Lowering::Args(TypeOf)),
+    Builtin::new("gcd", 2..=2, INT, Lowering::Args(Gcd)),
+    Builtin::new("lcm", 2..=2, INT, Lowering::Args(Lcm)),
+    Builtin::new("fib", 1..=1, INT, Lowering::Args(Fib)),
+    Builtin::new("abs", 1..=1, None, Lowering::Args(Abs)),
+    Builtin::new("floor", 1..=1, None, Lowering::Args(Floor)),
+    Builtin::new("ceil", 1..=1, None, Lowering::Args(Ceil)),
+    Builtin::new("exit", 0..=1, None, Lowering::Args(Exit)),
+    Builtin::new("raise", 1..=1, None, Lowering::Args(Raise)),
+    Builtin::new("rnd", 1..=2, None, Lowering::Optional(Rand, Some(0))),
+    Builtin::new("rng", 1..=3, ITER, Lowering::Range),
+    Builtin::new("sqrt", 1..=2, FLOAT, Lowering::Optional(Sqrt, Some(2))),
+    Builtin::new("round", 1..=2, None, Lowering::Optional(Round, Some(1))),
+    Builtin::new("isprime", 1..=1, BOOL, Lowering::Args(IsPrime)),
+    Builtin::new("nextprime", 1..=1, INT, Lowering::Args(NextPrime)),
+    Builtin::new("factor", 1..=1, ARRAY, Lowering::Args(Factor)),
+    Builtin::new("powmod", 3..=3, INT, Lowering::Args(PowMod)),
+    Builtin::new("invmod", 2..=2, INT, Lowering::Args(InvMod)),
+    Builtin::new("binomial", 2..=2, INT, Lowering::Args(Binomial)),
+    Builtin::new("isqrt", 1..=1, INT, Lowering::Args(Isqrt)),
+    Builtin::new("digits", 1..=2, ARRAY, Lowering::Optional(Digits, Some(10))),
+    Builtin::new("tobase", 1..=2, STR, Lowering::Optional(ToBase, Some(2))),
+    Builtin::new("frombase", 1..=2, INT, Lowering::Optional(FromBase, Some(2))),
+    Builtin::new("perms", 1..=2, ITER, Lowering::Optional(Perms, None)),
+    Builtin::new("combs", 2..=2, ITER, Lowering::Args(Combs)),
+    Builtin::new("powerset", 1..=1, ITER, Lowering::Args(Powerset)),
+    Builtin::new("product", 0..=usize::MAX, ITER, Lowering::Product),
+    Builtin::new("sin", 1..=2, FLOAT, Lowering::Math(Sin)),
+    Builtin::new("cos", 1..=2, FLOAT, Lowering::Math(Cos)),
+    Builtin::new("tan", 1..=2, FLOAT, Lowering::Math(Tan)),
+    Builtin::new("asin", 1..=2, FLOAT, Lowering::Math(Asin)),
+    Builtin::new("acos", 1..=2, FLOAT, Lowering::Math(Acos)),
+    Builtin::new("atan", 1..=2, FLOAT, Lowering::Math(Atan)),
+    Builtin::new("atan2", 2..=3, FLOAT, Lowering::Math(Atan2)),
+    Builtin::new("ln", 1..=2, FLOAT, Lowering::Math(Ln)),
+    Builtin::new("log", 2..=3, FLOAT, Lowering::Math(Log)),
+    Builtin::new("log2", 1..=2, FLOAT, Lowering::Math(Log2)),
+    Builtin::new("log10", 1..=2, FLOAT, Lowering::Math(Log10)),
+    Builtin::new("exp", 1..=2, FLOAT, Lowering::Math(Exp)),
+    Builtin::new("hypot", 2..=3, FLOAT, Lowering::Math(Hypot)),
+    Builtin::new("pi", 0..=1, FLOAT, Lowering::Math(Pi)),
+    Builtin::new("e", 0..=1, FLOAT, Lowering::Math(E)),
+    Builtin::new("prec", 0..=1, None, Lowering::Math(Prec)),
+    Builtin::new("re", 2..=2, BOOL, Lowering::Regex(ReMatch)),
+    Builtin::new("ref", 2..=2, ARRAY, Lowering::Regex(ReFind)),
+    Builtin::new("rec", 2..=2, ARRAY, Lowering::Regex(ReCaptures)),
+    Builtin::new("rer", 3..=3, STR, Lowering::Regex(ReReplace)),
+    Builtin::new("res", 2..=2, ARRAY, Lowering::Regex(ReSplit)),
+];
+
+/// The builtin function called `name`.
+pub fn builtin(name: &str) -> Option<&'static Builtin> {
+    BUILTINS.iter().find(|builtin| builtin.name == name)
+}
@@ -1,4 +1,5 @@
-use super::value::Type;
+use super::matching::Matcher;
+use super::value::{Type, Value};
 
 #[allow(non_camel_case_types)]
 #[derive(Clone, Debug, PartialEq)]
@@ -27,6 +28,10 @@ pub enum Bytecode {
     Le,
     And,
     Or,
+    // `GetVar`, `LoadConst` and `Add` or `Sub` fused by the peephole
+    // optimizer, the args are the variable and the constant
+    AddConst,
+    SubConst,
 
     // Some built in functions
     Print,
@@ -48,7 +53,11 @@ pub enum Bytecode {
     // FUNCTION,
     // Function,
     FnCall,
+    // An `FnCall` whose value is returned right away, it reuses the frame of
+    // the function making it
+    TailCall,
     Ret,
+    Yield,
 
     Mod,
     BinaryPow,
@@ -60,16 +69,30 @@ pub enum Bytecode {
     // Conditionals
     TernaryStart,
     While,
+    // Matches the variable args[0], binding the names of the pattern, or
+    // jumps to the next arm at args[1]
+    MatchArm(Matcher),
 
     Jmp,
-    ForLoopJmp {
-        ran_once: *mut bool,
-    },
+    // A comparison fused with the `TernaryStart` or `While` after it, jumps to
+    // args[0] when it's false. The args are the ones of the instruction it
+    // replaces, so `br` and `ct` find the loop in it
+    JmpIfNot(Comparison),
     Break,
     Continue,
 
+    // Exceptions, TryStart has [catch_pc, try_end] as args and TryEnd the
+    // end of the handler
+    TryStart,
+    TryEnd,
+    Raise,
+
     // Array,
     Array,
+    // Pushes the args[0] items of the popped array, the last one on top
+    Unpack,
+    // Pushes the first item of the popped array and then the rest
+    UnpackHeadTail,
     Index,
     AddEq,
     SubEq,
@@ -77,14 +100,12 @@ pub enum Bytecode {
     DivEq,
     Not,
     Neg,
-    Every {
-        loop_end: usize,
-        index: *mut usize,
-        ran_once: *mut bool,
-        var_ptr: usize,
-    },
+    // Stores the iterator of the popped value in the hidden variable args[0]
+    IterStart,
+    // Pulls the next item into the loop variable, the args are laid out like
+    // the ones of `While`, [loop_end, loop_start]
+    Every { iter_ptr: usize, var_ptr: usize },
     Pop,
-    Push,
     Dup,
     Sqrt,
     Gcd,
@@ -99,6 +120,50 @@ pub enum Bytecode {
     ToString,
     Exit,
 
+    // Math, the last operand is the precision (or nil for the global one)
+    Sin,
+    Cos,
+    Tan,
+    Asin,
+    Acos,
+    Atan,
+    Atan2,
+    Ln,
+    Log,
+    Log2,
+    Log10,
+    Exp,
+    Hypot,
+    Pi,
+    E,
+    Prec,
+
+    // Number theory
+    IsPrime,
+    NextPrime,
+    Factor,
+    PowMod,
+    InvMod,
+    Binomial,
+    Isqrt,
+    Digits,
+    ToBase,
+    FromBase,
+
+    // Combinatorics
+    Perms,
+    Combs,
+    Powerset,
+    Product,
+
+    // Regular expressions, the argument is the constants pool slot
+    // caching the compiled pattern
+    ReMatch,
+    ReFind,
+    ReCaptures,
+    ReReplace,
+    ReSplit,
+
     // Misc
     ConcatUpTo,
 }
@@ -111,6 +176,42 @@ pub struct MethodFunction {
     pub in_built: bool,
 }
 
+/// The comparisons a conditional jump can be fused with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Comparison {
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    Eq,
+    Neq,
+}
+
+impl Comparison {
+    pub fn of(bytecode: &Bytecode) -> Option<Comparison> {
+        Some(match bytecode {
+            Bytecode::Lt => Comparison::Lt,
+            Bytecode::Gt => Comparison::Gt,
+            Bytecode::Le => Comparison::Le,
+            Bytecode::Ge => Comparison::Ge,
+            Bytecode::Eq => Comparison::Eq,
+            Bytecode::Neq => Comparison::Neq,
+            _ => return None,
+        })
+    }
+
+    pub fn compare(self, a: &Value, b: &Value) -> Option<Value> {
+        match self {
+            Comparison::Lt => a.less_than(b),
+            Comparison::Gt => a.greater_than(b),
+            Comparison::Le => a.less_than_or_equal(b),
+            Comparison::Ge => a.greater_than_or_equal(b),
+            Comparison::Eq => a.equal_to(b),
+            Comparison::Neq => a.not_equal_to(b),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Instr(pub Bytecode, pub Vec<usize>);
 
@@ -0,0 +1,733 @@
+//! The bytecode files `ShortLang build` writes, so that a program runs
+//! without being parsed and compiled again.
+//!
+//! A file starts with a header: the magic bytes, the version of the format,
+//! the version of ShortLang that wrote it, and the path and a checksum of the
+//! source it was compiled from. The compiled program follows, numbers being written as
+//! LEB128 varints, and then a checksum of everything before it. The spans of
+//! the instructions, and the source they point into, are left out of
+//! stripped files.
+
+use rug::integer::Order;
+use rug::{Float, Integer};
+use std::collections::HashMap;
+use std::ops::Range;
+
+use super::bytecode::{Bytecode, Comparison, Instr, MethodFunction};
+use super::matching::Matcher;
+use super::pattern::Pattern;
+use super::utils::FunctionData;
+use super::value::{Type, Value};
+use super::vm::VarId;
+
+const MAGIC: &[u8; 4] = b"SLC\0";
+/// Bumped whenever the layout of the file or the meaning of an opcode
+/// changes, older files can't be read then.
+const FORMAT_VERSION: u32 = 2;
+
+const TYPES: [Type; 8] = [
+    Type::Integer,
+    Type::Float,
+    Type::String,
+    Type::Bool,
+    Type::Array,
+    Type::Iter,
+    Type::Error,
+    Type::Nil,
+];
+const COMPARISONS: [Comparison; 6] = [
+    Comparison::Lt,
+    Comparison::Gt,
+    Comparison::Le,
+    Comparison::Ge,
+    Comparison::Eq,
+    Comparison::Neq,
+];
+
+/// Everything `VM::compile` produces, which is all a program needs to run.
+#[derive(Debug)]
+pub(crate) struct Program {
+    /// The source of the program, empty when the file is stripped.
+    pub src: String,
+    /// The path of the source it was compiled from, made absolute so that
+    /// it is found from any directory.
+    pub path: String,
+    pub checksum: u64,
+    /// Whether the instructions kept their spans.
+    pub spans: bool,
+    /// The precision of floats the program was compiled with.
+    pub precision: u32,
+    pub instructions: Vec<(Instr, Range<usize>)>,
+    pub constants: Vec<Value>,
+    pub functions: HashMap<String, FunctionData>,
+    pub impl_methods: HashMap<(String, Type), FunctionData>,
+    /// The variables of every scope, none of them is set before it runs.
+    pub scopes: Vec<Vec<VarId>>,
+    pub var_id_count: usize,
+    pub var_names: HashMap<VarId, String>,
+    pub variables_id: HashMap<String, VarId>,
+    /// The number of nils the compiler left on the value stack.
+    pub stack_len: usize,
+}
+
+/// The checksum of a source, which tells whether a bytecode file was
+/// compiled from it.
+pub(crate) fn checksum(src: &str) -> u64 {
+    fnv(src.as_bytes())
+}
+
+/// The FNV-1a hash of `bytes`.
+fn fnv(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+macro_rules! opcodes {
+    { $($bytecode:ident = $opcode:literal),* $(,)? } => {
+        /// The opcode of an instruction without data, `None` for the others.
+        fn simple_opcode(bytecode: &Bytecode) -> Option<u8> {
+            match bytecode {
+                $(Bytecode::$bytecode => Some($opcode),)*
+                Bytecode::Method(_)
+                | Bytecode::MatchArm(_)
+                | Bytecode::JmpIfNot(_)
+                | Bytecode::Every { .. } => None,
+            }
+        }
+
+        fn simple_bytecode(opcode: u8) -> Option<Bytecode> {
+            match opcode {
+                $($opcode => Some(Bytecode::$bytecode),)*
+                _ => None,
+            }
+        }
+    };
+}
+
+opcodes! {
+    Halt = 0, LoadConst = 1, Replace = 2, GetVar = 3, MakeVar = 4,
+    Add = 5, Sub = 6, Mul = 7, Div = 8, Eq = 9, Neq = 10, Gt = 11, Lt = 12,
+    Ge = 13, Le = 14, And = 15, Or = 16, AddConst = 17, SubConst = 18,
+    Print = 19, Println = 20, Len = 21, Input = 22, TypeOf = 23, ToInt = 24,
+    ToFloat = 25, FnCall = 26, TailCall = 27, Ret = 28, Yield = 29, Mod = 30,
+    BinaryPow = 31, Pow = 32, Inc = 33, Dec = 34, Factorial = 35,
+    TernaryStart = 36, While = 37, Jmp = 38, Break = 39, Continue = 40,
+    TryStart = 41, TryEnd = 42, Raise = 43, Array = 44, Unpack = 45,
+    UnpackHeadTail = 46, Index = 47, AddEq = 48, SubEq = 49, MulEq = 50,
+    DivEq = 51, Not = 52, Neg = 53, IterStart = 54, Pop = 55, Dup = 56,
+    Sqrt = 57, Gcd = 58, Lcm = 59, Fib = 60, Abs = 61, Round = 62,
+    Floor = 63, Ceil = 64, Rand = 65, Range = 66, ToString = 67, Exit = 68,
+    Sin = 69, Cos = 70, Tan = 71, Asin = 72, Acos = 73, Atan = 74,
+    Atan2 = 75, Ln = 76, Log = 77, Log2 = 78, Log10 = 79, Exp = 80,
+    Hypot = 81, Pi = 82, E = 83, Prec = 84, IsPrime = 85, NextPrime = 86,
+    Factor = 87, PowMod = 88, InvMod = 89, Binomial = 90, Isqrt = 91,
+    Digits = 92, ToBase = 93, FromBase = 94, Perms = 95, Combs = 96,
+    Powerset = 97, Product = 98, ReMatch = 99, ReFind = 100,
+    ReCaptures = 101, ReReplace = 102, ReSplit = 103, ConcatUpTo = 104,
+}
+
+const METHOD: u8 = 105;
+const MATCH_ARM: u8 = 106;
+const JMP_IF_NOT: u8 = 107;
+const EVERY: u8 = 108;
+
+impl Program {
+    pub fn encode(&self) -> Result<Vec<u8>, String> {
+        let mut w = Writer::default();
+        w.bytes.extend(MAGIC);
+        w.bytes.extend(FORMAT_VERSION.to_le_bytes());
+        w.str(env!("CARGO_PKG_VERSION"));
+        w.str(&self.path);
+        w.bytes.extend(self.checksum.to_le_bytes());
+        w.bool(self.spans);
+        if self.spans {
+            w.str(&self.src);
+        }
+        w.uint(self.precision as u64);
+        w.uint(self.var_id_count as u64);
+        w.uint(self.stack_len as u64);
+
+        w.uint(self.constants.len() as u64);
+        for value in &self.constants {
+            w.value(value)?;
+        }
+
+        w.uint(self.instructions.len() as u64);
+        for (Instr(bytecode, args), span) in &self.instructions {
+            w.bytecode(bytecode)?;
+            w.uint(args.len() as u64);
+            for arg in args {
+                w.uint(*arg as u64);
+            }
+            if self.spans {
+                w.uint(span.start as u64);
+                w.uint(span.len() as u64);
+            }
+        }
+
+        // sorted, so that a program is always written the same way
+        let mut functions = self.functions.values().collect::<Vec<_>>();
+        functions.sort_by(|a, b| a.name.cmp(&b.name));
+        w.uint(functions.len() as u64);
+        for function in functions {
+            w.function(function);
+        }
+        let mut methods = self.impl_methods.iter().collect::<Vec<_>>();
+        methods.sort_by_key(|((name, ty), _)| (name, ty.get_type()));
+        w.uint(methods.len() as u64);
+        for ((name, ty), function) in methods {
+            w.str(name);
+            w.ty(*ty);
+            w.function(function);
+        }
+
+        w.uint(self.scopes.len() as u64);
+        for scope in &self.scopes {
+            let mut scope = scope.clone();
+            scope.sort();
+            w.uint(scope.len() as u64);
+            for id in scope {
+                w.uint(id as u64);
+            }
+        }
+        let mut var_names = self.var_names.iter().collect::<Vec<_>>();
+        var_names.sort();
+        w.uint(var_names.len() as u64);
+        for (id, name) in var_names {
+            w.uint(*id as u64);
+            w.str(name);
+        }
+        let mut variables_id = self.variables_id.iter().collect::<Vec<_>>();
+        variables_id.sort();
+        w.uint(variables_id.len() as u64);
+        for (name, id) in variables_id {
+            w.str(name);
+            w.uint(*id as u64);
+        }
+
+        let hash = fnv(&w.bytes);
+        w.bytes.extend(hash.to_le_bytes());
+        Ok(w.bytes)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Program, String> {
+        if !bytes.starts_with(MAGIC) {
+            return Err("This is not a ShortLang bytecode file".to_string());
+        }
+        let mut r = Reader { bytes, pos: 4 };
+        let format = u32::from_le_bytes(r.take(4)?.try_into().unwrap());
+        let version = r.str()?;
+        if format != FORMAT_VERSION || version != env!("CARGO_PKG_VERSION") {
+            return Err(format!(
+                "This file was built by ShortLang {version}, build it again with {}",
+                env!("CARGO_PKG_VERSION")
+            ));
+        }
+        // the rest is only read once it is known to be intact
+        let Some(body) = bytes.len().checked_sub(8).filter(|end| *end >= r.pos) else {
+            return Err("The bytecode file ends too early".to_string());
+        };
+        let hash = u64::from_le_bytes(bytes[body..].try_into().unwrap());
+        if fnv(&bytes[..body]) != hash {
+            return Err("The bytecode file is corrupted".to_string());
+        }
+        r.bytes = &bytes[..body];
+
+        let path = r.str()?;
+        let checksum = u64::from_le_bytes(r.take(8)?.try_into().unwrap());
+        let spans = r.bool()?;
+        let src = match spans {
+            true => r.str()?,
+            false => String::new(),
+        };
+        let precision = r.u32()?;
+        let var_id_count = r.usize()?;
+        let stack_len = r.usize()?;
+
+        let constants = r.list(Reader::value)?;
+        let instructions = r.list(|r| {
+            let bytecode = r.bytecode()?;
+            let args = r.list(Reader::usize)?;
+            let span = match spans {
+                true => {
+                    let start = r.usize()?;
+                    start..start + r.usize()?
+                }
+                false => 0..0,
+            };
+            Ok((Instr(bytecode, args), span))
+        })?;
+
+        let functions = r
+            .list(Reader::function)?
+            .into_iter()
+            .map(|function| (function.name.clone(), function))
+            .collect();
+        let impl_methods = r
+            .list(|r| Ok(((r.str()?, r.ty()?), r.function()?)))?
+            .into_iter()
+            .collect();
+        let scopes = r.list(|r| r.list(Reader::u32))?;
+        let var_names = r.list(|r| Ok((r.u32()?, r.str()?)))?.into_iter().collect();
+        let variables_id = r.list(|r| Ok((r.str()?, r.u32()?)))?.into_iter().collect();
+
+        if r.pos != r.bytes.len() {
+            return Err(r.corrupted());
+        }
+        Ok(Program {
+            src,
+            path,
+            checksum,
+            spans,
+            precision,
+            instructions,
+            constants,
+            functions,
+            impl_methods,
+            scopes,
+            var_id_count,
+            var_names,
+            variables_id,
+            stack_len,
+        })
+    }
+}
+
+#[derive(Default)]
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn uint(&mut self, mut n: u64) {
+        loop {
+            let byte = (n & 0x7f) as u8;
+            n >>= 7;
+            if n == 0 {
+                self.bytes.push(byte);
+                return;
+            }
+            self.bytes.push(byte | 0x80);
+        }
+    }
+
+    fn bool(&mut self, b: bool) {
+        self.bytes.push(b as u8);
+    }
+
+    fn str(&mut self, s: &str) {
+        self.uint(s.len() as u64);
+        self.bytes.extend(s.as_bytes());
+    }
+
+    fn ty(&mut self, ty: Type) {
+        self.bytes
+            .push(TYPES.iter().position(|t| *t == ty).unwrap() as u8);
+    }
+
+    fn value(&mut self, value: &Value) -> Result<(), String> {
+        match value {
+            Value::Nil => self.bytes.push(0),
+            Value::Int(i) => {
+                self.bytes.push(1);
+                self.bool(*i < 0);
+                let mut digits = vec![0; i.significant_digits::<u8>()];
+                i.write_digits(&mut digits, Order::Lsf);
+                self.uint(digits.len() as u64);
+                self.bytes.extend(digits);
+            }
+            // in hexadecimal, the digits are exactly the ones of the float
+            Value::Float(f) => {
+                self.bytes.push(2);
+                self.uint(f.prec() as u64);
+                self.str(&format!("{f:x}"));
+            }
+            Value::String(s) => {
+                self.bytes.push(3);
+                self.str(s);
+            }
+            Value::Bool(b) => {
+                self.bytes.push(4);
+                self.bool(*b);
+            }
+            Value::Array(items) => {
+                self.bytes.push(5);
+                self.uint(items.len() as u64);
+                for item in items {
+                    self.value(item)?;
+                }
+            }
+            Value::Regex(pattern) => {
+                self.bytes.push(6);
+                self.str(pattern.as_str());
+            }
+            Value::Iter(_) | Value::Error(_) => {
+                return Err(format!(
+                    "A constant of type {} can't be written to bytecode",
+                    value.get_type()
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    fn matcher(&mut self, matcher: &Matcher) -> Result<(), String> {
+        match matcher {
+            Matcher::Any => self.bytes.push(0),
+            Matcher::Bind(id) => {
+                self.bytes.push(1);
+                self.uint(*id as u64);
+            }
+            Matcher::Value(value) => {
+                self.bytes.push(2);
+                self.value(value)?;
+            }
+            Matcher::Range(lo, hi) => {
+                self.bytes.push(3);
+                self.value(lo)?;
+                self.value(hi)?;
+            }
+            Matcher::Type(ty) => {
+                self.bytes.push(4);
+                self.ty(*ty);
+            }
+            Matcher::Array(items, rest) => {
+                self.bytes.push(5);
+                self.matchers(items)?;
+                self.bool(rest.is_some());
+                if let Some((id, after)) = rest {
+                    self.uint(*id as u64);
+                    self.matchers(after)?;
+                }
+            }
+            Matcher::HeadTail(head, tail) => {
+                self.bytes.push(6);
+                self.matcher(head)?;
+                self.matcher(tail)?;
+            }
+            Matcher::Or(matchers) => {
+                self.bytes.push(7);
+                self.matchers(matchers)?;
+            }
+            Matcher::Var(id) => {
+                self.bytes.push(8);
+                self.uint(*id as u64);
+            }
+        }
+        Ok(())
+    }
+
+    fn matchers(&mut self, matchers: &[Matcher]) -> Result<(), String> {
+        self.uint(matchers.len() as u64);
+        matchers
+            .iter()
+            .try_for_each(|matcher| self.matcher(matcher))
+    }
+
+    fn bytecode(&mut self, bytecode: &Bytecode) -> Result<(), String> {
+        if let Some(opcode) = simple_opcode(bytecode) {
+            self.bytes.push(opcode);
+            return Ok(());
+        }
+
+        match bytecode {
+            Bytecode::Method(method) => {
+                self.bytes.push(METHOD);
+                self.str(&method.name);
+                self.uint(method.on_types.len() as u64);
+                for ty in &method.on_types {
+                    self.ty(*ty);
+                }
+                self.uint(method.num_args as u64);
+                self.bool(method.in_built);
+            }
+            Bytecode::MatchArm(matcher) => {
+                self.bytes.push(MATCH_ARM);
+                self.matcher(matcher)?;
+            }
+            Bytecode::JmpIfNot(comparison) => {
+                self.bytes.push(JMP_IF_NOT);
+                self.bytes
+                    .push(COMPARISONS.iter().position(|c| c == comparison).unwrap() as u8);
+            }
+            Bytecode::Every { iter_ptr, var_ptr } => {
+                self.bytes.push(EVERY);
+                self.uint(*iter_ptr as u64);
+                self.uint(*var_ptr as u64);
+            }
+            _ => unreachable!("{bytecode} has an opcode"),
+        }
+        Ok(())
+    }
+
+    fn function(&mut self, function: &FunctionData) {
+        self.str(&function.name);
+        self.uint(function.parameters.len() as u64);
+        for (name, id) in &function.parameters {
+            self.str(name);
+            self.uint(*id as u64);
+        }
+        self.uint(function.instruction_range.start as u64);
+        self.uint(function.instruction_range.end as u64);
+        self.uint(function.scope_idx as u64);
+        self.bool(function.returns);
+        self.bool(function.generator);
+        self.uint(function.required as u64);
+        self.uint(function.entry_points.len() as u64);
+        for entry_point in &function.entry_points {
+            self.uint(*entry_point as u64);
+        }
+        self.bool(function.variadic);
+        self.bool(function.memo.is_some());
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn corrupted(&self) -> String {
+        format!("The bytecode file is corrupted at byte {}", self.pos)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len());
+        let Some(end) = end else {
+            return Err("The bytecode file ends too early".to_string());
+        };
+        let bytes = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn uint(&mut self) -> Result<u64, String> {
+        let mut n = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            n |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err(self.corrupted())
+    }
+
+    fn usize(&mut self) -> Result<usize, String> {
+        let n = self.uint()?;
+        n.try_into().map_err(|_| self.corrupted())
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let n = self.uint()?;
+        n.try_into().map_err(|_| self.corrupted())
+    }
+
+    fn bool(&mut self) -> Result<bool, String> {
+        match self.byte()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(self.corrupted()),
+        }
+    }
+
+    fn str(&mut self) -> Result<String, String> {
+        let len = self.usize()?;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| self.corrupted())
+    }
+
+    /// A length followed by that many items.
+    fn list<T>(
+        &mut self,
+        mut item: impl FnMut(&mut Self) -> Result<T, String>,
+    ) -> Result<Vec<T>, String> {
+        let len = self.usize()?;
+        // every item takes at least a byte, a corrupted length can't
+        // allocate more than the file
+        let mut items = Vec::with_capacity(len.min(self.bytes.len() - self.pos));
+        for _ in 0..len {
+            items.push(item(self)?);
+        }
+        Ok(items)
+    }
+
+    fn ty(&mut self) -> Result<Type, String> {
+        let tag = self.byte()?;
+        TYPES
+            .get(tag as usize)
+            .copied()
+            .ok_or_else(|| self.corrupted())
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        Ok(match self.byte()? {
+            0 => Value::Nil,
+            1 => {
+                let negative = self.bool()?;
+                let len = self.usize()?;
+                let i = Integer::from_digits(self.take(len)?, Order::Lsf);
+                Value::Int(if negative { -i } else { i })
+            }
+            2 => {
+                let prec = self.u32()?;
+                let digits = self.str()?;
+                match Float::parse_radix(&digits, 16) {
+                    Ok(f) if (rug::float::prec_min()..=rug::float::prec_max()).contains(&prec) => {
+                        Value::Float(Float::with_val(prec, f))
+                    }
+                    _ => return Err(self.corrupted()),
+                }
+            }
+            3 => Value::String(self.str()?),
+            4 => Value::Bool(self.bool()?),
+            5 => Value::Array(self.list(Reader::value)?),
+            6 => {
+                let pattern = self.str()?;
+                Value::Regex(Pattern::new(&pattern).map_err(|_| self.corrupted())?)
+            }
+            _ => return Err(self.corrupted()),
+        })
+    }
+
+    fn matcher(&mut self) -> Result<Matcher, String> {
+        Ok(match self.byte()? {
+            0 => Matcher::Any,
+            1 => Matcher::Bind(self.u32()?),
+            2 => Matcher::Value(self.value()?),
+            3 => Matcher::Range(self.value()?, self.value()?),
+            4 => Matcher::Type(self.ty()?),
+            5 => {
+                let items = self.list(Reader::matcher)?;
+                let rest = match self.bool()? {
+                    true => Some((self.u32()?, self.list(Reader::matcher)?)),
+                    false => None,
+                };
+                Matcher::Array(items, rest)
+            }
+            6 => Matcher::HeadTail(Box::new(self.matcher()?), Box::new(self.matcher()?)),
+            7 => Matcher::Or(self.list(Reader::matcher)?),
+            8 => Matcher::Var(self.u32()?),
+            _ => return Err(self.corrupted()),
+        })
+    }
+
+    fn bytecode(&mut self) -> Result<Bytecode, String> {
+        let opcode = self.byte()?;
+        if let Some(bytecode) = simple_bytecode(opcode) {
+            return Ok(bytecode);
+        }
+
+        Ok(match opcode {
+            METHOD => Bytecode::Method(MethodFunction {
+                name: self.str()?,
+                on_types: self.list(Reader::ty)?,
+                num_args: self.usize()?,
+                in_built: self.bool()?,
+            }),
+            MATCH_ARM => Bytecode::MatchArm(self.matcher()?),
+            JMP_IF_NOT => {
+                let tag = self.byte()?;
+                let comparison = COMPARISONS
+                    .get(tag as usize)
+                    .ok_or_else(|| self.corrupted())?;
+                Bytecode::JmpIfNot(*comparison)
+            }
+            EVERY => Bytecode::Every {
+                iter_ptr: self.usize()?,
+                var_ptr: self.usize()?,
+            },
+            _ => return Err(self.corrupted()),
+        })
+    }
+
+    fn function(&mut self) -> Result<FunctionData, String> {
+        let name = self.str()?;
+        let parameters = self.list(|r| Ok((r.str()?, r.u32()?)))?;
+        let start = self.usize()?;
+        let instruction_range = start..self.usize()?;
+        Ok(FunctionData {
+            name,
+            parameters,
+            instruction_range,
+            scope_idx: self.usize()?,
+            returns: self.bool()?,
+            generator: self.bool()?,
+            required: self.usize()?,
+            entry_points: self.list(Reader::usize)?,
+            variadic: self.bool()?,
+            memo: self.bool()?.then(HashMap::new),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::PParser;
+    use crate::vm::VM;
+
+    fn compile(src: &str, spans: bool) -> Program {
+        let ast = PParser::new(src, crate::tokenize(src)).parse();
+        let mut vm = VM::new(src, ast);
+        vm.compile();
+        vm.optimize();
+        vm.program("/src/prog.sl", spans)
+    }
+
+    const SRC: &str = "@f n: n < 2 ? n : f(n - 1) + f(n - 2)
+g a b = 2 ...c: [a, b, c]
+x = [1.5, 2 ** 70, \"s\", nil]
+mc x { [a, ...r]: $r\n _: $0 }
+ev rng(3) { $re(\"ab\", \"b+\") }
+impl int { dbl: self * 2 }";
+
+    #[test]
+    fn test_round_trip() {
+        for spans in [true, false] {
+            let program = compile(SRC, spans);
+            let bytes = program.encode().unwrap();
+            let decoded = Program::decode(&bytes).unwrap();
+
+            assert_eq!(decoded.encode().unwrap(), bytes);
+            assert_eq!(
+                format!("{:?}", decoded.instructions),
+                format!("{:?}", program.instructions)
+            );
+            assert_eq!(decoded.constants, program.constants);
+            assert_eq!(decoded.functions["f"].memo, Some(HashMap::new()));
+            assert_eq!(decoded.functions["g"].entry_points.len(), 2);
+            assert_eq!(decoded.impl_methods.len(), 1);
+            assert_eq!(decoded.path, "/src/prog.sl");
+            assert_eq!(decoded.checksum, checksum(SRC));
+            assert_eq!(decoded.src, if spans { SRC } else { "" });
+        }
+    }
+
+    #[test]
+    fn test_invalid_files() {
+        let bytes = compile(SRC, true).encode().unwrap();
+
+        let mut corrupted = bytes.clone();
+        corrupted[bytes.len() / 2] ^= 1;
+        assert!(Program::decode(&corrupted).is_err());
+        assert!(Program::decode(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Program::decode(&bytes[..6]).is_err());
+        assert!(Program::decode(b"x = 1").is_err());
+
+        let mut newer = bytes.clone();
+        newer[4] += 1;
+        assert!(Program::decode(&newer)
+            .unwrap_err()
+            .contains("build it again"));
+    }
+}
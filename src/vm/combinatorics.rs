@@ -0,0 +1,215 @@
+use super::value::Value;
+
+/// The `k` long permutations of `pool`, in the order of the indices of the
+/// picked items.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Permutations {
+    pool: Vec<Value>,
+    k: usize,
+    indices: Vec<usize>,
+    cycles: Vec<usize>,
+    first: bool,
+    done: bool,
+}
+
+impl Permutations {
+    pub fn new(pool: Vec<Value>, k: usize) -> Self {
+        let n = pool.len();
+        Self {
+            indices: (0..n).collect(),
+            cycles: (n.saturating_sub(k) + 1..=n).rev().collect(),
+            done: k > n,
+            first: true,
+            pool,
+            k,
+        }
+    }
+
+    fn current(&self) -> Value {
+        Value::Array(
+            self.indices[..self.k]
+                .iter()
+                .map(|&i| self.pool[i].clone())
+                .collect(),
+        )
+    }
+}
+
+impl Iterator for Permutations {
+    type Item = Value;
+
+    fn next(&mut self) -> Option<Value> {
+        if self.done {
+            return None;
+        }
+
+        if self.first {
+            self.first = false;
+            return Some(self.current());
+        }
+
+        let n = self.pool.len();
+        for i in (0..self.k).rev() {
+            self.cycles[i] -= 1;
+            if self.cycles[i] == 0 {
+                // rotate the picked item to the back
+                let index = self.indices.remove(i);
+                self.indices.push(index);
+                self.cycles[i] = n - i;
+            } else {
+                let j = self.cycles[i];
+                self.indices.swap(i, n - j);
+                return Some(self.current());
+            }
+        }
+
+        self.done = true;
+        None
+    }
+}
+
+/// The `k` long combinations of `pool`, keeping the order of the items.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Combinations {
+    pool: Vec<Value>,
+    indices: Vec<usize>,
+    first: bool,
+    done: bool,
+}
+
+impl Combinations {
+    pub fn new(pool: Vec<Value>, k: usize) -> Self {
+        Self {
+            indices: (0..k).collect(),
+            done: k > pool.len(),
+            first: true,
+            pool,
+        }
+    }
+
+    fn current(&self) -> Value {
+        Value::Array(self.indices.iter().map(|&i| self.pool[i].clone()).collect())
+    }
+}
+
+impl Iterator for Combinations {
+    type Item = Value;
+
+    fn next(&mut self) -> Option<Value> {
+        if self.done {
+            return None;
+        }
+
+        if self.first {
+            self.first = false;
+            return Some(self.current());
+        }
+
+        let (n, k) = (self.pool.len(), self.indices.len());
+        let Some(i) = (0..k).rev().find(|&i| self.indices[i] != i + n - k) else {
+            self.done = true;
+            return None;
+        };
+
+        self.indices[i] += 1;
+        for j in i + 1..k {
+            self.indices[j] = self.indices[j - 1] + 1;
+        }
+
+        Some(self.current())
+    }
+}
+
+/// Every subset of `pool`, from the smallest to the largest.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Powerset {
+    pool: Vec<Value>,
+    combs: Combinations,
+    k: usize,
+}
+
+impl Powerset {
+    pub fn new(pool: Vec<Value>) -> Self {
+        Self {
+            combs: Combinations::new(pool.clone(), 0),
+            pool,
+            k: 0,
+        }
+    }
+}
+
+impl Iterator for Powerset {
+    type Item = Value;
+
+    fn next(&mut self) -> Option<Value> {
+        loop {
+            if let Some(subset) = self.combs.next() {
+                return Some(subset);
+            }
+
+            if self.k == self.pool.len() {
+                return None;
+            }
+
+            self.k += 1;
+            self.combs = Combinations::new(self.pool.clone(), self.k);
+        }
+    }
+}
+
+/// The cartesian product of `pools`, the last pool changing the fastest.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Product {
+    pools: Vec<Vec<Value>>,
+    indices: Vec<usize>,
+    first: bool,
+    done: bool,
+}
+
+impl Product {
+    pub fn new(pools: Vec<Vec<Value>>) -> Self {
+        Self {
+            indices: vec![0; pools.len()],
+            done: pools.iter().any(|pool| pool.is_empty()),
+            first: true,
+            pools,
+        }
+    }
+
+    fn current(&self) -> Value {
+        Value::Array(
+            self.indices
+                .iter()
+                .zip(&self.pools)
+                .map(|(&i, pool)| pool[i].clone())
+                .collect(),
+        )
+    }
+}
+
+impl Iterator for Product {
+    type Item = Value;
+
+    fn next(&mut self) -> Option<Value> {
+        if self.done {
+            return None;
+        }
+
+        if self.first {
+            self.first = false;
+            return Some(self.current());
+        }
+
+        for i in (0..self.pools.len()).rev() {
+            self.indices[i] += 1;
+            if self.indices[i] < self.pools[i].len() {
+                return Some(self.current());
+            }
+
+            self.indices[i] = 0;
+        }
+
+        self.done = true;
+        None
+    }
+}
@@ -0,0 +1,217 @@
+use std::collections::BTreeSet;
+
+pub(crate) const HELP: &str = "\
+c, continue       run until the next breakpoint
+s, step           run to the next line, going into calls
+n, next           run to the next line of this function
+o, out            run until the current function returns
+b, break [LINE]   set a breakpoint, or list them without a line
+d, delete LINE    remove a breakpoint
+bt, backtrace     show the calls that led here
+v, vars           show the variables of the current function
+st, stack         show the value stack
+p, print EXPR     evaluate an expression in the current function
+l, list           show the code around the current line
+q, quit           stop the program
+h, help           show this list
+An empty line repeats the last command.";
+
+/// What the program runs until it pauses again.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Mode {
+    /// Until a breakpoint.
+    Continue,
+    /// Until the next line, in whatever function it is.
+    Step,
+    /// Until the next line in a call at most this deep.
+    Next(usize),
+    /// Until the call stack is less deep than this.
+    Out(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Command {
+    Continue,
+    Step,
+    Next,
+    Out,
+    Break(Option<usize>),
+    Delete(usize),
+    Backtrace,
+    Vars,
+    Stack,
+    Print(String),
+    List,
+    Quit,
+    Help,
+}
+
+impl Command {
+    pub(crate) fn parse(line: &str) -> Result<Command, String> {
+        let line = line.trim();
+        let (name, arg) = line.split_once(' ').unwrap_or((line, ""));
+        let arg = arg.trim();
+        let line_arg = || {
+            arg.parse::<usize>()
+                .ok()
+                .filter(|&line| line > 0)
+                .ok_or_else(|| format!("Expected a line number, found `{arg}`"))
+        };
+
+        Ok(match name {
+            "c" | "continue" => Command::Continue,
+            "s" | "step" => Command::Step,
+            "n" | "next" => Command::Next,
+            "o" | "out" => Command::Out,
+            "b" | "break" if arg.is_empty() => Command::Break(None),
+            "b" | "break" => Command::Break(Some(line_arg()?)),
+            "d" | "delete" => Command::Delete(line_arg()?),
+            "bt" | "backtrace" => Command::Backtrace,
+            "v" | "vars" => Command::Vars,
+            "st" | "stack" => Command::Stack,
+            "p" | "print" if arg.is_empty() => return Err("Expected an expression".to_string()),
+            "p" | "print" => Command::Print(arg.to_string()),
+            "l" | "list" => Command::List,
+            "q" | "quit" => Command::Quit,
+            "h" | "help" => Command::Help,
+            _ => return Err(format!("Unknown command `{name}`, `h` lists them")),
+        })
+    }
+}
+
+/// The state of `--debug`, the VM asks it before every instruction whether
+/// to pause.
+pub(crate) struct Debugger {
+    /// The lines some instruction starts on, where a breakpoint can be.
+    code_lines: BTreeSet<usize>,
+    pub(crate) breakpoints: BTreeSet<usize>,
+    pub(crate) mode: Mode,
+    /// The instruction last run at every depth of the call stack, with its
+    /// line.
+    positions: Vec<Option<(usize, usize)>>,
+    pub(crate) last_command: Option<Command>,
+}
+
+impl Debugger {
+    /// Pauses at the first line, or at the first breakpoint if any are given.
+    pub(crate) fn new(breakpoints: Vec<usize>) -> Self {
+        Self {
+            code_lines: BTreeSet::new(),
+            mode: match breakpoints.is_empty() {
+                true => Mode::Step,
+                false => Mode::Continue,
+            },
+            breakpoints: breakpoints.into_iter().collect(),
+            positions: vec![],
+            last_command: None,
+        }
+    }
+
+    /// Learns the lines the compiled instructions are on, moving the
+    /// breakpoints that were given on lines without any.
+    pub(crate) fn load(&mut self, code_lines: impl Iterator<Item = usize>) {
+        self.code_lines = code_lines.collect();
+
+        let breakpoints = std::mem::take(&mut self.breakpoints);
+        for line in breakpoints {
+            if let Err(message) = self.add_breakpoint(line) {
+                println!("{message}");
+            }
+        }
+    }
+
+    /// Sets a breakpoint on `line`, or on the first line with code after it,
+    /// returning the line it was set on.
+    pub(crate) fn add_breakpoint(&mut self, line: usize) -> Result<usize, String> {
+        match self.code_lines.range(line..).next() {
+            Some(&code_line) => {
+                self.breakpoints.insert(code_line);
+                Ok(code_line)
+            }
+            None => Err(format!("No code on line {line} or after it")),
+        }
+    }
+
+    /// Records that the instruction `pc` on `line` is about to run in a call
+    /// `depth` deep, returning whether to pause before it. Instructions
+    /// without a line, like returns and the jumps back of loops, never pause.
+    pub(crate) fn arrive(&mut self, pc: usize, line: Option<usize>, depth: usize) -> bool {
+        let Some(line) = line else {
+            return false;
+        };
+
+        // a new call starts on a new line, a returning one is forgotten
+        self.positions.resize(depth + 1, None);
+        // going back, like in the next iteration of a loop, starts the line
+        // again
+        let new_line = match self.positions[depth] {
+            Some((last_pc, last_line)) => line != last_line || pc <= last_pc,
+            None => true,
+        };
+        self.positions[depth] = Some((pc, line));
+
+        match self.mode {
+            Mode::Out(out) if depth < out => true,
+            _ if !new_line => false,
+            _ if self.breakpoints.contains(&line) => true,
+            Mode::Step => true,
+            Mode::Next(next) => depth <= next,
+            Mode::Continue | Mode::Out(_) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_debugger_stepping() {
+        // f x: {
+        //     x + 1
+        // }
+        // $f(1)
+        // $2
+        let mut debugger = Debugger::new(vec![]);
+        debugger.load([2, 4, 5].into_iter());
+
+        // `$f(1)` calls into line 2 and returns to line 4
+        let run = [(5, 4, 0), (1, 2, 1), (2, 2, 1), (6, 4, 0)];
+        let arrive = |debugger: &mut Debugger, &(pc, line, depth): &(usize, usize, usize)| {
+            debugger.arrive(pc, Some(line), depth)
+        };
+
+        assert!(arrive(&mut debugger, &run[0]));
+        assert!(arrive(&mut debugger, &run[1]));
+        assert!(!arrive(&mut debugger, &run[2]));
+        // back on line 4 after the call
+        assert!(!arrive(&mut debugger, &run[3]));
+        assert!(arrive(&mut debugger, &(7, 5, 0)));
+
+        debugger.positions.clear();
+        debugger.mode = Mode::Next(0);
+        assert!(arrive(&mut debugger, &run[0]));
+        assert!(!arrive(&mut debugger, &run[1]));
+
+        debugger.mode = Mode::Out(1);
+        assert!(!arrive(&mut debugger, &run[2]));
+        assert!(arrive(&mut debugger, &run[3]));
+
+        // the next line with code
+        debugger.mode = Mode::Continue;
+        assert_eq!(debugger.add_breakpoint(3), Ok(4));
+        debugger.positions.clear();
+        assert!(arrive(&mut debugger, &run[0]));
+        assert_eq!(
+            debugger.add_breakpoint(6),
+            Err("No code on line 6 or after it".to_string())
+        );
+
+        assert_eq!(Command::parse(" b 3 "), Ok(Command::Break(Some(3))));
+        assert_eq!(
+            Command::parse("p x + 1"),
+            Ok(Command::Print("x + 1".to_string()))
+        );
+        assert!(Command::parse("d x").is_err());
+    }
+}
@@ -0,0 +1,218 @@
+use rug::{Complete, Integer};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use super::combinatorics::{Combinations, Permutations, Powerset, Product};
+use super::value::Value;
+
+/// A lazily evaluated sequence, which `ev` pulls one item at a time.
+///
+/// Iterating always happens on a clone, so a sequence stored in a variable
+/// can be looped over any number of times.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Iter {
+    Array { items: Vec<Value>, index: usize },
+    Chars { string: String, offset: usize },
+    Range(RangeIter),
+    Permutations(Permutations),
+    Combinations(Combinations),
+    Powerset(Powerset),
+    Product(Product),
+    // Only the VM can resume it, see `VM::resume_generator`
+    Generator(Generator),
+}
+
+impl Iter {
+    /// The iterator `ev` walks for `value`, if it is iterable at all.
+    pub fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Array(items) => Some(Self::Array {
+                items: items.clone(),
+                index: 0,
+            }),
+            Value::String(string) => Some(Self::Chars {
+                string: string.clone(),
+                offset: 0,
+            }),
+            Value::Iter(iter) => Some(iter.as_ref().clone()),
+            _ => None,
+        }
+    }
+
+    pub fn is_range(&self) -> bool {
+        matches!(self, Self::Range(_))
+    }
+
+    pub fn is_infinite(&self) -> bool {
+        matches!(self, Self::Range(range) if range.end.is_none())
+    }
+
+    /// Whether the items can be collected without running any code.
+    pub fn is_collectable(&self) -> bool {
+        !self.is_infinite() && !matches!(self, Self::Generator(_))
+    }
+
+    /// The number of items left, or `None` for infinite ranges and generators.
+    pub fn len(&self) -> Option<Integer> {
+        match self {
+            Self::Range(range) => range.len(),
+            Self::Generator(_) => None,
+            Self::Array { items, index } => Some(Integer::from(items.len() - index)),
+            _ => Some(Integer::from(self.clone().count())),
+        }
+    }
+
+    /// Collects the remaining items, `None` for infinite ranges and
+    /// generators.
+    pub fn to_vec(&self) -> Option<Vec<Value>> {
+        self.is_collectable().then(|| self.clone().collect())
+    }
+}
+
+impl Iterator for Iter {
+    type Item = Value;
+
+    fn next(&mut self) -> Option<Value> {
+        match self {
+            Self::Array { items, index } => {
+                let item = items.get(*index)?.clone();
+                *index += 1;
+                Some(item)
+            }
+            Self::Chars { string, offset } => {
+                let c = string[*offset..].chars().next()?;
+                *offset += c.len_utf8();
+                Some(Value::String(c.to_string()))
+            }
+            Self::Range(range) => range.next().map(Value::Int),
+            Self::Permutations(perms) => perms.next(),
+            Self::Combinations(combs) => combs.next(),
+            Self::Powerset(subsets) => subsets.next(),
+            Self::Product(product) => product.next(),
+            Self::Generator(_) => unreachable!("generators are resumed by the VM"),
+        }
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Value> {
+        match self {
+            Self::Array { index, .. } => {
+                *index += n;
+                self.next()
+            }
+            Self::Range(range) => {
+                range.next += Integer::from(n) * &range.step;
+                self.next()
+            }
+            _ => {
+                for _ in 0..n {
+                    self.next()?;
+                }
+                self.next()
+            }
+        }
+    }
+}
+
+// Sequences have no meaningful order, only arrays do
+impl PartialOrd for Iter {
+    fn partial_cmp(&self, _: &Self) -> Option<Ordering> {
+        None
+    }
+}
+
+impl std::fmt::Display for Iter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Range(range) if self.is_infinite() => {
+                let sign = if range.step < 0 { "-" } else { "" };
+                write!(f, "rng({}, {sign}inf, {})", range.next, range.step)
+            }
+            Self::Generator(generator) => write!(f, "<generator {}>", generator.name),
+            _ => write!(f, "{}", Value::Array(self.to_vec().unwrap_or_default())),
+        }
+    }
+}
+
+/// The integers from `next` up to (but excluding) `end` by `step`, counting
+/// down when the step is negative. Without an end the range never stops.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RangeIter {
+    next: Integer,
+    end: Option<Integer>,
+    step: Integer,
+}
+
+impl RangeIter {
+    /// `step` must not be zero.
+    pub fn new(start: Integer, end: Option<Integer>, step: Integer) -> Self {
+        Self {
+            next: start,
+            end,
+            step,
+        }
+    }
+
+    pub fn len(&self) -> Option<Integer> {
+        let end = self.end.as_ref()?;
+        let (distance, step) = if self.step > 0 {
+            ((end - &self.next).complete(), self.step.clone())
+        } else {
+            ((&self.next - end).complete(), (-&self.step).complete())
+        };
+
+        if distance <= 0 {
+            return Some(Integer::new());
+        }
+
+        Some((distance + &step - 1u32) / step)
+    }
+}
+
+impl Iterator for RangeIter {
+    type Item = Integer;
+
+    fn next(&mut self) -> Option<Integer> {
+        if let Some(end) = &self.end {
+            let done = if self.step > 0 {
+                self.next >= *end
+            } else {
+                self.next <= *end
+            };
+
+            if done {
+                return None;
+            }
+        }
+
+        let item = self.next.clone();
+        self.next += &self.step;
+        Some(item)
+    }
+}
+
+/// The suspended frame of a call to a function containing `yd`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Generator {
+    pub name: String,
+    pub scope_idx: usize,
+    /// Where the function continues when it is resumed.
+    pub pc: usize,
+    /// The locals of the function, saved when it yields.
+    pub variables: HashMap<u32, Value>,
+    /// The last yielded value, not yet taken by the loop.
+    pub yielded: Option<Value>,
+    pub done: bool,
+}
+
+impl Generator {
+    pub fn new(name: String, scope_idx: usize, pc: usize, variables: HashMap<u32, Value>) -> Self {
+        Self {
+            name,
+            scope_idx,
+            pc,
+            variables,
+            yielded: None,
+            done: false,
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use super::value::{Type, Value};
+
+/// A compiled `mc` pattern, the names it binds are replaced by the ids of
+/// their variables.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Matcher {
+    Any,
+    Bind(u32),
+    // Equal to the value of the variable
+    Var(u32),
+    Value(Value),
+    // Both ends included
+    Range(Value, Value),
+    Type(Type),
+    // The items before the rest, the rest and the items after it
+    Array(Vec<Matcher>, Option<(u32, Vec<Matcher>)>),
+    HeadTail(Box<Matcher>, Box<Matcher>),
+    Or(Vec<Matcher>),
+}
+
+impl Matcher {
+    /// Whether `value` matches, pushing the values of the names it binds to
+    /// `bindings`. Nothing is pushed when it doesn't match. `variable` gives
+    /// the values of the variables it compares against.
+    pub fn matches(
+        &self,
+        value: &Value,
+        variable: &dyn Fn(u32) -> Value,
+        bindings: &mut Vec<(u32, Value)>,
+    ) -> bool {
+        let bound = bindings.len();
+        let matched = self.match_value(value, variable, bindings);
+        if !matched {
+            bindings.truncate(bound);
+        }
+
+        matched
+    }
+
+    fn match_value(
+        &self,
+        value: &Value,
+        variable: &dyn Fn(u32) -> Value,
+        bindings: &mut Vec<(u32, Value)>,
+    ) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Bind(id) => {
+                bindings.push((*id, value.clone()));
+                true
+            }
+            Self::Var(id) => value.equal_to(&variable(*id)) == Some(Value::Bool(true)),
+            Self::Value(expected) => value.equal_to(expected) == Some(Value::Bool(true)),
+            Self::Range(lo, hi) => {
+                lo.less_than_or_equal(value) == Some(Value::Bool(true))
+                    && value.less_than_or_equal(hi) == Some(Value::Bool(true))
+            }
+            Self::Type(t) => t.is_same_type(value),
+            Self::Array(before, rest) => {
+                let Some(items) = Self::items(value) else {
+                    return false;
+                };
+
+                let Some((id, after)) = rest else {
+                    return items.len() == before.len()
+                        && before
+                            .iter()
+                            .zip(&items)
+                            .all(|(matcher, item)| matcher.matches(item, variable, bindings));
+                };
+
+                if items.len() < before.len() + after.len() {
+                    return false;
+                }
+
+                let rest_end = items.len() - after.len();
+                before
+                    .iter()
+                    .zip(&items)
+                    .all(|(matcher, item)| matcher.matches(item, variable, bindings))
+                    && after
+                        .iter()
+                        .zip(&items[rest_end..])
+                        .all(|(matcher, item)| matcher.matches(item, variable, bindings))
+                    && {
+                        let rest = items[before.len()..rest_end].to_vec();
+                        bindings.push((*id, Value::Array(rest)));
+                        true
+                    }
+            }
+            Self::HeadTail(head, tail) => {
+                let Some(items) = Self::items(value) else {
+                    return false;
+                };
+
+                let Some((first, others)) = items.split_first() else {
+                    return false;
+                };
+
+                // The tail of a string is still a string
+                let others = match value {
+                    Value::String(s) => Value::String(s.chars().skip(1).collect()),
+                    _ => Value::Array(others.to_vec()),
+                };
+
+                head.matches(first, variable, bindings) && tail.matches(&others, variable, bindings)
+            }
+            Self::Or(alternatives) => alternatives
+                .iter()
+                .any(|matcher| matcher.matches(value, variable, bindings)),
+        }
+    }
+
+    fn items(value: &Value) -> Option<Vec<Value>> {
+        value.as_array().map(|items| items.into_owned())
+    }
+}
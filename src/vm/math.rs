@@ -0,0 +1,57 @@
+use rug::float::{prec_min, Constant};
+use rug::{Float, Integer};
+
+use super::bytecode::Bytecode::{self, *};
+use super::value::Value;
+
+pub fn to_float(value: &Value, prec: u32) -> Option<Float> {
+    match value {
+        Value::Int(i) => Some(Float::with_val(prec, i)),
+        Value::Float(f) => Some(Float::with_val(prec, f)),
+        _ => None,
+    }
+}
+
+/// The highest precision floats can have, about 20000 digits. rug allows far
+/// more, but computing anything with it would take forever.
+pub const MAX_PRECISION: u32 = 1 << 16;
+
+/// Returns the precision if floats can be given it.
+pub fn valid_precision(prec: &Integer) -> Option<u32> {
+    prec.to_u32()
+        .filter(|prec| (prec_min()..=MAX_PRECISION).contains(prec))
+}
+
+pub fn constant(op: &Bytecode, prec: u32) -> Float {
+    match op {
+        Pi => Float::with_val(prec, Constant::Pi),
+        E => Float::with_val(prec, 1).exp(),
+        _ => unreachable!("{op} is not a math constant"),
+    }
+}
+
+pub fn unary(op: &Bytecode, x: Float) -> Float {
+    match op {
+        Sin => x.sin(),
+        Cos => x.cos(),
+        Tan => x.tan(),
+        Asin => x.asin(),
+        Acos => x.acos(),
+        Atan => x.atan(),
+        Ln => x.ln(),
+        Log2 => x.log2(),
+        Log10 => x.log10(),
+        Exp => x.exp(),
+        _ => unreachable!("{op} is not a unary math function"),
+    }
+}
+
+pub fn binary(op: &Bytecode, a: Float, b: Float) -> Float {
+    match op {
+        Atan2 => a.atan2(&b),
+        Hypot => a.hypot(&b),
+        // logarithm of `a` in base `b`
+        Log => a.ln() / b.ln(),
+        _ => unreachable!("{op} is not a binary math function"),
+    }
+}
@@ -1,6 +1,4 @@
-use std::{collections::HashMap, ptr::NonNull, sync::Mutex};
-
-use lazy_static::lazy_static;
+use std::{cell::RefCell, collections::HashMap, ptr::NonNull};
 
 use super::value::Value;
 
@@ -12,18 +10,21 @@ pub enum GCItemState {
     Black,
 }
 
-lazy_static! {
-    pub static ref ALL_ALLOCATIONS: Mutex<HashMap<usize, GCItemState>> = Mutex::new(HashMap::new());
+thread_local! {
+    // Every VM runs on a single thread, keeping the registry per thread stops
+    // VMs on other threads (e.g. the test harness) from sweeping our values.
+    pub static ALL_ALLOCATIONS: RefCell<HashMap<usize, GCItemState>> = RefCell::new(HashMap::new());
 }
 
 pub fn alloc_value_ptr() -> *mut Value {
     // let ptr = unsafe { alloc(LAYOUT) as *mut Value };
     let ptr = Box::leak(Box::new(Value::Nil));
 
-    ALL_ALLOCATIONS
-        .lock()
-        .unwrap()
-        .insert(ptr as *mut Value as usize, GCItemState::White);
+    ALL_ALLOCATIONS.with(|all_allocations| {
+        all_allocations
+            .borrow_mut()
+            .insert(ptr as *mut Value as usize, GCItemState::White)
+    });
 
     ptr
 }
@@ -37,7 +38,13 @@ pub fn alloc_new_value(val: Value) -> *mut Value {
 }
 #[allow(clippy::not_unsafe_ptr_arg_deref)]
 pub fn mark(node: NonNull<Value>) -> NonNull<Value> {
-    let mut all_allocations = ALL_ALLOCATIONS.lock().unwrap();
+    ALL_ALLOCATIONS.with(|all_allocations| mark_in(&mut all_allocations.borrow_mut(), node))
+}
+
+fn mark_in(
+    all_allocations: &mut HashMap<usize, GCItemState>,
+    node: NonNull<Value>,
+) -> NonNull<Value> {
     let mut grey_objects = Vec::new();
 
     if let Some(item) = all_allocations.get_mut(&(node.as_ptr() as usize)) {
@@ -70,7 +77,10 @@ pub fn mark(node: NonNull<Value>) -> NonNull<Value> {
 }
 
 pub fn sweep() {
-    let mut all_allocations = ALL_ALLOCATIONS.lock().unwrap();
+    ALL_ALLOCATIONS.with(|all_allocations| sweep_in(&mut all_allocations.borrow_mut()))
+}
+
+fn sweep_in(all_allocations: &mut HashMap<usize, GCItemState>) {
     let mut to_remove = Vec::new();
     for (ptr, state) in all_allocations.iter() {
         if *state == GCItemState::White {
@@ -89,10 +99,13 @@ pub fn sweep() {
 }
 
 pub fn deallocate_all() {
-    for (ptr, _) in ALL_ALLOCATIONS.lock().unwrap().iter() {
-        let ptr: *mut Value = *ptr as *mut usize as _;
-        drop(unsafe { Box::from_raw(ptr) });
-    }
+    ALL_ALLOCATIONS.with(|all_allocations| {
+        // drain so that a later VM on this thread doesn't free these again
+        for (ptr, _) in all_allocations.borrow_mut().drain() {
+            let ptr: *mut Value = ptr as *mut usize as _;
+            drop(unsafe { Box::from_raw(ptr) });
+        }
+    })
 }
 
 pub fn dealloc(ptr: *mut Value) {
@@ -1,7 +1,28 @@
+mod builtins;
 mod bytecode;
+mod cache;
+mod combinatorics;
+mod debugger;
+mod iter;
+mod matching;
+mod math;
 mod memory;
+mod number;
+mod pattern;
+mod peephole;
+mod profiler;
 mod utils;
 mod value;
 mod vm;
 
+pub use builtins::builtin;
+#[cfg(test)]
+pub(crate) use builtins::BUILTINS;
+pub(crate) use cache::{checksum, Program};
+pub use math::MAX_PRECISION;
+pub use number::is_prime;
+pub use utils::{float_precision, set_float_precision};
+pub(crate) use utils::{fstring_parts, FStringPart};
+pub use value::Type;
+pub(crate) use value::Value;
 pub use vm::VM;
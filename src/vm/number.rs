@@ -0,0 +1,117 @@
+use rug::integer::IsPrime;
+use rug::{Complete, Integer};
+
+const PRIMALITY_REPS: u32 = 30;
+
+pub fn is_prime(n: &Integer) -> bool {
+    n.is_probably_prime(PRIMALITY_REPS) != IsPrime::No
+}
+
+/// The prime factors of `n` (which must be positive) in ascending order,
+/// repeated by their multiplicity.
+pub fn factor(n: &Integer) -> Vec<Integer> {
+    let mut n = n.clone();
+    let mut factors = vec![];
+
+    // get rid of the small factors first, pollard's rho is slow on them
+    let mut p = 2u32;
+    while p < 1000 && n > 1 {
+        while n.is_divisible_u(p) {
+            n /= p;
+            factors.push(Integer::from(p));
+        }
+        p += if p == 2 { 1 } else { 2 };
+    }
+
+    factor_large(n, &mut factors);
+    factors.sort();
+    factors
+}
+
+fn factor_large(n: Integer, factors: &mut Vec<Integer>) {
+    if n == 1 {
+        return;
+    }
+
+    if is_prime(&n) {
+        factors.push(n);
+        return;
+    }
+
+    let divisor = pollard_rho(&n);
+    let rest = Integer::from(&n / &divisor);
+    factor_large(divisor, factors);
+    factor_large(rest, factors);
+}
+
+/// Finds a non trivial divisor of the odd composite `n`.
+fn pollard_rho(n: &Integer) -> Integer {
+    let mut c = Integer::from(1);
+    loop {
+        let step = |x: &Integer| (x.square_ref().complete() + &c) % n;
+
+        let mut x = Integer::from(2);
+        let mut y = x.clone();
+        let mut d = Integer::from(1);
+        while d == 1 {
+            x = step(&x);
+            y = step(&step(&y));
+            d = (&x - &y).complete().abs().gcd(n);
+        }
+
+        if d != *n {
+            return d;
+        }
+
+        c += 1;
+    }
+}
+
+/// The digits of `|n|` in the given base (at least 2), most significant first.
+pub fn digits(n: &Integer, base: &Integer) -> Vec<Integer> {
+    let n = n.clone().abs();
+    if n == 0 {
+        return vec![n];
+    }
+
+    // dividing by the base once per digit is quadratic, `n` is split in
+    // halves by base², base⁴, base⁸... instead
+    let mut powers = vec![base.clone()];
+    while *powers.last().unwrap() <= n {
+        let square = powers.last().unwrap().square_ref().complete();
+        powers.push(square);
+    }
+
+    let mut digits = vec![];
+    split_digits(n, &powers[..powers.len() - 1], false, &mut digits);
+    digits
+}
+
+/// Pushes the digits of `n`, which has at most twice as many digits as the
+/// last of `powers`. `pad` keeps the leading zeros, `n` being the lower half
+/// of a larger number.
+fn split_digits(n: Integer, powers: &[Integer], pad: bool, digits: &mut Vec<Integer>) {
+    let Some((power, powers)) = powers.split_last() else {
+        digits.push(n);
+        return;
+    };
+
+    let (high, low) = n.div_rem_ref(power).complete();
+    if pad || high != 0 {
+        split_digits(high, powers, pad, digits);
+        split_digits(low, powers, true, digits);
+    } else {
+        split_digits(low, powers, false, digits);
+    }
+}
+
+/// Formats `n` in the given base (between 2 and 36) using lowercase letters.
+pub fn to_base(n: &Integer, base: i32) -> String {
+    let sign = if *n < 0 { "-" } else { "" };
+    let digits = digits(n, &Integer::from(base))
+        .into_iter()
+        .map(|d| char::from_digit(d.to_u32_wrapping(), base as u32).unwrap())
+        .collect::<String>();
+
+    format!("{sign}{digits}")
+}
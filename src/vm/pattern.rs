@@ -0,0 +1,74 @@
+use regex::Regex;
+use std::cmp::Ordering;
+
+use super::value::Value;
+
+/// A compiled regular expression, cached per call site in the constants pool.
+#[derive(Clone, Debug)]
+pub struct Pattern(Regex);
+
+impl Pattern {
+    pub fn new(pattern: &str) -> Result<Self, regex::Error> {
+        Regex::new(pattern).map(Self)
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+
+    pub fn is_match(&self, haystack: &str) -> Value {
+        Value::Bool(self.0.is_match(haystack))
+    }
+
+    pub fn find_all(&self, haystack: &str) -> Value {
+        Value::Array(
+            self.0
+                .find_iter(haystack)
+                .map(|m| m.as_str().into())
+                .collect(),
+        )
+    }
+
+    /// Every match as an array of its groups, group 0 being the whole match.
+    /// Groups that didn't participate in the match are `nil`.
+    pub fn captures(&self, haystack: &str) -> Value {
+        Value::Array(
+            self.0
+                .captures_iter(haystack)
+                .map(|caps| {
+                    Value::Array(
+                        caps.iter()
+                            .map(|group| group.map(|m| m.as_str().into()).unwrap_or_default())
+                            .collect(),
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    pub fn replace(&self, haystack: &str, replacement: &str) -> Value {
+        Value::String(self.0.replace_all(haystack, replacement).into_owned())
+    }
+
+    pub fn split(&self, haystack: &str) -> Value {
+        Value::Array(self.0.split(haystack).map(|i| i.into()).collect())
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl PartialOrd for Pattern {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.as_str().partial_cmp(other.as_str())
+    }
+}
+
+impl std::fmt::Display for Pattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
@@ -0,0 +1,307 @@
+//! The peephole optimizer, run over the instructions once they are compiled.
+//!
+//! Jumps to jumps are threaded, instructions that do nothing or never run are
+//! removed, and a few common sequences are fused into one instruction. The
+//! instructions only ever shrink, so every pointer into them, of jumps, loops,
+//! handlers and functions, is remapped once at the end.
+
+use std::ops::Range;
+
+use super::bytecode::{Bytecode, Bytecode::*, Comparison, Instr};
+use super::utils::FunctionData;
+
+type Code = Vec<(Instr, Range<usize>)>;
+
+/// The positions of the args of `bytecode` that point to instructions.
+/// `Break` and `Continue` point to their loop rather than jump there.
+fn pointer_args(bytecode: &Bytecode) -> &'static [usize] {
+    match bytecode {
+        Jmp | TernaryStart | TryEnd | Break | Continue => &[0],
+        While | Every { .. } | TryStart | JmpIfNot(_) => &[0, 1],
+        MatchArm(_) => &[1],
+        _ => &[],
+    }
+}
+
+/// The args of `bytecode` that can be threaded, the places it jumps to.
+/// The handler of `TryStart` has to come right after its `TryEnd`.
+fn jump_args(bytecode: &Bytecode) -> &'static [usize] {
+    match bytecode {
+        Jmp | TernaryStart | TryEnd | While | Every { .. } | JmpIfNot(_) => &[0],
+        MatchArm(_) => &[1],
+        _ => &[],
+    }
+}
+
+/// Whether the instruction after `bytecode` only runs when something jumps
+/// to it.
+fn ends_block(bytecode: &Bytecode) -> bool {
+    matches!(
+        bytecode,
+        Jmp | Ret | Halt | Break | Continue | Raise | TryEnd
+    )
+}
+
+/// Optimizes `code` in place, updating the functions that point into it.
+pub(crate) fn optimize<'a>(code: &mut Code, functions: impl Iterator<Item = &'a mut FunctionData>) {
+    let mut functions = functions.collect::<Vec<_>>();
+
+    thread_jumps(code);
+
+    let targets = jump_targets(code, &functions);
+    let mut removed = vec![false; code.len()];
+    // the instruction each of the removed ones was fused into
+    let mut fused_into = vec![None; code.len()];
+
+    let mut reachable = true;
+    let mut i = 0;
+    while i < code.len() {
+        reachable |= targets[i];
+        if !reachable {
+            removed[i] = true;
+            i += 1;
+            continue;
+        }
+        reachable = !ends_block(&code[i].0 .0);
+
+        // the instructions of a sequence after the first can't be jumped to
+        let sequence = |len: usize| -> Option<Vec<&Bytecode>> {
+            let end = i + len;
+            (end <= code.len() && !targets[i + 1..end].iter().any(|&t| t))
+                .then(|| code[i..end].iter().map(|(Instr(b, _), _)| b).collect())
+        };
+
+        let fused = match (sequence(3).as_deref(), sequence(2).as_deref()) {
+            (Some([GetVar, LoadConst, op @ (Add | Sub)]), _) => {
+                let var = code[i].0 .1[0];
+                let constant = code[i + 1].0 .1[0];
+                let bytecode = if **op == Add { AddConst } else { SubConst };
+                let span = code[i + 2].1.clone();
+                Some((3, Instr(bytecode, vec![var, constant]), span))
+            }
+            (_, Some([compare, TernaryStart | While])) if Comparison::of(compare).is_some() => {
+                let comparison = Comparison::of(compare).unwrap();
+                let args = code[i + 1].0 .1.clone();
+                // errors can only come from the comparison
+                let span = code[i].1.clone();
+                Some((2, Instr(JmpIfNot(comparison), args), span))
+            }
+            (_, Some([LoadConst | Dup, Pop])) => {
+                removed[i] = true;
+                removed[i + 1] = true;
+                i += 2;
+                continue;
+            }
+            _ => None,
+        };
+
+        match fused {
+            Some((len, instr, span)) => {
+                code[i] = (instr, span);
+                for j in i + 1..i + len {
+                    removed[j] = true;
+                    fused_into[j] = Some(i);
+                }
+                i += len;
+            }
+            None => {
+                // it only reserved the id of its variable
+                removed[i] = code[i].0 .0 == MakeVar;
+                i += 1;
+            }
+        }
+    }
+
+    // a jump to where the code goes anyway, from the last as removing one
+    // can make the one before it pointless too
+    for i in (0..code.len()).rev() {
+        if let (Instr(Jmp, args), _) = &code[i] {
+            if !removed[i] && args[0] > i && removed[i + 1..args[0]].iter().all(|&r| r) {
+                removed[i] = true;
+            }
+        }
+    }
+
+    // a removed instruction is replaced by the next one that is kept, or by
+    // the one it was fused into
+    let mut new_index = Vec::with_capacity(code.len() + 1);
+    let mut kept = 0;
+    for &removed in &removed {
+        new_index.push(kept);
+        kept += !removed as usize;
+    }
+    new_index.push(kept);
+    for (i, head) in fused_into.iter().enumerate() {
+        if let Some(head) = head {
+            new_index[i] = new_index[*head];
+        }
+    }
+
+    let old_code = std::mem::take(code);
+    for (i, (Instr(bytecode, mut args), span)) in old_code.into_iter().enumerate() {
+        if removed[i] {
+            continue;
+        }
+        for &arg in pointer_args(&bytecode) {
+            if let Some(pointer) = args.get_mut(arg) {
+                *pointer = new_index[*pointer];
+            }
+        }
+        code.push((Instr(bytecode, args), span));
+    }
+
+    for function in functions.iter_mut() {
+        let range = &function.instruction_range;
+        function.instruction_range = new_index[range.start]..new_index[range.end];
+        for entry in &mut function.entry_points {
+            *entry = new_index[*entry];
+        }
+    }
+}
+
+/// Makes the jumps to a `Jmp` go where it goes, and the jumps to a `Ret` or
+/// a `Halt` return or halt themselves.
+fn thread_jumps(code: &mut Code) {
+    for i in 0..code.len() {
+        for &arg in jump_args(&code[i].0 .0) {
+            let Some(&target) = code[i].0 .1.get(arg) else {
+                continue;
+            };
+
+            // a loop of jumps never gets anywhere, it's left alone
+            let mut target = target;
+            for _ in 0..code.len() {
+                match &code[target].0 {
+                    Instr(Jmp, args) => target = args[0],
+                    _ => break,
+                }
+            }
+            code[i].0 .1[arg] = target;
+        }
+
+        if let Instr(Jmp, args) = &code[i].0 {
+            if matches!(code[args[0]].0 .0, Ret | Halt) {
+                code[i] = code[args[0]].clone();
+            }
+        }
+    }
+}
+
+/// Whether something can jump to each instruction, or a function start or
+/// end there. The end of the code is included.
+fn jump_targets(code: &Code, functions: &[&mut FunctionData]) -> Vec<bool> {
+    let mut targets = vec![false; code.len() + 1];
+    targets[0] = true;
+    for (Instr(bytecode, args), _) in code {
+        if matches!(bytecode, Break | Continue) {
+            continue;
+        }
+        for &arg in pointer_args(bytecode) {
+            if let Some(&target) = args.get(arg) {
+                targets[target] = true;
+            }
+        }
+    }
+
+    for function in functions {
+        targets[function.instruction_range.start] = true;
+        targets[function.instruction_range.end] = true;
+        for &entry in &function.entry_points {
+            targets[entry] = true;
+        }
+    }
+
+    targets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instr(bytecode: Bytecode, args: &[usize]) -> (Instr, Range<usize>) {
+        (Instr(bytecode, args.to_vec()), 1..2)
+    }
+
+    fn bytecodes(code: &Code) -> Vec<(Bytecode, Vec<usize>)> {
+        code.iter()
+            .map(|(Instr(bytecode, args), _)| (bytecode.clone(), args.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_peephole() {
+        // x = 1
+        // f: { 2; >. x < 3 { x = x + 1; br } }
+        // x ? $x : 2
+        let mut code = vec![
+            instr(MakeVar, &[]),
+            instr(LoadConst, &[0]),
+            instr(Replace, &[0]),
+            instr(Jmp, &[17]),
+            // f
+            instr(LoadConst, &[2]),
+            instr(Pop, &[]),
+            instr(GetVar, &[0]),
+            instr(LoadConst, &[1]),
+            instr(Lt, &[]),
+            instr(While, &[16, 6]),
+            instr(GetVar, &[0]),
+            instr(LoadConst, &[0]),
+            instr(Add, &[]),
+            instr(Replace, &[0]),
+            instr(Break, &[9]),
+            instr(Jmp, &[6]),
+            instr(Ret, &[]),
+            // the ternary
+            instr(GetVar, &[0]),
+            instr(TernaryStart, &[22]),
+            instr(GetVar, &[0]),
+            instr(Println, &[]),
+            instr(Jmp, &[23]),
+            instr(LoadConst, &[2]),
+            instr(Halt, &[]),
+        ];
+        let mut f = FunctionData {
+            name: "f".to_string(),
+            parameters: vec![],
+            instruction_range: 4..17,
+            scope_idx: 1,
+            returns: false,
+            generator: false,
+            required: 0,
+            entry_points: vec![4],
+            variadic: false,
+            memo: None,
+        };
+        optimize(&mut code, [&mut f].into_iter());
+
+        assert_eq!(
+            bytecodes(&code),
+            [
+                (LoadConst, vec![0]),
+                (Replace, vec![0]),
+                (Jmp, vec![10]),
+                // f
+                (GetVar, vec![0]),
+                (LoadConst, vec![1]),
+                (JmpIfNot(Comparison::Lt), vec![9, 3]),
+                (AddConst, vec![0, 0]),
+                (Replace, vec![0]),
+                // the loop is the instruction it was fused into, and the jump
+                // back after the `br` is gone
+                (Break, vec![5]),
+                (Ret, vec![]),
+                // the ternary
+                (GetVar, vec![0]),
+                (TernaryStart, vec![15]),
+                (GetVar, vec![0]),
+                (Println, vec![]),
+                (Halt, vec![]),
+                (LoadConst, vec![2]),
+                (Halt, vec![]),
+            ]
+        );
+        assert_eq!(f.instruction_range, 3..10);
+        assert_eq!(f.entry_points, [3]);
+    }
+}
@@ -0,0 +1,344 @@
+use std::collections::HashMap;
+use std::mem::Discriminant;
+use std::time::{Duration, Instant};
+
+use super::bytecode::Bytecode;
+use super::utils::Lines;
+
+/// The name of the frame of the code outside of functions.
+const MAIN: &str = "main";
+/// The lines shown in the report, the hottest ones.
+const TOP_LINES: usize = 15;
+
+/// How often something ran and for how long.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Stat {
+    pub(crate) count: u64,
+    pub(crate) time: Duration,
+}
+
+impl Stat {
+    fn add(&mut self, time: Duration) {
+        self.count += 1;
+        self.time += time;
+    }
+}
+
+#[derive(Debug, Default)]
+struct FunctionStat {
+    calls: u64,
+    /// The instructions of the function itself, not of what it calls.
+    own: Stat,
+    /// Everything that ran while the function was being called.
+    total: Duration,
+}
+
+/// A distinct stack of calls, the calls made from it are its children.
+#[derive(Debug)]
+struct Node {
+    name: String,
+    parent: usize,
+    /// The instructions that ran in this stack.
+    own: Stat,
+}
+
+/// The state of `--profile`, every instruction `run` executes is recorded
+/// with the time it took.
+pub(crate) struct Profiler {
+    bytecodes: HashMap<Discriminant<Bytecode>, (String, Stat)>,
+    by_line: HashMap<usize, Stat>,
+    /// Every stack seen, the first one is the code outside of functions.
+    nodes: Vec<Node>,
+    children: HashMap<(usize, String), usize>,
+    /// The stacks of the functions being called, the innermost last.
+    frames: Vec<usize>,
+    calls: HashMap<String, u64>,
+    started: Instant,
+    /// Where the folded stacks are written, if anywhere.
+    pub(crate) folded: Option<String>,
+}
+
+/// The name of the kind of a bytecode, without what it holds.
+pub(crate) fn kind_name(bytecode: &Bytecode) -> String {
+    let mut name = format!("{bytecode:?}");
+    name.truncate(name.find([' ', '(']).unwrap_or(name.len()));
+    name
+}
+
+impl Profiler {
+    pub(crate) fn new(folded: Option<String>) -> Self {
+        let main = Node {
+            name: MAIN.to_string(),
+            parent: 0,
+            own: Stat::default(),
+        };
+
+        Self {
+            bytecodes: HashMap::new(),
+            by_line: HashMap::new(),
+            nodes: vec![main],
+            children: HashMap::new(),
+            frames: vec![],
+            calls: HashMap::new(),
+            started: Instant::now(),
+            folded,
+        }
+    }
+
+    /// Records an instruction that took `time`, in the current function.
+    /// Instructions without a span, like returns, have no line.
+    pub(crate) fn record(&mut self, bytecode: &Bytecode, line: Option<usize>, time: Duration) {
+        self.bytecodes
+            .entry(std::mem::discriminant(bytecode))
+            .or_insert_with(|| (kind_name(bytecode), Stat::default()))
+            .1
+            .add(time);
+        if let Some(line) = line {
+            self.by_line.entry(line).or_default().add(time);
+        }
+
+        let node = self.frames.last().copied().unwrap_or(0);
+        self.nodes[node].own.add(time);
+    }
+
+    pub(crate) fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Enters the function `name`.
+    pub(crate) fn call(&mut self, name: String) {
+        *self.calls.entry(name.clone()).or_default() += 1;
+
+        let parent = self.frames.last().copied().unwrap_or(0);
+        let next = self.nodes.len();
+        let node = *self.children.entry((parent, name.clone())).or_insert(next);
+        if node == next {
+            self.nodes.push(Node {
+                name,
+                parent,
+                own: Stat::default(),
+            });
+        }
+        self.frames.push(node);
+    }
+
+    /// Leaves the functions deeper than `depth`.
+    pub(crate) fn return_to(&mut self, depth: usize) {
+        self.frames.truncate(depth);
+    }
+
+    /// The names of the functions of the stack `node`, the outermost first.
+    fn path(&self, mut node: usize) -> Vec<&str> {
+        let mut path = vec![self.nodes[node].name.as_str()];
+        while node != 0 {
+            node = self.nodes[node].parent;
+            path.push(&self.nodes[node].name);
+        }
+
+        path.reverse();
+        path
+    }
+
+    /// The recorded stacks in the folded format of flamegraph tools, one
+    /// `main;f;g nanoseconds` per line.
+    pub(crate) fn folded_stacks(&self) -> String {
+        let mut stacks = (0..self.nodes.len())
+            .filter(|&node| !self.nodes[node].own.time.is_zero())
+            .map(|node| {
+                let time = self.nodes[node].own.time.as_nanos();
+                format!("{} {time}\n", self.path(node).join(";"))
+            })
+            .collect::<Vec<_>>();
+        stacks.sort();
+        stacks.concat()
+    }
+
+    fn functions(&self) -> Vec<(&str, FunctionStat)> {
+        // everything that ran in a stack and in the ones made from it, the
+        // children come after their parent
+        let mut inclusive = self
+            .nodes
+            .iter()
+            .map(|node| node.own.time)
+            .collect::<Vec<_>>();
+        for node in (1..self.nodes.len()).rev() {
+            let parent = self.nodes[node].parent;
+            let time = inclusive[node];
+            inclusive[parent] += time;
+        }
+
+        let mut children = vec![vec![]; self.nodes.len()];
+        for node in 1..self.nodes.len() {
+            children[self.nodes[node].parent].push(node);
+        }
+
+        let mut functions: HashMap<&str, FunctionStat> = HashMap::new();
+        // how many times each function is in the stack being visited, a
+        // recursive call is already in the total of the outermost one
+        let mut active: HashMap<&str, usize> = HashMap::new();
+        let mut visit = vec![(0, true)];
+        while let Some((node, entering)) = visit.pop() {
+            let name = self.nodes[node].name.as_str();
+            if node == 0 {
+                visit.extend(children[0].iter().map(|&child| (child, true)));
+                continue;
+            }
+            if !entering {
+                *active.get_mut(name).unwrap() -= 1;
+                continue;
+            }
+
+            let function = functions.entry(name).or_default();
+            function.own.count += self.nodes[node].own.count;
+            function.own.time += self.nodes[node].own.time;
+            let depth = active.entry(name).or_default();
+            if *depth == 0 {
+                function.total += inclusive[node];
+            }
+            *depth += 1;
+
+            visit.push((node, false));
+            visit.extend(children[node].iter().map(|&child| (child, true)));
+        }
+
+        for (name, calls) in &self.calls {
+            functions.entry(name).or_default().calls = *calls;
+        }
+
+        functions.into_iter().collect()
+    }
+
+    /// The tables of the report, the slowest first.
+    pub(crate) fn report(&self, src: &str, lines: &Lines) -> String {
+        let total = self
+            .bytecodes
+            .values()
+            .map(|(_, stat)| stat.time)
+            .sum::<Duration>();
+        let instructions = self
+            .bytecodes
+            .values()
+            .map(|(_, stat)| stat.count)
+            .sum::<u64>();
+        let percent = |time: Duration| match total.is_zero() {
+            true => 0.0,
+            false => time.as_secs_f64() / total.as_secs_f64() * 100.0,
+        };
+
+        let mut report = format!(
+            "{instructions} instructions in {}, {} in total\n",
+            format_time(total),
+            format_time(self.started.elapsed())
+        );
+
+        let mut bytecodes = self.bytecodes.values().collect::<Vec<_>>();
+        bytecodes.sort_by_key(|(_, stat)| std::cmp::Reverse(stat.time));
+        report += &format!(
+            "\n{:<14}{:>12}{:>12}{:>8}\n",
+            "Bytecode", "Count", "Time", "%"
+        );
+        for (name, stat) in bytecodes {
+            report += &format!(
+                "{name:<14}{:>12}{:>12}{:>7.1}%\n",
+                stat.count,
+                format_time(stat.time),
+                percent(stat.time)
+            );
+        }
+
+        let mut by_line = self.by_line.iter().collect::<Vec<_>>();
+        by_line.sort_by(|a, b| b.1.time.cmp(&a.1.time).then(a.0.cmp(b.0)));
+        report += &format!(
+            "\n{:<14}{:>12}{:>12}{:>8}  Code\n",
+            "Line", "Count", "Time", "%"
+        );
+        for (&line, stat) in by_line.into_iter().take(TOP_LINES) {
+            let code = lines.text(src, line).trim();
+            let code = match code.char_indices().nth(40) {
+                Some((end, _)) => format!("{}...", &code[..end]),
+                None => code.to_string(),
+            };
+            report += &format!(
+                "{line:<14}{:>12}{:>12}{:>7.1}%  {code}\n",
+                stat.count,
+                format_time(stat.time),
+                percent(stat.time)
+            );
+        }
+
+        let mut functions = self.functions();
+        functions.sort_by(|a, b| b.1.total.cmp(&a.1.total).then(a.0.cmp(b.0)));
+        if !functions.is_empty() {
+            report += &format!(
+                "\n{:<14}{:>12}{:>12}{:>12}{:>12}{:>8}\n",
+                "Function", "Calls", "Count", "Own time", "Total time", "%"
+            );
+        }
+        for (name, stat) in functions {
+            report += &format!(
+                "{name:<14}{:>12}{:>12}{:>12}{:>12}{:>7.1}%\n",
+                stat.calls,
+                stat.own.count,
+                format_time(stat.own.time),
+                format_time(stat.total),
+                percent(stat.total)
+            );
+        }
+
+        report
+    }
+}
+
+/// A duration in the largest unit that keeps it above 1.
+fn format_time(time: Duration) -> String {
+    let nanos = time.as_nanos() as f64;
+    match nanos {
+        _ if nanos >= 1e9 => format!("{:.2}s", nanos / 1e9),
+        _ if nanos >= 1e6 => format!("{:.2}ms", nanos / 1e6),
+        _ if nanos >= 1e3 => format!("{:.2}μs", nanos / 1e3),
+        _ => format!("{nanos}ns"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profiler() {
+        let mut profiler = Profiler::new(None);
+        let ns = Duration::from_nanos;
+
+        // f calls itself and then g
+        profiler.record(&Bytecode::FnCall, Some(3), ns(10));
+        profiler.call("f".to_string());
+        profiler.record(&Bytecode::Add, Some(1), ns(20));
+        profiler.call("f".to_string());
+        profiler.record(&Bytecode::Add, Some(1), ns(30));
+        profiler.return_to(1);
+        profiler.call("g".to_string());
+        profiler.record(&Bytecode::Ret, None, ns(5));
+        profiler.return_to(0);
+        profiler.record(&Bytecode::Println, Some(3), ns(1));
+
+        assert_eq!(
+            profiler.folded_stacks(),
+            "main 11\nmain;f 20\nmain;f;f 30\nmain;f;g 5\n"
+        );
+
+        let mut functions = profiler.functions();
+        functions.sort_by_key(|(name, _)| *name);
+        let functions = functions
+            .iter()
+            .map(|(name, stat)| (*name, stat.calls, stat.own.count, stat.total.as_nanos()))
+            .collect::<Vec<_>>();
+        // the recursive call is in the total of the outer one
+        assert_eq!(functions, [("f", 2, 2, 55), ("g", 1, 1, 5)]);
+
+        let src = "f n: n + 1\n\n$f(1)";
+        let report = profiler.report(src, &Lines::new(src));
+        assert!(report.starts_with("5 instructions in 66ns"));
+        assert!(report.contains("\nAdd                      2        50ns   75.8%\n"));
+        assert!(report.contains("\n1                        2        50ns   75.8%  f n: n + 1\n"));
+    }
+}
@@ -1,7 +1,12 @@
+use std::cell::Cell;
 use std::ptr::NonNull;
 use std::{collections::HashMap, ops::Range};
 
-use super::{memory::alloc_new_value, value::Value, vm::VarId};
+use super::{
+    memory::alloc_new_value,
+    value::{ErrorValue, Value},
+    vm::VarId,
+};
 
 #[macro_export]
 macro_rules! for_each_arg {
@@ -10,40 +15,54 @@ macro_rules! for_each_arg {
     };
 
     { $arg:ident, $n:expr, Some($e:ident) => { $($some:tt)* }, None => { $($none:tt)* } } => {
-        $arg.as_ref()
+        let args = $arg.as_ref()
             .unwrap_or(&vec![])
             .into_iter()
             .cloned()
             .map(|i| Some(i))
             .chain(std::iter::repeat(None))
             .take($n)
-            .for_each(|i| match i {
+            .collect::<Vec<_>>();
+        for i in args {
+            match i {
                 Some($e) => $($some)*,
                 None => $($none)*,
             }
-        )
+        }
     };
 
     { $arg:ident, $e:ident => { $($b:tt)* }} => {
-        $arg.as_ref()
-            .unwrap_or(&vec![])
-            .into_iter()
-            .cloned()
-            .for_each(|$e| $($b)*)
+        for $e in $arg.as_ref().unwrap_or(&vec![]).iter().cloned() {
+            $($b)*
+        }
     };
 }
 
 #[macro_export]
 macro_rules! float {
     ($val:expr) => {
-        rug::Float::with_val(53, $val)
+        rug::Float::with_val($crate::vm::float_precision(), $val)
     };
 }
 
+thread_local! {
+    static FLOAT_PRECISION: Cell<u32> = const { Cell::new(53) };
+}
+
+/// The precision in bits new floats are created with.
+pub fn float_precision() -> u32 {
+    FLOAT_PRECISION.with(|p| p.get())
+}
+
+/// Sets the precision of new floats, returning the previous one.
+pub fn set_float_precision(prec: u32) -> u32 {
+    FLOAT_PRECISION.with(|p| p.replace(prec))
+}
+
 #[macro_export]
 macro_rules! process_placeholder {
     { $self:ident, $placeholder:expr, $span:expr } => {
-        let parsed_exprs = PParser::new(
+        let mut parser = PParser::new(
             $placeholder,
             LogosToken::lexer($placeholder)
                 .spanned()
@@ -52,8 +71,15 @@ macro_rules! process_placeholder {
                     Err(()) => (LogosToken::Error, span.into()),
                 })
                 .collect::<Vec<_>>(),
-        )
-        .parse()
+        );
+        let parsed_exprs = parser.parse_recovering();
+        if let Some(error) = parser.errors().first() {
+            return Err($self.runtime_error(
+                &format!("Invalid placeholder: {}", error.message),
+                $span.clone(),
+            ));
+        }
+        let parsed_exprs = parsed_exprs
         .into_iter()
         .map(|mut i| {
             i.span = $span.clone();
@@ -61,8 +87,18 @@ macro_rules! process_placeholder {
         })
         .collect::<Vec<_>>();
 
+        // every placeholder leaves exactly one value for `ConcatUpTo`
+        if parsed_exprs.len() != 1 {
+            return Err($self.runtime_error(
+                &format!(
+                    "A placeholder has to be one expression, found {}",
+                    parsed_exprs.len()
+                ),
+                $span.clone(),
+            ));
+        }
         for expr in parsed_exprs {
-            $self.compile_expr(expr);
+            $self.compile_expr(expr)?;
         }
     };
 }
@@ -74,6 +110,17 @@ pub(crate) struct FunctionData {
     pub instruction_range: Range<usize>,
     pub scope_idx: usize,
     pub returns: bool,
+    /// Whether the body contains `yd`, calling it only creates a generator.
+    pub generator: bool,
+    /// The number of parameters without a default value, `self` included.
+    pub required: usize,
+    /// Where a call starts when `i` of the parameters with a default value
+    /// were given, the body sets the ones after them first.
+    pub entry_points: Vec<usize>,
+    /// Whether the last parameter collects the extra arguments.
+    pub variadic: bool,
+    /// The results of a memoized function, by its arguments.
+    pub memo: Option<HashMap<Vec<Value>, Value>>,
 }
 
 impl FunctionData {
@@ -103,5 +150,148 @@ pub(crate) struct FnStackData {
     pub(crate) previous_stack_len: usize,
     pub(crate) variables_id: HashMap<String, u32>,
     pub(crate) variables: HashMap<u32, Option<NonNull<Value>>>,
+    /// The hidden variable holding the generator this frame resumed.
+    pub(crate) generator: Option<u32>,
+    /// The memoized function running and its arguments, its result is
+    /// cached when it returns.
+    pub(crate) memo: Option<(String, Vec<Value>)>,
+    /// The last tail call that replaced the function called at `pc_before`,
+    /// the frame runs the function it called.
+    pub(crate) tail_call: Option<usize>,
     // pub(crate) self_ptr: Option<NonNull<Value>>,
 }
+
+/// A call that was running when an error happened, `count` merges the calls
+/// of a recursion made from the same place.
+pub(crate) struct TracedCall {
+    pub(crate) name: String,
+    /// The call with the values of its parameters, like `f(1, "a")`.
+    pub(crate) call: String,
+    pub(crate) span: Range<usize>,
+    pub(crate) count: usize,
+    /// Made in place of the call of the function that made it.
+    pub(crate) tail: bool,
+}
+
+/// A running `tr` block, the errors raised inside of it jump to `catch_pc`.
+#[derive(Debug, Clone)]
+pub(crate) struct Handler {
+    /// The instructions of the `tr` block.
+    pub(crate) body: Range<usize>,
+    pub(crate) catch_pc: usize,
+    pub(crate) call_depth: usize,
+    pub(crate) stack_len: usize,
+}
+
+/// An error raised while compiling or running, it goes back up to `run`
+/// which jumps to the handler catching it or reports it.
+#[derive(Debug, Clone)]
+pub(crate) struct RuntimeError {
+    pub(crate) message: String,
+    pub(crate) help: Option<String>,
+    pub(crate) span: Range<usize>,
+}
+
+impl From<RuntimeError> for ErrorValue {
+    fn from(error: RuntimeError) -> Self {
+        ErrorValue {
+            message: error.message,
+            start: error.span.start,
+            end: error.span.end,
+        }
+    }
+}
+
+/// Where the lines of the source start, to tell the line of a span.
+#[derive(Debug, Clone)]
+pub(crate) struct Lines {
+    starts: Vec<usize>,
+}
+
+impl Lines {
+    pub(crate) fn new(src: &str) -> Self {
+        let starts = std::iter::once(0)
+            .chain(src.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Self { starts }
+    }
+
+    /// The line, from 1, of the byte `offset` of the source.
+    pub(crate) fn line_of(&self, offset: usize) -> usize {
+        self.starts.partition_point(|&start| start <= offset)
+    }
+
+    /// The offset the line `line` starts at.
+    pub(crate) fn start(&self, line: usize) -> usize {
+        self.starts[line - 1]
+    }
+
+    /// The text of `line`, without its newline.
+    pub(crate) fn text<'a>(&self, src: &'a str, line: usize) -> &'a str {
+        let end = self.starts.get(line).map_or(src.len(), |&end| end - 1);
+        src[self.start(line)..end].trim_end_matches('\r')
+    }
+
+    pub(crate) fn count(&self) -> usize {
+        self.starts.len()
+    }
+}
+
+/// A piece of an f-string, the text between the placeholders or the code of
+/// one.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum FStringPart {
+    Text(String),
+    Code(String),
+}
+
+/// Splits an f-string into its text and the `{code}` and `$name`
+/// placeholders in it.
+pub(crate) fn fstring_parts(value: &str) -> Vec<FStringPart> {
+    let mut parts = Vec::new();
+    let mut iter = value.chars();
+
+    let mut buffer = String::with_capacity(50);
+    let mut escaped = false;
+    let mut placeholder_start = false;
+    let mut dollar_placeholder = false;
+
+    while let Some(ch) = iter.next() {
+        if ch == '\\' {
+            escaped = true;
+            if let Some(next_ch) = iter.next() {
+                match next_ch {
+                    'n' => buffer.push_str("\\n"),
+                    't' => buffer.push_str("\\t"),
+                    'r' => buffer.push_str("\\r"),
+                    'x' => buffer.push_str("\\x"),
+                    _ => buffer.push(next_ch),
+                }
+            }
+        } else if !escaped && !dollar_placeholder && ch == '}' && placeholder_start {
+            parts.push(FStringPart::Code(std::mem::take(&mut buffer)));
+            placeholder_start = false;
+        } else if !escaped && !dollar_placeholder && ch == '{' {
+            parts.push(FStringPart::Text(std::mem::take(&mut buffer)));
+            placeholder_start = true;
+        } else if !escaped && !placeholder_start && !dollar_placeholder && ch == '$' {
+            parts.push(FStringPart::Text(std::mem::take(&mut buffer)));
+            dollar_placeholder = true;
+        } else if dollar_placeholder && ch == ' ' {
+            parts.push(FStringPart::Code(std::mem::take(&mut buffer)));
+            buffer.push(' ');
+            dollar_placeholder = false;
+        } else {
+            buffer.push(ch);
+            escaped = false;
+        }
+    }
+
+    if !buffer.is_empty() {
+        parts.push(match dollar_placeholder {
+            true => FStringPart::Code(buffer),
+            false => FStringPart::Text(buffer),
+        });
+    }
+    parts
+}
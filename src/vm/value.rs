@@ -1,7 +1,10 @@
+use super::iter::Iter;
+use super::pattern::Pattern;
 use crate::float;
 use rug::ops::Pow;
 use rug::{Float, Integer};
 use std::borrow::Cow;
+use std::hash::{Hash, Hasher};
 use std::ops::*;
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
@@ -11,18 +14,22 @@ pub enum Type {
     String,
     Bool,
     Array,
+    Iter,
+    Error,
     Nil,
 }
 
 impl TryFrom<&str> for Type {
     type Error = ();
-    fn try_from(value: &str) -> Result<Self, Self::Error> {
+    fn try_from(value: &str) -> Result<Self, ()> {
         Ok(match value {
             "int" => Type::Integer,
             "float" => Type::Float,
             "bool" => Type::Bool,
             "str" => Type::String,
             "array" => Type::Array,
+            "iter" => Type::Iter,
+            "error" => Type::Error,
             "nil" => Type::Nil,
 
             _ => return Err(()),
@@ -41,6 +48,8 @@ impl Type {
             Self::Float => "float",
             Self::String => "str",
             Self::Array => "array",
+            Self::Iter => "iter",
+            Self::Error => "error",
             Self::Bool => "bool",
             Self::Nil => "nil",
         }
@@ -60,50 +69,66 @@ pub enum Value {
     String(String),
     Bool(bool),
     Array(Vec<Value>),
+    // Ranges and other sequences that are only materialized when needed
+    Iter(Box<Iter>),
+    Error(Box<ErrorValue>),
+
+    // Only lives in the constants pool, as the cache of a regex call site
+    Regex(Pattern),
 
     #[default]
     Nil,
 }
 
+/// A runtime error caught by `ca`, with the span of the code that failed.
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub struct ErrorValue {
+    pub message: String,
+    pub start: usize,
+    pub end: usize,
+}
+
 impl Value {
-    pub fn as_int(&self) -> Integer {
-        match self.clone() {
-            Self::Int(i) => i,
-            _ => panic!("Expected an int value, found: {}", self.get_type()),
+    pub fn as_int(&self) -> Option<&Integer> {
+        match self {
+            Self::Int(i) => Some(i),
+            _ => None,
         }
     }
 
-    pub fn as_float(&self) -> Float {
-        match self.clone() {
-            Self::Float(f) => f,
-            _ => panic!("Expected an float value, found: {}", self.get_type()),
+    pub fn as_float(&self) -> Option<&Float> {
+        match self {
+            Self::Float(f) => Some(f),
+            _ => None,
         }
     }
 
-    pub fn as_bool(&self) -> bool {
+    pub fn as_bool(&self) -> Option<bool> {
         match self {
-            &Self::Bool(i) => i,
-            _ => panic!("Expected an bool value, found: {}", self.get_type()),
+            &Self::Bool(b) => Some(b),
+            _ => None,
         }
     }
 
-    pub fn as_str(&self) -> &str {
+    pub fn as_str(&self) -> Option<&str> {
         match self {
-            Self::String(i) => i,
-            _ => panic!("Expected an string value, found: {}", self.get_type()),
+            Self::String(s) => Some(s),
+            _ => None,
         }
     }
 
-    pub fn as_array(&self) -> Cow<Vec<Value>> {
+    /// The items of an array, the characters of a string or the values an
+    /// iter gives.
+    pub fn as_array(&self) -> Option<Cow<'_, Vec<Value>>> {
         match self {
-            Self::Array(arr) => Cow::Borrowed(arr),
-            Self::String(s) => Cow::Owned(
+            Self::Array(arr) => Some(Cow::Borrowed(arr)),
+            Self::String(s) => Some(Cow::Owned(
                 s.chars()
                     .map(|i| Value::String(i.to_string()))
                     .collect::<Vec<Value>>(),
-            ),
-
-            _ => panic!("Expected an array value, found, {}", self.get_type()),
+            )),
+            Self::Iter(iter) => iter.to_vec().map(Cow::Owned),
+            _ => None,
         }
     }
 
@@ -118,6 +143,13 @@ impl Value {
                 arr.extend(rhs.clone());
                 Some(Value::Array(arr))
             }
+            // a range only becomes the array of its numbers once it's added to
+            (Value::Iter(iter), rhs) if iter.is_range() => {
+                Value::Array(iter.to_vec()?).binary_add(rhs)
+            }
+            (Value::Array(_), Value::Iter(iter)) if iter.is_range() => {
+                self.binary_add(&Value::Array(iter.to_vec()?))
+            }
             (Value::Array(lhs), rhs) => {
                 let mut arr = lhs.clone();
                 arr.push(rhs.clone());
@@ -136,6 +168,9 @@ impl Value {
             Value::String(_) => "str",
             Value::Bool(_) => "bool",
             Value::Array(_) => "array",
+            Value::Iter(_) => "iter",
+            Value::Error(_) => "error",
+            Value::Regex(_) => "regex",
             Value::Nil => "nil",
         }
     }
@@ -174,11 +209,8 @@ impl Value {
             (Value::Float(lhs), Value::Float(rhs)) => Some(Value::Float(float!(lhs * rhs))),
             (Value::Int(lhs), Value::Float(rhs)) => Some(Value::Float(float!(lhs * rhs))),
             (Value::Float(lhs), Value::Int(rhs)) => Some(Value::Float(float!(lhs * rhs))),
-            (Value::String(lhs), Value::Int(rhs)) => {
-                Some(Value::String(lhs.repeat(rhs.to_usize().unwrap())))
-            }
-            (Value::Int(lhs), Value::String(rhs)) => {
-                Some(Value::String(rhs.repeat(lhs.to_usize().unwrap())))
+            (Value::String(s), Value::Int(n)) | (Value::Int(n), Value::String(s)) => {
+                repeat(s, n).map(Value::String)
             }
             _ => None,
         }
@@ -281,7 +313,12 @@ impl Value {
             (Value::Bool(lhs), Value::Bool(rhs)) => lhs == rhs,
             (Value::String(lhs), Value::String(rhs)) => lhs == rhs,
             (Value::Array(lhs), Value::Array(rhs)) => lhs == rhs,
+            (Value::Error(lhs), Value::Error(rhs)) => lhs == rhs,
             (Value::Nil, Value::Nil) => true,
+            (Value::Iter(iter), other) | (other, Value::Iter(iter)) => match iter.to_vec() {
+                Some(items) => return Value::Array(items).equal_to(other),
+                None => false,
+            },
 
             _ => false,
         }))
@@ -333,28 +370,47 @@ impl Value {
     }
 }
 
-impl From<Value> for Integer {
-    fn from(value: Value) -> Self {
-        value.as_int()
-    }
-}
+// Equal values hash the same, which makes them usable as the keys of the
+// memoization cache. NaN is the only value not equal to itself, so it never
+// hits the cache.
+impl Eq for Value {}
 
-impl From<Value> for Float {
-    fn from(value: Value) -> Self {
-        value.as_float()
-    }
-}
-
-impl From<Value> for bool {
-    fn from(value: Value) -> Self {
-        value.as_bool()
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Value::Int(i) => i.hash(state),
+            // floats of different precisions can be equal, so only the value
+            // is hashed, as an odd significand and an exponent
+            Value::Float(f) => match f.to_integer_exp() {
+                Some((significand, exp)) if significand != 0 => {
+                    let zeros = significand.find_one(0).unwrap();
+                    (significand >> zeros).hash(state);
+                    (exp + zeros as i32).hash(state);
+                }
+                Some(_) => 0.hash(state),
+                None => f.is_sign_negative().hash(state),
+            },
+            Value::String(s) => s.hash(state),
+            Value::Bool(b) => b.hash(state),
+            Value::Array(items) => items.hash(state),
+            Value::Error(error) => error.message.hash(state),
+            Value::Regex(pattern) => pattern.as_str().hash(state),
+            Value::Iter(_) | Value::Nil => {}
+        }
     }
 }
 
-impl From<Value> for String {
-    fn from(value: Value) -> Self {
-        value.as_str().to_owned()
+/// `s` repeated `n` times, `None` when `n` is negative or the string would be
+/// too long to allocate.
+pub fn repeat(s: &str, n: &Integer) -> Option<String> {
+    let len = s.len().checked_mul(n.to_usize()?)?;
+    let mut repeated = String::new();
+    repeated.try_reserve_exact(len).ok()?;
+    if !s.is_empty() {
+        (0..len / s.len()).for_each(|_| repeated.push_str(s));
     }
+    Some(repeated)
 }
 
 impl From<String> for Value {
@@ -462,6 +518,9 @@ impl std::fmt::Display for Value {
                         .collect::<Vec<_>>()
                         .join(", ")
                 ),
+                Self::Iter(iter) => iter.to_string(),
+                Self::Error(error) => error.message.clone(),
+                Self::Regex(r) => r.to_string(),
 
                 Self::Nil => "nil".to_string(),
             }
@@ -3,18 +3,34 @@ use logos::Logos;
 use miette::{miette, LabeledSpan};
 use rug::ops::CompleteRound;
 use rug::{Complete, Float, Integer};
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::io::*;
 use std::ops::Range;
 use std::ptr::NonNull;
 use std::string::ToString;
 
+use super::builtins::{builtin, Builtin, Lowering};
 use super::bytecode::Bytecode::*;
-use super::value::{Type, Value};
+use super::cache::{self, Program};
+use super::combinatorics::{Combinations, Permutations, Powerset, Product as CartesianProduct};
+use super::debugger::{Command, Debugger, Mode, HELP};
+use super::iter::{Generator, Iter, RangeIter};
+use super::matching::Matcher;
+use super::math;
+use super::number;
+use super::pattern::Pattern;
+use super::peephole;
+use super::profiler::{kind_name, Profiler};
+use super::utils::{fstring_parts, FStringPart, Lines};
+use super::value::{ErrorValue, Type, Value};
 use crate::for_each_arg;
-use crate::parser::{LogosToken, PParser, PostfixOp, UnaryOp};
+use crate::parser::{
+    LogosToken, MatchArm, PParser, Param, Pattern as AstPattern, PostfixOp, UnaryOp,
+};
 use crate::vm::bytecode::MethodFunction;
 use crate::vm::memory;
+use crate::vm::{float_precision, set_float_precision};
 use crate::{float, process_placeholder};
 use crate::{
     parser::{BinaryOp, Expr, ExprKind},
@@ -30,8 +46,13 @@ use super::{
 pub type VarId = u32;
 pub type VarPtr = Option<NonNull<Value>>;
 pub(crate) type CallStack = Vec<FnStackData>;
+type Result<T, E = RuntimeError> = std::result::Result<T, E>;
 
 const GC_TRIGGER: usize = 1 << 20;
+const MAX_CALL_DEPTH: usize = 10_000;
+// the largest arguments whose result takes well under a second to make
+const MAX_FACTORIAL: u32 = 1_000_000;
+const MAX_FIBONACCI: u32 = 50_000_000;
 
 macro_rules! inbuilt_methods {
     { $self:ident, $names:expr, $args:ident, $([ $fn_name:expr => [$($ty:expr),+ $(,)?], $num_args:expr, $span:expr, { $($preprocess:tt)* } ]),*, _ => { $($tt:tt)* } $(,)? } => {
@@ -41,7 +62,7 @@ macro_rules! inbuilt_methods {
                     { $($preprocess)* }
 
                     for_each_arg!($args, $num_args,
-                        Some(e) => { $self.compile_expr(e) },
+                        Some(e) => { $self.compile_expr(e)? },
                         None => { $self.stack.push(allocate(Value::Nil)) }
                     );
 
@@ -59,22 +80,6 @@ macro_rules! inbuilt_methods {
     }
 }
 
-macro_rules! compile_call {
-    { $self:ident, $name:expr, $args:ident, $instr:expr, $span:expr, $num_args:expr } => {
-        {
-            for_each_arg!($args, $num_args,
-                Some(e) => { $self.compile_expr(e) },
-                None => { $self.stack.push(allocate(Value::Nil)) }
-            );
-
-            $self.instructions.push((Instr($instr, vec![]), $span));
-        }
-    };
-    { $self:ident, $name:expr, $args:ident, $instr:expr, $span:expr } => {
-        compile_call!($self, $name, $args, $instr, $span, 1)
-    };
-}
-
 pub struct VM {
     src: String,
     pc: usize,
@@ -101,6 +106,19 @@ pub struct VM {
     call_stack: CallStack,
 
     impl_methods: HashMap<(String, Type), FunctionData>,
+    /// Whether the function being compiled yields, `None` outside functions.
+    yields: Option<bool>,
+    handlers: Vec<Handler>,
+    max_call_depth: usize,
+    /// Whether runtime errors list every call that led to them.
+    backtrace: bool,
+    /// The names of the variables, by their id.
+    var_names: HashMap<VarId, String>,
+    lines: Lines,
+    debugger: Option<Debugger>,
+    profiler: Option<Profiler>,
+    /// Whether every instruction is logged as it runs.
+    trace: bool,
 }
 
 impl VM {
@@ -121,29 +139,130 @@ impl VM {
             functions: HashMap::new(),
             call_stack: CallStack::new(),
             impl_methods: HashMap::new(),
+            yields: None,
+            handlers: vec![],
+            max_call_depth: MAX_CALL_DEPTH,
+            backtrace: false,
+            var_names: HashMap::new(),
+            lines: Lines::new(src),
+            debugger: None,
+            profiler: None,
+            trace: false,
             // memory: Memory::new(),
         }
     }
 
+    /// Sets how many calls can be nested before the program stops with a
+    /// stack overflow.
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.max_call_depth = depth;
+    }
+
+    /// Makes runtime errors list every call that led to them, instead of only
+    /// pointing at the innermost ones.
+    pub fn set_backtrace(&mut self, backtrace: bool) {
+        self.backtrace = backtrace;
+    }
+
+    /// Runs the program in the debugger, which pauses at the first line or
+    /// at the given breakpoints.
+    pub fn set_debugger(&mut self, breakpoints: Vec<usize>) {
+        self.debugger = Some(Debugger::new(breakpoints));
+    }
+
+    /// Counts and times the instructions that run, reporting them when the
+    /// program ends. The folded stacks are also written to `folded`.
+    pub fn set_profiler(&mut self, folded: Option<String>) {
+        self.profiler = Some(Profiler::new(folded));
+    }
+
+    /// Logs every instruction as it runs, to stderr.
+    pub fn set_trace(&mut self, trace: bool) {
+        self.trace = trace;
+    }
+
+    /// The line of the span of an instruction, `None` for the ones that
+    /// aren't written in the source, like returns.
+    fn line_of(&self, span: &Range<usize>) -> Option<usize> {
+        (!span.is_empty()).then(|| self.lines.line_of(span.start))
+    }
+
     pub fn run(&mut self) {
+        if let Some(debugger) = &mut self.debugger {
+            let spans = self.instructions.iter().map(|(_, span)| span);
+            let lines = spans.filter(|span| !span.is_empty());
+            debugger.load(lines.map(|span| self.lines.line_of(span.start)));
+        }
+
         while self.pc < self.instructions.len() {
-            if self.iteration == GC_TRIGGER {
-                self.gc_recollect();
+            match self.step() {
+                Ok(true) => break,
+                Ok(false) => {}
+                Err(error) => self.report_error(error),
             }
+        }
+
+        self.gc_recollect();
+        self.report_profile();
+    }
 
-            let instr = &self.instructions[self.pc];
-            if self.run_byte(instr.0.clone(), instr.1.clone()) {
+    /// Runs at most `steps` instructions, returning the error no handler
+    /// caught instead of reporting it.
+    #[cfg(test)]
+    pub(crate) fn run_steps(&mut self, steps: usize) -> Result<()> {
+        for _ in 0..steps {
+            if self.pc >= self.instructions.len() || self.step()? {
                 break;
             }
         }
+        Ok(())
+    }
 
-        self.gc_recollect();
+    /// Runs the next instruction, `true` once the program is done.
+    fn step(&mut self) -> Result<bool> {
+        if self.iteration == GC_TRIGGER {
+            self.gc_recollect();
+        }
+
+        let (instr, span) = self.instructions[self.pc].clone();
+        if let Some(debugger) = &mut self.debugger {
+            let line = (!span.is_empty()).then(|| self.lines.line_of(span.start));
+            if debugger.arrive(self.pc, line, self.call_stack.len()) {
+                self.debug_pause(&span);
+            }
+        }
+        if self.trace {
+            self.trace_instruction(self.pc);
+        }
+
+        let pc = self.pc;
+        let start = self.profiler.is_some().then(std::time::Instant::now);
+        let outcome = self.run_byte(instr, span);
+        if let Some(start) = start {
+            self.profile_instruction(pc, start.elapsed());
+        }
+
+        match outcome {
+            Err(error) if self.handlers.iter().any(|h| self.is_handling(h)) => {
+                self.catch_error(error.into());
+                Ok(false)
+            }
+            outcome => outcome,
+        }
     }
 
     pub fn compile(&mut self) {
+        if let Err(error) = self.try_compile() {
+            self.report_error(error);
+        }
+    }
+
+    /// Compiles the program, returning the first error instead of reporting
+    /// it.
+    pub(crate) fn try_compile(&mut self) -> Result<()> {
         let exprs = self.exprs.clone();
         for expr in exprs.iter() {
-            self.compile_expr(expr.clone());
+            self.compile_expr(expr.clone())?;
         }
 
         self.instructions.push((Instr(Halt, vec![]), 0..0));
@@ -151,9 +270,74 @@ impl VM {
         // for (idx, (Instr(bytecode, args), _)) in self.instructions.iter().enumerate() {
         //     println!("instr[{idx}] = ({bytecode}, {args:?})");
         // }
+        Ok(())
+    }
+
+    /// Runs the peephole optimizer over the compiled instructions.
+    pub fn optimize(&mut self) {
+        let functions = self.functions.values_mut();
+        peephole::optimize(
+            &mut self.instructions,
+            functions.chain(self.impl_methods.values_mut()),
+        );
+    }
+
+    /// The compiled program, to be written to a bytecode file. `path` is
+    /// where the source was read from, `spans` keeps the spans of the
+    /// instructions and the source they point into.
+    pub(crate) fn program(&self, path: &str, spans: bool) -> Program {
+        let instructions = self.instructions.iter().cloned();
+        Program {
+            src: if spans {
+                self.src.clone()
+            } else {
+                String::new()
+            },
+            path: path.to_string(),
+            checksum: cache::checksum(&self.src),
+            spans,
+            precision: float_precision(),
+            instructions: match spans {
+                true => instructions.collect(),
+                false => instructions.map(|(instr, _)| (instr, 0..0)).collect(),
+            },
+            constants: self.constants.clone(),
+            functions: self.functions.clone(),
+            impl_methods: self.impl_methods.clone(),
+            scopes: self
+                .variables
+                .iter()
+                .map(|scope| scope.keys().copied().collect())
+                .collect(),
+            var_id_count: self.var_id_count,
+            var_names: self.var_names.clone(),
+            variables_id: self.variables_id.clone(),
+            stack_len: self.stack.len(),
+        }
+    }
+
+    /// A VM ready to run a program loaded from a bytecode file.
+    pub(crate) fn from_program(program: Program) -> Self {
+        let mut vm = Self::new(&program.src, vec![]);
+        vm.instructions = program.instructions;
+        vm.constants = program.constants;
+        vm.functions = program.functions;
+        vm.impl_methods = program.impl_methods;
+        vm.variables = program
+            .scopes
+            .into_iter()
+            .map(|scope| scope.into_iter().map(|id| (id, None)).collect())
+            .collect();
+        vm.var_id_count = program.var_id_count;
+        vm.var_names = program.var_names;
+        vm.variables_id = program.variables_id;
+        vm.stack = (0..program.stack_len)
+            .map(|_| allocate(Value::Nil))
+            .collect();
+        vm
     }
 
-    fn compile_expr(&mut self, expr: Expr) {
+    fn compile_expr(&mut self, expr: Expr) -> Result<()> {
         match expr.inner {
             ExprKind::Int(integer) => {
                 let index = self.add_constant(Value::Int(integer));
@@ -176,18 +360,18 @@ impl VM {
             ExprKind::Postfix(expr, op) => match op {
                 PostfixOp::Increase => {
                     let span = expr.span.clone();
-                    self.compile_expr(*expr);
+                    self.compile_expr(*expr)?;
                     self.instructions.push((Instr(Inc, vec![]), span));
                 }
 
                 PostfixOp::Decrease => {
                     let span = expr.span.clone();
-                    self.compile_expr(*expr);
+                    self.compile_expr(*expr)?;
                     self.instructions.push((Instr(Dec, vec![]), span));
                 }
 
                 PostfixOp::Factorial => {
-                    self.compile_expr(*expr.clone());
+                    self.compile_expr(*expr.clone())?;
                     self.instructions
                         .push((Instr(Factorial, vec![]), expr.span));
                 }
@@ -197,13 +381,13 @@ impl VM {
                 let id = self.variables_id.clone();
                 let id = id.get(&name);
                 if self.variables_id.get(&name).is_none() {
-                    self.runtime_error("Variable not found", expr.span.clone());
+                    return Err(self.runtime_error("Variable not found", expr.span.clone()));
                 }
 
                 let id = id.unwrap();
                 self.instructions
                     .push((Instr(GetVar, vec![*id as usize]), expr.span.clone()));
-                self.compile_expr(*val);
+                self.compile_expr(*val)?;
                 match op {
                     BinaryOp::AddEq => {
                         self.instructions
@@ -232,7 +416,7 @@ impl VM {
             ExprKind::Ident(x) => {
                 let id = self.variables_id.get(&x);
                 if id.is_none() {
-                    self.runtime_error("Variable not found", expr.span);
+                    return Err(self.runtime_error("Variable not found", expr.span));
                 }
 
                 let id = id.unwrap();
@@ -241,8 +425,8 @@ impl VM {
             }
 
             ExprKind::Index(array, index) => {
-                self.compile_expr(*array);
-                self.compile_expr(*index);
+                self.compile_expr(*array)?;
+                self.compile_expr(*index)?;
 
                 self.instructions.push((Instr(Index, vec![]), expr.span))
             }
@@ -251,21 +435,23 @@ impl VM {
                 // Check if the variable exists
                 // If not create a new one
                 if self.variables_id.get(&name).is_none() {
-                    self.variables_id.insert(name, self.var_id_count as u32);
+                    // reserved before the value is compiled, which may need
+                    // hidden variables of its own
+                    let id = self.var_id_count;
+                    self.var_id_count += 1;
+                    self.bind_name(name, id as u32);
 
                     self.instructions
                         .push((Instr(MakeVar, vec![]), expr.span.clone()));
 
-                    self.compile_expr(*value);
+                    self.compile_expr(*value)?;
 
                     self.instructions
-                        .push((Instr(Replace, vec![self.var_id_count]), expr.span));
-
-                    self.var_id_count += 1;
-                    return;
+                        .push((Instr(Replace, vec![id]), expr.span));
+                    return Ok(());
                 }
 
-                self.compile_expr(*value);
+                self.compile_expr(*value)?;
                 self.instructions.push((
                     Instr(
                         Replace,
@@ -275,6 +461,26 @@ impl VM {
                 ));
             }
 
+            ExprKind::Destructure(pattern, value) => match (pattern, value.inner) {
+                // nothing to pack into an array and unpack again, the items
+                // are stored from the last one as that's on top of the stack
+                (AstPattern::Array(patterns), ExprKind::Array(values))
+                    if patterns.len() == values.len() =>
+                {
+                    for value in values {
+                        self.compile_expr(value)?;
+                    }
+
+                    for pattern in patterns.into_iter().rev() {
+                        self.compile_pattern(pattern, expr.span.clone())?;
+                    }
+                }
+                (pattern, inner) => {
+                    self.compile_expr(Expr::new(value.span, inner))?;
+                    self.compile_pattern(pattern, expr.span)?;
+                }
+            },
+
             ExprKind::String(string) => {
                 let index = self.add_constant(Value::String(string));
                 self.instructions
@@ -282,68 +488,17 @@ impl VM {
             }
 
             ExprKind::FString(value) => {
-                let mut iter = value.chars();
-
-                let mut buffer = String::with_capacity(50);
-                let mut escaped = false;
-                let mut placeholder_start = false;
-                let mut dollar_placeholder = false;
-                let mut len = 0;
-
-                while let Some(ch) = iter.next() {
-                    if ch == '\\' {
-                        escaped = true;
-                        if let Some(next_ch) = iter.next() {
-                            match next_ch {
-                                'n' => buffer.push_str("\\n"),
-                                't' => buffer.push_str("\\t"),
-                                'r' => buffer.push_str("\\r"),
-                                'x' => buffer.push_str("\\x"),
-                                _ => buffer.push(next_ch),
-                            }
+                let parts = fstring_parts(&value);
+                let len = parts.len();
+                for part in parts {
+                    match part {
+                        FStringPart::Text(text) => {
+                            self.push_data(Value::String(text), expr.span.clone())
+                        }
+                        FStringPart::Code(code) => {
+                            process_placeholder!(self, &code, expr.span);
                         }
-                    } else if !escaped && !dollar_placeholder && ch == '}' && placeholder_start {
-                        process_placeholder!(self, &buffer, expr.span);
-
-                        buffer.clear();
-                        placeholder_start = false;
-
-                        len += 1;
-                    } else if !escaped && !dollar_placeholder && ch == '{' {
-                        self.push_data(Value::String(buffer.clone()), expr.span.clone());
-
-                        buffer.clear();
-                        placeholder_start = true;
-
-                        len += 1;
-                    } else if !escaped && !placeholder_start && !dollar_placeholder && ch == '$' {
-                        self.push_data(Value::String(buffer.clone()), expr.span.clone());
-
-                        buffer.clear();
-                        dollar_placeholder = true;
-
-                        len += 1;
-                    } else if dollar_placeholder && ch == ' ' {
-                        process_placeholder!(self, &buffer, expr.span);
-
-                        buffer.clear();
-                        buffer.push(' ');
-                        dollar_placeholder = false;
-
-                        len += 1;
-                    } else {
-                        buffer.push(ch);
-                        escaped = false;
-                    }
-                }
-
-                if !buffer.is_empty() {
-                    if dollar_placeholder {
-                        process_placeholder!(self, &buffer, expr.span);
-                    } else {
-                        self.push_data(Value::String(buffer.clone()), expr.span.clone());
                     }
-                    len += 1;
                 }
 
                 self.instructions
@@ -359,7 +514,7 @@ impl VM {
             ExprKind::Array(val) => {
                 let len = val.len();
                 for elem in val {
-                    self.compile_expr(elem);
+                    self.compile_expr(elem)?;
                 }
 
                 self.instructions.push((Instr(Array, vec![len]), expr.span));
@@ -370,8 +525,8 @@ impl VM {
                     (&op, &b.inner),
                     (&BinaryOp::Attr, &ExprKind::Call(..) | &ExprKind::Int(..))
                 ) {
-                    self.compile_expr(*a.clone());
-                    self.compile_expr(*b.clone());
+                    self.compile_expr(*a.clone())?;
+                    self.compile_expr(*b.clone())?;
                 }
 
                 match op {
@@ -400,23 +555,27 @@ impl VM {
                     BinaryOp::Attr => match b.inner {
                         ExprKind::Call(name, args) => {
                             inbuilt_methods!(self, name.as_str(), args,
-                                [ "push"  => [Type::Array, Type::String], 1, expr.span, { self.compile_expr(*a); } ],
-                                [ "clear" => [Type::Array, Type::String], 0, expr.span, { self.compile_expr(*a); } ],
-                                [ "join"  => [Type::Array],               1, expr.span, { self.compile_expr(*a); } ],
-                                [ "split" => [Type::String],              1, expr.span, { self.compile_expr(*a); } ],
+                                [ "push"  => [Type::Array, Type::String, Type::Iter], 1, expr.span, { self.compile_expr(*a)?; } ],
+                                [ "clear" => [Type::Array, Type::String], 0, expr.span, { self.compile_expr(*a)?; } ],
+                                [ "join"  => [Type::Array, Type::Iter],   1, expr.span, { self.compile_expr(*a)?; } ],
+                                [ "split" => [Type::String],              1, expr.span, { self.compile_expr(*a)?; } ],
+                                [ "msg"   => [Type::Error],               0, expr.span, { self.compile_expr(*a)?; } ],
+                                [ "span"  => [Type::Error],               0, expr.span, { self.compile_expr(*a)?; } ],
                                 _ => {
-                                    for arg in args.unwrap_or_else(|| vec![]) {
-                                        self.compile_expr(arg);
+                                    let args = args.unwrap_or_else(|| vec![]);
+                                    let num_args = args.len();
+                                    for arg in args {
+                                        self.compile_expr(arg)?;
                                     }
 
-                                    self.compile_expr(*a);
+                                    self.compile_expr(*a)?;
 
                                     self.instructions.push((
                                         Instr(
                                             Bytecode::Method(MethodFunction {
                                                 name: name,
                                                 on_types: vec![],
-                                                num_args: 0,
+                                                num_args,
                                                 in_built: false
                                             }),
                                             vec![]
@@ -427,112 +586,77 @@ impl VM {
                             )
                         }
 
-                        _ => self.runtime_error("Expected a function call", expr.span),
+                        _ => return Err(self.runtime_error("Expected a function call", expr.span)),
                     },
                 }
             }
 
-            ExprKind::MultilineFunction(name, param_names, body) => {
-                let old_id = self.variables_id.clone();
-                self.variables_id.clear();
-
-                let mut scope = HashMap::new();
-
-                let mut fn_params = vec![];
-
-                for param_name in param_names.into_iter() {
-                    fn_params.push((param_name.clone(), self.var_id_count as _));
-                    self.variables_id.insert(param_name, self.var_id_count as _);
-                    scope.insert(self.var_id_count as _, None);
-                    self.var_id_count += 1;
-                }
+            ExprKind::MultilineFunction(name, params, _, body) => {
+                let function =
+                    self.compile_function(name.clone(), params, body, false, expr.span)?;
+                self.functions.insert(name, function);
+            }
 
-                let scope_idx = self.variables.len();
-                self.variables.push(scope);
+            ExprKind::Memoized(function) => {
+                let (ExprKind::InlineFunction(name, ..) | ExprKind::MultilineFunction(name, ..)) =
+                    &function.inner
+                else {
+                    unreachable!("the parser only memoizes functions")
+                };
 
-                let jmp_instr_ptr = self.instructions.len();
-                self.instructions.push((Instr(Jmp, vec![]), expr.span));
+                let name = name.clone();
+                self.compile_expr(*function)?;
+                self.functions.get_mut(&name).unwrap().memo = Some(HashMap::new());
+            }
 
-                let body_start = self.instructions.len();
-                let mut returns = false;
-                for expr in body {
-                    if matches![expr.inner, ExprKind::Return(..)] {
-                        returns = true;
-                    }
+            ExprKind::InlineFunction(name, params, returns, body) => {
+                self.compile_expr(Expr::new(
+                    expr.span,
+                    ExprKind::MultilineFunction(name, params, returns, vec![*body]),
+                ))?
+            }
 
-                    self.compile_expr(expr);
+            ExprKind::Return(val) => {
+                if self.yields.is_none() {
+                    return Err(self.runtime_error("& outside of a function", expr.span));
                 }
-
-                self.instructions.push((Instr(Ret, vec![]), 0..0));
-
-                let body_end = self.instructions.len();
-                self.instructions[jmp_instr_ptr].0 .1.push(body_end);
-
-                self.functions.insert(
-                    name.clone(),
-                    FunctionData {
-                        name: name.clone(),
-                        parameters: fn_params,
-                        instruction_range: body_start..body_end,
-                        scope_idx,
-                        returns,
-                    },
-                );
-
-                self.variables_id = old_id;
+                self.compile_expr(*val)?;
+                self.instructions.push((Instr(Ret, vec![]), expr.span));
             }
 
-            ExprKind::InlineFunction(name, param_names, body) => self.compile_expr(Expr::new(
-                expr.span,
-                ExprKind::MultilineFunction(name, param_names, vec![*body]),
-            )),
+            ExprKind::Yield(val) => {
+                let Some(yields) = self.yields.as_mut() else {
+                    return Err(self.runtime_error("yd outside of a function", expr.span));
+                };
+                *yields = true;
 
-            ExprKind::Return(val) => {
-                self.compile_expr(*val);
-                self.instructions.push((Instr(Ret, vec![]), expr.span));
+                self.compile_expr(*val)?;
+                self.instructions.push((Instr(Yield, vec![]), expr.span));
             }
 
-            ExprKind::Call(ref name, ref args) => match name.as_str() {
-                "$" => compile_call!(self, name, args, Println, expr.span),
-                "$$" => compile_call!(self, name, args, Print, expr.span),
-                "int" => compile_call!(self, name, args, ToInt, expr.span),
-                "flt" => compile_call!(self, name, args, ToFloat, expr.span),
-                "str" => compile_call!(self, name, args, ToString, expr.span),
-                "inp" => compile_call!(self, name, args, Input, expr.span),
-                "len" => compile_call!(self, name, args, Len, expr.span),
-                "type" => compile_call!(self, name, args, TypeOf, expr.span),
-                "gcd" => compile_call!(self, name, args, Gcd, expr.span, 2),
-                "lcm" => compile_call!(self, name, args, Lcm, expr.span, 2),
-                "fib" => compile_call!(self, name, args, Fib, expr.span),
-                "abs" => compile_call!(self, name, args, Abs, expr.span),
-                "floor" => compile_call!(self, name, args, Floor, expr.span),
-                "ceil" => compile_call!(self, name, args, Ceil, expr.span),
-                "exit" => compile_call!(self, name, args, Exit, expr.span),
-                "rnd" => self.handle_optional_args(args, None, Rand, expr.span),
-                "rng" => self.handle_optional_args(args, None, Range, expr.span),
-                "sqrt" => self.handle_optional_args(args, Some(Integer::from(2)), Sqrt, expr.span),
-                "round" => {
-                    self.handle_optional_args(args, Some(Integer::from(1)), Round, expr.span)
-                }
-
-                _ => {
-                    for_each_arg!(args, arg => { self.compile_expr(arg) });
+            ExprKind::Call(ref name, ref args) => match builtin(name) {
+                Some(builtin) => self.compile_builtin(builtin, args, expr.span)?,
+
+                None => {
+                    for_each_arg!(args, arg => { self.compile_expr(arg)?; });
 
+                    let argc = args.as_ref().map_or(0, Vec::len);
                     self.push_data(name.as_str().into(), expr.span.clone());
-                    self.instructions.push((Instr(FnCall, vec![]), expr.span));
+                    self.instructions
+                        .push((Instr(FnCall, vec![argc]), expr.span));
                     self.stack.push(allocate(Value::Nil));
                 }
             },
 
             ExprKind::Ternary(condition, then_block, else_block) => {
-                self.compile_expr(*condition);
+                self.compile_expr(*condition)?;
 
                 let ternary_instr_ptr = self.instructions.len();
                 self.instructions
                     .push((Instr(TernaryStart, vec![]), expr.span));
 
                 for expr in then_block {
-                    self.compile_expr(expr);
+                    self.compile_expr(expr)?;
                 }
 
                 let jump_instr_ptr = self.instructions.len();
@@ -540,7 +664,7 @@ impl VM {
 
                 let ternary_else_start = self.instructions.len();
                 for expr in else_block.unwrap_or(vec![]) {
-                    self.compile_expr(expr);
+                    self.compile_expr(expr)?;
                 }
 
                 let ternary_end = self.instructions.len();
@@ -554,14 +678,14 @@ impl VM {
 
             ExprKind::While(condition, body) => {
                 let body_start = self.instructions.len();
-                self.compile_expr(*condition);
+                self.compile_expr(*condition)?;
 
                 let while_instr_ptr = self.instructions.len();
                 self.instructions
                     .push((Instr(While, vec![]), expr.span.clone()));
 
                 for expr in body {
-                    self.compile_expr(expr);
+                    self.compile_expr(expr)?;
                 }
 
                 self.instructions.push((Instr(Jmp, vec![body_start]), 0..0));
@@ -578,7 +702,7 @@ impl VM {
                 let (parent_loop_instr_ptr, _) =
                     match self.find_parent_loop_start_instr(self.instructions.len()) {
                         Some(ptr) => ptr,
-                        None => self.runtime_error("break outside a loop?", expr.span),
+                        None => return Err(self.runtime_error("break outside a loop?", expr.span)),
                     };
 
                 self.instructions
@@ -589,7 +713,7 @@ impl VM {
                 let (parent_loop_instr_ptr, _) =
                     match self.find_parent_loop_start_instr(self.instructions.len()) {
                         Some(ptr) => ptr,
-                        None => self.runtime_error("break outside a loop?", expr.span),
+                        None => return Err(self.runtime_error("break outside a loop?", expr.span)),
                     };
 
                 self.instructions
@@ -597,7 +721,7 @@ impl VM {
             }
 
             ExprKind::Unary(op, expr) => {
-                self.compile_expr(*expr.clone());
+                self.compile_expr(*expr.clone())?;
 
                 match op {
                     UnaryOp::Not => self.instructions.push((Instr(Not, vec![]), expr.span)),
@@ -607,229 +731,301 @@ impl VM {
             }
 
             ExprKind::Every(list, body) => {
-                self.variables_id
-                    .insert("i".to_string(), self.var_id_count as u32);
-                self.instructions
-                    .push((Instr(MakeVar, vec![]), expr.span.clone()));
+                // the iterator lives in a hidden variable, so that every call
+                // of a recursive function gets its own loop state
+                let iter_ptr = self.var_id_count;
+                let var_ptr = self.var_id_count + 1;
+                self.var_id_count += 2;
+
+                self.compile_expr(*list)?;
                 self.instructions
-                    .push((Instr(Replace, vec![self.var_id_count]), expr.span.clone()));
+                    .push((Instr(IterStart, vec![iter_ptr]), expr.span.clone()));
 
-                let var_ptr = self.var_id_count;
-                self.var_id_count += 1;
+                let outer_i = self.bind_name("i".to_string(), var_ptr as u32);
 
                 let loop_start = self.instructions.len();
-                self.compile_expr(*list);
+                self.instructions
+                    .push((Instr(Every { iter_ptr, var_ptr }, vec![]), expr.span));
+
+                for expr in body {
+                    self.compile_expr(expr)?;
+                }
 
-                let instr_ptr = self.instructions.len();
-                let ran_once = Box::leak(Box::new(false));
+                self.instructions.push((Instr(Jmp, vec![loop_start]), 0..0));
 
-                self.instructions.push((
-                    Instr(
-                        Every {
-                            loop_end: 0, // will be changed later
-                            index: Box::leak(Box::new(0usize)),
-                            ran_once,
-                            var_ptr,
-                        },
-                        vec![],
-                    ),
-                    expr.span,
-                ));
+                let loop_end = self.instructions.len();
+                self.instructions[loop_start]
+                    .0
+                     .1
+                    .extend_from_slice(&[loop_end, loop_start]);
 
-                for expr in body {
-                    self.compile_expr(expr);
+                // `i` of a nested loop shouldn't leak into the outer one
+                if let Some(id) = outer_i {
+                    self.variables_id.insert("i".to_string(), id);
                 }
+            }
 
+            ExprKind::Try(body, name, handler) => {
+                let try_start = self.instructions.len();
                 self.instructions
-                    .push((Instr(ForLoopJmp { ran_once }, vec![loop_start]), 0..0));
+                    .push((Instr(TryStart, vec![]), expr.span.clone()));
 
-                let end = self.instructions.len();
-                let Every { loop_end, .. } = &mut self.instructions[instr_ptr].0 .0 else {
-                    unreachable!()
-                };
+                for expr in body {
+                    self.compile_expr(expr)?;
+                }
+
+                let try_end = self.instructions.len();
+                self.instructions.push((Instr(TryEnd, vec![]), 0..0));
+
+                // the error is on top of the stack when the handler starts
+                let catch_start = self.instructions.len();
+                match name {
+                    Some(name) => {
+                        let id = self.var_id(name);
+                        self.instructions
+                            .push((Instr(Replace, vec![id as usize]), expr.span));
+                    }
+                    None => self.instructions.push((Instr(Pop, vec![]), expr.span)),
+                }
+
+                for expr in handler {
+                    self.compile_expr(expr)?;
+                }
 
-                *loop_end = end;
+                let catch_end = self.instructions.len();
+                self.instructions[try_start]
+                    .0
+                     .1
+                    .extend_from_slice(&[catch_start, try_end]);
+                self.instructions[try_end].0 .1.push(catch_end);
             }
 
             ExprKind::Impl(tyname, body) => {
                 let Ok(ty) = Type::try_from(tyname.as_str()) else {
-                    self.runtime_error(&format!("Invalid type name: '{tyname}'"), expr.span);
+                    return Err(
+                        self.runtime_error(&format!("Invalid type name: '{tyname}'"), expr.span)
+                    );
                 };
 
                 for e in body {
                     // convert inline function to multiline
                     let e = match e {
                         Expr {
-                            inner: ExprKind::InlineFunction(name, params, body),
+                            inner: ExprKind::InlineFunction(name, params, returns, body),
                             span,
-                        } => {
-                            Expr::new(span, ExprKind::MultilineFunction(name, params, vec![*body]))
-                        }
+                        } => Expr::new(
+                            span,
+                            ExprKind::MultilineFunction(name, params, returns, vec![*body]),
+                        ),
 
                         _ => e,
                     };
 
-                    let ExprKind::MultilineFunction(name, param_names, body) = e.inner else {
-                        self.runtime_error(
+                    let ExprKind::MultilineFunction(name, params, _, body) = e.inner else {
+                        return Err(self.runtime_error(
                             "Only function declaration is allowed in impl block",
                             expr.span,
-                        );
+                        ));
                     };
 
-                    let old_id = self.variables_id.clone();
-                    self.variables_id.clear();
+                    let method = self.compile_function(name.clone(), params, body, true, e.span)?;
+                    self.impl_methods.insert((name, ty), method);
+                }
+            }
 
-                    let mut scope = HashMap::new();
-                    let mut fn_params: Vec<(String, u32)> = vec![];
+            ExprKind::Match(value, arms) => {
+                let value_ptr = self.var_id_count;
+                self.var_id_count += 1;
 
-                    // add self
-                    fn_params.push(("self".to_owned(), self.var_id_count as u32));
-                    self.variables_id
-                        .insert("self".to_owned(), self.var_id_count as _);
-                    scope.insert(self.var_id_count as _, None);
-                    self.var_id_count += 1;
+                self.compile_expr(*value)?;
+                self.instructions
+                    .push((Instr(Replace, vec![value_ptr]), expr.span.clone()));
+
+                let mut end_jumps = Vec::new();
+                for MatchArm {
+                    pattern,
+                    guard,
+                    body,
+                } in arms
+                {
+                    let mut bound = Vec::new();
+                    let matcher = self.compile_matcher(pattern, expr.span.clone(), &mut bound)?;
+                    let mut next_arm_jumps = vec![self.instructions.len()];
+                    self.instructions
+                        .push((Instr(MatchArm(matcher), vec![value_ptr]), expr.span.clone()));
 
-                    for param_name in param_names.into_iter() {
-                        fn_params.push((param_name.clone(), self.var_id_count as u32));
-                        self.variables_id.insert(param_name, self.var_id_count as _);
-                        scope.insert(self.var_id_count as _, None);
-                        self.var_id_count += 1;
+                    if let Some(guard) = guard {
+                        let guard_span = guard.span.clone();
+                        self.compile_expr(guard)?;
+                        next_arm_jumps.push(self.instructions.len());
+                        self.instructions
+                            .push((Instr(TernaryStart, vec![]), guard_span));
                     }
 
-                    let scope_idx = self.variables.len();
-                    self.variables.push(scope);
-
-                    let jmp_instr_ptr = self.instructions.len();
-                    self.instructions.push((Instr(Jmp, vec![]), e.span));
-
-                    let body_start = self.instructions.len();
-                    let mut returns = false;
+                    // every arm leaves exactly one value, like a ternary
+                    if body.is_empty() {
+                        self.push_data(Value::Nil, expr.span.clone());
+                    }
                     for expr in body {
-                        if matches![expr.inner, ExprKind::Return(..)] {
-                            returns = true;
-                        }
-
-                        self.compile_expr(expr);
+                        self.compile_expr(expr)?;
+                    }
+                    for (name, outer) in bound.into_iter().rev() {
+                        match outer {
+                            Some(id) => self.bind_name(name, id),
+                            None => self.variables_id.remove(&name),
+                        };
                     }
 
-                    self.instructions.push((Instr(Ret, vec![]), 0..0));
+                    end_jumps.push(self.instructions.len());
+                    self.instructions.push((Instr(Jmp, vec![]), 0..0));
 
-                    let body_end = self.instructions.len();
-                    self.instructions[jmp_instr_ptr].0 .1.push(body_end);
+                    let next_arm = self.instructions.len();
+                    for ptr in next_arm_jumps {
+                        self.instructions[ptr].0 .1.push(next_arm);
+                    }
+                }
 
-                    self.impl_methods.insert(
-                        (name.clone(), ty),
-                        FunctionData {
-                            name,
-                            parameters: fn_params,
-                            instruction_range: body_start..body_end,
-                            scope_idx,
-                            returns,
-                        },
-                    );
+                // nothing matched
+                self.push_data(Value::Nil, expr.span);
 
-                    self.variables_id = old_id;
+                let match_end = self.instructions.len();
+                for ptr in end_jumps {
+                    self.instructions[ptr].0 .1.push(match_end);
                 }
             }
 
-            ExprKind::Match(lhs, branches) => {
-                let mut conditionals = branches
-                    .clone()
-                    .into_iter()
-                    .filter_map(|(rhs, block)| {
-                        if let ExprKind::DefaultCase = rhs.inner {
-                            None
-                        } else {
-                            Some(Expr::new(
-                                rhs.span.start
-                                    ..block.last().map(|i| i.span.end).unwrap_or(rhs.span.end),
-                                ExprKind::Ternary(
-                                    Box::new(Expr::new(
-                                        rhs.span.clone(),
-                                        ExprKind::Binary(
-                                            lhs.clone(),
-                                            BinaryOp::Eq,
-                                            Box::new(rhs.clone()),
-                                        ),
-                                    )),
-                                    block,
-                                    None,
-                                ),
-                            ))
-                        }
-                    })
-                    .collect::<Vec<_>>();
-
-                if let Some((default_case, block)) = branches
-                    .into_iter()
-                    .find(|(rhs, _)| matches!(rhs.inner, ExprKind::DefaultCase))
-                {
-                    conditionals.push(Expr::new(
-                        default_case.span.start
-                            ..block
-                                .last()
-                                .map(|i| i.span.end)
-                                .unwrap_or(default_case.span.end),
-                        ExprKind::Ternary(
-                            Box::new(Expr::new(default_case.span.clone(), ExprKind::Bool(true))),
-                            block,
-                            None,
-                        ),
-                    ));
-                }
+            _ => {}
+        }
 
-                if !conditionals.is_empty() {
-                    let mut iter = conditionals.into_iter();
-                    let mut first = iter.next().unwrap();
+        Ok(())
+    }
 
-                    let mut else_block_ptr = &mut first;
+    pub fn add_constant(&mut self, val: Value) -> usize {
+        self.constants.push(val);
+        self.constants.len()
+    }
 
-                    for c in iter {
-                        let Expr {
-                            inner: ExprKind::Ternary(_, _, else_block),
-                            ..
-                        } = else_block_ptr
-                        else {
-                            unreachable!();
-                        };
+    fn runtime_error(&self, message: &str, span: Range<usize>) -> RuntimeError {
+        self.runtime_error_with_help(message, None, span)
+    }
 
-                        *else_block = Some(vec![c]);
-                        else_block_ptr = else_block.as_mut().unwrap().last_mut().unwrap();
-                    }
+    fn runtime_error_with_help(
+        &self,
+        message: &str,
+        help: Option<String>,
+        span: Range<usize>,
+    ) -> RuntimeError {
+        RuntimeError {
+            message: message.to_string(),
+            help,
+            span,
+        }
+    }
 
-                    self.compile_expr(first);
-                }
+    /// Shows an error no handler caught and stops the program.
+    fn report_error(&self, error: RuntimeError) -> ! {
+        let RuntimeError {
+            message,
+            help,
+            span,
+        } = error;
+
+        // the places the innermost calls were made from, unless all of them
+        // are listed
+        let calls = self.backtrace();
+        let listed = self.backtrace && !calls.is_empty();
+        let mut labels = vec![LabeledSpan::at(span.clone(), message.clone())];
+        let mut labeled = vec![span];
+        for TracedCall {
+            call,
+            span,
+            count,
+            tail,
+            ..
+        } in calls.iter().rev()
+        {
+            if listed || labeled.len() > 3 {
+                break;
+            }
+            if labeled.contains(span) {
+                continue;
             }
 
-            _ => {}
+            let called = if *tail { "tail called" } else { "called" };
+            let label = match count {
+                1 => format!("`{call}` {called} here"),
+                _ => format!("`{call}` {called} here ({count} times)"),
+            };
+            labels.push(LabeledSpan::at(span.clone(), label));
+            labeled.push(span.clone());
         }
+
+        let help = match help {
+            _ if listed => Some(self.backtrace_listing(&calls)),
+            None if calls.len() >= labeled.len() => Some(format!(
+                "{} calls deep, use --backtrace to list them all",
+                self.call_stack.len()
+            )),
+            help => help,
+        };
+        // a stripped bytecode file has no source for the labels to point into
+        let (title, labels) = match self.src.is_empty() {
+            true => (format!("Runtime Error: {message}"), vec![]),
+            false => ("Runtime Error".to_string(), labels),
+        };
+        let report = match help {
+            Some(help) => miette!(labels = labels, help = help, "{title}"),
+            None => miette!(labels = labels, "{title}"),
+        };
+        println!("{:?}", report.with_source_code(self.src.clone()));
+
+        self.report_profile();
+        std::process::exit(1);
     }
 
-    pub fn add_constant(&mut self, val: Value) -> usize {
-        self.constants.push(val);
-        self.constants.len()
+    /// Whether the current instruction is inside the `tr` block of `handler`,
+    /// handlers that were left through `br`, `ct` or `&` are not.
+    fn is_handling(&self, handler: &Handler) -> bool {
+        let pc = match handler.call_depth.cmp(&self.call_stack.len()) {
+            std::cmp::Ordering::Equal => self.pc,
+            // the call made from the `tr` block
+            std::cmp::Ordering::Less => self.call_stack[handler.call_depth].pc_before,
+            std::cmp::Ordering::Greater => return false,
+        };
+
+        handler.body.contains(&pc)
     }
 
-    fn runtime_error(&self, message: &str, span: Range<usize>) -> ! {
-        let reason = message.to_string();
-        println!(
-            "{:?}",
-            miette!(
-                labels = vec![LabeledSpan::at(span, reason)],
-                "Runtime Error"
-            )
-            .with_source_code(self.src.clone())
-        );
+    /// Unwinds the calls and the stack up to the innermost handler.
+    fn catch_error(&mut self, error: ErrorValue) {
+        let handler = loop {
+            let handler = self.handlers.pop().unwrap();
+            if self.is_handling(&handler) {
+                break handler;
+            }
+        };
 
-        std::process::exit(1);
+        while self.call_stack.len() > handler.call_depth {
+            self.pop_call_stack();
+        }
+
+        self.stack.truncate(handler.stack_len);
+        self.stack.push(allocate(Value::Error(Box::new(error))));
+        self.pc = handler.catch_pc;
+    }
+
+    /// The scope of the running function, or the global one.
+    fn current_scope(&self) -> usize {
+        self.call_stack.last().map_or(0, |frame| frame.scope_idx)
     }
 
-    fn get_var(&mut self, id: u32) -> Option<NonNull<Value>> {
+    fn get_var(&self, id: u32) -> Option<NonNull<Value>> {
         let mut scope_index = (self.variables.len() - 1) as i64;
         while scope_index >= 0 {
             if let Some(scope) = self.variables.get(scope_index as usize) {
+                // declared in the scope but not set yet
                 if let Some(&v) = scope.get(&id) {
-                    return Some(v.unwrap());
+                    return v;
                 }
             }
             scope_index -= 1;
@@ -838,7 +1034,7 @@ impl VM {
         None
     }
 
-    fn run_byte(&mut self, instr: Instr, span: Range<usize>) -> bool {
+    fn run_byte(&mut self, instr: Instr, span: Range<usize>) -> Result<bool> {
         let args = instr.1.clone();
         let byte = instr.0;
 
@@ -849,33 +1045,25 @@ impl VM {
         match byte {
             Halt => {
                 self.gc_recollect();
-                return true;
-            }
-
-            Push => {
-                let value = self.stack.last().cloned();
-                match value {
-                    Some(val) => self.stack.push(val),
-                    None => self.runtime_error("Stack underflow", span),
-                }
+                return Ok(true);
             }
 
             Dup => {
                 let value = self.stack.last().cloned();
                 match value {
                     Some(val) => self.stack.push(val),
-                    None => self.runtime_error("Stack underflow", span),
+                    None => return Err(self.runtime_error("Stack underflow", span)),
                 }
             }
 
             Pop => {
                 if self.stack.pop().is_none() {
-                    self.runtime_error("Stack underflow", span);
+                    return Err(self.runtime_error("Stack underflow", span));
                 }
             }
 
             TypeOf => unsafe {
-                let value = self.stack.pop().unwrap();
+                let value = self.pop(&span)?;
                 let ty = value.as_ref().get_type();
                 self.stack
                     .push(NonNull::new_unchecked(alloc_new_value(Value::String(
@@ -884,12 +1072,12 @@ impl VM {
             },
 
             ToString => unsafe {
-                let s = self.stack.pop().unwrap().as_ref().to_string();
+                let s = self.pop(&span)?.as_ref().to_string();
                 self.stack.push(allocate(Value::String(s)));
             },
 
             Sqrt => unsafe {
-                let sqrt_to = self.stack.pop().unwrap().as_ref();
+                let sqrt_to = self.pop(&span)?.as_ref();
                 let value = self
                     .stack
                     .pop()
@@ -897,8 +1085,10 @@ impl VM {
                     .as_ref();
                 let sqrt_to = match sqrt_to {
                     Value::Int(i) => i.saturating_cast(),
-                    Value::Float(f) => f.to_u32_saturating().unwrap(),
-                    _ => self.runtime_error("Expected a number", span),
+                    Value::Float(f) => f.to_u32_saturating().ok_or_else(|| {
+                        self.runtime_error("Expected a finite number", span.clone())
+                    })?,
+                    _ => return Err(self.runtime_error("Expected a number", span)),
                 };
                 let value = match value {
                     Value::Int(i) => match sqrt_to {
@@ -911,7 +1101,7 @@ impl VM {
                         3 => f.clone().cbrt(),
                         _ => f.clone().root(sqrt_to),
                     },
-                    _ => self.runtime_error("Expected a number", span),
+                    _ => return Err(self.runtime_error("Expected a number", span)),
                 };
 
                 self.stack
@@ -919,8 +1109,8 @@ impl VM {
             },
 
             Gcd => unsafe {
-                let a = self.stack.pop().unwrap().as_ref();
-                let b = self.stack.pop().unwrap().as_ref();
+                let a = self.pop(&span)?.as_ref();
+                let b = self.pop(&span)?.as_ref();
 
                 let gcd = match (a, b) {
                     (Value::Int(a), Value::Int(b)) => a.gcd_ref(b).complete(),
@@ -934,7 +1124,7 @@ impl VM {
                         .to_integer()
                         .unwrap_or(Integer::from(0))
                         .gcd(&b.to_integer().unwrap_or(Integer::from(0))),
-                    _ => self.runtime_error("Expected a number", span),
+                    _ => return Err(self.runtime_error("Expected a number", span)),
                 };
 
                 self.stack
@@ -942,8 +1132,8 @@ impl VM {
             },
 
             Lcm => unsafe {
-                let a = self.stack.pop().unwrap().as_ref();
-                let b = self.stack.pop().unwrap().as_ref();
+                let a = self.pop(&span)?.as_ref();
+                let b = self.pop(&span)?.as_ref();
 
                 let lcm = match (a, b) {
                     (Value::Int(a), Value::Int(b)) => a.lcm_ref(b).complete(),
@@ -957,7 +1147,7 @@ impl VM {
                         .to_integer()
                         .unwrap_or(Integer::from(0))
                         .lcm(&b.to_integer().unwrap_or(Integer::from(0))),
-                    _ => self.runtime_error("Expected a number", span),
+                    _ => return Err(self.runtime_error("Expected a number", span)),
                 };
 
                 self.stack
@@ -965,28 +1155,34 @@ impl VM {
             },
 
             Fib => unsafe {
-                let n = self.stack.pop().unwrap().as_ref();
+                let n = self.pop(&span)?.as_ref();
                 match n {
                     Value::Int(n) => {
+                        let n =
+                            self.check_size(n.saturating_cast(), MAX_FIBONACCI, "fib", &span)?;
                         self.stack
                             .push(NonNull::new_unchecked(alloc_new_value(Value::Int(
-                                Integer::from(Integer::fibonacci(n.saturating_cast())),
+                                Integer::from(Integer::fibonacci(n)),
                             ))));
                     }
 
                     Value::Float(n) => {
+                        let n = n.to_u32_saturating().ok_or_else(|| {
+                            self.runtime_error("Expected a finite number", span.clone())
+                        })?;
+                        let n = self.check_size(n, MAX_FIBONACCI, "fib", &span)?;
                         self.stack
                             .push(NonNull::new_unchecked(alloc_new_value(Value::Int(
-                                Integer::from(Integer::fibonacci(n.to_u32_saturating().unwrap())),
+                                Integer::from(Integer::fibonacci(n)),
                             ))));
                     }
 
-                    _ => self.runtime_error("Expected a number", span),
+                    _ => return Err(self.runtime_error("Expected a number", span)),
                 }
             },
 
             Abs => unsafe {
-                let n = self.stack.pop().unwrap().as_ref();
+                let n = self.pop(&span)?.as_ref();
                 match n {
                     Value::Int(n) => {
                         self.stack
@@ -998,17 +1194,17 @@ impl VM {
                     Value::Float(n) => {
                         self.stack
                             .push(NonNull::new_unchecked(alloc_new_value(Value::Float(
-                                n.abs_ref().complete(53),
+                                n.abs_ref().complete(float_precision()),
                             ))));
                     }
 
-                    _ => self.runtime_error("Expected a number", span),
+                    _ => return Err(self.runtime_error("Expected a number", span)),
                 }
             },
 
             Round => unsafe {
-                let precision = self.stack.pop().unwrap().as_ref();
-                let n = self.stack.pop().unwrap().as_ref();
+                let precision = self.pop(&span)?.as_ref();
+                let n = self.pop(&span)?.as_ref();
                 match (n, precision) {
                     (Value::Int(n), Value::Int(_)) => {
                         self.stack
@@ -1023,22 +1219,22 @@ impl VM {
                                 Float::parse(format!(
                                     "{:.1$}",
                                     n,
-                                    precision.to_usize().unwrap_or_else(|| self.runtime_error(
+                                    precision.to_usize().ok_or_else(|| self.runtime_error(
                                         "Precision must be a positive integer",
                                         span
-                                    ))
+                                    ))?
                                 ))
                                 .unwrap()
-                                .complete(53),
+                                .complete(float_precision()),
                             ))));
                     }
 
-                    _ => self.runtime_error("Expected a number", span),
+                    _ => return Err(self.runtime_error("Expected a number", span)),
                 }
             },
 
             Floor => unsafe {
-                let n = self.stack.pop().unwrap().as_ref();
+                let n = self.pop(&span)?.as_ref();
                 match n {
                     Value::Int(n) => {
                         self.stack
@@ -1050,16 +1246,16 @@ impl VM {
                     Value::Float(n) => {
                         self.stack
                             .push(NonNull::new_unchecked(alloc_new_value(Value::Float(
-                                n.floor_ref().complete(53),
+                                n.floor_ref().complete(float_precision()),
                             ))));
                     }
 
-                    _ => self.runtime_error("Expected a number", span),
+                    _ => return Err(self.runtime_error("Expected a number", span)),
                 }
             },
 
             Ceil => unsafe {
-                let n = self.stack.pop().unwrap().as_ref();
+                let n = self.pop(&span)?.as_ref();
                 match n {
                     Value::Int(n) => {
                         self.stack
@@ -1071,11 +1267,11 @@ impl VM {
                     Value::Float(n) => {
                         self.stack
                             .push(NonNull::new_unchecked(alloc_new_value(Value::Float(
-                                n.ceil_ref().complete(53),
+                                n.ceil_ref().complete(float_precision()),
                             ))));
                     }
 
-                    _ => self.runtime_error("Expected a number", span),
+                    _ => return Err(self.runtime_error("Expected a number", span)),
                 }
             },
 
@@ -1084,7 +1280,7 @@ impl VM {
                 let mut v = vec![];
 
                 for _ in 0..num_vals {
-                    v.push(self.stack.pop().unwrap().as_ref().to_string());
+                    v.push(self.pop(&span)?.as_ref().to_string());
                 }
 
                 v.reverse();
@@ -1092,80 +1288,306 @@ impl VM {
                 self.stack.push(allocate(Value::String(v.join(""))));
             },
 
-            Exit => std::process::exit(0),
-            Range => unsafe {
-                let end = self.stack.pop().unwrap().as_ref();
-                let start = self
-                    .stack
-                    .pop()
-                    .unwrap_or_else(|| allocate(Value::Int(Integer::from(0))))
-                    .as_ref();
+            // the program ends like it does after its last line
+            Exit => {
+                self.pc = self.instructions.len();
+                return Ok(true);
+            }
 
-                match (start, end) {
-                    (Value::Int(start), Value::Int(end)) => {
-                        let start_value: i64 = start.saturating_cast();
-                        let end_value: i64 = end.saturating_cast();
+            IsPrime => {
+                let n = self.pop_integer(span)?;
+                self.stack.push(allocate(Value::Bool(number::is_prime(&n))));
+            }
 
-                        let mut array = vec![];
-                        if start_value > end_value {
-                            for i in (end_value + 1..=start_value).rev() {
-                                array.push(Value::Int(Integer::from(i)));
-                            }
-                        } else {
-                            for i in start_value..end_value {
-                                array.push(Value::Int(Integer::from(i)));
-                            }
-                        }
+            NextPrime => {
+                let n = self.pop_integer(span)?;
+                self.stack.push(allocate(Value::Int(n.next_prime())));
+            }
 
-                        self.stack
-                            .push(NonNull::new_unchecked(alloc_new_value(Value::Array(array))));
-                    }
-                    _ => self.runtime_error("Expected an integer", span),
+            Factor => {
+                let n = self.pop_integer(span.clone())?;
+                if n < 1 {
+                    return Err(self.runtime_error(
+                        &format!("Expected a positive integer to factor, found {n}"),
+                        span,
+                    ));
                 }
-            },
 
-            Rand => unsafe {
-                let popped1 = self.stack.pop().unwrap();
-                let popped2 = self
-                    .stack
-                    .pop()
-                    .unwrap_or_else(|| allocate(Value::Int(Integer::from(0))));
-                let mut end = self.convert_to_i128(popped1.as_ref(), span.clone());
-                let mut start = self.convert_to_i128(popped2.as_ref(), span);
-                if start == end {
-                    self.stack
-                        .push(NonNull::new_unchecked(alloc_new_value(Value::Int(
-                            Integer::from(start),
-                        ))));
-                } else {
-                    if start > end {
-                        std::mem::swap(&mut start, &mut end);
-                    }
-                    self.stack
-                        .push(NonNull::new_unchecked(alloc_new_value(Value::Int(
-                            Integer::from(self.rng.i128(start..end)),
-                        ))));
+                let factors = number::factor(&n).into_iter().map(Value::Int).collect();
+                self.stack.push(allocate(Value::Array(factors)));
+            }
+
+            PowMod => {
+                let modulo = self.pop_integer(span.clone())?;
+                let exponent = self.pop_integer(span.clone())?;
+                let base = self.pop_integer(span.clone())?;
+                if modulo == 0 {
+                    return Err(self.runtime_error("Modulo cannot be zero", span));
                 }
-            },
 
-            MakeVar => {
-                self.variables
-                    .last_mut()
-                    .unwrap()
-                    .insert(self.var_id_count as u32, None);
+                let result = base.pow_mod(&exponent, &modulo).map_err(|_| {
+                    self.runtime_error("The base has no inverse for the negative exponent", span)
+                })?;
+                self.stack.push(allocate(Value::Int(result)));
             }
 
-            Replace => {
-                let value = self
-                    .stack
-                    .pop()
-                    .map(|i| unsafe { i.as_ref().clone() })
-                    .unwrap_or_else(|| Value::Nil);
+            InvMod => {
+                let modulo = self.pop_integer(span.clone())?;
+                let n = self.pop_integer(span.clone())?;
+                let result = n.clone().invert(&modulo).map_err(|_| {
+                    self.runtime_error(&format!("{n} has no inverse modulo {modulo}"), span)
+                })?;
+                self.stack.push(allocate(Value::Int(result)));
+            }
 
-                self.variables
-                    .last_mut()
-                    .unwrap()
-                    .insert(args[0] as u32, Some(allocate(value)));
+            Binomial => {
+                let k = self.pop_integer(span.clone())?;
+                let n = self.pop_integer(span.clone())?;
+                let Some(k) = k.to_u32() else {
+                    return Err(self.runtime_error(&format!("Cannot choose {k} items"), span));
+                };
+
+                self.stack.push(allocate(Value::Int(n.binomial(k))));
+            }
+
+            Isqrt => {
+                let n = self.pop_integer(span.clone())?;
+                if n < 0 {
+                    return Err(self
+                        .runtime_error("Cannot take the square root of a negative integer", span));
+                }
+
+                self.stack.push(allocate(Value::Int(n.sqrt())));
+            }
+
+            Digits => {
+                let base = self.pop_integer(span.clone())?;
+                let n = self.pop_integer(span.clone())?;
+                if base < 2 {
+                    return Err(self.runtime_error("Base must be at least 2", span));
+                }
+
+                let digits = number::digits(&n, &base)
+                    .into_iter()
+                    .map(Value::Int)
+                    .collect();
+                self.stack.push(allocate(Value::Array(digits)));
+            }
+
+            ToBase => {
+                let base = self.convert_to_radix(span.clone())?;
+                let n = self.pop_integer(span)?;
+                self.stack
+                    .push(allocate(Value::String(number::to_base(&n, base))));
+            }
+
+            FromBase => unsafe {
+                let base = self.convert_to_radix(span.clone())?;
+                let value = self.pop(&span)?.as_ref();
+                let Value::String(s) = value else {
+                    return Err(self.runtime_error(
+                        &format!("Expected 'str' to convert, found '{}'", value.get_type()),
+                        span,
+                    ));
+                };
+
+                let n = Integer::from_str_radix(s, base).map_err(|e| {
+                    self.runtime_error(&format!("Cannot parse '{s}' in base {base}, {e}"), span)
+                })?;
+                self.stack.push(allocate(Value::Int(n)));
+            },
+
+            Perms => unsafe {
+                let k = self.pop(&span)?.as_ref();
+                let pool = self.pop_pool(span.clone())?;
+                let k = match k {
+                    Value::Nil => pool.len(),
+                    _ => self.convert_to_count(k, span)?,
+                };
+
+                let perms = Iter::Permutations(Permutations::new(pool, k));
+                self.stack.push(allocate(Value::Iter(Box::new(perms))));
+            },
+
+            Combs => unsafe {
+                let k = self.pop(&span)?.as_ref();
+                let pool = self.pop_pool(span.clone())?;
+                let k = self.convert_to_count(k, span)?;
+
+                let combs = Iter::Combinations(Combinations::new(pool, k));
+                self.stack.push(allocate(Value::Iter(Box::new(combs))));
+            },
+
+            Powerset => {
+                let pool = self.pop_pool(span.clone())?;
+                let subsets = Iter::Powerset(Powerset::new(pool));
+                self.stack.push(allocate(Value::Iter(Box::new(subsets))));
+            }
+
+            Product => {
+                let mut pools = (0..args[0])
+                    .map(|_| self.pop_pool(span.clone()))
+                    .collect::<Result<Vec<_>>>()?;
+                pools.reverse();
+
+                let product = Iter::Product(CartesianProduct::new(pools));
+                self.stack.push(allocate(Value::Iter(Box::new(product))));
+            }
+
+            Pi | E => {
+                let prec = self.pop_precision(span)?;
+                self.stack
+                    .push(allocate(Value::Float(math::constant(&byte, prec))));
+            }
+
+            Sin | Cos | Tan | Asin | Acos | Atan | Ln | Log2 | Log10 | Exp => {
+                let prec = self.pop_precision(span.clone())?;
+                let x = self.pop_float(prec, span)?;
+                self.stack
+                    .push(allocate(Value::Float(math::unary(&byte, x))));
+            }
+
+            Atan2 | Hypot | Log => {
+                let prec = self.pop_precision(span.clone())?;
+                let b = self.pop_float(prec, span.clone())?;
+                let a = self.pop_float(prec, span)?;
+                self.stack
+                    .push(allocate(Value::Float(math::binary(&byte, a, b))));
+            }
+
+            Prec => unsafe {
+                let prec = self.pop(&span)?.as_ref();
+                let previous = match prec {
+                    Value::Nil => float_precision(),
+                    _ => set_float_precision(self.convert_to_precision(prec, span)?),
+                };
+
+                self.stack.push(allocate(Value::Int(previous.into())));
+            },
+
+            ReMatch | ReFind | ReCaptures | ReReplace | ReSplit => unsafe {
+                let replacement = if byte == ReReplace {
+                    Some(self.pop(&span)?.as_ref())
+                } else {
+                    None
+                };
+                let pattern = self.pop(&span)?.as_ref();
+                let haystack = self.pop(&span)?.as_ref();
+
+                let pattern = self.cached_pattern(args[0], pattern, span.clone())?;
+                let Value::String(haystack) = haystack else {
+                    return Err(self.runtime_error(
+                        &format!(
+                            "Expected 'str' to match against, found '{}'",
+                            haystack.get_type()
+                        ),
+                        span,
+                    ));
+                };
+
+                let result = match byte {
+                    ReMatch => pattern.is_match(haystack),
+                    ReFind => pattern.find_all(haystack),
+                    ReCaptures => pattern.captures(haystack),
+                    ReSplit => pattern.split(haystack),
+                    _ => match replacement {
+                        Some(Value::String(replacement)) => pattern.replace(haystack, replacement),
+                        Some(Value::Nil) => pattern.replace(haystack, ""),
+                        Some(v) => {
+                            return Err(self.runtime_error(
+                                &format!("Expected 'str' as replacement, found '{}'", v.get_type()),
+                                span,
+                            ))
+                        }
+                        None => unreachable!(),
+                    },
+                };
+
+                self.stack.push(allocate(result));
+            },
+            Range => unsafe {
+                let step = self.pop(&span)?.as_ref();
+                let end = self.pop(&span)?.as_ref();
+                let Value::Int(start) = self.pop(&span)?.as_ref() else {
+                    return Err(self.runtime_error("Expected an integer to start the range", span));
+                };
+
+                let (end, descending) = match end {
+                    Value::Int(end) => (Some(end.clone()), end < start),
+                    Value::Float(f) if f.is_infinite() => (None, f.is_sign_negative()),
+                    Value::Float(f) if f.is_integer() => {
+                        let end = f.to_integer().unwrap();
+                        let descending = end < *start;
+                        (Some(end), descending)
+                    }
+                    _ => {
+                        return Err(self.runtime_error(
+                            &format!(
+                                "Expected an integer or inf to end the range, found '{}'",
+                                end.get_type()
+                            ),
+                            span,
+                        ))
+                    }
+                };
+
+                let step = match step {
+                    Value::Nil => Integer::from(if descending { -1 } else { 1 }),
+                    Value::Int(step) if *step != 0 => step.clone(),
+                    Value::Int(_) => {
+                        return Err(self.runtime_error("The step of a range cannot be 0", span))
+                    }
+                    _ => {
+                        return Err(self.runtime_error(
+                            &format!("Expected an integer step, found '{}'", step.get_type()),
+                            span,
+                        ))
+                    }
+                };
+
+                let range = RangeIter::new(start.clone(), end, step);
+                self.stack
+                    .push(allocate(Value::Iter(Box::new(Iter::Range(range)))));
+            },
+
+            Rand => unsafe {
+                let popped1 = self.pop(&span)?;
+                let popped2 = self
+                    .stack
+                    .pop()
+                    .unwrap_or_else(|| allocate(Value::Int(Integer::from(0))));
+                let mut end = self.convert_to_i128(popped1.as_ref(), span.clone())?;
+                let mut start = self.convert_to_i128(popped2.as_ref(), span)?;
+                if start == end {
+                    self.stack
+                        .push(NonNull::new_unchecked(alloc_new_value(Value::Int(
+                            Integer::from(start),
+                        ))));
+                } else {
+                    if start > end {
+                        std::mem::swap(&mut start, &mut end);
+                    }
+                    self.stack
+                        .push(NonNull::new_unchecked(alloc_new_value(Value::Int(
+                            Integer::from(self.rng.i128(start..end)),
+                        ))));
+                }
+            },
+
+            MakeVar => {
+                let scope = self.current_scope();
+                self.variables[scope].insert(self.var_id_count as u32, None);
+            }
+
+            Replace => {
+                let value = self
+                    .stack
+                    .pop()
+                    .map(|i| unsafe { i.as_ref().clone() })
+                    .unwrap_or_else(|| Value::Nil);
+
+                let scope = self.current_scope();
+                self.variables[scope].insert(args[0] as u32, Some(allocate(value)));
             }
 
             GetVar => {
@@ -1174,7 +1596,7 @@ impl VM {
                 if self.get_var(id as u32).is_some() {
                     self.stack.push(v.unwrap_or_else(|| allocate(Value::Nil)));
                 } else {
-                    self.runtime_error("Variable not found", span)
+                    return Err(self.runtime_error("Variable not found", span));
                 }
             }
 
@@ -1184,12 +1606,12 @@ impl VM {
                     Some(c) => self
                         .stack
                         .push(NonNull::new_unchecked(alloc_new_value(c.to_owned()))),
-                    None => self.runtime_error("Stack overflow", span),
+                    None => return Err(self.runtime_error("Stack overflow", span)),
                 }
             },
 
             Not => unsafe {
-                let value = self.stack.pop().unwrap().as_ref();
+                let value = self.pop(&span)?.as_ref();
                 self.stack
                     .push(NonNull::new_unchecked(alloc_new_value(Value::Bool(
                         !value.bool_eval(),
@@ -1197,79 +1619,96 @@ impl VM {
             },
 
             Neg => unsafe {
-                let value = self.stack.pop().unwrap().as_ref();
+                let value = self.pop(&span)?.as_ref();
                 self.stack
                     .push(NonNull::new_unchecked(alloc_new_value(match value {
                         Value::Int(i) => Value::Int((-i).complete()),
-                        Value::Float(f) => Value::Float((-f).complete(53)),
-                        _ => self.runtime_error(
-                            &format!("Cannot negate the value of type {}", value.get_type()),
-                            span,
-                        ),
+                        Value::Float(f) => Value::Float((-f).complete(float_precision())),
+                        _ => {
+                            return Err(self.runtime_error(
+                                &format!("Cannot negate the value of type {}", value.get_type()),
+                                span,
+                            ))
+                        }
                     })));
             },
 
             While => unsafe {
-                let loop_end = args.get(0).unwrap_or_else(|| {
-                    self.runtime_error("Expected a loop end instruction pointer", span)
-                });
+                let loop_end = args.get(0).ok_or_else(|| {
+                    self.runtime_error("Expected a loop end instruction pointer", span.clone())
+                })?;
 
-                let condition = self.stack.pop().unwrap().as_ref().bool_eval();
+                let condition = self.pop(&span)?.as_ref().bool_eval();
 
                 if !condition {
                     self.pc = *loop_end;
-                    return false;
+                    return Ok(false);
                 }
             },
 
-            Every {
-                loop_end,
-                index,
-                ran_once,
-                var_ptr,
-            } => unsafe {
-                let array = self.stack.pop().unwrap().as_ref().as_array();
-
-                if *ran_once {
-                    *index += 1;
-                }
-
-                if *index < array.len() {
-                    // load the value into the variable
-                    *self
-                        .variables
-                        .last_mut()
-                        .unwrap()
-                        .get_mut(&(var_ptr as u32))
-                        .unwrap() = array.get(*index).map(|i| allocate(i.clone()));
-                } else {
-                    self.pc = loop_end;
-                    *index = 0;
-                    *ran_once = false;
-                    return false;
-                }
+            IterStart => unsafe {
+                let value = self.pop(&span)?.as_ref();
+                let Some(iter) = Iter::from_value(value) else {
+                    return Err(self.runtime_error(
+                        &format!(
+                            "Cannot iterate over the value of type '{}'",
+                            value.get_type()
+                        ),
+                        span,
+                    ));
+                };
+
+                let scope = self.current_scope();
+                self.variables[scope]
+                    .insert(args[0] as u32, Some(allocate(Value::Iter(Box::new(iter)))));
             },
 
-            FnCall => unsafe {
-                let fn_name = self
-                    .stack
-                    .pop()
-                    .unwrap_or_else(|| allocate(Value::Nil))
-                    .as_ref()
-                    .as_str();
-                let fn_obj_option = self.functions.get(fn_name);
-                if fn_obj_option.is_none() {
-                    self.runtime_error(format!("Function `{}` not found", fn_name).as_str(), span);
+            Every { iter_ptr, var_ptr } => unsafe {
+                let Some(Value::Iter(iter)) = self.get_var(iter_ptr as u32).map(|mut i| i.as_mut())
+                else {
+                    unreachable!("the iterator of a loop is always set before it starts")
+                };
+
+                let item = match iter.as_mut() {
+                    Iter::Generator(generator) => match generator.yielded.take() {
+                        Some(item) => Some(item),
+                        None if generator.done => None,
+                        None => {
+                            // the loop is run again once the generator yields
+                            let generator = generator.clone();
+                            self.resume_generator(generator, iter_ptr as u32)?;
+                            return Ok(false);
+                        }
+                    },
+                    iter => iter.next(),
+                };
+
+                match item {
+                    Some(item) => {
+                        let scope = self.current_scope();
+                        self.variables[scope].insert(var_ptr as u32, Some(allocate(item)));
+                    }
+                    None => {
+                        self.pc = args[0];
+                        return Ok(false);
+                    }
                 }
+            },
+
+            call @ (FnCall | TailCall) => unsafe {
+                let fn_name = self.pop(&span)?.as_ref();
+                let Some(fn_obj) = fn_name.as_str().and_then(|name| self.functions.get(name))
+                else {
+                    return Err(
+                        self.runtime_error(&format!("Function `{fn_name}` not found"), span)
+                    );
+                };
 
-                let fn_obj @ FunctionData {
-                    parameters,
-                    scope_idx,
-                    returns,
-                    ..
-                } = fn_obj_option.unwrap();
+                let FunctionData {
+                    scope_idx, returns, ..
+                } = fn_obj;
 
-                let mut fn_args = (0..parameters.len())
+                let mut fn_args = (0..args[0])
                     .map(|_| {
                         self.stack
                             .pop()
@@ -1279,87 +1718,257 @@ impl VM {
 
                 fn_args.reverse();
 
-                let variables = self.variables[*scope_idx].clone();
+                let memo_key = fn_obj
+                    .memo
+                    .as_ref()
+                    .map(|_| fn_args.iter().map(|arg| arg.as_ref().clone()).collect());
+                let cached = fn_obj.memo.as_ref().zip(memo_key.as_ref());
+                if let Some(value) = cached.and_then(|(memo, key)| memo.get(key)) {
+                    self.stack.push(allocate(value.clone()));
+                    self.pc += 1;
+                    return Ok(false);
+                }
+                let memo = memo_key.map(|key| (fn_obj.name.clone(), key));
+
+                let (fn_args, entry) = self.bind_args(fn_obj, fn_args, 0, span)?;
+
+                if fn_obj.generator {
+                    let generator = Self::new_generator(fn_obj, fn_args, entry);
+                    self.stack.push(allocate(generator));
+                    self.pc += 1;
+                    return Ok(false);
+                }
+
+                let (scope_idx, returns) = (*scope_idx, *returns);
+                let param_ids = fn_obj.get_var_ids();
+
+                // the caller is done, so its frame is restored as if it had
+                // returned and then handed over to the callee
+                let frame = self.call_stack.last_mut();
+                // the result of a memoized call is cached when its own frame returns
+                let tail_call = call == TailCall
+                    && memo.is_none()
+                    && frame
+                        .as_ref()
+                        .is_some_and(|frame| frame.generator.is_none() && frame.memo.is_none());
+                if let Some(frame) = frame.filter(|_| tail_call) {
+                    self.variables[frame.scope_idx] = std::mem::take(&mut frame.variables);
+                    self.stack.truncate(frame.previous_stack_len);
+                }
+
+                let variables = self.variables[scope_idx].clone();
 
-                for (idx, param_var_idx) in fn_obj.get_var_ids().into_iter().enumerate() {
-                    *self.variables[*scope_idx].get_mut(&param_var_idx).unwrap() =
-                        Some(fn_args[idx]);
+                for (param_var_idx, arg) in param_ids.into_iter().zip(fn_args) {
+                    *self.variables[scope_idx].get_mut(&param_var_idx).unwrap() = Some(arg);
                 }
 
-                let returns = *returns;
-                self.push_call_stack(fn_obj.instruction_range.start, *scope_idx, variables);
+                if tail_call {
+                    let frame = self.call_stack.last_mut().unwrap();
+                    frame.scope_idx = scope_idx;
+                    frame.variables = variables;
+                    frame.tail_call = Some(self.pc);
+                    self.pc = entry - 1;
+                } else {
+                    self.push_call_stack(entry, scope_idx, variables)?;
+                    self.call_stack.last_mut().unwrap().memo = memo;
+                }
 
                 if !returns {
                     self.stack.push(allocate(Value::Nil));
                 }
             },
 
-            Ret => self.pop_call_stack(),
+            Ret => {
+                let generator = self.call_stack.last().and_then(|frame| frame.generator);
+                let memo = self
+                    .call_stack
+                    .last_mut()
+                    .and_then(|frame| frame.memo.take());
+                self.pop_call_stack();
+
+                if let Some((name, key)) = memo {
+                    let value = unsafe { self.stack.last().unwrap().as_ref().clone() };
+                    let memo = self.functions.get_mut(&name).unwrap().memo.as_mut();
+                    memo.unwrap().insert(key, value);
+                }
+
+                // a generator that returns is finished
+                if let Some(iter_ptr) = generator {
+                    self.generator_mut(iter_ptr).done = true;
+                }
+            }
+
+            Yield => unsafe {
+                let value = self.pop(&span)?.as_ref().clone();
+                let frame = self.call_stack.last().unwrap();
+                let (iter_ptr, scope_idx) = (frame.generator.unwrap(), frame.scope_idx);
+
+                let variables = self.variables[scope_idx]
+                    .iter()
+                    .filter_map(|(id, value)| value.map(|v| (*id, v.as_ref().clone())))
+                    .collect();
+                let resume_pc = self.pc + 1;
+
+                self.pop_call_stack();
+
+                let generator = self.generator_mut(iter_ptr);
+                generator.pc = resume_pc;
+                generator.variables = variables;
+                generator.yielded = Some(value);
+            },
 
             Array => unsafe {
                 let items = args[0];
                 let mut array = vec![];
 
-                (0..items).for_each(|_| array.push(self.stack.pop().unwrap().as_ref().clone()));
+                for _ in 0..items {
+                    array.push(self.pop(&span)?.as_ref().clone());
+                }
                 array.reverse();
 
                 self.stack.push(allocate(Value::Array(array)));
             },
 
-            Index => unsafe {
-                let index = self.stack.pop().unwrap().as_ref().as_int();
-                let array = self.stack.pop().unwrap().as_ref().as_array();
-
-                self.stack.push(allocate(
-                    match array.get(index.to_usize().unwrap()) {
-                        Some(e) => e,
-                        None => self.runtime_error(
-                            &format!(
-                                "Index out of bounds, size is: {size}, index is: {index}",
-                                size = array.len()
-                            ),
-                            span,
+            Unpack => unsafe {
+                let value = self.pop(&span)?.as_ref();
+                let items = self.items(value, "destructure", span.clone())?;
+                if items.len() != args[0] {
+                    return Err(self.runtime_error(
+                        &format!(
+                            "Expected {} values to destructure, found {}",
+                            args[0],
+                            items.len()
                         ),
+                        span,
+                    ));
+                }
+
+                for item in items {
+                    self.stack.push(allocate(item));
+                }
+            },
+
+            UnpackHeadTail => unsafe {
+                let value = self.pop(&span)?.as_ref();
+                let (head, tail) = match value {
+                    // the tail of a string stays a string
+                    Value::String(s) if !s.is_empty() => {
+                        let c = s.chars().next().unwrap();
+                        (c.to_string().into(), s[c.len_utf8()..].into())
                     }
-                    .clone(),
-                ));
+                    _ => {
+                        let items = self.items(value, "destructure", span.clone())?;
+                        let Some((head, tail)) = items.split_first() else {
+                            return Err(
+                                self.runtime_error("Cannot take the head of an empty value", span)
+                            );
+                        };
+                        (head.clone(), Value::Array(tail.to_vec()))
+                    }
+                };
+
+                self.stack.push(allocate(head));
+                self.stack.push(allocate(tail));
             },
 
-            Mul => self.perform_bin_op(byte, span, |_, a, b| a.binary_mul(b)),
-            Mod => self.perform_bin_op(byte, span, |_, a, b| a.binary_mod(b)),
-            BinaryPow => self.perform_bin_op(byte, span, |_, a, b| a.binary_bitwise_xor(b)),
-            Pow => self.perform_bin_op(byte, span, |_, a, b| a.binary_pow(b)),
-            Sub => self.perform_bin_op(byte, span, |_, a, b| a.binary_sub(b)),
-            Add => self.perform_bin_op(byte, span, |_, a, b| a.binary_add(b)),
-            AddEq => self.perform_bin_op_in_place(byte, span, |_, a, b| a.binary_add(b)),
-            SubEq => self.perform_bin_op_in_place(byte, span, |_, a, b| a.binary_sub(b)),
-            MulEq => self.perform_bin_op_in_place(byte, span, |_, a, b| a.binary_mul(b)),
-            DivEq => self.perform_bin_op_in_place(byte, span, |_, a, b| a.binary_div(b)),
+            Index => unsafe {
+                let index = match self.pop(&span)?.as_ref() {
+                    Value::Int(index) => index.clone(),
+                    index => {
+                        return Err(self.runtime_error(
+                            &format!("Cannot index with the value of type '{}'", index.get_type()),
+                            span,
+                        ))
+                    }
+                };
+                let value = self.pop(&span)?.as_ref();
+
+                // the size is unknown for sequences
+                let (item, size) = match value {
+                    Value::Iter(iter) if matches!(iter.as_ref(), Iter::Generator(_)) => {
+                        return Err(self
+                            .runtime_error("Cannot index a generator, loop over it instead", span))
+                    }
+                    // sequences are only walked up to the index
+                    Value::Iter(iter) => (
+                        index.to_usize().and_then(|i| iter.as_ref().clone().nth(i)),
+                        None,
+                    ),
+                    Value::Array(items) => (
+                        index.to_usize().and_then(|i| items.get(i).cloned()),
+                        Some(items.len()),
+                    ),
+                    _ => {
+                        let items = self.items(value, "index", span.clone())?;
+                        (
+                            index.to_usize().and_then(|i| items.get(i).cloned()),
+                            Some(items.len()),
+                        )
+                    }
+                };
 
-            Div => self.perform_bin_op(byte, span.clone(), |s, a, b| {
-                if b.is_zero() {
-                    s.runtime_error("Cannot divide by zero", span);
+                match item {
+                    Some(item) => self.stack.push(allocate(item)),
+                    None => {
+                        let message = match size {
+                            None => format!("Index out of bounds, index is: {index}"),
+                            Some(size) => {
+                                format!("Index out of bounds, size is: {size}, index is: {index}")
+                            }
+                        };
+                        return Err(self.runtime_error(&message, span));
+                    }
                 }
+            },
 
-                a.binary_div(b)
-            }),
+            Mul => {
+                self.check_repeat(&span)?;
+                self.perform_bin_op(byte, span, |a, b| a.binary_mul(b))?
+            }
+            Mod => {
+                self.check_divisor(&span)?;
+                self.perform_bin_op(byte, span, |a, b| a.binary_mod(b))?
+            }
+            BinaryPow => self.perform_bin_op(byte, span, |a, b| a.binary_bitwise_xor(b))?,
+            Pow => self.perform_bin_op(byte, span, |a, b| a.binary_pow(b))?,
+            Sub => self.perform_bin_op(byte, span, |a, b| a.binary_sub(b))?,
+            Add => self.perform_bin_op(byte, span, |a, b| a.binary_add(b))?,
+            AddConst => self.perform_bin_op_const(Add, &args, span, |a, b| a.binary_add(b))?,
+            SubConst => self.perform_bin_op_const(Sub, &args, span, |a, b| a.binary_sub(b))?,
+            AddEq => self.perform_bin_op_in_place(byte, span, |a, b| a.binary_add(b))?,
+            SubEq => self.perform_bin_op_in_place(byte, span, |a, b| a.binary_sub(b))?,
+            MulEq => {
+                self.check_repeat(&span)?;
+                self.perform_bin_op_in_place(byte, span, |a, b| a.binary_mul(b))?
+            }
+            DivEq => {
+                self.check_divisor(&span)?;
+                self.perform_bin_op_in_place(byte, span, |a, b| a.binary_div(b))?
+            }
+
+            Div => {
+                self.check_divisor(&span)?;
+                self.perform_bin_op(byte, span, |a, b| a.binary_div(b))?
+            }
 
             Inc => unsafe {
-                let value = self.stack.pop().unwrap().as_mut();
+                let value = self.pop(&span)?.as_mut();
 
                 match value {
                     Value::Int(i) => *i += 1,
                     Value::Float(f) => *f += 1,
                     Value::Bool(b) => *b = !*b,
-                    _ => self.runtime_error(
-                        &format!("Cannot increment the value of type {}", value.get_type()),
-                        span,
-                    ),
+                    _ => {
+                        return Err(self.runtime_error(
+                            &format!("Cannot increment the value of type {}", value.get_type()),
+                            span,
+                        ))
+                    }
                 }
             },
 
             Dec => unsafe {
-                let value = self.stack.pop().unwrap().as_mut();
+                let value = self.pop(&span)?.as_mut();
 
                 match value {
                     Value::Int(i) => *i -= 1,
@@ -1369,111 +1978,200 @@ impl VM {
                         a.pop();
                     }
 
-                    _ => self.runtime_error(
-                        &format!("Cannot decrement the value of type {}", value.get_type()),
-                        span,
-                    ),
+                    _ => {
+                        return Err(self.runtime_error(
+                            &format!("Cannot decrement the value of type {}", value.get_type()),
+                            span,
+                        ))
+                    }
                 }
             },
 
             Factorial => unsafe {
-                let val = self.stack.pop().unwrap().as_ref();
+                let val = self.pop(&span)?.as_ref();
                 self.stack
                     .push(NonNull::new_unchecked(alloc_new_value(match val {
                         Value::Int(i) => {
-                            Value::Int(Integer::factorial(i.saturating_cast()).complete())
+                            let n =
+                                self.check_size(i.saturating_cast(), MAX_FACTORIAL, "!", &span)?;
+                            Value::Int(Integer::factorial(n).complete())
+                        }
+                        Value::Float(f) => {
+                            let n = f.to_u32_saturating().ok_or_else(|| {
+                                self.runtime_error("Cannot perform factorial on NaN", span.clone())
+                            })?;
+                            let n = self.check_size(n, MAX_FACTORIAL, "!", &span)?;
+                            Value::Float(Float::factorial(n).complete(float_precision()))
+                        }
+                        _ => {
+                            return Err(self.runtime_error(
+                                &format!(
+                                    "Cannot perform factorial on value of type {:?}",
+                                    val.get_type()
+                                ),
+                                span,
+                            ))
                         }
-                        Value::Float(f) => Value::Float(
-                            Float::factorial(f.to_u32_saturating().unwrap()).complete(53),
-                        ),
-                        _ => self.runtime_error(
-                            &format!(
-                                "Cannot perform factorial on value of type {:?}",
-                                val.get_type()
-                            ),
-                            span,
-                        ),
                     })));
             },
 
             Jmp => {
                 self.pc = args[0];
-                return false;
-            }
-
-            ForLoopJmp { ran_once } => {
-                self.pc = args[0];
-                *unsafe { &mut *ran_once } = true;
-
-                return false;
+                return Ok(false);
             }
 
             Break => {
                 let while_instr_ptr = args[0];
                 let (Instr(_, loop_args), _) = &self.instructions[while_instr_ptr];
                 self.pc = loop_args[0];
-                return false;
+                return Ok(false);
             }
 
             Continue => {
                 let while_instr_ptr = args[0];
                 let (Instr(_, loop_args), _) = &self.instructions[while_instr_ptr];
                 self.pc = loop_args[1];
-                return false;
+                return Ok(false);
+            }
+
+            TryStart => self.handlers.push(Handler {
+                body: self.pc..args[1],
+                catch_pc: args[0],
+                call_depth: self.call_stack.len(),
+                stack_len: self.stack.len(),
+            }),
+
+            TryEnd => {
+                // handlers of inner blocks left with `br` or `ct` go as well
+                while let Some(handler) = self.handlers.pop() {
+                    if handler.catch_pc == self.pc + 1 {
+                        break;
+                    }
+                }
+
+                self.pc = args[0];
+                return Ok(false);
+            }
+
+            Raise => unsafe {
+                match self.pop(&span)?.as_ref() {
+                    // raising a caught error again keeps where it happened
+                    Value::Error(error) => {
+                        return Err(self.runtime_error(&error.message, error.start..error.end))
+                    }
+                    value => return Err(self.runtime_error(&value.to_string(), span)),
+                }
+            },
+
+            MatchArm(matcher) => {
+                let value = self
+                    .get_var(args[0] as u32)
+                    .map(|v| unsafe { v.as_ref().clone() })
+                    .unwrap_or(Value::Nil);
+
+                let mut bindings = Vec::new();
+                let variable = |id| {
+                    self.get_var(id)
+                        .map_or(Value::Nil, |v| unsafe { v.as_ref().clone() })
+                };
+                if !matcher.matches(&value, &variable, &mut bindings) {
+                    self.pc = args[1];
+                    return Ok(false);
+                }
+
+                let scope = self.current_scope();
+                for (id, value) in bindings {
+                    self.variables[scope].insert(id, Some(allocate(value)));
+                }
             }
 
             TernaryStart => unsafe {
                 let ternary_else_start = args[0];
-                let condition = self.stack.pop().unwrap().as_ref().bool_eval();
+                let condition = self.pop(&span)?.as_ref().bool_eval();
 
                 if !condition {
                     self.pc = ternary_else_start;
-                    return false;
+                    return Ok(false);
                 }
             },
 
-            Lt => self.compare_values(span, |a, b| a.less_than(b)),
-            Gt => self.compare_values(span, |a, b| a.greater_than(b)),
-            Le => self.compare_values(span, |a, b| a.less_than_or_equal(b)),
-            Ge => self.compare_values(span, |a, b| a.greater_than_or_equal(b)),
-            Eq => self.compare_values(span, |a, b| a.equal_to(b)),
-            Neq => self.compare_values(span, |a, b| a.not_equal_to(b)),
-            And => self.compare_values(span, |a, b| a.and(b)),
-            Or => self.compare_values(span, |a, b| a.or(b)),
+            JmpIfNot(comparison) => {
+                let condition = self.compare(span, |a, b| comparison.compare(a, b))?;
+                if !condition.bool_eval() {
+                    self.pc = args[0];
+                    return Ok(false);
+                }
+            }
+
+            Lt => self.compare_values(span, |a, b| a.less_than(b))?,
+            Gt => self.compare_values(span, |a, b| a.greater_than(b))?,
+            Le => self.compare_values(span, |a, b| a.less_than_or_equal(b))?,
+            Ge => self.compare_values(span, |a, b| a.greater_than_or_equal(b))?,
+            Eq => self.compare_values(span, |a, b| a.equal_to(b))?,
+            Neq => self.compare_values(span, |a, b| a.not_equal_to(b))?,
+            And => self.compare_values(span, |a, b| a.and(b))?,
+            Or => self.compare_values(span, |a, b| a.or(b))?,
 
             Method(MethodFunction {
                 name,
                 on_types,
+                num_args,
                 in_built,
-                ..
             }) => match name.as_str() {
                 "push" if in_built => unsafe {
-                    let src = self.stack.pop().unwrap().as_ref();
-                    let dest = self.stack.pop().unwrap().as_mut();
+                    let src = self.pop(&span)?.as_ref();
+                    let dest = self.pop(&span)?.as_mut();
 
-                    self.check_type(&name, on_types, dest, span);
+                    self.check_type(&name, on_types, dest, span.clone())?;
 
-                    *dest = dest.binary_add(src).unwrap();
+                    let Some(pushed) = dest.binary_add(src) else {
+                        return Err(self.runtime_error(
+                            &format!("Cannot push to the value of type '{}'", dest.get_type()),
+                            span,
+                        ));
+                    };
+                    *dest = pushed;
                     self.stack.push(NonNull::new_unchecked(dest as *mut Value));
                 },
 
                 "clear" if in_built => unsafe {
-                    let var = self.stack.pop().unwrap().as_mut();
+                    let var = self.pop(&span)?.as_mut();
 
-                    self.check_type(&name, on_types, var, span);
+                    self.check_type(&name, on_types, var, span)?;
 
                     if !var.clear() {
                         panic!();
                     }
                 },
 
-                "join" if in_built => unsafe {
-                    let separator = self.stack.pop().unwrap().as_ref();
-                    let dest = self.stack.pop().unwrap().as_ref();
-
-                    self.check_type(&name, on_types, dest, span.clone());
+                "msg" if in_built => unsafe {
+                    let error = self.pop(&span)?.as_ref();
+                    self.check_type(&name, on_types, error, span)?;
 
-                    let array = dest.as_array();
+                    let Value::Error(error) = error else {
+                        unreachable!()
+                    };
+                    self.stack.push(allocate(error.message.as_str().into()));
+                },
+
+                "span" if in_built => unsafe {
+                    let error = self.pop(&span)?.as_ref();
+                    self.check_type(&name, on_types, error, span)?;
+
+                    let Value::Error(error) = error else {
+                        unreachable!()
+                    };
+                    let span = vec![Value::Int(error.start.into()), Value::Int(error.end.into())];
+                    self.stack.push(allocate(Value::Array(span)));
+                },
+
+                "join" if in_built => unsafe {
+                    let separator = self.pop(&span)?.as_ref();
+                    let dest = self.pop(&span)?.as_ref();
+
+                    self.check_type(&name, on_types, dest, span.clone())?;
+
+                    let array = self.items(dest, "join", span.clone())?;
                     let result_string = array
                         .iter()
                         .map(|i| i.to_string())
@@ -1482,32 +2180,34 @@ impl VM {
                             Value::Nil => "",
                             Value::String(s) => s,
 
-                            _ => self.runtime_error(
-                                &format!(
-                                    "Cannot join with the value of type '{}'",
-                                    separator.get_type()
-                                ),
-                                span,
-                            ),
+                            _ => {
+                                return Err(self.runtime_error(
+                                    &format!(
+                                        "Cannot join with the value of type '{}'",
+                                        separator.get_type()
+                                    ),
+                                    span,
+                                ))
+                            }
                         });
 
                     self.stack.push(allocate(result_string.into()));
                 },
 
                 "split" if in_built => unsafe {
-                    let split = self.stack.pop().unwrap().as_ref();
-                    let val = self.stack.pop().unwrap().as_ref();
+                    let split = self.pop(&span)?.as_ref();
+                    let val = self.pop(&span)?.as_ref();
 
-                    self.check_type(&name, on_types, val, span.clone());
+                    self.check_type(&name, on_types, val, span.clone())?;
 
                     let (Value::String(val_str), Value::String(split_str)) = (val, split) else {
-                        self.runtime_error(
+                        return Err(self.runtime_error(
                             &format!(
                                 "Expected 'str' as argument of split, found '{}'",
                                 split.get_type()
                             ),
                             span,
-                        );
+                        ));
                     };
 
                     let split = val_str
@@ -1519,27 +2219,29 @@ impl VM {
                 },
 
                 _ => unsafe {
-                    let object = self.stack.pop().unwrap();
-                    let object_type = Type::try_from(object.as_ref().get_type()).unwrap();
-
-                    let Some(fn_obj) = self.impl_methods.get(&(name.clone(), object_type)) else {
-                        self.runtime_error(
+                    let object = self.pop(&span)?;
+                    // regexes can't have methods
+                    let object_type = Type::try_from(object.as_ref().get_type()).ok();
+
+                    let Some(fn_obj) = object_type.and_then(|object_type| {
+                        self.impl_methods.get(&(name.clone(), object_type))
+                    }) else {
+                        return Err(self.runtime_error(
                             &format!(
                                 "No method named '{name}' found on the type '{}'",
-                                object_type.get_type()
+                                object.as_ref().get_type()
                             ),
                             span,
-                        );
+                        ));
                     };
 
                     let FunctionData {
-                        parameters,
                         scope_idx,
                         // returns,
                         ..
                     } = fn_obj;
 
-                    let mut fn_args = (1..parameters.len())
+                    let mut fn_args = (0..num_args)
                         .map(|_| {
                             self.stack
                                 .pop()
@@ -1547,22 +2249,25 @@ impl VM {
                         })
                         .collect::<Vec<_>>();
 
+                    fn_args.push(object);
                     fn_args.reverse();
+                    let (fn_args, entry) = self.bind_args(fn_obj, fn_args, 1, span)?;
 
-                    let variables = self.variables[*scope_idx].clone();
-
-                    let mut arg_iter = fn_obj.get_var_ids().into_iter().enumerate();
+                    if fn_obj.generator {
+                        let generator = Self::new_generator(fn_obj, fn_args, entry);
+                        self.stack.push(allocate(generator));
+                        self.pc += 1;
+                        return Ok(false);
+                    }
 
-                    let (_, self_var_idx) = arg_iter.next().unwrap();
-                    *self.variables[*scope_idx].get_mut(&self_var_idx).unwrap() = Some(object);
+                    let variables = self.variables[*scope_idx].clone();
 
-                    for (idx, param_var_idx) in arg_iter {
-                        *self.variables[*scope_idx].get_mut(&param_var_idx).unwrap() =
-                            Some(fn_args[idx - 1]);
+                    for (param_var_idx, arg) in fn_obj.get_var_ids().into_iter().zip(fn_args) {
+                        *self.variables[*scope_idx].get_mut(&param_var_idx).unwrap() = Some(arg);
                     }
 
                     // let returns = *returns;
-                    self.push_call_stack(fn_obj.instruction_range.start, *scope_idx, variables);
+                    self.push_call_stack(entry, *scope_idx, variables)?;
 
                     // if !returns {
                     // self.stack.push(allocate(Value::Nil));
@@ -1584,7 +2289,7 @@ impl VM {
                 );
 
                 if let Err(e) = stdout().flush() {
-                    self.runtime_error(&format!("Failed to flush stdout, {e:?}"), span);
+                    return Err(self.runtime_error(&format!("Failed to flush stdout, {e:?}"), span));
                 }
             },
 
@@ -1603,12 +2308,20 @@ impl VM {
             },
 
             Len => unsafe {
-                let len = self.stack.pop().unwrap().as_ref().as_array().len();
-                self.stack.push(allocate(Value::Int(len.into())));
+                let len = match self.pop(&span)?.as_ref() {
+                    Value::Iter(iter) => iter.len().ok_or_else(|| {
+                        self.runtime_error(
+                            "Cannot take the length of an infinite range or a generator",
+                            span,
+                        )
+                    })?,
+                    value => self.items(value, "take the length of", span)?.len().into(),
+                };
+                self.stack.push(allocate(Value::Int(len)));
             },
 
             Input => unsafe {
-                let prompt = self.stack.pop().unwrap().as_ref();
+                let prompt = self.pop(&span)?.as_ref();
 
                 match prompt {
                     Value::Nil => {}
@@ -1621,7 +2334,7 @@ impl VM {
                 let mut s = String::new();
                 match stdin().read_line(&mut s) {
                     Err(x) => {
-                        self.runtime_error(x.to_string().as_str(), span);
+                        return Err(self.runtime_error(x.to_string().as_str(), span));
                     }
                     Ok(_) => {}
                 };
@@ -1635,45 +2348,55 @@ impl VM {
             },
 
             ToInt => unsafe {
-                let val = self.stack.pop().unwrap().as_ref();
+                let val = self.pop(&span)?.as_ref();
                 self.stack.push(allocate(Value::Int(match val {
                     Value::Int(i) => i.clone(),
-                    Value::Float(f) => f.to_integer().unwrap(),
+                    Value::Float(f) => f.to_integer().ok_or_else(|| {
+                        self.runtime_error(&format!("cannot convert {f} to int"), span.clone())
+                    })?,
                     Value::Bool(b) => Integer::from(*b as i32),
                     Value::String(s) => match Integer::parse(s) {
                         Ok(i) => i.complete(),
                         Err(e) => {
-                            self.runtime_error(
+                            return Err(self.runtime_error(
                                 &format!("cannot parse the string to int value, {e:?}"),
                                 span,
-                            );
+                            ));
                         }
                     },
 
                     Value::Nil => Integer::new(),
-                    Value::Array(_) => self.runtime_error("cannot convert array type to int", span),
+                    Value::Array(_) | Value::Iter(_) | Value::Error(_) | Value::Regex(_) => {
+                        return Err(self.runtime_error(
+                            &format!("cannot convert {} type to int", val.get_type()),
+                            span,
+                        ))
+                    }
                 })));
             },
 
             ToFloat => unsafe {
-                let val = self.stack.pop().unwrap().as_ref();
+                let val = self.pop(&span)?.as_ref();
                 self.stack.push(allocate(Value::Float(match val {
                     Value::Int(i) => float!(i),
                     Value::Float(f) => f.clone(),
                     Value::Bool(b) => float!(*b as i32),
                     Value::String(s) => match Float::parse(s) {
-                        Ok(i) => i.complete(53),
+                        Ok(i) => i.complete(float_precision()),
                         Err(e) => {
-                            self.runtime_error(
+                            return Err(self.runtime_error(
                                 &format!("cannot parse the string to float value, {e:?}"),
                                 span,
-                            );
+                            ));
                         }
                     },
 
-                    Value::Nil => Float::new(53),
-                    Value::Array(_) => {
-                        self.runtime_error("cannot convert array type to float", span)
+                    Value::Nil => Float::new(float_precision()),
+                    Value::Array(_) | Value::Iter(_) | Value::Error(_) | Value::Regex(_) => {
+                        return Err(self.runtime_error(
+                            &format!("cannot convert {} type to float", val.get_type()),
+                            span,
+                        ))
                     }
                 })));
             },
@@ -1681,18 +2404,19 @@ impl VM {
 
         self.pc += 1;
         self.iteration += 1;
-        false
+        Ok(false)
     }
 
-    fn call_function(&mut self, name: &str) {
+    fn call_function(&mut self, name: &str) -> Result<()> {
         let pc = self.pc;
         let fn_obj = &self.functions[name];
         for i in fn_obj.instruction_range.clone() {
             let (instr, span) = self.instructions[i].clone();
-            self.run_byte(instr, span);
+            self.run_byte(instr, span)?;
         }
 
         self.pc = pc;
+        Ok(())
     }
 
     pub fn gc_recollect(&mut self) {
@@ -1708,6 +2432,13 @@ impl VM {
                 }
             }
         }
+
+        // and in the scopes that calls will restore
+        for frame in &self.call_stack {
+            for item in frame.variables.values().flatten() {
+                mark(*item);
+            }
+        }
         // Delete the useless memory
         sweep();
     }
@@ -1718,7 +2449,17 @@ impl VM {
             .push((Instr(LoadConst, vec![const_idx - 1]), span));
     }
 
-    fn compare_values<F>(&mut self, span: Range<usize>, compare_fn: F)
+    fn compare_values<F>(&mut self, span: Range<usize>, compare_fn: F) -> Result<()>
+    where
+        F: FnOnce(&Value, &Value) -> Option<Value>,
+    {
+        let result = self.compare(span, compare_fn)?;
+        self.stack.push(allocate(result));
+        Ok(())
+    }
+
+    /// Compares the two values on top of the stack, popping them.
+    fn compare<F>(&mut self, span: Range<usize>, compare_fn: F) -> Result<Value>
     where
         F: FnOnce(&Value, &Value) -> Option<Value>,
     {
@@ -1726,18 +2467,16 @@ impl VM {
             let b = self
                 .stack
                 .pop()
-                .unwrap_or_else(|| self.runtime_error("Stack underflow", span.clone()))
+                .ok_or_else(|| self.runtime_error("Stack underflow", span.clone()))?
                 .as_ref();
             let a = self
                 .stack
                 .pop()
-                .unwrap_or_else(|| self.runtime_error("Stack underflow", span.clone()))
+                .ok_or_else(|| self.runtime_error("Stack underflow", span.clone()))?
                 .as_ref();
 
-            let result = compare_fn(a, b);
-            match result {
-                Some(r) => self.stack.push(NonNull::new_unchecked(alloc_new_value(r))),
-                None => self.runtime_error(
+            compare_fn(a, b).ok_or_else(|| {
+                self.runtime_error(
                     format!(
                         "Cannot compare values of type {:?} and {:?}",
                         a.get_type(),
@@ -1745,30 +2484,116 @@ impl VM {
                     )
                     .as_str(),
                     span,
-                ),
-            }
+                )
+            })
         }
     }
 
-    fn perform_bin_op<F>(&mut self, op: Bytecode, span: Range<usize>, binary_op: F)
+    fn perform_bin_op<F>(&mut self, op: Bytecode, span: Range<usize>, binary_op: F) -> Result<()>
     where
-        F: FnOnce(&Self, &Value, &Value) -> Option<Value>,
+        F: FnOnce(&Value, &Value) -> Option<Value>,
     {
         unsafe {
             let b = self
                 .stack
                 .pop()
-                .unwrap_or_else(|| self.runtime_error("Stack underflow", span.clone()))
+                .ok_or_else(|| self.runtime_error("Stack underflow", span.clone()))?
                 .as_ref();
             let a = self
                 .stack
                 .pop()
-                .unwrap_or_else(|| self.runtime_error("Stack underflow", span.clone()))
+                .ok_or_else(|| self.runtime_error("Stack underflow", span.clone()))?
                 .as_ref();
 
-            match binary_op(self, a, b) {
+            match binary_op(a, b) {
                 Some(r) => self.stack.push(NonNull::new_unchecked(alloc_new_value(r))),
-                None => self.runtime_error(
+                None => {
+                    return Err(self.runtime_error(
+                        format!(
+                            "Cannot perform {op} operation on values of type {:?} and {:?}",
+                            a.get_type(),
+                            b.get_type()
+                        )
+                        .as_str(),
+                        span,
+                    ))
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Raises the division by zero of the operation on top of the stack.
+    fn check_divisor(&self, span: &Range<usize>) -> Result<()> {
+        let divisor = self.stack.last().map(|b| unsafe { b.as_ref() });
+        match divisor.is_some_and(Value::is_zero) {
+            true => Err(self.runtime_error("Cannot divide by zero", span.clone())),
+            false => Ok(()),
+        }
+    }
+
+    /// Raises the repeat count of a string on top of the stack that is
+    /// negative or too large.
+    fn check_repeat(&self, span: &Range<usize>) -> Result<()> {
+        let [a, b] = match self.stack.len() {
+            len if len >= 2 => [len - 2, len - 1].map(|i| unsafe { self.stack[i].as_ref() }),
+            _ => return Ok(()),
+        };
+        let (s, n) = match (a, b) {
+            (Value::String(s), Value::Int(n)) | (Value::Int(n), Value::String(s)) => (s, n),
+            _ => return Ok(()),
+        };
+
+        if *n < 0 {
+            return Err(self.runtime_error(
+                &format!("Cannot repeat a string a negative number of times ({n})"),
+                span.clone(),
+            ));
+        }
+        match n.to_usize().and_then(|n| s.len().checked_mul(n)) {
+            Some(len) if len <= isize::MAX as usize => Ok(()),
+            _ => Err(self.runtime_error(
+                &format!("Cannot repeat a string {n} times, it would be too long"),
+                span.clone(),
+            )),
+        }
+    }
+
+    /// Raises when `n` is above `max`, making the result of `name` would take
+    /// too long.
+    fn check_size(&self, n: u32, max: u32, name: &str, span: &Range<usize>) -> Result<u32> {
+        match n <= max {
+            true => Ok(n),
+            false => Err(self.runtime_error(
+                &format!("`{name}` of a number above {max} is too large to compute"),
+                span.clone(),
+            )),
+        }
+    }
+
+    /// `op` on the variable args[0] and the constant args[1], like the
+    /// `GetVar`, `LoadConst` and `op` it was fused from.
+    fn perform_bin_op_const<F>(
+        &mut self,
+        op: Bytecode,
+        args: &[usize],
+        span: Range<usize>,
+        binary_op: F,
+    ) -> Result<()>
+    where
+        F: FnOnce(&Value, &Value) -> Option<Value>,
+    {
+        let Some(a) = self.get_var(args[0] as u32) else {
+            return Err(self.runtime_error("Variable not found", span));
+        };
+        let a = unsafe { a.as_ref() };
+        let b = &self.constants[args[1]];
+
+        match binary_op(a, b) {
+            Some(r) => self.stack.push(allocate(r)),
+            None => {
+                return Err(self.runtime_error(
                     format!(
                         "Cannot perform {op} operation on values of type {:?} and {:?}",
                         a.get_type(),
@@ -1776,54 +2601,65 @@ impl VM {
                     )
                     .as_str(),
                     span,
-                ),
+                ))
             }
         }
+
+        Ok(())
     }
 
-    fn perform_bin_op_in_place<F>(&mut self, op: Bytecode, span: Range<usize>, binary_op: F)
+    fn perform_bin_op_in_place<F>(
+        &mut self,
+        op: Bytecode,
+        span: Range<usize>,
+        binary_op: F,
+    ) -> Result<()>
     where
-        F: FnOnce(&Self, &Value, &Value) -> Option<Value>,
+        F: FnOnce(&Value, &Value) -> Option<Value>,
     {
         unsafe {
             let b = self
                 .stack
                 .pop()
-                .unwrap_or_else(|| {
+                .ok_or_else(|| {
                     self.runtime_error(
                         format!("Stack underflow while performing {op} operation", op = op)
                             .as_str(),
                         span.clone(),
                     )
-                })
+                })?
                 .as_ref();
             let a = self
                 .stack
                 .pop()
-                .unwrap_or_else(|| {
+                .ok_or_else(|| {
                     self.runtime_error(
                         format!("Stack underflow while performing {op} operation", op = op)
                             .as_str(),
                         span.clone(),
                     )
-                })
+                })?
                 .as_mut();
 
-            let result = binary_op(self, a, b);
+            let result = binary_op(a, b);
 
             match result {
                 Some(r) => *a = r,
-                None => self.runtime_error(
-                    format!(
-                        "Cannot perform {op} operation on values of type {:?} and {:?}",
-                        a.get_type(),
-                        b.get_type()
-                    )
-                    .as_str(),
-                    span,
-                ),
+                None => {
+                    return Err(self.runtime_error(
+                        format!(
+                            "Cannot perform {op} operation on values of type {:?} and {:?}",
+                            a.get_type(),
+                            b.get_type()
+                        )
+                        .as_str(),
+                        span,
+                    ))
+                }
             }
         }
+
+        Ok(())
     }
 
     fn push_call_stack(
@@ -1831,7 +2667,7 @@ impl VM {
         fn_ptr: usize,
         scope_idx: usize,
         variables: HashMap<u32, VarPtr>,
-    ) {
+    ) -> Result<()> {
         // For debugging weird stack state
         // println!("stack before ==========");
         // for (idx, itm) in self.stack.iter().enumerate() {
@@ -1839,6 +2675,10 @@ impl VM {
         // }
         // println!("=======================");
 
+        if self.call_stack.len() >= self.max_call_depth {
+            return Err(self.stack_overflow());
+        }
+
         let new_pc = fn_ptr - 1;
 
         self.call_stack.push(FnStackData {
@@ -1847,10 +2687,511 @@ impl VM {
             previous_stack_len: self.stack.len(),
             variables_id: self.variables_id.clone(),
             variables,
+            generator: None,
+            memo: None,
+            tail_call: None,
             // self_ptr: todo!(),
         });
 
         self.pc = new_pc;
+        Ok(())
+    }
+
+    fn stack_overflow(&self) -> RuntimeError {
+        self.runtime_error(
+            &format!(
+                "Stack overflow, more than {} nested calls",
+                self.max_call_depth
+            ),
+            self.instructions[self.pc].1.clone(),
+        )
+    }
+
+    /// The functions being called with where they were called from, the
+    /// innermost call last. Consecutive calls from the same place, like the
+    /// ones of a recursion, are merged into the last of them. A frame that
+    /// tail calls were made in is the last of them, made where it was called.
+    fn backtrace(&self) -> Vec<TracedCall> {
+        let mut calls: Vec<TracedCall> = vec![];
+        for (depth, frame) in self.call_stack.iter().enumerate() {
+            let pc = self
+                .call_stack
+                .get(depth + 1)
+                .map_or(self.pc, |callee| callee.pc_before);
+            let (name, call) = self.describe_call(depth, pc);
+            let tail = frame.tail_call.is_some();
+            let span = self.instructions[frame.tail_call.unwrap_or(frame.pc_before)]
+                .1
+                .clone();
+
+            match calls.last_mut() {
+                Some(last) if last.name == name && last.span == span && last.tail == tail => {
+                    last.call = call;
+                    last.count += 1;
+                }
+                _ => calls.push(TracedCall {
+                    name,
+                    call,
+                    span,
+                    count: 1,
+                    tail,
+                }),
+            }
+        }
+
+        calls
+    }
+
+    /// The calls of a backtrace with the lines they were made on.
+    fn backtrace_listing(&self, calls: &[TracedCall]) -> String {
+        let lines = Lines::new(&self.src);
+        let mut trace = String::from("most recent call last:");
+        for TracedCall {
+            call,
+            span,
+            count,
+            tail,
+            ..
+        } in calls
+        {
+            let line = lines.line_of(span.start);
+            let called = if *tail { "tail called" } else { "called" };
+            trace += &format!("\n    {call}, {called} on line {line}");
+            if *count > 1 {
+                trace += &format!(" ({count} times)");
+            }
+        }
+
+        trace
+    }
+
+    /// The name of the function running at `depth` of the call stack, while
+    /// at `pc`, and the call with the current values of its parameters.
+    fn describe_call(&self, depth: usize, pc: usize) -> (String, String) {
+        let Some(function) = self.function_at(pc) else {
+            return ("?".to_string(), "?".to_string());
+        };
+
+        // a later call of the same function keeps these values in its frame
+        let scope_idx = self.call_stack[depth].scope_idx;
+        let variables = self.call_stack[depth + 1..]
+            .iter()
+            .find(|frame| frame.scope_idx == scope_idx)
+            .map(|frame| &frame.variables)
+            .or(self.variables.get(scope_idx));
+
+        let args = function
+            .parameters
+            .iter()
+            .map(|(_, id)| match variables.and_then(|vars| vars.get(id)) {
+                Some(Some(value)) => Self::describe_value(unsafe { value.as_ref() }),
+                _ => "nil".to_string(),
+            })
+            .collect::<Vec<_>>();
+        let name = function.name.clone();
+        let call = format!("{name}({})", args.join(", "));
+        (name, call)
+    }
+
+    /// The value as it would be written, strings are quoted.
+    fn inspect_value(value: &Value) -> String {
+        match value {
+            Value::String(s) => format!("{s:?}"),
+            value => value.to_string(),
+        }
+    }
+
+    /// A short text for a value, strings are quoted.
+    fn describe_value(value: &Value) -> String {
+        let text = Self::inspect_value(value);
+        match text.char_indices().nth(30) {
+            Some((end, _)) => format!("{}...", &text[..end]),
+            None => text,
+        }
+    }
+
+    /// The innermost function containing the instruction `pc`.
+    fn function_at(&self, pc: usize) -> Option<&FunctionData> {
+        self.functions
+            .values()
+            .chain(self.impl_methods.values())
+            .filter(|function| function.instruction_range.contains(&pc))
+            .min_by_key(|function| function.instruction_range.len())
+    }
+
+    /// Records the instruction `pc` that just ran, and the call it made or
+    /// the ones it left.
+    fn profile_instruction(&mut self, pc: usize, time: std::time::Duration) {
+        let line = self.line_of(&self.instructions[pc].1);
+        let bytecode = &self.instructions[pc].0 .0;
+        let depth = self.call_stack.len();
+        let profiler = self.profiler.as_ref().unwrap();
+        // a tail call leaves the function making it
+        let entered = depth > profiler.depth() || (*bytecode == TailCall && depth > 0);
+        let name = entered.then(|| {
+            self.function_at(self.pc)
+                .map_or_else(|| "?".to_string(), |function| function.name.clone())
+        });
+
+        let profiler = self.profiler.as_mut().unwrap();
+        profiler.record(bytecode, line, time);
+        match name {
+            Some(name) => {
+                profiler.return_to(depth - 1);
+                profiler.call(name);
+            }
+            None if depth < profiler.depth() => profiler.return_to(depth),
+            None => {}
+        }
+    }
+
+    /// Prints the report of `--profile` and writes the folded stacks.
+    fn report_profile(&self) {
+        let Some(profiler) = &self.profiler else {
+            return;
+        };
+
+        println!("\nProfile: {}", profiler.report(&self.src, &self.lines));
+        if let Some(path) = &profiler.folded {
+            if let Err(error) = std::fs::write(path, profiler.folded_stacks()) {
+                println!("Error: The folded stacks could not be written to {path}: {error}");
+            }
+        }
+    }
+
+    /// Logs the instruction `pc` about to run for `--trace`, with the depth of
+    /// the call stack and the size of the value stack.
+    fn trace_instruction(&self, pc: usize) {
+        let (Instr(bytecode, args), span) = &self.instructions[pc];
+        let mut entry = format!(
+            "{pc:>6}  depth {}  stack {}  {}",
+            self.call_stack.len(),
+            self.stack.len(),
+            kind_name(bytecode)
+        );
+        if let Method(method) = bytecode {
+            entry += &format!(" {}", method.name);
+        }
+        for arg in args {
+            entry += &format!(" {arg}");
+        }
+        if let Some(line) = self.line_of(span) {
+            entry += &format!("  line {line}");
+        }
+
+        eprintln!("{entry}");
+    }
+
+    /// Shows where the program paused and runs the commands of the debugger
+    /// until one of them lets the program go on.
+    fn debug_pause(&mut self, span: &Range<usize>) {
+        self.show_location(span);
+        loop {
+            print!("debug> ");
+            stdout().flush().ok();
+            let mut line = String::new();
+            // without any more commands the program runs to its end
+            if stdin().read_line(&mut line).unwrap_or(0) == 0 {
+                println!();
+                self.debugger = None;
+                return;
+            }
+
+            let debugger = self.debugger.as_mut().unwrap();
+            let command = match line.trim() {
+                "" => match debugger.last_command.clone() {
+                    Some(command) => command,
+                    None => continue,
+                },
+                line => match Command::parse(line) {
+                    Ok(command) => command,
+                    Err(message) => {
+                        println!("{message}");
+                        continue;
+                    }
+                },
+            };
+            debugger.last_command = Some(command.clone());
+
+            let depth = self.call_stack.len();
+            debugger.mode = match command {
+                Command::Continue => Mode::Continue,
+                Command::Step => Mode::Step,
+                Command::Next => Mode::Next(depth),
+                Command::Out => Mode::Out(depth),
+                command => {
+                    self.debug_command(command, span);
+                    continue;
+                }
+            };
+            return;
+        }
+    }
+
+    /// Runs a command of the debugger that doesn't resume the program.
+    fn debug_command(&mut self, command: Command, span: &Range<usize>) {
+        let debugger = self.debugger.as_mut().unwrap();
+        match command {
+            Command::Break(Some(line)) => match debugger.add_breakpoint(line) {
+                Ok(line) => println!("Breakpoint on line {line}"),
+                Err(message) => println!("{message}"),
+            },
+            Command::Break(None) if debugger.breakpoints.is_empty() => {
+                println!("No breakpoints")
+            }
+            Command::Break(None) => {
+                let lines = debugger.breakpoints.iter().map(|line| line.to_string());
+                println!(
+                    "Breakpoints on lines {}",
+                    lines.collect::<Vec<_>>().join(", ")
+                );
+            }
+            Command::Delete(line) => match debugger.breakpoints.remove(&line) {
+                true => println!("Removed the breakpoint on line {line}"),
+                false => println!("No breakpoint on line {line}"),
+            },
+            Command::Backtrace => match self.backtrace() {
+                calls if calls.is_empty() => println!("Not in a function"),
+                calls => println!("{}", self.backtrace_listing(&calls)),
+            },
+            Command::Vars => {
+                let variables = self.frame_variables();
+                if variables.is_empty() {
+                    println!("No variables");
+                }
+                for (name, id) in variables {
+                    if let Some(value) = self.get_var(id) {
+                        println!(
+                            "{name} = {}",
+                            Self::inspect_value(unsafe { value.as_ref() })
+                        );
+                    }
+                }
+            }
+            Command::Stack => {
+                if self.stack.is_empty() {
+                    println!("The stack is empty");
+                }
+                // the top of the stack first
+                for (i, value) in self.stack.iter().enumerate().rev() {
+                    println!("[{i}] {}", Self::inspect_value(unsafe { value.as_ref() }));
+                }
+            }
+            Command::Print(code) => match self.debug_eval(&code) {
+                Ok(value) => println!("{}", Self::inspect_value(&value)),
+                Err(message) => println!("Error: {message}"),
+            },
+            Command::List => {
+                let line = self.lines.line_of(span.start);
+                let last = (line + 2).min(self.lines.count());
+                for n in line.saturating_sub(2).max(1)..=last {
+                    let marker = match n == line {
+                        true => '>',
+                        false if debugger.breakpoints.contains(&n) => '*',
+                        false => ' ',
+                    };
+                    println!("{marker}{n:>4} | {}", self.lines.text(&self.src, n));
+                }
+            }
+            Command::Quit => std::process::exit(0),
+            Command::Help => println!("{HELP}"),
+            Command::Continue | Command::Step | Command::Next | Command::Out => unreachable!(),
+        }
+    }
+
+    /// Prints the line the program paused on, marking the code about to run.
+    fn show_location(&self, span: &Range<usize>) {
+        let line = self.lines.line_of(span.start);
+        match self.call_stack.len() {
+            0 => println!("Paused on line {line}"),
+            depth => println!(
+                "Paused on line {line} in {}",
+                self.describe_call(depth - 1, self.pc).1
+            ),
+        }
+
+        let text = self.lines.text(&self.src, line);
+        let start = span.start - self.lines.start(line);
+        let end = (span.end - self.lines.start(line)).min(text.len());
+        let prefix = format!("{line:>4} | ");
+        println!("{prefix}{text}");
+        println!(
+            "{}{}",
+            " ".repeat(prefix.len() + text[..start].chars().count()),
+            "^".repeat(text[start..end].chars().count().max(1))
+        );
+    }
+
+    /// The variables of the running function, or the global ones, by name.
+    fn frame_variables(&self) -> Vec<(String, VarId)> {
+        let mut variables = self.variables[self.current_scope()]
+            .keys()
+            .filter_map(|id| Some((self.var_names.get(id)?.clone(), *id)))
+            .collect::<Vec<_>>();
+
+        // the `i` of a nested loop hides the one of the outer loop
+        variables.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)));
+        variables.dedup_by(|a, b| a.0 == b.0);
+        variables
+    }
+
+    /// Evaluates `code` in the running function. Only what the code does
+    /// itself, like setting a variable, changes the state of the program.
+    fn debug_eval(&mut self, code: &str) -> Result<Value, String> {
+        let mut parser = PParser::new(code, crate::tokenize(code));
+        let exprs = parser.parse_recovering();
+        if let Some(error) = parser.errors().first() {
+            return Err(error.message.clone());
+        }
+
+        let names = self.frame_variables().into_iter().collect();
+        let variables_id = std::mem::replace(&mut self.variables_id, names);
+        let (pc, stack_len, depth) = (self.pc, self.stack.len(), self.call_stack.len());
+        let (handlers, scopes, code_start) = (
+            self.handlers.len(),
+            self.variables.len(),
+            self.instructions.len(),
+        );
+        // functions defined by the code are gone with its instructions
+        let functions = (self.functions.clone(), self.impl_methods.clone());
+
+        let result = self.debug_run(exprs, handlers);
+
+        while self.call_stack.len() > depth {
+            self.pop_call_stack();
+        }
+        self.handlers.truncate(handlers);
+        self.instructions.truncate(code_start);
+        self.variables.truncate(scopes);
+        (self.functions, self.impl_methods) = functions;
+        self.variables_id = variables_id;
+        self.pc = pc;
+
+        let value = match self.stack.len() > stack_len {
+            true => unsafe { self.stack.last().unwrap().as_ref().clone() },
+            false => Value::Nil,
+        };
+        self.stack.truncate(stack_len);
+        result.map(|_| value)
+    }
+
+    /// Compiles the expressions after the program and runs them, the errors
+    /// they don't catch themselves are returned.
+    fn debug_run(&mut self, exprs: Vec<Expr>, handlers: usize) -> Result<(), String> {
+        let start = self.instructions.len();
+        for expr in exprs {
+            self.compile_expr(expr).map_err(|error| error.message)?;
+        }
+
+        // calls run the functions of the program and come back
+        let end = self.instructions.len();
+        self.pc = start;
+        while self.pc != end {
+            let (instr, span) = self.instructions[self.pc].clone();
+            match self.run_byte(instr, span) {
+                Ok(true) => break,
+                Ok(false) => {}
+                Err(error)
+                    if self.handlers[handlers..]
+                        .iter()
+                        .any(|h| self.is_handling(h)) =>
+                {
+                    self.catch_error(error.into())
+                }
+                Err(error) => return Err(error.message),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Lines up the arguments of a call with the parameters of `fn_obj`,
+    /// returning them along with where the call starts. `implicit` is the
+    /// number of arguments that aren't written in the call, like `self`.
+    fn bind_args(
+        &self,
+        fn_obj: &FunctionData,
+        mut args: Vec<NonNull<Value>>,
+        implicit: usize,
+        span: Range<usize>,
+    ) -> Result<(Vec<NonNull<Value>>, usize)> {
+        let fixed = fn_obj.parameters.len() - fn_obj.variadic as usize;
+        if args.len() < fn_obj.required || (!fn_obj.variadic && args.len() > fixed) {
+            let (min, max) = (fn_obj.required - implicit, fixed - implicit);
+            let expected = match fn_obj.variadic {
+                true => format!("at least {min}"),
+                false if min == max => min.to_string(),
+                false => format!("{min} to {max}"),
+            };
+            return Err(self.runtime_error(
+                &format!(
+                    "`{}` takes {expected} argument(s), found {}",
+                    fn_obj.name,
+                    args.len() - implicit
+                ),
+                span,
+            ));
+        }
+
+        let rest = fn_obj.variadic.then(|| {
+            let extra = args.split_off(args.len().min(fixed));
+            let extra = extra.iter().map(|v| unsafe { v.as_ref().clone() });
+            allocate(Value::Array(extra.collect()))
+        });
+
+        // the defaults of the missing ones are set by the function itself
+        let entry = fn_obj.entry_points[args.len() - fn_obj.required];
+        args.resize_with(fixed, || allocate(Value::Nil));
+        args.extend(rest);
+        Ok((args, entry))
+    }
+
+    /// The suspended call of the generator function `fn_obj`.
+    fn new_generator(fn_obj: &FunctionData, args: Vec<NonNull<Value>>, pc: usize) -> Value {
+        let variables = fn_obj
+            .get_var_ids()
+            .into_iter()
+            .zip(args)
+            .map(|(id, arg)| (id, unsafe { arg.as_ref().clone() }))
+            .collect();
+
+        Value::Iter(Box::new(Iter::Generator(Generator::new(
+            fn_obj.name.clone(),
+            fn_obj.scope_idx,
+            pc,
+            variables,
+        ))))
+    }
+
+    fn generator_mut(&mut self, iter_ptr: u32) -> &mut Generator {
+        let value = self.get_var(iter_ptr).map(|mut i| unsafe { i.as_mut() });
+        let Some(Value::Iter(iter)) = value else {
+            unreachable!("generators are only resumed by loops")
+        };
+        let Iter::Generator(generator) = iter.as_mut() else {
+            unreachable!("generators are only resumed by loops")
+        };
+        generator
+    }
+
+    /// Runs the generator stored in the variable `iter_ptr` until it yields
+    /// or returns, then goes back to the current instruction.
+    fn resume_generator(&mut self, generator: Generator, iter_ptr: u32) -> Result<()> {
+        let variables = std::mem::replace(
+            &mut self.variables[generator.scope_idx],
+            generator
+                .variables
+                .into_iter()
+                .map(|(id, value)| (id, Some(allocate(value))))
+                .collect(),
+        );
+
+        // `Ret` and `Yield` continue after the instruction that was running
+        self.pc -= 1;
+        self.push_call_stack(generator.pc, generator.scope_idx, variables)?;
+        self.call_stack.last_mut().unwrap().generator = Some(iter_ptr);
+        self.pc += 1;
+        Ok(())
     }
 
     fn pop_call_stack(&mut self) {
@@ -1860,81 +3201,588 @@ impl VM {
             previous_stack_len,
             variables_id,
             variables,
-            // self_ptr: todo!(),
+            generator,
+            ..
         } = self.call_stack.pop().unwrap();
 
-        // Remove any extra variables that has been pushed onto the
-        // stack except the return value
-        if previous_stack_len < self.stack.len().saturating_sub(1) {
-            while previous_stack_len < self.stack.len() - 1 {
-                self.stack.remove(self.stack.len() - 2);
+        // the `tr` blocks of the frame are left with it, by `&` or `yd`
+        let depth = self.call_stack.len();
+        while self.handlers.last().is_some_and(|h| h.call_depth > depth) {
+            self.handlers.pop();
+        }
+
+        // nothing a generator leaves behind is used by the loop resuming it
+        if generator.is_some() {
+            self.stack.truncate(previous_stack_len);
+        }
+
+        // Remove any extra variables that has been pushed onto the
+        // stack except the return value
+        if previous_stack_len < self.stack.len().saturating_sub(1) {
+            while previous_stack_len < self.stack.len() - 1 {
+                self.stack.remove(self.stack.len() - 2);
+            }
+        }
+
+        self.pc = pc_before;
+        self.variables[scope_idx] = variables;
+        self.variables_id = variables_id;
+
+        // For debugging weird stack state
+        // println!("stack after ==========");
+        // for (idx, itm) in self.stack.iter().enumerate() {
+        //     println!("stack[{idx}] = {}", unsafe { itm.as_ref() });
+        // }
+        // println!("=======================");
+    }
+
+    fn find_parent_loop_start_instr(
+        &mut self,
+        current_instr_ptr: usize,
+    ) -> Option<(usize, &(Instr, Range<usize>))> {
+        // the loops we are inside of don't know where they end yet
+        (0..current_instr_ptr)
+            .rev()
+            .map(|i| (i, &self.instructions[i]))
+            .find(|&(_, (instr, _))| matches!(instr.0, While | Every { .. }) && instr.1.is_empty())
+    }
+
+    /// The items of an array, the characters of a string or the values of a
+    /// finite sequence, `action` says what needed them when it is none of those.
+    fn items(&self, value: &Value, action: &str, span: Range<usize>) -> Result<Vec<Value>> {
+        value.as_array().map(Cow::into_owned).ok_or_else(|| {
+            self.runtime_error(
+                &format!("Cannot {action} the value of type '{}'", value.get_type()),
+                span,
+            )
+        })
+    }
+
+    fn check_type(
+        &self,
+        fn_name: &str,
+        types: Vec<Type>,
+        value: &Value,
+        span: Range<usize>,
+    ) -> Result<()> {
+        if types.into_iter().find(|i| i.is_same_type(value)).is_none() {
+            return Err(self.runtime_error(
+                &format!(
+                    "No method named '{fn_name}' found on the type '{}'",
+                    value.get_type(),
+                ),
+                span,
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// The arguments of a call of `builtin`, if it takes that many.
+    fn builtin_args(
+        &self,
+        builtin: &Builtin,
+        args: &Option<Vec<Expr>>,
+        span: Range<usize>,
+    ) -> Result<Vec<Expr>> {
+        let args = args.clone().unwrap_or_default();
+        if !builtin.arity.contains(&args.len()) {
+            return Err(self.runtime_error(&builtin.arity_error(args.len()), span));
+        }
+
+        Ok(args)
+    }
+
+    fn compile_builtin(
+        &mut self,
+        builtin: &Builtin,
+        args: &Option<Vec<Expr>>,
+        span: Range<usize>,
+    ) -> Result<()> {
+        let args = self.builtin_args(builtin, args, span.clone())?;
+        let argc = args.len();
+        let bytecode = match builtin.lowering {
+            Lowering::Args(ref bytecode) => {
+                for arg in args {
+                    self.compile_expr(arg)?;
+                }
+                for _ in argc..*builtin.arity.end() {
+                    self.push_data(Value::Nil, span.clone());
+                }
+                bytecode.clone()
+            }
+            Lowering::Optional(ref bytecode, default) => {
+                for arg in args {
+                    self.compile_expr(arg)?;
+                }
+                if argc == 1 {
+                    self.push_data(default.map_or(Value::Nil, Value::from), span.clone());
+                }
+                bytecode.clone()
+            }
+            Lowering::Math(ref bytecode) => {
+                let num_args = *builtin.arity.start();
+                return self.compile_math_call(args, num_args, bytecode.clone(), span);
+            }
+            Lowering::Regex(ref bytecode) => {
+                return self.compile_regex_call(args, bytecode.clone(), span);
+            }
+            Lowering::Range => return self.compile_range_call(args, span),
+            Lowering::Product => {
+                for arg in args {
+                    self.compile_expr(arg)?;
+                }
+                self.instructions.push((Instr(Product, vec![argc]), span));
+                return Ok(());
+            }
+        };
+
+        self.instructions.push((Instr(bytecode, vec![]), span));
+        Ok(())
+    }
+
+    /// The id of the variable `name`, which is created if it doesn't exist.
+    fn var_id(&mut self, name: String) -> u32 {
+        if let Some(id) = self.variables_id.get(&name) {
+            return *id;
+        }
+
+        let id = self.var_id_count as u32;
+        self.bind_name(name, id);
+        self.var_id_count += 1;
+        id
+    }
+
+    /// Makes `name` refer to the variable `id` in the code compiled next,
+    /// returning the variable it referred to before.
+    fn bind_name(&mut self, name: String, id: VarId) -> Option<VarId> {
+        self.var_names.insert(id, name.clone());
+        self.variables_id.insert(name, id)
+    }
+
+    /// Stores the value on top of the stack into the names of `pattern`.
+    fn compile_pattern(&mut self, pattern: AstPattern, span: Range<usize>) -> Result<()> {
+        match pattern {
+            AstPattern::Ident(name) => {
+                let id = self.var_id(name) as usize;
+                self.instructions.push((Instr(Replace, vec![id]), span));
+            }
+            AstPattern::Array(patterns) => {
+                self.instructions
+                    .push((Instr(Unpack, vec![patterns.len()]), span.clone()));
+
+                for pattern in patterns.into_iter().rev() {
+                    self.compile_pattern(pattern, span.clone())?;
+                }
+            }
+            AstPattern::HeadTail(head, tail) => {
+                self.instructions
+                    .push((Instr(UnpackHeadTail, vec![]), span.clone()));
+
+                self.compile_pattern(*tail, span.clone())?;
+                self.compile_pattern(*head, span)?;
+            }
+            // the parser only allows the ones above outside of `mc`
+            _ => unreachable!(),
+        }
+
+        Ok(())
+    }
+
+    /// Compiles the body of a function, `self` is added as the first
+    /// parameter of methods. Parameters with a default value are set at the
+    /// start of the body, a call skips the ones it gives.
+    fn compile_function(
+        &mut self,
+        name: String,
+        params: Vec<Param>,
+        body: Vec<Expr>,
+        method: bool,
+        span: Range<usize>,
+    ) -> Result<FunctionData> {
+        let old_id = self.variables_id.clone();
+        self.variables_id.clear();
+
+        let mut scope = HashMap::new();
+        let mut fn_params: Vec<(String, u32)> = vec![];
+
+        let self_param = method.then(|| Param::new("self".to_owned()));
+        let params = self_param.into_iter().chain(params).collect::<Vec<_>>();
+        for param in &params {
+            fn_params.push((param.name.clone(), self.var_id_count as u32));
+            self.bind_name(param.name.clone(), self.var_id_count as _);
+            scope.insert(self.var_id_count as _, None);
+            self.var_id_count += 1;
+        }
+
+        let scope_idx = self.variables.len();
+        self.variables.push(scope);
+
+        let jmp_instr_ptr = self.instructions.len();
+        self.instructions.push((Instr(Jmp, vec![]), span));
+
+        let body_start = self.instructions.len();
+        let variadic = params.last().is_some_and(|param| param.variadic);
+        let mut entry_points = vec![];
+        for (param, (_, id)) in params.into_iter().zip(&fn_params) {
+            let Some(default) = param.default else {
+                continue;
+            };
+
+            entry_points.push(self.instructions.len());
+            let default_span = default.span.clone();
+            self.compile_expr(default)?;
+            self.instructions
+                .push((Instr(Replace, vec![*id as usize]), default_span));
+        }
+        let required = fn_params.len() - entry_points.len() - variadic as usize;
+        entry_points.push(self.instructions.len());
+
+        let mut returns = false;
+        let outer_yields = self.yields.replace(false);
+        for expr in body {
+            if matches![expr.inner, ExprKind::Return(..)] {
+                returns = true;
+            }
+
+            self.compile_expr(expr)?;
+        }
+
+        let generator = std::mem::replace(&mut self.yields, outer_yields).unwrap();
+        self.instructions.push((Instr(Ret, vec![]), 0..0));
+
+        let body_end = self.instructions.len();
+        self.instructions[jmp_instr_ptr].0 .1.push(body_end);
+
+        // a generator has to keep its frame to be resumed
+        if !generator {
+            self.mark_tail_calls(body_start..body_end);
+        }
+
+        self.variables_id = old_id;
+
+        Ok(FunctionData {
+            name,
+            parameters: fn_params,
+            instruction_range: body_start..body_end,
+            scope_idx,
+            returns,
+            generator,
+            required,
+            entry_points,
+            variadic,
+            memo: None,
+        })
+    }
+
+    /// Turns the calls in `range` whose value is returned right away into
+    /// `TailCall`s. Calls inside of a `tr` block are left alone, as the
+    /// handler needs the frame they would replace.
+    fn mark_tail_calls(&mut self, range: Range<usize>) {
+        let try_blocks = range
+            .clone()
+            .filter_map(|i| match &self.instructions[i].0 {
+                Instr(TryStart, args) => Some(i..args[1]),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        for i in range {
+            if self.instructions[i].0 .0 != FnCall
+                || try_blocks.iter().any(|block| block.contains(&i))
+            {
+                continue;
+            }
+
+            let mut next = i + 1;
+            while let Instr(Jmp, args) = &self.instructions[next].0 {
+                next = args[0];
+            }
+
+            if self.instructions[next].0 .0 == Ret {
+                self.instructions[i].0 .0 = TailCall;
+            }
+        }
+    }
+
+    /// Turns an `mc` pattern into the matcher run by `MatchArm`. A name that
+    /// is already defined is compared against, the others are bound for the
+    /// arm only, `bound` gets them along with what they referred to before.
+    fn compile_matcher(
+        &mut self,
+        pattern: AstPattern,
+        span: Range<usize>,
+        bound: &mut Vec<(String, Option<VarId>)>,
+    ) -> Result<Matcher> {
+        Ok(match pattern {
+            AstPattern::Wildcard => Matcher::Any,
+            AstPattern::Ident(name) => match self.variables_id.get(&name) {
+                Some(&id) if bound.iter().all(|(bound, _)| *bound != name) => Matcher::Var(id),
+                _ => Matcher::Bind(self.bind_arm_name(name, bound)),
+            },
+            AstPattern::Value(value) => Matcher::Value(self.pattern_literal(*value)?),
+            AstPattern::Range(lo, hi) => {
+                Matcher::Range(self.pattern_literal(*lo)?, self.pattern_literal(*hi)?)
+            }
+            AstPattern::Type(name) => Matcher::Type(Type::try_from(name.as_str()).unwrap()),
+            AstPattern::Array(patterns) => {
+                let mut before = Vec::new();
+                let mut rest: Option<(u32, Vec<Matcher>)> = None;
+                for pattern in patterns {
+                    match (pattern, &mut rest) {
+                        (AstPattern::Rest(_), Some(_)) => {
+                            return Err(self.runtime_error(
+                                "An array pattern can only have one rest (`...name`)",
+                                span.clone(),
+                            ))
+                        }
+                        (AstPattern::Rest(name), None) => {
+                            rest = Some((self.bind_arm_name(name, bound), vec![]))
+                        }
+                        (pattern, None) => {
+                            before.push(self.compile_matcher(pattern, span.clone(), bound)?)
+                        }
+                        (pattern, Some((_, after))) => {
+                            let matcher = self.compile_matcher(pattern, span.clone(), bound)?;
+                            after.push(matcher)
+                        }
+                    }
+                }
+
+                Matcher::Array(before, rest)
+            }
+            AstPattern::HeadTail(head, tail) => Matcher::HeadTail(
+                Box::new(self.compile_matcher(*head, span.clone(), bound)?),
+                Box::new(self.compile_matcher(*tail, span, bound)?),
+            ),
+            AstPattern::Or(alternatives) => Matcher::Or(
+                alternatives
+                    .into_iter()
+                    .map(|pattern| self.compile_matcher(pattern, span.clone(), bound))
+                    .collect::<Result<_>>()?,
+            ),
+            AstPattern::Rest(_) => {
+                return Err(
+                    self.runtime_error("`...name` can only be used inside of an array", span)
+                )
+            }
+        })
+    }
+
+    /// A new variable for a name bound by an `mc` pattern, the same one for
+    /// the name repeated in the pattern.
+    fn bind_arm_name(&mut self, name: String, bound: &mut Vec<(String, Option<VarId>)>) -> VarId {
+        if bound.iter().any(|(bound, _)| *bound == name) {
+            return self.variables_id[&name];
+        }
+
+        let id = self.var_id_count as VarId;
+        self.var_id_count += 1;
+        let outer = self.bind_name(name.clone(), id);
+        bound.push((name, outer));
+        id
+    }
+
+    fn pattern_literal(&self, expr: Expr) -> Result<Value> {
+        Ok(match expr.inner {
+            ExprKind::Int(i) => Value::Int(i),
+            ExprKind::Float(f) => Value::Float(f),
+            ExprKind::String(s) => Value::String(s),
+            ExprKind::Bool(b) => Value::Bool(b),
+            ExprKind::Nil => Value::Nil,
+            ExprKind::Unary(UnaryOp::Neg, value) => match value.inner {
+                ExprKind::Int(i) => Value::Int(-i),
+                ExprKind::Float(f) => Value::Float(-f),
+                _ => return Err(self.runtime_error("Expected a number after '-'", expr.span)),
+            },
+            _ => return Err(self.runtime_error("Patterns can only contain literals", expr.span)),
+        })
+    }
+
+    /// Compiles `rng(end)`, `rng(start, end)` or `rng(start, end, step)`, the
+    /// start defaults to 0 and the step to nil (1 or -1 depending on the ends).
+    fn compile_range_call(&mut self, args: Vec<Expr>, span: Range<usize>) -> Result<()> {
+        if args.len() == 1 {
+            self.push_data(0u32.into(), span.clone());
+        }
+
+        let num_args = args.len();
+        for arg in args {
+            self.compile_expr(arg)?;
+        }
+
+        if num_args < 3 {
+            self.push_data(Value::Nil, span.clone());
+        }
+
+        self.instructions.push((Instr(Range, vec![]), span));
+        Ok(())
+    }
+
+    /// Compiles a math builtin taking `num_args` numbers and an optional
+    /// precision, which defaults to nil (the global precision).
+    fn compile_math_call(
+        &mut self,
+        args: Vec<Expr>,
+        num_args: usize,
+        bytecode: Bytecode,
+        span: Range<usize>,
+    ) -> Result<()> {
+        let has_precision = args.len() > num_args;
+        for arg in args {
+            self.compile_expr(arg)?;
+        }
+
+        if !has_precision {
+            self.push_data(Value::Nil, span.clone());
+        }
+
+        self.instructions.push((Instr(bytecode, vec![]), span));
+        Ok(())
+    }
+
+    fn compile_regex_call(
+        &mut self,
+        args: Vec<Expr>,
+        bytecode: Bytecode,
+        span: Range<usize>,
+    ) -> Result<()> {
+        // Literal patterns are compiled once here, so that they are reported
+        // before the program starts. Other patterns fill the slot at runtime.
+        let cached = match &args[1].inner {
+            ExprKind::String(pattern) => match Pattern::new(pattern) {
+                Ok(pattern) => Value::Regex(pattern),
+                Err(e) => {
+                    return Err(
+                        self.runtime_error(&format!("Invalid regex: {e}"), args[1].span.clone())
+                    )
+                }
+            },
+            _ => Value::Nil,
+        };
+        let slot = self.add_constant(cached) - 1;
+
+        for arg in args {
+            self.compile_expr(arg)?;
+        }
+
+        self.instructions.push((Instr(bytecode, vec![slot]), span));
+        Ok(())
+    }
+
+    fn cached_pattern(
+        &mut self,
+        slot: usize,
+        pattern: &Value,
+        span: Range<usize>,
+    ) -> Result<Pattern> {
+        let Value::String(pattern) = pattern else {
+            return Err(self.runtime_error(
+                &format!(
+                    "Expected 'str' as the pattern, found '{}'",
+                    pattern.get_type()
+                ),
+                span,
+            ));
+        };
+
+        if let Value::Regex(cached) = &self.constants[slot] {
+            if cached.as_str() == pattern {
+                return Ok(cached.clone());
             }
         }
 
-        self.pc = pc_before;
-        self.variables[scope_idx] = variables;
-        self.variables_id = variables_id;
-
-        // For debugging weird stack state
-        // println!("stack after ==========");
-        // for (idx, itm) in self.stack.iter().enumerate() {
-        //     println!("stack[{idx}] = {}", unsafe { itm.as_ref() });
-        // }
-        // println!("=======================");
+        let compiled = Pattern::new(pattern)
+            .map_err(|e| self.runtime_error(&format!("Invalid regex: {e}"), span))?;
+        self.constants[slot] = Value::Regex(compiled.clone());
+        Ok(compiled)
     }
 
-    fn find_parent_loop_start_instr(
-        &mut self,
-        current_instr_ptr: usize,
-    ) -> Option<(usize, &(Instr, Range<usize>))> {
-        (0..current_instr_ptr)
-            .rev()
-            .map(|i| (i, &self.instructions[i]))
-            .find(|&(_, (instr, _))| matches!(instr.0, While))
+    /// Pops the top of the stack, running out of values is a bug in the
+    /// compiler but still ends with a diagnostic.
+    fn pop(&mut self, span: &Range<usize>) -> Result<NonNull<Value>> {
+        self.stack
+            .pop()
+            .ok_or_else(|| self.runtime_error("Stack underflow", span.clone()))
     }
 
-    fn check_type(&self, fn_name: &str, types: Vec<Type>, value: &Value, span: Range<usize>) {
-        if types.into_iter().find(|i| i.is_same_type(value)).is_none() {
-            self.runtime_error(
-                &format!(
-                    "No method named '{fn_name}' found on the type '{}'",
-                    value.get_type(),
-                ),
+    fn pop_integer(&mut self, span: Range<usize>) -> Result<Integer> {
+        let value = unsafe { self.pop(&span)?.as_ref() };
+        match value {
+            Value::Int(i) => Ok(i.clone()),
+            // `7.0` is fine, `7.6` isn't rounded
+            Value::Float(f) if f.is_integer() => Ok(f.to_integer().unwrap()),
+            Value::Float(_) => {
+                Err(self.runtime_error(&format!("Expected an integer, found {value}"), span))
+            }
+            _ => Err(self.runtime_error(
+                &format!("Expected an integer, found '{}'", value.get_type()),
                 span,
-            );
+            )),
         }
     }
 
-    fn handle_optional_args(
-        &mut self,
-        args: &Option<Vec<Expr>>,
-        default_arg: Option<Integer>,
-        bytecode: Bytecode,
-        span: Range<usize>,
-    ) {
-        let args = args.clone().unwrap();
-        let num_args = args.len();
-        if num_args < 1 || num_args > 2 {
-            self.runtime_error(
-                &format!("Expected 1 or 2 arguments, found {}", args.len()),
+    /// Pops a base that `rug` can format and parse integers in.
+    fn convert_to_radix(&mut self, span: Range<usize>) -> Result<i32> {
+        match self.pop_integer(span.clone())?.to_i32() {
+            Some(base @ 2..=36) => Ok(base),
+            _ => Err(self.runtime_error("Base must be between 2 and 36", span)),
+        }
+    }
+
+    /// Pops the items a combinatorics builtin picks from.
+    fn pop_pool(&mut self, span: Range<usize>) -> Result<Vec<Value>> {
+        let pool = unsafe { self.pop(&span)?.as_ref() };
+        self.items(pool, "pick items from", span)
+    }
+
+    /// Converts the number of items to pick in a combinatorics builtin.
+    fn convert_to_count(&self, value: &Value, span: Range<usize>) -> Result<usize> {
+        match value {
+            Value::Int(k) => k
+                .to_usize()
+                .ok_or_else(|| self.runtime_error(&format!("Cannot pick {k} items"), span)),
+            _ => Err(self.runtime_error(
+                &format!("Expected an integer, found '{}'", value.get_type()),
                 span,
-            );
+            )),
         }
+    }
 
-        self.compile_expr(args[0].clone());
-        if num_args == 2 {
-            self.compile_expr(args[1].clone());
-        } else if let Some(default_arg) = default_arg {
-            self.push_data(Value::Int(default_arg), span.clone());
+    fn pop_precision(&mut self, span: Range<usize>) -> Result<u32> {
+        let prec = unsafe { self.pop(&span)?.as_ref() };
+        match prec {
+            Value::Nil => Ok(float_precision()),
+            _ => self.convert_to_precision(prec, span),
         }
+    }
 
-        self.instructions.push((Instr(bytecode, vec![]), span));
+    fn pop_float(&mut self, prec: u32, span: Range<usize>) -> Result<Float> {
+        let value = unsafe { self.pop(&span)?.as_ref() };
+        math::to_float(value, prec).ok_or_else(|| self.runtime_error("Expected a number", span))
+    }
+
+    fn convert_to_precision(&self, value: &Value, span: Range<usize>) -> Result<u32> {
+        let Value::Int(prec) = value else {
+            return Err(self.runtime_error("Expected an integer precision", span));
+        };
+
+        math::valid_precision(prec).ok_or_else(|| {
+            self.runtime_error(
+                &format!(
+                    "Precision must be between {} and {} bits",
+                    rug::float::prec_min(),
+                    math::MAX_PRECISION
+                ),
+                span,
+            )
+        })
     }
 
-    fn convert_to_i128(&self, value: &Value, span: Range<usize>) -> i128 {
+    fn convert_to_i128(&self, value: &Value, span: Range<usize>) -> Result<i128> {
         match value {
-            Value::Int(i) => i.saturating_cast(),
-            Value::Float(f) => f
+            Value::Int(i) => Ok(i.saturating_cast()),
+            Value::Float(f) => Ok(f
                 .to_integer()
                 .unwrap_or_else(|| {
                     // Only way for the unwrap to fail is if the float is infinity
@@ -1944,8 +3792,8 @@ impl VM {
                         Integer::from(i128::MAX)
                     }
                 })
-                .saturating_cast(),
-            _ => self.runtime_error("Expected a number", span),
+                .saturating_cast()),
+            _ => Err(self.runtime_error("Expected a number", span)),
         }
     }
 }
@@ -1961,13 +3809,35 @@ mod tests {
     use super::*;
     use crate::parser::ExprKind;
 
+    fn run_source(src: &str) -> VM {
+        let ast = PParser::new(src, crate::tokenize(src)).parse();
+        let mut vm = VM::new(src, ast);
+        vm.compile();
+        vm.run();
+        vm
+    }
+
+    /// The message of the runtime error `src` raises.
+    fn run_error(src: &str) -> String {
+        let vm = run_source(&format!("tr {{\n{src}\n}} ca e {{ e }}"));
+        match top_of_stack(&vm) {
+            Value::Error(error) => error.message.clone(),
+            value => panic!("expected an error, found {value:?}"),
+        }
+    }
+
+    fn top_of_stack(vm: &VM) -> &Value {
+        unsafe { vm.stack.last().unwrap().as_ref() }
+    }
+
     #[test]
     fn test_compile_expr_int() {
         let mut vm = VM::new("", vec![]);
         vm.compile_expr(Expr {
             span: 0..0,
             inner: ExprKind::Int(Integer::from(5)),
-        });
+        })
+        .unwrap();
         assert_eq!(vm.instructions.len(), 1);
         assert_eq!(vm.instructions[0].0 .0, LoadConst);
         assert_eq!(vm.constants[0], Value::Int(Integer::from(5)));
@@ -1979,7 +3849,8 @@ mod tests {
         vm.compile_expr(Expr {
             span: 0..0,
             inner: ExprKind::Float(float!(5.0)),
-        });
+        })
+        .unwrap();
         assert_eq!(vm.instructions.len(), 1);
         assert_eq!(vm.instructions[0].0 .0, LoadConst);
         assert_eq!(vm.constants[0], Value::Float(float!(5.0)));
@@ -1992,7 +3863,8 @@ mod tests {
         vm.compile_expr(Expr {
             span: 0..0,
             inner: ExprKind::Ident("x".to_string()),
-        });
+        })
+        .unwrap();
         assert_eq!(vm.instructions.len(), 1);
         assert_eq!(vm.instructions[0].0 .0, GetVar);
     }
@@ -2009,7 +3881,8 @@ mod tests {
                     inner: ExprKind::Int(Integer::from(5)),
                 }),
             ),
-        });
+        })
+        .unwrap();
         assert_eq!(vm.instructions.len(), 3);
         assert_eq!(vm.instructions[0].0 .0, MakeVar);
         assert_eq!(vm.instructions[1].0 .0, LoadConst);
@@ -2021,7 +3894,7 @@ mod tests {
         let mut vm = VM::new("", vec![]);
         vm.add_constant(Value::Int(Integer::from(5)));
         let instr = Instr(LoadConst, vec![0]);
-        vm.run_byte(instr, 0..0);
+        vm.run_byte(instr, 0..0).unwrap();
         assert_eq!(vm.stack.len(), 1);
         assert_eq!(
             unsafe { vm.stack[0].as_ref() },
@@ -2036,13 +3909,15 @@ mod tests {
             span: 0..0,
             inner: ExprKind::InlineFunction(
                 "f".to_string(),
-                vec!["x".to_string()],
+                vec![Param::new("x".to_string())],
+                None,
                 Box::new(Expr {
                     span: 0..0,
                     inner: ExprKind::Ident("x".to_string()),
                 }),
             ),
-        });
+        })
+        .unwrap();
         assert_eq!(vm.functions.len(), 1);
         assert!(vm.functions.contains_key("f"));
     }
@@ -2058,6 +3933,11 @@ mod tests {
                 instruction_range: 0..0,
                 scope_idx: 0,
                 returns: false,
+                generator: false,
+                required: 1,
+                entry_points: vec![0],
+                variadic: false,
+                memo: None,
             },
         );
         vm.compile_expr(Expr {
@@ -2069,7 +3949,8 @@ mod tests {
                     inner: ExprKind::Int(Integer::from(5)),
                 }]),
             ),
-        });
+        })
+        .unwrap();
         assert_eq!(vm.instructions.len(), 3);
         assert_eq!(vm.instructions[1].0 .0, LoadConst);
         assert_eq!(vm.instructions[2].0 .0, FnCall);
@@ -2078,22 +3959,30 @@ mod tests {
     #[test]
     fn test_run_byte_fn_call() {
         let mut vm = VM::new("", vec![]);
-        vm.add_constant(Value::Int(Integer::from(5)));
+        vm.variables[0].insert(0, None);
         vm.functions.insert(
             "f".to_string(),
             FunctionData {
                 name: "f".to_string(),
                 parameters: vec![("x".to_string(), 0)],
-                instruction_range: 0..0,
+                instruction_range: 1..1,
                 scope_idx: 0,
                 returns: true,
+                generator: false,
+                required: 1,
+                entry_points: vec![1],
+                variadic: false,
+                memo: None,
             },
         );
-        let instr = Instr(FnCall, vec![0]);
-        vm.run_byte(instr, 0..0);
-        assert_eq!(vm.stack.len(), 1);
+        vm.stack.push(allocate(Value::Int(Integer::from(5))));
+        vm.stack.push(allocate(Value::from("f")));
+        let instr = Instr(FnCall, vec![1]);
+        vm.run_byte(instr, 0..0).unwrap();
+        assert_eq!(vm.stack.len(), 0);
+        assert_eq!(vm.call_stack.len(), 1);
         assert_eq!(
-            unsafe { vm.stack[0].as_ref() },
+            unsafe { vm.get_var(0).unwrap().as_ref() },
             &Value::Int(Integer::from(5))
         );
     }
@@ -2114,7 +4003,8 @@ mod tests {
                     inner: ExprKind::Int(Integer::from(3)),
                 }),
             ),
-        });
+        })
+        .unwrap();
         assert_eq!(vm.instructions.len(), 3);
         assert_eq!(vm.instructions[0].0 .0, LoadConst);
         assert_eq!(vm.instructions[1].0 .0, LoadConst);
@@ -2144,4 +4034,551 @@ mod tests {
         assert_eq!(vm.constants[0], Value::Int(Integer::from(5)));
         assert_eq!(vm.constants[1], Value::Int(Integer::from(3)));
     }
+
+    #[test]
+    fn test_compile_regex_literal_is_cached() {
+        let mut vm = VM::new("", vec![]);
+        vm.compile_expr(Expr {
+            span: 0..0,
+            inner: ExprKind::Call(
+                "ref".to_string(),
+                Some(vec![
+                    Expr {
+                        span: 0..0,
+                        inner: ExprKind::String("a1b22".to_string()),
+                    },
+                    Expr {
+                        span: 0..0,
+                        inner: ExprKind::String(r"\d+".to_string()),
+                    },
+                ]),
+            ),
+        })
+        .unwrap();
+        assert!(matches!(&vm.constants[0], Value::Regex(p) if p.as_str() == r"\d+"));
+        assert_eq!(vm.instructions.last().unwrap().0 .0, ReFind);
+        assert_eq!(vm.instructions.last().unwrap().0 .1, vec![0]);
+    }
+
+    #[test]
+    fn test_run_regex_builtins() {
+        let vm = run_source(r#"ref("a1b22c", "\\d+")"#);
+        assert_eq!(
+            top_of_stack(&vm),
+            &Value::Array(vec!["1".into(), "22".into()])
+        );
+
+        let vm = run_source(r#"rec("k=v", "(\\w)=(\\w)(x)?")"#);
+        assert_eq!(
+            top_of_stack(&vm),
+            &Value::Array(vec![Value::Array(vec![
+                "k=v".into(),
+                "k".into(),
+                "v".into(),
+                Value::Nil
+            ])])
+        );
+
+        let vm = run_source(r#"rer("2023-10", "(\\d+)-(\\d+)", "$2/$1")"#);
+        assert_eq!(top_of_stack(&vm), &Value::from("10/2023"));
+
+        let vm = run_source(r#"p = ", *"; res("a, b,c", p)"#);
+        assert_eq!(
+            top_of_stack(&vm),
+            &Value::Array(vec!["a".into(), "b".into(), "c".into()])
+        );
+    }
+
+    #[test]
+    fn test_run_math_builtins() {
+        let vm = run_source("hypot(3, 4)");
+        assert_eq!(top_of_stack(&vm), &Value::Float(float!(5)));
+
+        let vm = run_source("pi(200)");
+        let Value::Float(pi) = top_of_stack(&vm) else {
+            panic!("expected a float");
+        };
+        assert_eq!(pi.prec(), 200);
+        assert_eq!(*pi, Float::with_val(200, rug::float::Constant::Pi));
+    }
+
+    #[test]
+    fn test_run_prec_sets_global_precision() {
+        let vm = run_source("old = prec(100)\nold");
+        assert_eq!(top_of_stack(&vm), &Value::Int(Integer::from(53)));
+
+        let vm = run_source("e()");
+        let Value::Float(e) = top_of_stack(&vm) else {
+            panic!("expected a float");
+        };
+        assert_eq!(e.prec(), 100);
+        set_float_precision(53);
+
+        assert_eq!(
+            run_error("prec(4000000000)"),
+            "Precision must be between 1 and 65536 bits"
+        );
+        assert_eq!(float_precision(), 53);
+    }
+
+    #[test]
+    fn test_run_number_theory_builtins() {
+        let ints = |v: &[u32]| Value::Array(v.iter().map(Value::from).collect());
+
+        let vm = run_source("factor(1000000007 * 360)");
+        assert_eq!(
+            top_of_stack(&vm),
+            &Value::Array(
+                [2, 2, 2, 3, 3, 5, 1000000007]
+                    .iter()
+                    .map(|&i| Value::Int(Integer::from(i)))
+                    .collect()
+            )
+        );
+
+        let vm = run_source("powmod(2, 100, 1000000007)");
+        assert_eq!(top_of_stack(&vm), &Value::from(976371285u32));
+
+        let vm = run_source("digits(255, 16)");
+        assert_eq!(top_of_stack(&vm), &ints(&[15, 15]));
+        // the zeros inside the halves it is split in are kept
+        let vm = run_source("digits(1000001000)");
+        assert_eq!(top_of_stack(&vm), &ints(&[1, 0, 0, 0, 0, 0, 1, 0, 0, 0]));
+        let vm = run_source("digits(1000000000000000000000000007, 1000)");
+        assert_eq!(top_of_stack(&vm), &ints(&[1, 0, 0, 0, 0, 0, 0, 0, 0, 7]));
+
+        let vm = run_source("frombase(tobase(-255, 16), 16)");
+        assert_eq!(top_of_stack(&vm), &Value::Int(Integer::from(-255)));
+
+        let vm = run_source("isprime(7.0)");
+        assert_eq!(top_of_stack(&vm), &Value::Bool(true));
+        for src in [
+            "isprime(7.6)",
+            "factor(12.4)",
+            "tobase(10.7, 2)",
+            "powmod(2.5, 3, 5)",
+        ] {
+            assert!(
+                run_error(src).starts_with("Expected an integer, found "),
+                "{src}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_run_string_repeat() {
+        let vm = run_source("x = \"ab\"\nx *= 2\n3 * x");
+        assert_eq!(top_of_stack(&vm), &Value::from("abababababab"));
+
+        for src in ["\"ab\" * -1", "-1 * \"ab\"", "x = \"a\"\nx *= -1"] {
+            assert_eq!(
+                run_error(src),
+                "Cannot repeat a string a negative number of times (-1)",
+                "{src}"
+            );
+        }
+        assert_eq!(
+            run_error("\"a\" * 99999999999999999999"),
+            "Cannot repeat a string 99999999999999999999 times, it would be too long"
+        );
+    }
+
+    #[test]
+    fn test_run_too_large() {
+        let vm = run_source("20! + fib(20)");
+        assert_eq!(
+            top_of_stack(&vm),
+            &Value::Int(Integer::from(2432902008176646765u64))
+        );
+
+        for src in ["99999999999999999999999!", "1e400!"] {
+            let message = "`!` of a number above 1000000 is too large to compute";
+            assert_eq!(run_error(src), message, "{src}");
+        }
+        assert_eq!(
+            run_error("fib(99999999999999999999999)"),
+            "`fib` of a number above 50000000 is too large to compute"
+        );
+    }
+
+    #[test]
+    fn test_run_combinatorics_builtins() {
+        let arrays = |v: &[&[u32]]| {
+            Value::Array(
+                v.iter()
+                    .map(|a| Value::Array(a.iter().map(Value::from).collect()))
+                    .collect(),
+            )
+        };
+        // the builtins are lazy, collect them to compare
+        let collected = |vm: &VM| Value::Array(top_of_stack(vm).as_array().unwrap().into_owned());
+
+        let vm = run_source("perms([1, 2, 3], 2)");
+        assert_eq!(
+            collected(&vm),
+            arrays(&[&[1, 2], &[1, 3], &[2, 1], &[2, 3], &[3, 1], &[3, 2]])
+        );
+
+        let vm = run_source("combs([1, 2, 3, 4], 3)");
+        assert_eq!(
+            collected(&vm),
+            arrays(&[&[1, 2, 3], &[1, 2, 4], &[1, 3, 4], &[2, 3, 4]])
+        );
+
+        let vm = run_source("powerset([1, 2])");
+        assert_eq!(collected(&vm), arrays(&[&[], &[1], &[2], &[1, 2]]));
+
+        let vm = run_source("product([1, 2], [3], [4, 5])");
+        assert_eq!(
+            collected(&vm),
+            arrays(&[&[1, 3, 4], &[1, 3, 5], &[2, 3, 4], &[2, 3, 5]])
+        );
+    }
+
+    #[test]
+    fn test_run_lazy_ranges() {
+        let vm = run_source("rng(10, 0, -3)");
+        let range = top_of_stack(&vm);
+        assert_eq!(range.get_type(), "iter");
+        assert_eq!(
+            range.as_array().unwrap().into_owned(),
+            [10u32, 7, 4, 1].iter().map(Value::from).collect::<Vec<_>>()
+        );
+
+        // neither the length nor an index needs the whole range in memory
+        let vm = run_source("len(rng(0, 10**12, 7))");
+        assert_eq!(
+            top_of_stack(&vm),
+            &Value::Int(Integer::from(142857142858u64))
+        );
+        let vm = run_source("x = rng(0, 10**12, 7)\nx[100000000000]");
+        assert_eq!(
+            top_of_stack(&vm),
+            &Value::Int(Integer::from(700000000000u64))
+        );
+
+        let vm = run_source("c = 0\nev rng(0, 10**12, 7) {\n c += i\n i == 70 ? br\n}\nc");
+        assert_eq!(top_of_stack(&vm), &Value::Int(Integer::from(385)));
+
+        // changing a range or adding to it makes the array
+        let vm = run_source("x = rng(10, 0, -3)\nx.push(0)\nx + [9]");
+        assert_eq!(
+            top_of_stack(&vm),
+            &Value::Array([10u32, 7, 4, 1, 0, 9].iter().map(Value::from).collect())
+        );
+        let vm = run_source("[9] + rng(3)");
+        assert_eq!(
+            top_of_stack(&vm),
+            &Value::Array([9u32, 0, 1, 2].iter().map(Value::from).collect())
+        );
+
+        let vm = run_source("rng(5, inf)[1000000]");
+        assert_eq!(top_of_stack(&vm), &Value::Int(Integer::from(1000005)));
+
+        let vm = run_source("c = 0\nev rng(1, inf) {\n c += i\n i == 100 ? br\n}\nc");
+        assert_eq!(top_of_stack(&vm), &Value::Int(Integer::from(5050)));
+    }
+
+    #[test]
+    fn test_run_every_in_recursive_function() {
+        let vm = run_source("s n: {\n t = 0\n ev rng(n) {\n  t += s(i) + 1\n }\n t\n}\ns(5)");
+        assert_eq!(top_of_stack(&vm), &Value::Int(Integer::from(31)));
+    }
+
+    #[test]
+    fn test_run_generators() {
+        let vm = run_source(
+            "nat: {\n n = 1\n >. true {\n  yd n\n  n++\n }\n}\n\
+             c = 0\nev nat() {\n i > 100 ? br\n c += i\n}\nc",
+        );
+        assert_eq!(top_of_stack(&vm), &Value::Int(Integer::from(5050)));
+
+        // every loop over the same generator starts from the beginning
+        let vm = run_source(
+            "twice a: {\n yd a\n yd a * 2\n}\ng = twice(3)\n\
+             c = 0\nev g {\n c += i\n}\nev g {\n c += i\n}\nc",
+        );
+        assert_eq!(top_of_stack(&vm), &Value::Int(Integer::from(18)));
+
+        let vm = run_source(
+            "down n: {\n yd n\n n > 0 ? {\n  ev down(n - 1) {\n   yd i\n  }\n }\n}\n\
+             c = 0\nev down(4) {\n c = c * 10 + i\n}\nc",
+        );
+        assert_eq!(top_of_stack(&vm), &Value::Int(Integer::from(43210)));
+    }
+
+    #[test]
+    fn test_run_try_catch() {
+        let vm = run_source("tr {\n x = int(\"abc\")\n} ca e {\n x = e\n}\nx");
+        assert_eq!(top_of_stack(&vm).get_type(), "error");
+
+        // the calls between the error and the handler are unwound
+        let vm = run_source(
+            "deep n: n == 0 ? raise(\"bottom\") : deep(n - 1)\n\
+             tr {\n deep(5)\n} ca e {\n e.msg()\n}",
+        );
+        assert_eq!(top_of_stack(&vm), &Value::from("bottom"));
+
+        let vm = run_source(
+            "f s: {\n tr {\n  & int(s)\n } ca {\n  & -1\n }\n}\nf(\"4\") * 10 + f(\"x\")",
+        );
+        assert_eq!(top_of_stack(&vm), &Value::Int(Integer::from(39)));
+
+        // the handlers of blocks left with `&` or `yd` don't pile up
+        let vm = run_source(
+            "g x: {\n tr {\n  & x\n } ca {}\n}\n\
+             gen: {\n tr {\n  yd 1\n  yd 2\n } ca {}\n}\n\
+             c = 0\nev rng(100) {\n c += g(i)\n ev gen() {\n  c += i\n }\n}\nc",
+        );
+        assert_eq!(top_of_stack(&vm), &Value::Int(Integer::from(5250)));
+        assert!(vm.handlers.is_empty());
+    }
+
+    #[test]
+    fn test_run_destructuring() {
+        let vm = run_source("a, b = 1, 2\na, b = b, a\na * 10 + b");
+        assert_eq!(top_of_stack(&vm), &Value::Int(Integer::from(21)));
+
+        let vm = run_source("[x, [y, z]] = [1, [2, 3]]\nh::t = [x, y, z]\nt");
+        assert_eq!(
+            top_of_stack(&vm),
+            &Value::Array(vec![Value::from(2u32), Value::from(3u32)])
+        );
+
+        let vm = run_source("f [a, b] c::d: a * b + c\nf([2, 3], [4, 5])");
+        assert_eq!(top_of_stack(&vm), &Value::Int(Integer::from(10)));
+    }
+
+    #[test]
+    fn test_run_match() {
+        let source = "desc x: mc x {
+            0: \"zero\"
+            1 | 2: \"small\"
+            3..9: \"digit\"
+            str: \"string\"
+            [a, ...m, b]: m
+            h::t: t
+            n if n < 0: -n
+            _: \"big\"
+        }
+        ";
+        let cases = [
+            ("0", Value::String("zero".to_string())),
+            ("2", Value::String("small".to_string())),
+            ("9", Value::String("digit".to_string())),
+            ("\"s\"", Value::String("string".to_string())),
+            ("[1, 2, 3, 4]", Value::Array(vec![2u32.into(), 3u32.into()])),
+            ("[1]", Value::Array(vec![])),
+            ("-4", Value::from(4u32)),
+            ("100", Value::String("big".to_string())),
+        ];
+        for (arg, expected) in cases {
+            let vm = run_source(&format!("{source}desc({arg})"));
+            assert_eq!(top_of_stack(&vm), &expected, "desc({arg})");
+        }
+
+        let vm = run_source("x = mc 5 { 1: 2 }\nx");
+        assert_eq!(top_of_stack(&vm), &Value::Nil);
+
+        // a defined name is compared against, the others only exist in the arm
+        let vm = run_source("x = 2\nmc 3 { x: \"x\"; _: \"other\" }");
+        assert_eq!(top_of_stack(&vm), &Value::String("other".to_string()));
+        let vm = run_source("x = 2\nmc [2, 3] { [x, ...x]: 0 }\nx");
+        assert_eq!(top_of_stack(&vm), &Value::from(2u32));
+    }
+
+    #[test]
+    fn test_run_default_and_variadic_params() {
+        let vm = run_source("f a b=10 c=a*2: [a, b, c]\n[f(1), f(1, 2), f(1, 2, 3)]");
+        let expected = [[1u32, 10, 2], [1, 2, 2], [1, 2, 3]]
+            .iter()
+            .map(|items| Value::Array(items.iter().map(|&i| i.into()).collect()))
+            .collect();
+        assert_eq!(top_of_stack(&vm), &Value::Array(expected));
+
+        let vm = run_source("f a ...rest: [a, rest]\nf(1, 2, 3)");
+        assert_eq!(
+            top_of_stack(&vm),
+            &Value::Array(vec![
+                1u32.into(),
+                Value::Array(vec![2u32.into(), 3u32.into()])
+            ])
+        );
+
+        let vm = run_source("f a b=1: a\ntr { f(1, 2, 3) } ca e { e.msg() }");
+        assert_eq!(
+            top_of_stack(&vm),
+            &Value::from("`f` takes 1 to 2 argument(s), found 3")
+        );
+    }
+
+    #[test]
+    fn test_run_memoized_functions() {
+        let vm = run_source("@f n: n < 2 ? n : f(n - 1) + f(n - 2)\nf(80)");
+        assert_eq!(
+            top_of_stack(&vm),
+            &Value::Int(Integer::from(23416728348467685u64))
+        );
+        assert_eq!(vm.functions["f"].memo.as_ref().unwrap().len(), 81);
+
+        // floats are keys by their value, whatever their precision
+        let vm = run_source("@f x: [x]\nf(1.5)\nprec(100)\nf(1.5)\nf(2.0)");
+        assert_eq!(vm.functions["f"].memo.as_ref().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_run_tail_calls() {
+        let run = |src: &str| {
+            let ast = PParser::new(src, crate::tokenize(src)).parse();
+            let mut vm = VM::new(src, ast);
+            vm.set_max_call_depth(100);
+            vm.compile();
+            vm.run();
+            vm
+        };
+
+        // far deeper than the limit, which only tail calls can go
+        let vm = run("f n acc: n == 0 ? acc : f(n - 1, acc + n)\nf(20000, 0)");
+        assert_eq!(top_of_stack(&vm), &Value::Int(Integer::from(200010000)));
+
+        let vm = run("f n: n == 0 ? 0 : 1 + f(n - 1)\ntr { f(20000) } ca e { e.msg() }");
+        assert_eq!(
+            top_of_stack(&vm),
+            &Value::from("Stack overflow, more than 100 nested calls")
+        );
+    }
+
+    #[test]
+    fn test_backtrace() {
+        let src = "half n: n / 2\nf s n: n == 0 ? half(1) + 0 : f(s, n - 1) + 0\nf(\"a\", 3)";
+        let ast = PParser::new(src, crate::tokenize(src)).parse();
+        let mut vm = VM::new(src, ast);
+        vm.compile();
+        while vm.instructions[vm.pc].0 .0 != Div {
+            let (instr, span) = vm.instructions[vm.pc].clone();
+            vm.run_byte(instr, span).unwrap();
+        }
+
+        let calls = vm.backtrace();
+        let calls = calls
+            .iter()
+            .map(|traced| (traced.call.as_str(), traced.count))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            calls,
+            [("f(\"a\", 3)", 1), ("f(\"a\", 0)", 3), ("half(1)", 1)]
+        );
+        assert_eq!(
+            vm.backtrace_listing(&vm.backtrace()),
+            "most recent call last:\n    f(\"a\", 3), called on line 3\n    f(\"a\", 0), called on line 2 (3 times)\n    half(1), called on line 2"
+        );
+    }
+
+    #[test]
+    fn test_backtrace_tail_calls() {
+        let src = "half n: n / 2\nf n: n == 0 ? half(1) : f(n - 1)\nf(3)";
+        let ast = PParser::new(src, crate::tokenize(src)).parse();
+        let mut vm = VM::new(src, ast);
+        vm.compile();
+        while vm.instructions[vm.pc].0 .0 != Div {
+            let (instr, span) = vm.instructions[vm.pc].clone();
+            vm.run_byte(instr, span).unwrap();
+        }
+
+        // the frames of `f` were all replaced
+        assert_eq!(
+            vm.backtrace_listing(&vm.backtrace()),
+            "most recent call last:\n    half(1), tail called on line 2"
+        );
+    }
+
+    #[test]
+    fn test_debug_eval() {
+        let src = "sq x: x * x\ny = 4\nz = sq(y)";
+        let ast = PParser::new(src, crate::tokenize(src)).parse();
+        let mut vm = VM::new(src, ast);
+        vm.compile();
+        vm.set_debugger(vec![]);
+        // at the start of `sq`
+        while vm.call_stack.is_empty() {
+            let (instr, span) = vm.instructions[vm.pc].clone();
+            vm.run_byte(instr, span).unwrap();
+        }
+
+        let (pc, stack_len) = (vm.pc, vm.stack.len());
+        assert_eq!(vm.debug_eval("x + 1"), Ok(Value::Int(Integer::from(5))));
+        assert_eq!(vm.debug_eval("[sq(3), x]").unwrap().to_string(), "[9, 4]");
+        // only the variables of the function are there
+        assert_eq!(vm.debug_eval("y"), Err("Variable not found".to_string()));
+        assert_eq!(vm.debug_eval("raise(\"no\")"), Err("no".to_string()));
+        assert_eq!(
+            vm.debug_eval("tr { raise(1) } ca { 2 }").ok(),
+            Some(Value::Int(Integer::from(2)))
+        );
+        assert_eq!(vm.frame_variables(), [("x".to_string(), 0)]);
+        assert_eq!((vm.pc, vm.stack.len()), (pc, stack_len));
+
+        // changing a variable changes what the program does
+        vm.debug_eval("x = 5").unwrap();
+        vm.debugger.as_mut().unwrap().mode = Mode::Continue;
+        vm.run();
+        assert_eq!(vm.debug_eval("z"), Ok(Value::Int(Integer::from(25))));
+    }
+
+    /// The stack, the variables and the number of instructions run.
+    type RunResult = (Vec<Value>, Vec<(u32, Value)>, usize);
+
+    /// Runs `src` as it's compiled and once optimized, returning the values
+    /// left on the stack and in the variables, and how many instructions ran.
+    /// The iterators of loops are left out, a generator knows where in the
+    /// code it stopped.
+    fn run_both(src: &str) -> [RunResult; 2] {
+        [false, true].map(|optimize| {
+            let ast = PParser::new(src, crate::tokenize(src)).parse();
+            let mut vm = VM::new(src, ast);
+            vm.compile();
+            if optimize {
+                vm.optimize();
+            }
+            vm.run();
+
+            let stack = vm
+                .stack
+                .iter()
+                .map(|value| unsafe { value.as_ref().clone() });
+            let mut variables = vm.variables[0]
+                .iter()
+                .filter_map(|(&id, value)| Some((id, unsafe { value.as_ref()?.as_ref().clone() })))
+                .filter(|(_, value)| !matches!(value, Value::Iter(_)))
+                .collect::<Vec<_>>();
+            variables.sort_by_key(|&(id, _)| id);
+            (stack.collect(), variables, vm.iteration)
+        })
+    }
+
+    #[test]
+    fn test_peephole_differential() {
+        let programs = [
+            include_str!("../../examples/factorial.sl"),
+            include_str!("../../examples/fizzbuzz.sl"),
+            include_str!("../../examples/sqrt.sl"),
+            "i = 0\ns = 0\n>. i < 20 {\n    i = i + 1\n    i % 2 == 0 ? ct : 0\n    i > 15 ? br : 0\n    s = s + i\n}\n$s",
+            "f n: n < 2 ? n : f(n - 1) + f(n - 2)\nx = f(15)\ny = x - 1 >= 600 ? x - 600 : -x",
+            "sum n acc=0: n == 0 ? acc : sum(n - 1, acc + n)\nx = sum(500)\nv a ...r: a + len(r)\ny = v(1, 2, 3)",
+            "@g n: n <= 1 ? 1 : g(n - 1) + g(n - 2)\nx = g(40)",
+            "s = \"\"\nev rng(0, 10) { s = s + \"ab\"; i == 5 ? br : 0 }\nt = s == \"abababababab\"",
+            "p: {\n    x = 2\n    >. true { yd x; x = x + 3 }\n}\nxs = []\nev p() { i > 20 ? br : xs.push(i) }",
+            "tr { raise(\"no\") } ca e { m = e.msg() }\ntr { 1 / 0 } ca { z = 1 }\nw = 1 < 2",
+            "d v: mc v {\n    0 | 1: \"small\"\n    [a, ...r]: r\n    n if n < 0: -n\n    n if n > 100 != false: \"big\"\n    _: v + 1\n}\nxs = [d(1), d(-4), d(500), d([1, 2]), d(7)]",
+            "x = 3\nc v: v < 1 ? 1 < 2 : v > 4\ny = c(x) ? 5 : 6\nz = x > 1 ? x < 2 ? 7 : 8 : 9\nb = x != 3",
+            "a, b = [1, [2, 3]]\nc = a + 1\nf x: { y = x - 2; y * 2 }\nd = f(c)",
+        ];
+
+        for src in programs {
+            let [compiled, optimized] = run_both(src);
+            assert_eq!(compiled.0, optimized.0, "{src}");
+            assert_eq!(compiled.1, optimized.1, "{src}");
+            assert!(optimized.2 < compiled.2, "{src}");
+        }
+    }
 }
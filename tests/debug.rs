@@ -0,0 +1,46 @@
+//! Drives `--debug` the way someone at the prompt would.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+#[test]
+fn test_debug_session() {
+    let dir = std::env::temp_dir().join(format!("shortlang-debug-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("prog.sl");
+    std::fs::write(
+        &file,
+        "sq x: {\n    y = x * x\n    y\n}\nev [2, 3] { $sq(i) }\n",
+    )
+    .unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_ShortLang"))
+        .arg(&file)
+        .args(["--debug", "--break", "3"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"v\np y + 1\nbt\nd 3\nn\nc\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    std::fs::remove_dir_all(&dir).ok();
+
+    let output = String::from_utf8(output.stdout).unwrap();
+    let expected = [
+        "Paused on line 3 in sq(2)",
+        "debug> x = 2\ny = 4",
+        "debug> 5",
+        "    sq(2), called on line 5",
+        "debug> Removed the breakpoint on line 3",
+        "debug> 4\nPaused on line 5",
+        "debug> 9\n",
+    ];
+    for text in expected {
+        assert!(output.contains(text), "{text:?} not in\n{output}");
+    }
+}
@@ -0,0 +1,81 @@
+//! Talks to `ShortLang lsp` the way an editor would.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{ChildStdout, Command, Stdio};
+
+fn send(stdin: &mut impl Write, body: &str) {
+    write!(stdin, "Content-Length: {}\r\n\r\n{body}", body.len()).unwrap();
+    stdin.flush().unwrap();
+}
+
+fn receive(stdout: &mut BufReader<ChildStdout>) -> String {
+    let mut length = 0;
+    loop {
+        let mut line = String::new();
+        stdout.read_line(&mut line).unwrap();
+        match line.trim_end().split_once(": ") {
+            Some(("Content-Length", value)) => length = value.parse().unwrap(),
+            _ if line.trim_end().is_empty() => break,
+            _ => {}
+        }
+    }
+
+    let mut body = vec![0; length];
+    stdout.read_exact(&mut body).unwrap();
+    String::from_utf8(body).unwrap()
+}
+
+#[test]
+fn test_lsp_session() {
+    let mut server = Command::new(env!("CARGO_BIN_EXE_ShortLang"))
+        .arg("lsp")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = server.stdin.take().unwrap();
+    let mut stdout = BufReader::new(server.stdout.take().unwrap());
+
+    send(
+        &mut stdin,
+        r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"capabilities":{}}}"#,
+    );
+    let response = receive(&mut stdout);
+    assert!(response.contains(r#""id":1"#));
+    assert!(response.contains(r#""hoverProvider":true"#));
+
+    send(
+        &mut stdin,
+        r#"{"jsonrpc":"2.0","method":"initialized","params":{}}"#,
+    );
+    send(
+        &mut stdin,
+        r#"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"file:///a.sl","languageId":"shortlang","version":1,"text":"sq x: x * x\n$sq(2)\n$nope()\n"}}}"#,
+    );
+    let diagnostics = receive(&mut stdout);
+    assert!(diagnostics.contains("textDocument/publishDiagnostics"));
+    assert!(diagnostics.contains("function `nope` is not defined"));
+    assert!(diagnostics.contains(r#""start":{"line":2,"character":1}"#));
+
+    send(
+        &mut stdin,
+        r#"{"jsonrpc":"2.0","id":2,"method":"textDocument/hover","params":{"textDocument":{"uri":"file:///a.sl"},"position":{"line":1,"character":2}}}"#,
+    );
+    let hover = receive(&mut stdout);
+    assert!(hover.contains(r#""id":2"#));
+    assert!(hover.contains("sq x"));
+
+    send(
+        &mut stdin,
+        r#"{"jsonrpc":"2.0","id":3,"method":"nope","params":{}}"#,
+    );
+    assert!(receive(&mut stdout).contains("-32601"));
+
+    send(
+        &mut stdin,
+        r#"{"jsonrpc":"2.0","id":4,"method":"shutdown"}"#,
+    );
+    assert!(receive(&mut stdout).contains(r#""result":null"#));
+    send(&mut stdin, r#"{"jsonrpc":"2.0","method":"exit"}"#);
+    assert!(server.wait().unwrap().success());
+}